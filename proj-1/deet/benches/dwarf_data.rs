@@ -0,0 +1,136 @@
+//! How long it takes to load a binary's debug info and to answer the lookups the debugger makes
+//! at every stop. Run with `cargo bench`. By default this loads the benchmark's own executable,
+//! which carries the DWARF of the Rust standard library and all of deet's dependencies; set
+//! DEET_BENCH_BINARY to load something else, like a large C++ program.
+//!
+//! `from_file` reads only the root DIE of each unit. The first lookup by name reads the names in
+//! every unit, and the first lookup in a unit parses its DIEs (or decodes its line table), so the
+//! "first ..." benchmarks load the binary afresh each run and include that. The others sample
+//! functions from every unit, which parses them all, and then time lookups that find everything
+//! already parsed.
+
+use deet::dwarf_data::DwarfData;
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// How many functions the lookup benchmarks sample
+const SAMPLE_SIZE: usize = 2000;
+
+fn main() {
+    let path = match env::var("DEET_BENCH_BINARY") {
+        Ok(path) => path,
+        Err(_) => env::current_exe()
+            .expect("Could not find the benchmark executable")
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let size = std::fs::metadata(&path).map_or(0, |metadata| metadata.len());
+    println!("Benchmarking {} ({:.1} MB)", path, size as f64 / 1e6);
+
+    bench("from_file", 5, 1, || {
+        black_box(DwarfData::from_file(&path).expect("Could not load debug info"));
+    });
+
+    bench("first get_addr_for_function", 5, 1, || {
+        let debug_data = DwarfData::from_file(&path).expect("Could not load debug info");
+        black_box(debug_data.get_addr_for_function(None, "main"));
+    });
+    bench("first get_line_from_addr", 5, 1, || {
+        let debug_data = DwarfData::from_file(&path).expect("Could not load debug info");
+        let main = debug_data
+            .symbols()
+            .iter()
+            .find(|symbol| symbol.name == "main")
+            .map_or(0, |symbol| symbol.address);
+        black_box(debug_data.get_line_from_addr(main));
+    });
+
+    let debug_data = DwarfData::from_file(&path).expect("Could not load debug info");
+    let functions = sample_functions(&debug_data);
+    println!(
+        "{} functions in {} units, sampling {}",
+        debug_data.files().map(|file| file.functions.len()).sum::<usize>(),
+        debug_data.file_count(),
+        functions.len()
+    );
+    let lookups = functions.len() as u32;
+    // An address part way into each function, like a return address in a backtrace
+    let addresses: Vec<usize> = functions
+        .iter()
+        .map(|(_, address, length)| address + length / 2)
+        .collect();
+
+    bench("get_function_containing", 20, lookups, || {
+        for addr in &addresses {
+            black_box(debug_data.get_function_containing(*addr));
+        }
+    });
+    bench("get_function_from_addr", 20, lookups, || {
+        for addr in &addresses {
+            black_box(debug_data.get_function_from_addr(*addr));
+        }
+    });
+    bench("get_line_from_addr", 20, lookups, || {
+        for addr in &addresses {
+            black_box(debug_data.get_line_from_addr(*addr));
+        }
+    });
+    bench("is_line_start", 20, lookups, || {
+        for addr in &addresses {
+            black_box(debug_data.is_line_start(*addr));
+        }
+    });
+    bench("get_addr_after_prologue", 20, lookups, || {
+        for (_, address, _) in &functions {
+            black_box(debug_data.get_addr_after_prologue(*address));
+        }
+    });
+    bench("get_addr_for_function", 20, lookups, || {
+        for (name, _, _) in &functions {
+            black_box(debug_data.get_addr_for_function(None, name));
+        }
+    });
+    bench("get_variable", 20, lookups, || {
+        for addr in &addresses {
+            black_box(debug_data.get_variable(*addr, "self"));
+        }
+    });
+}
+
+/// Up to SAMPLE_SIZE functions with code, spread evenly over the whole binary, as (name,
+/// address, length).
+fn sample_functions(debug_data: &DwarfData) -> Vec<(String, usize, usize)> {
+    let functions: Vec<_> = debug_data
+        .files()
+        .flat_map(|file| file.functions.iter())
+        .filter(|func| func.address != 0 && !func.name.is_empty())
+        .collect();
+    let step = (functions.len() / SAMPLE_SIZE).max(1);
+    functions
+        .iter()
+        .step_by(step)
+        .map(|func| (func.name.clone(), func.address, func.text_length))
+        .collect()
+}
+
+/// Runs `f` `runs` times and prints the fastest and median run, divided by the number of
+/// lookups each run makes.
+fn bench<F: FnMut()>(name: &str, runs: usize, lookups: u32, mut f: F) {
+    let mut times: Vec<Duration> = (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    let lookups = lookups.max(1);
+    println!(
+        "{:<28} min {:>12?}   median {:>12?}{}",
+        name,
+        times[0] / lookups,
+        times[runs / 2] / lookups,
+        if lookups > 1 { "   per lookup" } else { "" }
+    );
+}
@@ -0,0 +1,71 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef struct node {
+    int value;
+    struct node *next;
+} node;
+
+typedef struct {
+    node *head;
+    int size;
+} list;
+
+typedef struct entry {
+    const char *key;
+    int value;
+    struct entry *next;
+} entry;
+
+typedef struct {
+    entry **buckets;
+    int capacity;
+    int count;
+} table;
+
+typedef struct {
+    int length;
+    char *chars;
+} str;
+
+typedef struct {
+    int x;
+    int y;
+} point;
+
+void push(list *l, int value) {
+    node *n = malloc(sizeof(node));
+    n->value = value;
+    n->next = l->head;
+    l->head = n;
+    l->size++;
+}
+
+void put(table *t, const char *key, int value) {
+    entry *e = malloc(sizeof(entry));
+    e->key = key;
+    e->value = value;
+    e->next = t->buckets[strlen(key) % t->capacity];
+    t->buckets[strlen(key) % t->capacity] = e;
+    t->count++;
+}
+
+int main() {
+    list numbers = {NULL, 0};
+    push(&numbers, 3);
+    push(&numbers, 2);
+    push(&numbers, 1);
+
+    table ages = {calloc(4, sizeof(entry *)), 4, 0};
+    put(&ages, "ann", 31);
+    put(&ages, "bob", 27);
+    put(&ages, "carol", 45);
+
+    str name = {5, "hello world"};
+    point origin = {3, -4};
+    node *first = numbers.head;
+    printf("%d %d %.*s %d %d\n", numbers.size, ages.count, name.length, name.chars, origin.x,
+           first->value);
+    return 0;
+}
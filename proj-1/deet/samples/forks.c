@@ -0,0 +1,24 @@
+#include <pthread.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+int after(int x) {
+    return x + 1;
+}
+
+void *worker(void *arg) {
+    return (void *)(long)after((long)arg);
+}
+
+int main() {
+    pid_t pid = vfork();
+    if (pid == 0) {
+        _exit(3);
+    }
+    int status;
+    waitpid(pid, &status, 0);
+    pthread_t thread;
+    pthread_create(&thread, NULL, worker, (void *)1L);
+    pthread_join(thread, NULL);
+    return after(WEXITSTATUS(status));
+}
@@ -0,0 +1,29 @@
+#include <stdio.h>
+
+volatile int sink;
+
+static inline __attribute__((always_inline)) int square(int x) {
+    sink = x;
+    return x * x;
+}
+
+static inline __attribute__((always_inline)) int sum_squares(int a, int b) {
+    int sum = square(a);
+    sum += square(b);
+    return sum;
+}
+
+__attribute__((noinline)) int report(int total) {
+    printf("%d\n", total);
+    return total;
+}
+
+__attribute__((noinline)) int compute(int a, int b) {
+    sink = a + b;
+    int total = sum_squares(a, b);
+    return report(total) + 1;
+}
+
+int main(int argc, char **argv) {
+    return compute(argc, argc + 1) == 6 ? 0 : 1;
+}
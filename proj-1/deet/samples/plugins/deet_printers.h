@@ -0,0 +1,31 @@
+/* The interface between deet and pretty-printer plugins (see src/printers.rs). A plugin is a
+ * shared library exporting deet_register_printers, loaded with `printer load <library>.so`. */
+#ifndef DEET_PRINTERS_H
+#define DEET_PRINTERS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <sys/types.h>
+
+struct deet_value {
+    /* The name of the type the formatter was registered for */
+    const char *type_name;
+    /* The value's bytes, as laid out in the inferior */
+    const uint8_t *bytes;
+    size_t len;
+    /* Reads len bytes of the inferior's memory at addr into buf; false if they can't be read */
+    bool (*read_memory)(const void *context, uint64_t addr, uint8_t *buf, size_t len);
+    const void *context;
+};
+
+/* Writes the formatted value to out, without a NUL, and returns its length. If that is more than
+ * out_len, it is called again with room for all of it. A negative return leaves the value to
+ * deet's own formatting. */
+typedef ssize_t (*deet_formatter)(const struct deet_value *value, char *out, size_t out_len);
+
+typedef void (*deet_register)(void *registry, const char *pattern, deet_formatter formatter);
+
+void deet_register_printers(void *registry, deet_register reg);
+
+#endif
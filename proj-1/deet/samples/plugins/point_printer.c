@@ -0,0 +1,50 @@
+/* A pretty-printer plugin for containers.c, built with
+ *     cc -shared -fPIC -o point_printer.so point_printer.c
+ * It prints points from their bytes, and follows a node's next pointers through the inferior's
+ * memory. */
+#include <stdio.h>
+#include <string.h>
+
+#include "deet_printers.h"
+
+struct node {
+    int value;
+    uint64_t next;
+};
+
+static ssize_t format_point(const struct deet_value *value, char *out, size_t out_len) {
+    int xy[2];
+    if (value->len < sizeof(xy)) {
+        return -1;
+    }
+    memcpy(xy, value->bytes, sizeof(xy));
+    return snprintf(out, out_len, "point(%d, %d)", xy[0], xy[1]);
+}
+
+static ssize_t format_node(const struct deet_value *value, char *out, size_t out_len) {
+    struct node node;
+    if (value->len < sizeof(node)) {
+        return -1;
+    }
+    memcpy(&node, value->bytes, sizeof(node));
+    char text[256];
+    size_t used = snprintf(text, sizeof(text), "%d", node.value);
+    for (int i = 0; node.next != 0 && i < 100 && used < sizeof(text); i++) {
+        if (!value->read_memory(value->context, node.next, (uint8_t *)&node, sizeof(node))) {
+            return -1;
+        }
+        used += snprintf(text + used, sizeof(text) - used, " -> %d", node.value);
+    }
+    if (used >= sizeof(text)) {
+        used = sizeof(text) - 1;
+    }
+    if (used <= out_len) {
+        memcpy(out, text, used);
+    }
+    return used;
+}
+
+void deet_register_printers(void *registry, deet_register reg) {
+    reg(registry, "^point$", format_point);
+    reg(registry, "^node$", format_node);
+}
@@ -0,0 +1,184 @@
+//! A programmatic interface to the debugger, for embedding deet in another program or
+//! scripting it from tests:
+//!
+//! ```no_run
+//! # fn main() -> Result<(), deet::Error> {
+//! let mut session = deet::Session::new("samples/function_calls")?;
+//! session.break_at("func2")?;
+//! session.run(&[])?;
+//! println!("a = {}", session.read_var("a")?);
+//! for frame in session.backtrace()? {
+//!     println!("{}", frame.function);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A Session drives the same Debugger the command line does, so breakpoints, stepping and
+//! variables behave exactly as they do there.
+
+use crate::debugger::Debugger;
+use crate::dwarf_data::Line;
+use crate::inferior::{Frame, Status};
+use crate::Error;
+use nix::sys::signal::Signal;
+use std::path::Path;
+
+pub struct Session {
+    debugger: Debugger,
+}
+
+/// Why the inferior stopped running.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopEvent {
+    /// Stopped at a breakpoint or at the end of a step
+    Stopped(StopLocation),
+    /// Stopped by a signal, e.g. SIGSEGV. The signal is delivered when the inferior continues.
+    /// Signals programs get in the normal course of things (SIGCHLD, SIGALRM, SIGWINCH and the
+    /// like) don't stop it: like gdb, deet passes those straight on.
+    Signal(Signal, StopLocation),
+    /// Stopped at a syscall, fork or exec a `catch` command asked for
+    Caught(StopLocation),
+    Exited(i32),
+    Killed(Signal),
+}
+
+/// Where the inferior is stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopLocation {
+    pub address: usize,
+    pub function: Option<String>,
+    pub line: Option<Line>,
+}
+
+impl Session {
+    /// Loads `target`, without starting it yet.
+    pub fn new(target: &str) -> Result<Session, Error> {
+        Ok(Session {
+            debugger: Debugger::new(target)?,
+        })
+    }
+
+    /// Starts the target with `args`, killing any earlier run of it, and runs it until it
+    /// stops.
+    pub fn run(&mut self, args: &[&str]) -> Result<StopEvent, Error> {
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        let status = self.debugger.start(&args)?;
+        self.stop_event(status)
+    }
+
+    /// Sets a breakpoint on a function, a line (`12` or `file.c:12`) or an address
+    /// (`*0x401136`), and returns the addresses it was placed at. A function that isn't found
+    /// may be in a library that hasn't been loaded yet, so it gets a pending breakpoint and no
+    /// addresses.
+    pub fn break_at(&mut self, location: &str) -> Result<Vec<usize>, Error> {
+        self.debugger.break_at(location)
+    }
+
+    /// Like `break_at`, but each breakpoint is taken out the first time it is hit.
+    pub fn tbreak(&mut self, location: &str) -> Result<Vec<usize>, Error> {
+        self.debugger.tbreak_at(location)
+    }
+
+    /// Continues the inferior until it stops again.
+    pub fn cont(&mut self) -> Result<StopEvent, Error> {
+        let status = self.debugger.resume()?;
+        self.stop_event(status)
+    }
+
+    /// Steps to the next source line, into any function called.
+    pub fn step(&mut self) -> Result<StopEvent, Error> {
+        let status = self.debugger.step_source_line(false)?;
+        self.stop_event(status)
+    }
+
+    /// Steps to the next source line in this function.
+    pub fn next(&mut self) -> Result<StopEvent, Error> {
+        let status = self.debugger.step_source_line(true)?;
+        self.stop_event(status)
+    }
+
+    /// Runs until `location` is reached in this frame or a caller, or the current function
+    /// returns. With an empty location, steps to a line after this one, so that the rest of a
+    /// loop runs without stopping.
+    pub fn until(&mut self, location: &str) -> Result<StopEvent, Error> {
+        let status = self.debugger.until(location)?;
+        self.stop_event(status)
+    }
+
+    /// Runs until `location` is reached, in any frame, or the current function returns.
+    pub fn advance(&mut self, location: &str) -> Result<StopEvent, Error> {
+        let status = self.debugger.advance(location)?;
+        self.stop_event(status)
+    }
+
+    /// Executes a single instruction.
+    pub fn stepi(&mut self) -> Result<StopEvent, Error> {
+        let status = self.debugger.step_one_instruction()?;
+        self.stop_event(status)
+    }
+
+    /// The value of a variable as seen from the innermost frame, formatted as `print` shows it.
+    /// Fails with `Error::Evaluation` if there's no such variable or its value can't be read.
+    pub fn read_var(&mut self, name: &str) -> Result<String, Error> {
+        self.debugger.read_variable(name)
+    }
+
+    /// Loads pretty-printers from a printer file or plugin, as `printer load` does. Returns how
+    /// many were added.
+    pub fn load_printers(&mut self, path: &str) -> Result<usize, Error> {
+        self.debugger.load_printers(Path::new(path))
+    }
+
+    /// The frames on the inferior's stack, innermost first.
+    pub fn backtrace(&self) -> Result<Vec<Frame>, Error> {
+        self.debugger.frames()
+    }
+
+    /// Where the inferior is stopped.
+    pub fn location(&self) -> Result<StopLocation, Error> {
+        let regs = self.debugger.registers().ok_or(Error::NotRunning)?;
+        Ok(self.stop_location(regs.pc() as usize))
+    }
+
+    /// Kills the inferior. This also happens when the session is dropped.
+    pub fn kill(&mut self) {
+        self.debugger.kill_inferior();
+    }
+
+    /// The debugger underneath, e.g. to execute commands the session has no method for.
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    fn stop_event(&self, status: Status) -> Result<StopEvent, Error> {
+        Ok(match status {
+            Status::Stopped(Signal::SIGTRAP, address) => StopEvent::Stopped(self.stop_location(address)),
+            Status::Stopped(signal, address) => StopEvent::Signal(signal, self.stop_location(address)),
+            Status::Exited(code) => StopEvent::Exited(code),
+            Status::Signaled(signal) => StopEvent::Killed(signal),
+            Status::SyscallEntry(..)
+            | Status::SyscallExit(..)
+            | Status::Exec(_)
+            | Status::Forked(..)
+            | Status::Cloned(..) => {
+                StopEvent::Caught(self.location()?)
+            }
+        })
+    }
+
+    fn stop_location(&self, address: usize) -> StopLocation {
+        let modules = self.debugger.modules();
+        StopLocation {
+            address,
+            function: modules.get_function_from_addr(address),
+            line: modules.get_line_from_addr(address),
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
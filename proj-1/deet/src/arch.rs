@@ -0,0 +1,317 @@
+//! What deet needs to know about the processor the inferior runs on: where each register is in
+//! the register set the kernel hands out, what a breakpoint instruction looks like and where it
+//! leaves the pc, how to get from a frame to its caller's, and where an instruction goes without
+//! running it. Inferior uses the architecture deet was built for, through `native()`; the others
+//! are still useful for reading registers and memory that came from somewhere else, such as a
+//! core file.
+//!
+//! Breakpoints, stepping, walking the stack, recording, making the inferior fork for a
+//! checkpoint, decoding syscalls and reading variables' registers all go through here. Only
+//! calling functions in the inferior is x86-64's alone, since it follows the System V AMD64
+//! calling convention.
+
+mod aarch64;
+mod x86_64;
+
+pub use aarch64::Aarch64;
+pub use x86_64::X86_64;
+
+use std::convert::TryInto;
+use std::fs;
+use std::io::Read;
+
+/// The note type of the general purpose registers, as PTRACE_GETREGSET takes it and core files
+/// label them.
+pub const NT_PRSTATUS: usize = 1;
+
+/// Somewhere to read the inferior's memory from: the live process, or a recording of it.
+pub trait Memory {
+    /// The 8 bytes at `addr`, little-endian.
+    fn read_word(&self, addr: u64) -> Option<u64>;
+}
+
+/// The general purpose registers of a stopped thread, by DWARF register number, which is how the
+/// unwind information names them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registers {
+    values: Vec<u64>,
+    /// eflags on x86-64, pstate on aarch64: the condition flags conditional branches test
+    pub flags: u64,
+    pc_register: u16,
+    sp_register: u16,
+    fp_register: u16,
+}
+
+impl Registers {
+    pub fn get(&self, register: u16) -> Option<u64> {
+        self.values.get(register as usize).cloned()
+    }
+
+    pub fn set(&mut self, register: u16, value: u64) {
+        if let Some(slot) = self.values.get_mut(register as usize) {
+            *slot = value;
+        }
+    }
+
+    pub fn pc(&self) -> u64 {
+        self.values[self.pc_register as usize]
+    }
+
+    pub fn sp(&self) -> u64 {
+        self.values[self.sp_register as usize]
+    }
+
+    /// The frame pointer: rbp, or x29
+    pub fn fp(&self) -> u64 {
+        self.values[self.fp_register as usize]
+    }
+
+    pub fn set_pc(&mut self, value: u64) {
+        self.values[self.pc_register as usize] = value;
+    }
+
+    pub fn set_sp(&mut self, value: u64) {
+        self.values[self.sp_register as usize] = value;
+    }
+
+    /// How many registers there are, numbered from 0
+    pub fn count(&self) -> u16 {
+        self.values.len() as u16
+    }
+}
+
+pub trait Arch: Sync {
+    fn name(&self) -> &'static str;
+
+    /// The ELF e_machine of programs for this architecture
+    fn machine(&self) -> u16;
+
+    /// The instruction written over code to break there
+    fn breakpoint_instruction(&self) -> &'static [u8];
+
+    /// The instruction that makes a syscall
+    fn syscall_instruction(&self) -> &'static [u8];
+
+    /// The register a syscall's number goes in
+    fn syscall_number_register(&self) -> u16;
+
+    /// The registers a syscall's arguments go in, in order
+    fn syscall_argument_registers(&self) -> &'static [u16];
+
+    /// The register a syscall's result comes back in, -errno if it failed
+    fn syscall_result_register(&self) -> u16;
+
+    /// Where the kernel reports the number of the syscall a thread is stopped in, as a byte
+    /// offset in the NT_PRSTATUS register set
+    fn syscall_number_offset(&self) -> usize;
+
+    /// The number and arguments of the syscall that makes a copy of the process, as fork() does
+    fn fork_syscall(&self) -> (u64, &'static [u64]);
+
+    /// Bytes below the stack pointer that code may use without moving it
+    fn red_zone_size(&self) -> u64;
+
+    /// Where the breakpoint the inferior just trapped on is, given the pc it stopped with.
+    fn breakpoint_address(&self, pc: u64) -> u64;
+
+    /// Where each DWARF register (numbered from 0, without gaps) is in the NT_PRSTATUS register
+    /// set, as a byte offset.
+    fn prstatus_offsets(&self) -> &'static [usize];
+
+    /// Where the condition flags are in the NT_PRSTATUS register set
+    fn flags_offset(&self) -> usize;
+
+    /// How big the NT_PRSTATUS register set is
+    fn prstatus_size(&self) -> usize;
+
+    fn pc_register(&self) -> u16;
+    fn sp_register(&self) -> u16;
+    fn fp_register(&self) -> u16;
+
+    /// What assembly language calls DWARF register `register`, e.g. rbp or x29
+    fn register_name(&self, register: u16) -> Option<&'static str>;
+
+    /// The register the unwind information keeps the return address in
+    fn return_address_register(&self) -> u16;
+
+    /// If the instruction just executed, which took the registers from `before` to `after`, was
+    /// a call, the address the call will return to.
+    fn call_return_address(&self, before: &Registers, after: &Registers, memory: &dyn Memory) -> Option<u64>;
+
+    /// Single-step emulation: where the instruction at the pc will go next, worked out from the
+    /// instruction and the registers. None if this architecture's instructions aren't decoded,
+    /// which only matters where the hardware can't single-step.
+    fn next_pc(&self, regs: &Registers, memory: &dyn Memory) -> Option<u64>;
+
+    /// Reads registers out of an NT_PRSTATUS register set.
+    fn decode_registers(&self, prstatus: &[u8]) -> Option<Registers> {
+        let values = self
+            .prstatus_offsets()
+            .iter()
+            .map(|offset| read_word(prstatus, *offset))
+            .collect::<Option<Vec<u64>>>()?;
+        Some(Registers {
+            values,
+            flags: read_word(prstatus, self.flags_offset())?,
+            pc_register: self.pc_register(),
+            sp_register: self.sp_register(),
+            fp_register: self.fp_register(),
+        })
+    }
+
+    /// Reads a syscall stop out of an NT_PRSTATUS register set: the syscall's number, its
+    /// arguments, and its result, which only means anything once it has returned.
+    fn decode_syscall(&self, prstatus: &[u8]) -> Option<(u64, [u64; 6], i64)> {
+        let regs = self.decode_registers(prstatus)?;
+        let mut args = [0; 6];
+        for (arg, register) in args.iter_mut().zip(self.syscall_argument_registers()) {
+            *arg = regs.get(*register)?;
+        }
+        let result = regs.get(self.syscall_result_register())? as i64;
+        Some((read_word(prstatus, self.syscall_number_offset())?, args, result))
+    }
+
+    /// Changes one register in an NT_PRSTATUS register set, leaving the rest as they were.
+    fn encode_register(&self, prstatus: &mut [u8], register: u16, value: u64) -> bool {
+        let offset = match self.prstatus_offsets().get(register as usize) {
+            Some(offset) => *offset,
+            None => return false,
+        };
+        match prstatus.get_mut(offset..offset + 8) {
+            Some(bytes) => {
+                bytes.copy_from_slice(&value.to_le_bytes());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Unwinds one frame by the frame pointer. Both architectures keep a frame record where it
+    /// points: the caller's frame pointer, then the return address. Only the pc, sp and frame
+    /// pointer of the caller are known afterwards; the rest are left as they were.
+    fn caller_registers(&self, regs: &Registers, memory: &dyn Memory) -> Option<Registers> {
+        let fp = regs.fp();
+        if fp == 0 {
+            return None;
+        }
+        let mut caller = regs.clone();
+        caller.set(self.fp_register(), memory.read_word(fp)?);
+        caller.set(self.pc_register(), memory.read_word(fp + 8)?);
+        caller.set(self.sp_register(), fp + 16);
+        Some(caller)
+    }
+}
+
+/// The little-endian word at `offset` in a register set.
+fn read_word(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+/// The architecture deet was built for, which is the one it can trace.
+pub fn native() -> &'static dyn Arch {
+    #[cfg(target_arch = "aarch64")]
+    return &Aarch64;
+    #[cfg(not(target_arch = "aarch64"))]
+    return &X86_64;
+}
+
+/// The e_machine of the ELF file at `path`, if it is one.
+pub fn elf_machine(path: &str) -> Option<u16> {
+    let mut header = [0u8; 20];
+    fs::File::open(path).ok()?.read_exact(&mut header).ok()?;
+    if header[..4] != *b"\x7fELF" {
+        return None;
+    }
+    // e_machine follows e_ident and e_type, in the file's byte order
+    let machine = [header[18], header[19]];
+    Some(match header[5] {
+        2 => u16::from_be_bytes(machine),
+        _ => u16::from_le_bytes(machine),
+    })
+}
+
+/// The architecture of programs with ELF e_machine `machine`, if it is one we know.
+pub fn for_machine(machine: u16) -> Option<&'static dyn Arch> {
+    [&X86_64 as &dyn Arch, &Aarch64]
+        .iter()
+        .find(|arch| arch.machine() == machine)
+        .cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Memory recorded from a stopped process: one word per address.
+    pub struct RecordedMemory(pub HashMap<u64, u64>);
+
+    impl Memory for RecordedMemory {
+        fn read_word(&self, addr: u64) -> Option<u64> {
+            self.0.get(&addr).cloned()
+        }
+    }
+
+    #[test]
+    fn test_elf_machine() {
+        // The test binary itself is a program for the architecture deet was built for
+        let path = std::env::current_exe().unwrap();
+        assert_eq!(elf_machine(path.to_str().unwrap()), Some(native().machine()));
+        assert_eq!(elf_machine("Cargo.toml"), None);
+    }
+
+    #[test]
+    fn test_for_machine() {
+        assert_eq!(for_machine(62).map(|arch| arch.name()), Some("x86-64"));
+        assert_eq!(for_machine(183).map(|arch| arch.name()), Some("aarch64"));
+        assert!(for_machine(40).is_none());
+    }
+
+    #[test]
+    fn test_x86_64_registers() {
+        // user_regs_struct: rbp is the 5th field, rip the 17th and rsp the 20th
+        let mut prstatus = vec![0u8; X86_64.prstatus_size()];
+        prstatus[4 * 8..5 * 8].copy_from_slice(&0x7fffffffe0f0u64.to_le_bytes());
+        prstatus[16 * 8..17 * 8].copy_from_slice(&0x401136u64.to_le_bytes());
+        prstatus[19 * 8..20 * 8].copy_from_slice(&0x7fffffffe0e0u64.to_le_bytes());
+        let regs = X86_64.decode_registers(&prstatus).expect("Expected registers");
+        assert_eq!(regs.pc(), 0x401136);
+        assert_eq!(regs.sp(), 0x7fffffffe0e0);
+        assert_eq!(regs.fp(), 0x7fffffffe0f0);
+        assert_eq!(regs.get(6), Some(0x7fffffffe0f0));
+        assert!(X86_64.encode_register(&mut prstatus, 16, 0x401135));
+        assert_eq!(X86_64.decode_registers(&prstatus).unwrap().pc(), 0x401135);
+        assert_eq!(X86_64.breakpoint_address(0x401137), 0x401136);
+        assert_eq!(X86_64.register_name(X86_64.fp_register()), Some("rbp"));
+        assert!(X86_64.decode_registers(&prstatus[..100]).is_none());
+    }
+
+    #[test]
+    fn test_x86_64_decode_syscall() {
+        // write(1, buf, 6) returning 6: orig_rax keeps the number once rax has the result
+        let mut prstatus = vec![0u8; X86_64.prstatus_size()];
+        for (register, value) in [(5, 1), (4, 0x402010), (1, 6), (0, 6)].iter() {
+            assert!(X86_64.encode_register(&mut prstatus, *register, *value));
+        }
+        prstatus[15 * 8..16 * 8].copy_from_slice(&1u64.to_le_bytes());
+        assert_eq!(X86_64.decode_syscall(&prstatus), Some((1, [1, 0x402010, 6, 0, 0, 0], 6)));
+    }
+
+    #[test]
+    fn test_x86_64_call_return_address() {
+        let mut prstatus = vec![0u8; X86_64.prstatus_size()];
+        prstatus[16 * 8..17 * 8].copy_from_slice(&0x401136u64.to_le_bytes());
+        prstatus[19 * 8..20 * 8].copy_from_slice(&0x7fffffffe0e0u64.to_le_bytes());
+        let before = X86_64.decode_registers(&prstatus).unwrap();
+        // call 0x401000, 5 bytes long
+        let mut after = before.clone();
+        after.set_pc(0x401000);
+        after.set_sp(0x7fffffffe0d8);
+        let memory = RecordedMemory(vec![(0x7fffffffe0d8, 0x40113b)].into_iter().collect());
+        assert_eq!(X86_64.call_return_address(&before, &after, &memory), Some(0x40113b));
+        // push, which moves the stack the same way but stays put
+        after.set_pc(0x401137);
+        let memory = RecordedMemory(vec![(0x7fffffffe0d8, 0x1234)].into_iter().collect());
+        assert_eq!(X86_64.call_return_address(&before, &after, &memory), None);
+    }
+}
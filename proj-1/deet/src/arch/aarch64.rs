@@ -0,0 +1,299 @@
+//! aarch64: BRK #0 breakpoints, which leave the pc on them, and the register set in
+//! `struct user_pt_regs` order (x0-x30, sp, pc, pstate). Every instruction is 4 bytes, so where
+//! one goes can be worked out from its encoding and the registers.
+
+use super::{Arch, Memory, Registers};
+
+pub struct Aarch64;
+
+/// x0-x30, sp and pc, which are DWARF registers 0-32 and come in that order in user_pt_regs
+const PRSTATUS_OFFSETS: [usize; 33] = [
+    0, 8, 16, 24, 32, 40, 48, 56, 64, 72, 80, 88, 96, 104, 112, 120, 128, 136, 144, 152, 160, 168,
+    176, 184, 192, 200, 208, 216, 224, 232, 240, 248, 256,
+];
+
+const REGISTER_NAMES: [&str; 33] = [
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14",
+    "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28",
+    "x29", "x30", "sp", "pc",
+];
+
+const SP: u16 = 31;
+const PC: u16 = 32;
+const FP: u16 = 29;
+const LR: u16 = 30;
+
+impl Arch for Aarch64 {
+    fn name(&self) -> &'static str {
+        "aarch64"
+    }
+
+    fn machine(&self) -> u16 {
+        183 // EM_AARCH64
+    }
+
+    fn breakpoint_instruction(&self) -> &'static [u8] {
+        // BRK #0, 0xd4200000
+        &[0x00, 0x00, 0x20, 0xd4]
+    }
+
+    fn syscall_instruction(&self) -> &'static [u8] {
+        // SVC #0, 0xd4000001
+        &[0x01, 0x00, 0x00, 0xd4]
+    }
+
+    fn syscall_number_register(&self) -> u16 {
+        8
+    }
+
+    fn syscall_argument_registers(&self) -> &'static [u16] {
+        &[0, 1, 2, 3, 4, 5]
+    }
+
+    fn syscall_result_register(&self) -> u16 {
+        0
+    }
+
+    fn syscall_number_offset(&self) -> usize {
+        // x8, which the syscall leaves alone
+        8 * 8
+    }
+
+    fn fork_syscall(&self) -> (u64, &'static [u64]) {
+        // There is no fork, only clone(SIGCHLD, 0, NULL, 0, NULL)
+        (220, &[17, 0, 0, 0, 0])
+    }
+
+    fn red_zone_size(&self) -> u64 {
+        0
+    }
+
+    fn breakpoint_address(&self, pc: u64) -> u64 {
+        // The trap is taken before the BRK completes
+        pc
+    }
+
+    fn prstatus_offsets(&self) -> &'static [usize] {
+        &PRSTATUS_OFFSETS
+    }
+
+    fn flags_offset(&self) -> usize {
+        33 * 8
+    }
+
+    fn prstatus_size(&self) -> usize {
+        34 * 8
+    }
+
+    fn pc_register(&self) -> u16 {
+        PC
+    }
+
+    fn sp_register(&self) -> u16 {
+        SP
+    }
+
+    fn fp_register(&self) -> u16 {
+        FP
+    }
+
+    fn register_name(&self, register: u16) -> Option<&'static str> {
+        REGISTER_NAMES.get(register as usize).cloned()
+    }
+
+    fn return_address_register(&self) -> u16 {
+        LR
+    }
+
+    fn call_return_address(&self, before: &Registers, after: &Registers, memory: &dyn Memory) -> Option<u64> {
+        // BL and BLR leave the address of the next instruction in the link register
+        let insn = memory.read_word(before.pc())? as u32;
+        let is_call = insn & 0xfc00_0000 == 0x9400_0000 || insn & 0xffff_fc1f == 0xd63f_0000;
+        let return_addr = before.pc() + 4;
+        if is_call && after.get(LR) == Some(return_addr) {
+            Some(return_addr)
+        } else {
+            None
+        }
+    }
+
+    fn next_pc(&self, regs: &Registers, memory: &dyn Memory) -> Option<u64> {
+        let pc = regs.pc();
+        let insn = memory.read_word(pc)? as u32;
+        // Register 31 is the zero register wherever these instructions name one
+        let xreg = |n: u32| if n == 31 { 0 } else { regs.get(n as u16).unwrap_or(0) };
+        let rt = insn & 0x1f;
+        let rn = (insn >> 5) & 0x1f;
+        let target = if insn & 0x7c00_0000 == 0x1400_0000 {
+            // B, BL: imm26
+            Some(offset(pc, insn & 0x03ff_ffff, 26))
+        } else if insn & 0xff00_0010 == 0x5400_0000 {
+            // B.cond: imm19, cond
+            if condition_holds(insn & 0xf, regs.flags) {
+                Some(offset(pc, (insn >> 5) & 0x7ffff, 19))
+            } else {
+                None
+            }
+        } else if insn & 0x7e00_0000 == 0x3400_0000 {
+            // CBZ, CBNZ: sf, op, imm19, Rt
+            let value = if insn & 0x8000_0000 != 0 { xreg(rt) } else { xreg(rt) & 0xffff_ffff };
+            if (value == 0) == (insn & 0x0100_0000 == 0) {
+                Some(offset(pc, (insn >> 5) & 0x7ffff, 19))
+            } else {
+                None
+            }
+        } else if insn & 0x7e00_0000 == 0x3600_0000 {
+            // TBZ, TBNZ: b5, op, b40, imm14, Rt
+            let bit = ((insn >> 26) & 0x20) | ((insn >> 19) & 0x1f);
+            if ((xreg(rt) >> bit) & 1 == 0) == (insn & 0x0100_0000 == 0) {
+                Some(offset(pc, (insn >> 5) & 0x3fff, 14))
+            } else {
+                None
+            }
+        } else if insn & 0xff9f_fc1f == 0xd61f_0000 {
+            // BR, BLR, RET: Rn
+            Some(xreg(rn))
+        } else {
+            None
+        };
+        Some(target.unwrap_or(pc + 4))
+    }
+}
+
+/// pc plus a signed word offset `bits` wide
+fn offset(pc: u64, imm: u32, bits: u32) -> u64 {
+    let words = ((imm << (32 - bits)) as i32) >> (32 - bits);
+    pc.wrapping_add((words as i64 * 4) as u64)
+}
+
+/// Whether condition code `cond` holds for the NZCV flags in pstate
+fn condition_holds(cond: u32, pstate: u64) -> bool {
+    let n = (pstate >> 31) & 1 == 1;
+    let z = (pstate >> 30) & 1 == 1;
+    let c = (pstate >> 29) & 1 == 1;
+    let v = (pstate >> 28) & 1 == 1;
+    let holds = match cond >> 1 {
+        0 => z,            // EQ
+        1 => c,            // CS
+        2 => n,            // MI
+        3 => v,            // VS
+        4 => c && !z,      // HI
+        5 => n == v,       // GE
+        6 => !z && n == v, // GT
+        _ => true,         // AL
+    };
+    // The odd conditions are the even ones negated, except that 0b1111 is also "always"
+    if cond & 1 == 1 && cond != 0xf {
+        !holds
+    } else {
+        holds
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::RecordedMemory;
+    use super::*;
+
+    /// The registers of a program stopped in a function two calls below main, laid out as
+    /// PTRACE_GETREGSET(NT_PRSTATUS) returns them, and the frame records on its stack.
+    fn fixture() -> (Vec<u8>, RecordedMemory) {
+        let mut prstatus = vec![0u8; Aarch64.prstatus_size()];
+        let mut put = |index: usize, value: u64| {
+            prstatus[index * 8..index * 8 + 8].copy_from_slice(&value.to_le_bytes())
+        };
+        put(0, 3); // x0
+        put(1, 0); // x1
+        put(2, 0x8000_0000); // x2
+        put(29, 0xffff_ffff_f0c0); // x29
+        put(30, 0x0040_0640); // x30
+        put(31, 0xffff_ffff_f0c0); // sp
+        put(32, 0x0040_0600); // pc
+        put(33, 0x6000_0000); // pstate: Z and C
+        let memory = RecordedMemory(
+            vec![
+                (0xffff_ffff_f0c0, 0xffff_ffff_f0e0),
+                (0xffff_ffff_f0c8, 0x0040_0640),
+                (0xffff_ffff_f0e0, 0xffff_ffff_f100),
+                (0xffff_ffff_f0e8, 0x0040_0680),
+                (0xffff_ffff_f100, 0),
+                (0xffff_ffff_f108, 0x0000_ffff_b7e1_2340),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        (prstatus, memory)
+    }
+
+    /// Where the instruction `insn` at the fixture's pc goes
+    fn next_pc(insn: u32) -> u64 {
+        let (prstatus, mut memory) = fixture();
+        let regs = Aarch64.decode_registers(&prstatus).unwrap();
+        memory.0.insert(regs.pc(), insn as u64);
+        Aarch64.next_pc(&regs, &memory).unwrap()
+    }
+
+    #[test]
+    fn test_decode_registers() {
+        let (mut prstatus, _) = fixture();
+        let regs = Aarch64.decode_registers(&prstatus).expect("Expected registers");
+        assert_eq!(regs.pc(), 0x400600);
+        assert_eq!(regs.sp(), 0xffff_ffff_f0c0);
+        assert_eq!(regs.fp(), 0xffff_ffff_f0c0);
+        assert_eq!(regs.get(LR), Some(0x400640));
+        assert_eq!(Aarch64.register_name(LR), Some("x30"));
+        assert_eq!(regs.get(0), Some(3));
+        assert_eq!(regs.flags, 0x6000_0000);
+        assert!(Aarch64.encode_register(&mut prstatus, PC, 0x400604));
+        assert_eq!(Aarch64.decode_registers(&prstatus).unwrap().pc(), 0x400604);
+        assert_eq!(Aarch64.breakpoint_address(0x400600), 0x400600);
+        assert_eq!(
+            u32::from_le_bytes([0x00, 0x00, 0x20, 0xd4]),
+            0xd420_0000,
+            "BRK #0"
+        );
+    }
+
+    #[test]
+    fn test_caller_registers() {
+        let (prstatus, memory) = fixture();
+        let mut regs = Aarch64.decode_registers(&prstatus).unwrap();
+        let mut pcs = vec![regs.pc()];
+        while let Some(caller) = Aarch64.caller_registers(&regs, &memory) {
+            pcs.push(caller.pc());
+            regs = caller;
+        }
+        assert_eq!(pcs, vec![0x400600, 0x400640, 0x400680, 0xffff_b7e1_2340]);
+        assert_eq!(regs.sp(), 0xffff_ffff_f110);
+    }
+
+    #[test]
+    fn test_next_pc() {
+        assert_eq!(next_pc(0xd503_201f), 0x400604, "nop");
+        assert_eq!(next_pc(0x1400_0004), 0x400610, "b .+16");
+        assert_eq!(next_pc(0x97ff_fffe), 0x4005f8, "bl .-8");
+        assert_eq!(next_pc(0xd65f_03c0), 0x400640, "ret");
+        assert_eq!(next_pc(0xd63f_0040), 0x8000_0000, "blr x2");
+        assert_eq!(next_pc(0x5400_0040), 0x400608, "b.eq .+8, Z set");
+        assert_eq!(next_pc(0x5400_0041), 0x400604, "b.ne .+8, Z set");
+        assert_eq!(next_pc(0x5400_004b), 0x400604, "b.lt .+8, N == V");
+        assert_eq!(next_pc(0xb400_0061), 0x40060c, "cbz x1, .+12");
+        assert_eq!(next_pc(0xb500_0061), 0x400604, "cbnz x1, .+12");
+        assert_eq!(next_pc(0x3600_0080), 0x400604, "tbz w0, #0, .+16");
+        assert_eq!(next_pc(0x3708_0080), 0x400610, "tbnz w0, #1, .+16");
+        assert_eq!(next_pc(0xb7f8_0082), 0x400604, "tbnz x2, #63, .+16");
+    }
+
+    #[test]
+    fn test_call_return_address() {
+        let (prstatus, mut memory) = fixture();
+        let before = Aarch64.decode_registers(&prstatus).unwrap();
+        let mut after = before.clone();
+        after.set_pc(0x4005f8);
+        after.set(LR, 0x400604);
+        memory.0.insert(before.pc(), 0x97ff_fffe); // bl .-8
+        assert_eq!(Aarch64.call_return_address(&before, &after, &memory), Some(0x400604));
+        memory.0.insert(before.pc(), 0x1400_0004); // b .+16
+        assert_eq!(Aarch64.call_return_address(&before, &after, &memory), None);
+    }
+}
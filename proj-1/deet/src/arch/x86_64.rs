@@ -0,0 +1,135 @@
+//! x86-64: int3 breakpoints, which leave rip one past them, and the register set in
+//! `struct user_regs_struct` order.
+
+use super::{Arch, Memory, Registers};
+
+pub struct X86_64;
+
+/// Offsets in user_regs_struct of rax, rdx, rcx, rbx, rsi, rdi, rbp, rsp, r8-r15 and rip, which
+/// are DWARF registers 0-16.
+const PRSTATUS_OFFSETS: [usize; 17] = [
+    10 * 8, // rax
+    12 * 8, // rdx
+    11 * 8, // rcx
+    5 * 8,  // rbx
+    13 * 8, // rsi
+    14 * 8, // rdi
+    4 * 8,  // rbp
+    19 * 8, // rsp
+    9 * 8,  // r8
+    8 * 8,  // r9
+    7 * 8,  // r10
+    6 * 8,  // r11
+    3 * 8,  // r12
+    2 * 8,  // r13
+    8,      // r14
+    0,      // r15
+    16 * 8, // rip
+];
+
+const REGISTER_NAMES: [&str; 17] = [
+    "rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip",
+];
+
+impl Arch for X86_64 {
+    fn name(&self) -> &'static str {
+        "x86-64"
+    }
+
+    fn machine(&self) -> u16 {
+        62 // EM_X86_64
+    }
+
+    fn breakpoint_instruction(&self) -> &'static [u8] {
+        &[0xcc]
+    }
+
+    fn syscall_instruction(&self) -> &'static [u8] {
+        &[0x0f, 0x05]
+    }
+
+    fn syscall_number_register(&self) -> u16 {
+        0 // rax
+    }
+
+    fn syscall_argument_registers(&self) -> &'static [u16] {
+        // rdi, rsi, rdx, r10, r8, r9
+        &[5, 4, 1, 10, 8, 9]
+    }
+
+    fn syscall_result_register(&self) -> u16 {
+        0 // rax
+    }
+
+    fn syscall_number_offset(&self) -> usize {
+        // orig_rax, since the result overwrites rax
+        15 * 8
+    }
+
+    fn fork_syscall(&self) -> (u64, &'static [u64]) {
+        (57, &[]) // SYS_fork
+    }
+
+    fn red_zone_size(&self) -> u64 {
+        // The System V psABI lets leaf functions keep data there
+        128
+    }
+
+    fn breakpoint_address(&self, pc: u64) -> u64 {
+        // The int3 has been executed, and rip is past it
+        pc - 1
+    }
+
+    fn prstatus_offsets(&self) -> &'static [usize] {
+        &PRSTATUS_OFFSETS
+    }
+
+    fn flags_offset(&self) -> usize {
+        18 * 8
+    }
+
+    fn prstatus_size(&self) -> usize {
+        27 * 8
+    }
+
+    fn pc_register(&self) -> u16 {
+        16
+    }
+
+    fn sp_register(&self) -> u16 {
+        7
+    }
+
+    fn fp_register(&self) -> u16 {
+        6
+    }
+
+    fn register_name(&self, register: u16) -> Option<&'static str> {
+        REGISTER_NAMES.get(register as usize).cloned()
+    }
+
+    fn return_address_register(&self) -> u16 {
+        16
+    }
+
+    fn call_return_address(&self, before: &Registers, after: &Registers, memory: &dyn Memory) -> Option<u64> {
+        // A call pushes the address of the instruction after it, which is 2 to 15 bytes long,
+        // and goes somewhere else
+        if after.sp() != before.sp().wrapping_sub(8) {
+            return None;
+        }
+        let return_addr = memory.read_word(after.sp())?;
+        let call_end = before.pc() + 2..=before.pc() + 15;
+        if call_end.contains(&return_addr) && after.pc() != return_addr {
+            Some(return_addr)
+        } else {
+            None
+        }
+    }
+
+    fn next_pc(&self, _regs: &Registers, _memory: &dyn Memory) -> Option<u64> {
+        // Instructions are variable-length and aren't decoded, but the hardware single-steps
+        None
+    }
+}
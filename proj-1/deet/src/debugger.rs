@@ -1,197 +1,2860 @@
 use crate::debugger_command::{DebuggerCommand,parse_address};
-use crate::inferior::{Inferior,Status,BreakPoint};
+use crate::arch::{self, Registers};
+use crate::inferior::{Inferior,Status,BreakPoint,CallArgument,Frame,FrameContext};
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
+use crate::dwarf_data::{DwarfData, Function, Line, Location, Type, TypeKind};
+use crate::dwarf_expr::{self, EvalError, Piece, PieceLocation};
+use crate::expr::{self, Expr};
+use crate::fault;
+use crate::frameinfo::{self, FrameInfo, Local};
+use crate::printf::{self, PrintfArg};
+use crate::session;
+use crate::pretty;
+use crate::printers::{self, Printers, Shape};
+use crate::modules::{self, ModuleList};
+use crate::procfs;
+use crate::procinfo;
+use crate::query;
+use crate::record::Recording;
+use crate::syscalls;
+use crate::Error;
+use nix::sys::signal::Signal;
+use regex::Regex;
 use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+
+/// How many bytes of code `print_fault` shows, from the faulting instruction on. x86-64
+/// instructions are at most 15 bytes long.
+const FAULT_CODE_BYTES: usize = 15;
+
+/// How many frames of the backtrace `print_fault` shows.
+const FAULT_BACKTRACE_FRAMES: usize = 20;
+
+/// How many frames we unwind looking for one with source information, before giving up.
+const MAX_UNWIND_DEPTH: usize = 64;
+
+/// How much of a string a `%s` in dprintf reads, without finding its end, before giving up
+const MAX_PRINTF_STRING: usize = 4096;
+
+/// A breakpoint we couldn't place yet, because the module it belongs in isn't loaded. The flag
+/// says whether it is a `tbreak`.
+enum PendingBreakpoint {
+    /// A function we haven't found in any loaded module yet
+    Function(String, bool),
+    /// A link-time address in a module, e.g. a breakpoint from a previous run of the inferior
+    ModuleOffset(String, usize, bool),
+}
 
 
 pub struct Debugger {
     target: String,
-    history_path: String,
+    history_path: Option<String>,
     readline: Editor<()>,
     inferior: Option<Inferior>,
-    debug_data :DwarfData,
+    modules: ModuleList,
     break_list:HashMap<usize,BreakPoint>,
+    pending_breaks: Vec<PendingBreakpoint>,
+    /// Address of the dynamic linker's `_r_debug`, once we know where it is loaded
+    r_debug_addr: Option<usize>,
+    /// Address of `_dl_debug_state`, which the dynamic linker calls whenever it loads or unloads
+    /// libraries
+    shlib_event_addr: Option<usize>,
+    /// Set while `record` is on, so that the reverse commands can undo what the inferior did
+    recording: Option<Recording>,
+    checkpoints: Vec<Checkpoint>,
+    next_checkpoint_id: usize,
+    catchpoints: Vec<Catchpoint>,
+    tracepoints: Vec<Tracepoint>,
+    next_tracepoint_number: usize,
+    trace_frames: Vec<TraceFrame>,
+    /// User breakpoint locations as they were given to `break`, which is how `save breakpoints`
+    /// and the session file record them
+    breakpoint_specs: Vec<String>,
+    /// Commands to run when a breakpoint is hit, set with `commands`, by breakpoint number (its
+    /// index in breakpoint_specs)
+    breakpoint_commands: HashMap<usize, Vec<String>>,
+    /// The breakpoint and commands so far of a `commands` list that is still being typed
+    command_list: Option<(usize, Vec<String>)>,
+    /// Set while breakpoint commands run, so that a command which resumes the program leaves
+    /// the commands of where it stops to run_breakpoint_commands instead of running them itself
+    running_commands: bool,
+    /// Where a command run by run_breakpoint_commands stopped the program
+    commands_stop: Option<usize>,
+    displays: Vec<Display>,
+    next_display_number: usize,
+    /// The user's pretty-printers, from ~/.deet/printers and `printer`
+    printers: Printers,
+}
+
+/// An event to stop at, set with `catch`. Catchpoints are numbered from 1 in the order they
+/// were set.
+enum Catchpoint {
+    /// Entry to and return from the given syscalls, or any syscall if there are none
+    Syscall(Vec<u64>),
+    Exec,
+    Fork,
+}
+
+/// A suspended copy of the inferior, made by `checkpoint`. It is never run itself: `restart`
+/// forks it again, so the same checkpoint can be gone back to any number of times.
+struct Checkpoint {
+    id: usize,
+    inferior: Inferior,
+    /// The breakpoints that were in the inferior's memory when it was copied
+    breakpoints: HashMap<usize, BreakPoint>,
+    instruction_ptr: usize,
+}
+
+/// An expression shown every time the inferior stops, added with `display`.
+struct Display {
+    number: usize,
+    expression: String,
+}
+
+/// A `dprintf` or `trace` location: a breakpoint that does something when it is hit and lets
+/// the inferior carry on, rather than stopping. Numbered from 1, separately from breakpoints.
+struct Tracepoint {
+    number: usize,
+    /// The location as the user gave it, resolved again in each run of the inferior
+    location: String,
+    /// Everything after the command, for `info tracepoints`
+    text: String,
+    /// Where the location is in the current inferior, once it has been resolved
+    address: Option<usize>,
+    action: TraceAction,
+    hits: usize,
 }
 
-impl Debugger {
-    /// Initializes the debugger.
-    pub fn new(target: &str) -> Debugger {
-        // TODO (milestone 3): initialize the DwarfData
-        let debug_data = match DwarfData::from_file(target) {
-            Ok(val) => val,
-            Err(DwarfError::ErrorOpeningFile) => {
-                println!("Could not open file {}", target);
-                std::process::exit(1);
-            }
-            Err(DwarfError::DwarfFormatError(err)) => {
-                println!("Could not debugging symbols from {}: {:?}", target, err);
-                std::process::exit(1);
-            }
+#[derive(Clone)]
+enum TraceAction {
+    /// Prints a line formatted like printf
+    Printf { format: String, args: Vec<Expr> },
+    /// Records the values of expressions in a trace frame
+    Collect(Vec<String>),
+}
+
+/// What a `trace` tracepoint saw when it was hit, for `tdump`.
+struct TraceFrame {
+    tracepoint: usize,
+    address: usize,
+    location: Option<String>,
+    values: Vec<(String, String)>,
+}
+
+/// How far `step_line` and `reverse_step_line` go.
+#[derive(Clone, Copy, PartialEq)]
+enum StepKind {
+    /// To the next line, into any function called that has line information
+    Into,
+    /// To the next line in this function, treating calls as a single step
+    Over,
+}
+
+impl Debugger {
+    /// Initializes the debugger. Fails if the target can't be opened or isn't an object file;
+    /// a target without debug info can still be debugged by instruction.
+    pub fn new(target: &str) -> Result<Debugger, Error> {
+        // Only programs for the processor deet runs on can be traced
+        if let Some(machine) = arch::elf_machine(target) {
+            if machine != arch::native().machine() {
+                let name = arch::for_machine(machine)
+                    .map_or_else(|| format!("ELF machine {}", machine), |arch| arch.name().to_string());
+                return Err(Error::WrongArchitecture(target.to_string(), name));
+            }
+        }
+        let modules = ModuleList::new(target)?;
+        modules.main().print_warnings();
+
+        // Without a home directory there is nowhere to keep history
+        let history_path = std::env::var("HOME")
+            .ok()
+            .map(|home| format!("{}/.deet_history", home));
+        let mut readline = Editor::<()>::new();
+        // Attempt to load history from ~/.deet_history if it exists
+        if let Some(history_path) = &history_path {
+            let _ = readline.load_history(history_path);
+        }
+        let mut printers = Printers::default();
+        if let Some(path) = printers::default_path().filter(|path| path.exists()) {
+            if let Err(err) = printers.load(&path) {
+                println!("{}", err);
+            }
+        }
+
+        Ok(Debugger {
+            target: target.to_string(),
+            history_path,
+            readline,
+            inferior: None,
+            modules,
+            break_list:HashMap::new(),
+            pending_breaks: Vec::new(),
+            r_debug_addr: None,
+            shlib_event_addr: None,
+            recording: None,
+            checkpoints: Vec::new(),
+            next_checkpoint_id: 1,
+            catchpoints: Vec::new(),
+            tracepoints: Vec::new(),
+            next_tracepoint_number: 1,
+            trace_frames: Vec::new(),
+            breakpoint_specs: Vec::new(),
+            breakpoint_commands: HashMap::new(),
+            command_list: None,
+            running_commands: false,
+            commands_stop: None,
+            displays: Vec::new(),
+            next_display_number: 1,
+            printers,
+        })
+    }
+
+    /// Reads and runs commands until `quit` or the end of input. Only fails if the input itself
+    /// can't be read.
+    pub fn run(&mut self) -> Result<(), Error> {
+        loop {
+            // println!{"run loop"};
+            let cmd = self.get_next_command()?;
+            if !self.execute(cmd) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs the target to completion, printing each syscall it makes the way strace does: the
+    /// call with decoded arguments, its result, the time it took, and the source line in the
+    /// program it came from. Returns the inferior's exit status.
+    pub fn trace_syscalls(&mut self, args: &Vec<String>) -> i32 {
+        match Inferior::new(&self.target, args) {
+            Ok(inferior) => self.inferior = Some(inferior),
+            Err(err) => {
+                println!("Error starting subprocess: {}", err);
+                return 1;
+            }
+        }
+        self.load_modules();
+        self.inferior.as_mut().unwrap().set_syscall_stops(true);
+        // The call we're waiting on the result of, with where it came from and when
+        let mut pending: Option<(String, String, Instant)> = None;
+        loop {
+            let status = match self.inferior.as_mut().unwrap().wakeup(&mut self.break_list) {
+                Ok(status) => status,
+                Err(e) => {
+                    println!("err {}", e);
+                    return 1;
+                }
+            };
+            match status {
+                Status::SyscallEntry(number, args) => {
+                    let call = syscalls::format_call(number, &args, self.inferior.as_ref().unwrap());
+                    let location = match self.source_location() {
+                        Some(location) => format!("  {}", location),
+                        None => String::new(),
+                    };
+                    pending = Some((call, location, Instant::now()));
+                }
+                Status::SyscallExit(number, value) => {
+                    if let Some((call, location, start)) = pending.take() {
+                        eprintln!(
+                            "{} = {} <{:.6}>{}",
+                            call,
+                            syscalls::format_return(number, value),
+                            start.elapsed().as_secs_f64(),
+                            location
+                        );
+                    }
+                }
+                Status::Stopped(Signal::SIGTRAP, instruction_ptr)
+                    if Some(instruction_ptr) == self.shlib_event_addr =>
+                {
+                    self.load_modules();
+                }
+                Status::Stopped(signal, _) => eprintln!("--- {} ---", signal),
+                Status::Exec(_) => self.follow_exec(),
+                Status::Forked(..) | Status::Cloned(..) => {}
+                Status::Exited(code) => {
+                    // exit_group never returns
+                    if let Some((call, location, _)) = pending {
+                        eprintln!("{} = ?{}", call, location);
+                    }
+                    eprintln!("+++ exited with {} +++", code);
+                    return code;
+                }
+                Status::Signaled(signal) => {
+                    eprintln!("+++ killed by {} +++", signal);
+                    return 128 + signal as i32;
+                }
+            }
+        }
+    }
+
+    /// Executes a single command. Both the line-oriented `run` loop and the TUI feed commands
+    /// through here, so every command behaves the same in both modes. Returns false once the
+    /// user has asked to quit.
+    pub fn execute(&mut self, cmd: DebuggerCommand) -> bool {
+        match cmd {
+            DebuggerCommand::Run(args) => {
+                self.kill_checkpoints();
+                if let Some(child)=self.inferior.as_mut(){      // kill any existing inferiors
+                    if let Ok(_)= child.kill(){
+                        println!("kill ok when re-run");
+                    }else{
+                        println!("kill failed when re-run");
+                    }
+                    child.wait(None).ok();
+                }
+                if let Err(err) = self.start_inferior(&args) {
+                    println!("Error starting subprocess: {}", err);
+                    return true;
+                }
+                // TODO (milestone 1): make the inferior run
+                // You may use self.inferior.as_mut().unwrap() to get a mutable reference
+                // to the Inferior object
+                let result = self.continue_inferior();
+                self.report_resume(result, true);
+            },
+            DebuggerCommand::Quit => {
+                self.kill_checkpoints();
+                if let Some(child)=self.inferior.as_mut(){
+                    if let Ok(_)= child.kill(){
+                        println!("kill exist child when quit");
+                    }
+                    child.wait(None).ok();
+                }
+                println!("quit");
+                return false;
+            },
+            DebuggerCommand::ContinueRun => {
+                if self.inferior.is_some(){
+                    let result = self.continue_inferior();
+                    self.report_resume(result, false);
+                }else{
+                    println!("no inferior to continue!");
+                }
+            },
+            DebuggerCommand::BackTrace=>{
+                if let Some(child)=self.inferior.as_mut(){
+                    match child.print_backtrace(&self.modules){
+                        Ok(_)=>{},
+                        Err(_)=>{},
+                    };
+                }else{
+                    println!("no inferior when backtrace!");
+                }
+            },
+            DebuggerCommand::Break(args)=>{
+                let total_address;
+                if &args[0..1]=="*"{
+                    if let Some(_address)=parse_address(&args[1..]){
+                        total_address=_address;
+                    }else{
+                        println!("Invalid Address");
+                        return true;
+                    }   
+                }else if let Some(_line_number)=args.parse::<usize>().ok(){
+                    if let Some(_address)=self.modules.get_addr_for_line(None,_line_number){
+                        println!("line number address {} {} ",_line_number,_address);
+                        total_address=_address;
+                    }else{
+                        println!("Invalid Address");
+                        return true;
+                    }
+                }else if let Some((file,Ok(line_number)))=args.rsplit_once(':').map(|(file,line)| (file,line.parse::<usize>())){
+                    if let Some(_address)=self.modules.get_addr_for_line(Some(file),line_number){
+                        total_address=_address;
+                    }else{
+                        println!("No line {} in file \"{}\".",line_number,file);
+                        return true;
+                    }
+                }else{
+                    let addresses=self.function_breakpoint_addrs(&args);
+                    match addresses.first(){
+                        Some(_address) if addresses.len()==1 => {
+                            total_address=*_address;
+                            println!("function number address {} {} ",args,_address);
+                        }
+                        Some(_) => {
+                            // An inlined function has a location for every place it was inlined
+                            println!("Breakpoint on \"{}\" at {} locations",args,addresses.len());
+                            self.remember_breakpoint(&args);
+                            for address in addresses{
+                                self.set_breakpoint(address);
+                            }
+                            return true;
+                        }
+                        None => {
+                            // It may live in a shared library that hasn't been loaded yet
+                            println!("Function \"{}\" not found. Breakpoint pending on future shared library load.",args);
+                            self.remember_breakpoint(&args);
+                            self.pending_breaks.push(PendingBreakpoint::Function(args, false));
+                            return true;
+                        }
+                    }
+                }
+
+                println!("0x{:x}",total_address);
+                self.remember_breakpoint(&args);
+                self.set_breakpoint(total_address);
+            },
+            DebuggerCommand::TBreak(location) => {
+                if location.is_empty() {
+                    println!("Usage: tbreak <location>");
+                    return true;
+                }
+                match self.tbreak_at(&location) {
+                    Ok(addresses) if addresses.is_empty() => println!(
+                        "Function \"{}\" not found. Temporary breakpoint pending on future shared library load.",
+                        location
+                    ),
+                    Ok(_) => {}
+                    Err(err) => println!("{}", err),
+                }
+            }
+            DebuggerCommand::Until(location) => {
+                let result = self.until(&location);
+                self.report_stop(result);
+            }
+            DebuggerCommand::Advance(location) => {
+                if location.is_empty() {
+                    println!("Usage: advance <location>");
+                    return true;
+                }
+                let result = self.advance(&location);
+                self.report_stop(result);
+            }
+            DebuggerCommand::Commands(number) => self.start_command_list(&number),
+            DebuggerCommand::Print(name)=>{
+                if name.len()==0{
+                    println!("Usage: print <variable>");
+                }else{
+                    self.print_expression(&name, true);
+                }
+            },
+            DebuggerCommand::Call(expression) => {
+                if expression.is_empty() {
+                    println!("Usage: call <function>(<arguments>)");
+                } else {
+                    self.print_expression(&expression, false);
+                }
+            },
+            DebuggerCommand::StepInstruction => {
+                if self.inferior.is_some() {
+                    let result = self.step_instruction();
+                    self.report_step(result);
+                } else {
+                    println!("The program is not being run.");
+                }
+            },
+            DebuggerCommand::Step | DebuggerCommand::Next => {
+                if self.inferior.is_some() {
+                    let kind = match cmd {
+                        DebuggerCommand::Step => StepKind::Into,
+                        _ => StepKind::Over,
+                    };
+                    let result = self.step_line(kind);
+                    self.report_step(result);
+                } else {
+                    println!("The program is not being run.");
+                }
+            },
+            DebuggerCommand::Record(true) => match self.inferior.as_ref() {
+                Some(_) if self.recording.is_some() => println!("Already recording."),
+                Some(child) => match Recording::start(child) {
+                    Ok(recording) => {
+                        self.recording = Some(recording);
+                        println!("Recording started");
+                    }
+                    Err(e) => println!("Could not start recording: {}", e),
+                },
+                None => println!("The program is not being run."),
+            },
+            DebuggerCommand::Record(false) => {
+                if self.recording.take().is_some() {
+                    println!("Recording stopped, execution history discarded");
+                } else {
+                    println!("Not recording.");
+                }
+            },
+            DebuggerCommand::ReverseStepInstruction
+            | DebuggerCommand::ReverseStep
+            | DebuggerCommand::ReverseNext
+            | DebuggerCommand::ReverseContinue => {
+                if self.inferior.is_none() || self.recording.is_none() {
+                    println!("Not recording. Use \"record\" before going backwards.");
+                    return true;
+                }
+                let result = match cmd {
+                    DebuggerCommand::ReverseStepInstruction => self.undo_instruction(),
+                    DebuggerCommand::ReverseStep => self.reverse_step_line(StepKind::Into),
+                    DebuggerCommand::ReverseNext => self.reverse_step_line(StepKind::Over),
+                    _ => self.reverse_continue(),
+                };
+                match result {
+                    Ok(true) => {}
+                    Ok(false) => println!("No more reverse-execution history."),
+                    Err(e) => println!("err {}", e),
+                }
+                if let Some(regs) = self.registers() {
+                    self.print_stop_location(regs.pc() as usize);
+                }
+            },
+            DebuggerCommand::Checkpoint => match self.inferior.as_mut() {
+                Some(child) => match child.fork() {
+                    Ok(copy) => {
+                        let instruction_ptr = child.registers().map_or(0, |regs| regs.pc() as usize);
+                        let id = self.next_checkpoint_id;
+                        self.next_checkpoint_id += 1;
+                        println!("checkpoint {}: fork returned pid {}.", id, copy.pid());
+                        self.checkpoints.push(Checkpoint {
+                            id,
+                            inferior: copy,
+                            breakpoints: self.break_list.clone(),
+                            instruction_ptr,
+                        });
+                    }
+                    Err(e) => println!("Could not make a checkpoint: {}", e),
+                },
+                None => println!("The program is not being run."),
+            },
+            DebuggerCommand::Restart(args) => match args.parse::<usize>() {
+                Ok(id) => self.restart_checkpoint(id),
+                Err(_) => println!("Usage: restart <checkpoint number>"),
+            },
+            DebuggerCommand::Catch(args) => self.add_catchpoint(&args),
+            DebuggerCommand::Save(args) => match args.as_slice() {
+                [what, file] if what == "breakpoints" => {
+                    let commands = self.session_commands();
+                    match session::write_commands(Path::new(file), &self.target, &commands) {
+                        Ok(()) => println!("Saved to file '{}'.", file),
+                        Err(err) => println!("Could not save to '{}': {}", file, err),
+                    }
+                }
+                _ => println!("Usage: save breakpoints <file>"),
+            },
+            DebuggerCommand::Restore(file) => {
+                if file.is_empty() {
+                    self.restore_session();
+                } else {
+                    self.restore(Path::new(&file));
+                }
+            }
+            DebuggerCommand::Display(expression) => self.add_display(&expression),
+            DebuggerCommand::Undisplay(numbers) => self.remove_displays(&numbers),
+            DebuggerCommand::Dprintf(args) => self.add_tracepoint(&args, true),
+            DebuggerCommand::Trace(args) => self.add_tracepoint(&args, false),
+            DebuggerCommand::TraceDump => self.print_trace_frames(),
+            DebuggerCommand::Info(what) => match what.as_str() {
+                "checkpoints" => self.print_checkpoints(),
+                "tracepoints" | "dprintf" => self.print_tracepoints(),
+                "display" => self.print_display_list(),
+                "printers" => self.print_printers(),
+                "locals" => self.info_locals(),
+                what if what.split_whitespace().next() == Some("proc") => self.info_proc(what),
+                what if what.split_whitespace().next() == Some("frame") => self.info_frame(what),
+                _ => self.info_query(&what),
+            },
+            DebuggerCommand::Ptype(name) => {
+                if name.is_empty() {
+                    println!("Usage: ptype <variable or type>");
+                } else {
+                    let pc = self.inferior.as_ref().and_then(|child| child.registers().ok());
+                    query::print_type(&self.modules, pc.map(|regs| regs.pc() as usize), &name);
+                }
+            }
+            DebuggerCommand::Printer(args) => self.define_printer(&args),
+        }
+        true
+    }
+
+    /// The `info` subcommands that look things up in the debug info: functions, variables,
+    /// scope, line and symbol.
+    fn info_query(&self, what: &str) {
+        let (subcommand, arg) = match what.split_once(' ') {
+            Some((subcommand, arg)) => (subcommand, arg.trim()),
+            None => (what, ""),
+        };
+        match subcommand {
+            "functions" | "variables" => {
+                let pattern = if arg.is_empty() {
+                    None
+                } else {
+                    match Regex::new(arg) {
+                        Ok(pattern) => Some(pattern),
+                        Err(err) => {
+                            println!("Invalid regexp: {}", err);
+                            return;
+                        }
+                    }
+                };
+                if subcommand == "functions" {
+                    query::print_functions(&self.modules, pattern.as_ref());
+                } else {
+                    query::print_variables(&self.modules, pattern.as_ref());
+                }
+            }
+            "scope" => {
+                if arg.is_empty() {
+                    println!("Usage: info scope <function or location>");
+                    return;
+                }
+                let addr = self
+                    .resolve_location(arg)
+                    .or_else(|| self.modules.get_addr_for_function(None, arg));
+                match addr {
+                    Some(addr) => query::print_scope(&self.modules, addr, arg),
+                    None => println!("No function \"{}\" in this program.", arg),
+                }
+            }
+            "line" => self.info_line(arg),
+            "symbol" => match parse_address(arg) {
+                Some(addr) => query::print_symbol(&self.modules, addr),
+                None => println!("Usage: info symbol <address>"),
+            },
+            _ => println!("Undefined info command: \"{}\".", what),
+        }
+    }
+
+    /// `info proc [mappings|fds|status]`, read from /proc for the running inferior.
+    fn info_proc(&self, what: &str) {
+        let child = match self.inferior.as_ref() {
+            Some(child) => child,
+            None => return println!("{}", Error::NotRunning),
+        };
+        match what.split_whitespace().nth(1) {
+            None => procinfo::print_summary(child.pid()),
+            Some("mappings") => procinfo::print_mappings(child.pid(), &self.modules),
+            Some("fds") => procinfo::print_fds(child.pid()),
+            Some("status") => procinfo::print_status(child.pid()),
+            Some(_) => println!("Usage: info proc [mappings|fds|status]"),
+        }
+    }
+
+    /// `info frame [diagram] [level]`: the layout of a frame of the stack, the innermost by
+    /// default, and with `diagram` a drawing of it. Levels are numbered as in `backtrace`, so
+    /// code inlined into a function has a level of its own, with the function's stack frame.
+    fn info_frame(&self, what: &str) {
+        let mut level = 0;
+        let mut draw = false;
+        for arg in what.split_whitespace().skip(1) {
+            match arg.parse::<usize>() {
+                Ok(number) => level = number,
+                Err(_) if arg == "diagram" => draw = true,
+                Err(_) => return println!("Usage: info frame [diagram] [level]"),
+            }
+        }
+        let child = match self.inferior.as_ref() {
+            Some(child) => child,
+            None => return println!("{}", Error::NotRunning),
+        };
+        match self.frame_info(child, level) {
+            Some(info) => {
+                frameinfo::print_frame(&info);
+                if draw {
+                    frameinfo::print_diagram(&info);
+                }
+            }
+            None => println!("No frame at level {}.", level),
+        }
+    }
+
+    /// Unwinds to the frame at backtrace level `level`, and gathers what `info frame` shows
+    /// about it.
+    fn frame_info(&self, child: &Inferior, level: usize) -> Option<FrameInfo> {
+        let mut regs = child.registers().ok()?;
+        let mut callee_cfa = None;
+        // Each stack frame has a level for every call inlined into it, as well as its own
+        let mut depth = 0;
+        let mut first_level = 0;
+        let (function, line) = loop {
+            let pc = unwind_pc(&regs, depth);
+            let inline_frames = self.modules.get_frames_at(pc);
+            let levels = inline_frames.len().max(1);
+            if level < first_level + levels {
+                break match inline_frames.into_iter().nth(level - first_level) {
+                    Some(frame) => frame,
+                    None => (
+                        self.modules
+                            .get_function_from_addr(pc)
+                            .unwrap_or_else(|| String::from("??")),
+                        self.modules.get_line_from_addr(pc),
+                    ),
+                };
+            }
+            first_level += levels;
+            callee_cfa = self.cfa_at(&regs, pc);
+            regs = self.caller_registers(&regs, pc)?;
+            depth += 1;
+        };
+        let pc = unwind_pc(&regs, depth);
+        let module = self.modules.module_for_addr(pc)?;
+        let link_pc = pc.checked_sub(module.bias)?;
+        let rule = module.debug_data.get_unwind_rule(link_pc);
+        let cfa = self.cfa_at(&regs, pc);
+
+        let mut return_address = None;
+        let mut saved_registers = Vec::new();
+        if let (Some(rule), Some(cfa)) = (&rule, cfa) {
+            for (register, offset) in &rule.saved_registers {
+                let slot = cfa.wrapping_add(*offset as u64);
+                // A slot we can't read is left out, rather than the whole frame
+                let value = match child.read_word(slot as usize) {
+                    Ok(value) => value as u64,
+                    Err(_) => continue,
+                };
+                if *register == child.arch().return_address_register() {
+                    return_address = Some((slot, value));
+                } else {
+                    saved_registers.push((*register, slot, value));
+                }
+            }
+        }
+        let caller_cfa = self
+            .caller_registers(&regs, pc)
+            .and_then(|caller| self.cfa_at(&caller, unwind_pc(&caller, depth + 1)));
+
+        // Only locals at a fixed offset from the frame base have a slot of their own
+        let mut locals = Vec::new();
+        if let Some(func) = module.debug_data.get_function_containing(link_pc) {
+            let ctx = FrameContext {
+                inferior: child,
+                regs: regs.clone(),
+                bias: module.bias,
+                cfa,
+            };
+            for var in &func.variables {
+                if let Location::FramePointerOffset(_) = var.location {
+                    let pieces = dwarf_expr::evaluate_location(
+                        &var.location,
+                        link_pc,
+                        func.frame_base.as_ref(),
+                        &ctx,
+                    );
+                    if let Ok([Piece { location: PieceLocation::Memory(addr), .. }]) =
+                        pieces.as_deref()
+                    {
+                        locals.push(Local {
+                            name: var.name.clone(),
+                            type_name: var.entity_type.name.clone(),
+                            addr: *addr,
+                            size: module.debug_data.type_size(&var.entity_type) as u64,
+                        });
+                    }
+                }
+            }
+        }
+
+        Some(FrameInfo {
+            arch: child.arch(),
+            level,
+            function,
+            line,
+            pc: regs.pc(),
+            cfa,
+            cfa_rule: rule.map(|rule| rule.cfa),
+            fp: regs.fp(),
+            sp: regs.sp(),
+            caller_cfa,
+            callee_cfa,
+            return_address,
+            saved_registers,
+            locals,
+        })
+    }
+
+    /// `info line`, for a line number, file:line, a function, or the line the program is
+    /// stopped at.
+    fn info_line(&self, arg: &str) {
+        if let Ok(line_number) = arg.parse::<usize>() {
+            return query::print_line(&self.modules, None, line_number);
+        }
+        if let Some((file, line)) = arg.rsplit_once(':') {
+            if let Ok(line_number) = line.parse::<usize>() {
+                return query::print_line(&self.modules, Some(file), line_number);
+            }
+        }
+        let addr = if arg.is_empty() {
+            match self.inferior.as_ref().and_then(|child| child.registers().ok()) {
+                Some(regs) => regs.pc() as usize,
+                None => {
+                    println!("The program is not being run.");
+                    return;
+                }
+            }
+        } else {
+            match self.modules.get_addr_for_function(None, arg) {
+                Some(addr) => addr,
+                None => {
+                    println!("Function \"{}\" not defined.", arg);
+                    return;
+                }
+            }
+        };
+        match self.modules.get_line_from_addr(addr) {
+            Some(line) => {
+                // The line table names files by their full path, the units by what was compiled
+                let file = line.file.rsplit('/').next().unwrap_or(&line.file);
+                query::print_line(&self.modules, Some(file), line.number)
+            }
+            None => println!("No line number information available for address 0x{:x}", addr),
+        }
+    }
+
+    /// Where `break <function>` stops: past the prologue of the function's own code if it has
+    /// any, so that its arguments can be printed, and at each place it was inlined.
+    fn function_breakpoint_addrs(&self, name: &str) -> Vec<usize> {
+        let mut addresses: Vec<usize> = self
+            .modules
+            .get_addr_for_function(None, name)
+            .map(|addr| self.modules.get_addr_after_prologue(addr).unwrap_or(addr))
+            .into_iter()
+            .collect();
+        for addr in self.modules.get_inlined_addrs(name) {
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        addresses
+    }
+
+    /// The number of the breakpoint at `addr`: the first one set whose location is there.
+    fn breakpoint_number(&self, addr: usize) -> Option<usize> {
+        self.breakpoint_specs.iter().position(|spec| {
+            self.breakpoint_addrs(spec)
+                .map_or(false, |addresses| addresses.contains(&addr))
+        })
+    }
+
+    /// `commands [N]`: starts taking the commands for breakpoint N, or the last one set, which
+    /// are typed one per line up to `end`.
+    fn start_command_list(&mut self, number: &str) {
+        let number = if number.is_empty() {
+            match self.breakpoint_specs.len() {
+                0 => return println!("No breakpoints specified."),
+                len => len - 1,
+            }
+        } else {
+            match number.parse::<usize>() {
+                Ok(number) if number < self.breakpoint_specs.len() => number,
+                _ => return println!("No breakpoint number {}.", number),
+            }
+        };
+        println!("Type commands for breakpoint {}, one per line.", number);
+        println!("End with a line saying just \"end\".");
+        self.command_list = Some((number, Vec::new()));
+    }
+
+    /// Takes `line` as the next line of a `commands` list, if one is being typed. Returns
+    /// whether it did, in which case the line isn't a command to run now.
+    pub fn add_command_list_line(&mut self, line: &str) -> bool {
+        let (number, commands) = match self.command_list.as_mut() {
+            Some(command_list) => command_list,
+            None => return false,
+        };
+        let line = line.trim();
+        if line == "end" {
+            let (number, commands) = (*number, std::mem::take(commands));
+            self.command_list = None;
+            if commands.is_empty() {
+                self.breakpoint_commands.remove(&number);
+            } else {
+                self.breakpoint_commands.insert(number, commands);
+            }
+        } else if !line.is_empty() {
+            commands.push(line.to_string());
+        }
+        true
+    }
+
+    /// The command lists of the breakpoints at `addr`, in breakpoint order. A breakpoint without
+    /// commands has an empty list.
+    fn commands_at(&self, addr: usize) -> Vec<Vec<String>> {
+        (0..self.breakpoint_specs.len())
+            .filter(|number| {
+                self.breakpoint_addrs(&self.breakpoint_specs[*number])
+                    .map_or(false, |addresses| addresses.contains(&addr))
+            })
+            .map(|number| self.breakpoint_commands.get(&number).cloned().unwrap_or_default())
+            .collect()
+    }
+
+    /// Whether to keep quiet about stopping at breakpoints with these command lists, which is
+    /// only when every one of them starts with `silent`.
+    fn silent(lists: &[Vec<String>]) -> bool {
+        !lists.is_empty() && lists.iter().all(|list| list.first().map(String::as_str) == Some("silent"))
+    }
+
+    /// Runs the command lists of the breakpoints the program stopped at. Returns true if one
+    /// of them asked for the program to be continued. `continue` ends its own list only; any
+    /// other command that resumes the program ends all of them, and the lists of wherever that
+    /// stops are run next, as gdb does.
+    fn run_breakpoint_commands(&mut self, mut lists: Vec<Vec<String>>) -> bool {
+        let mut continue_after = false;
+        'lists: while !lists.is_empty() {
+            for list in std::mem::take(&mut lists) {
+                for command in &list {
+                    let tokens: Vec<&str> = command.split_whitespace().collect();
+                    match tokens[0] {
+                        "silent" => continue,
+                        "c" | "cont" | "continue" => {
+                            continue_after = true;
+                            break;
+                        }
+                        _ => {}
+                    }
+                    let cmd = match DebuggerCommand::from_line(&command) {
+                        Some(cmd) => cmd,
+                        None => {
+                            println!("Unrecognized command in breakpoint commands: {}", command);
+                            continue;
+                        }
+                    };
+                    if !cmd.resumes() {
+                        self.execute(cmd);
+                        continue;
+                    }
+                    self.running_commands = true;
+                    self.execute(cmd);
+                    self.running_commands = false;
+                    continue_after = false;
+                    if let Some(addr) = self.commands_stop.take() {
+                        lists = self.commands_at(addr);
+                    }
+                    continue 'lists;
+                }
+            }
+        }
+        continue_after
+    }
+
+    fn remember_breakpoint(&mut self, location: &str) {
+        if !self.breakpoint_specs.iter().any(|spec| spec == location) {
+            self.breakpoint_specs.push(location.to_string());
+        }
+    }
+
+    /// The commands that recreate the breakpoints, dprintfs, tracepoints, catchpoints and
+    /// displays, for `save breakpoints` and the session file.
+    fn session_commands(&self) -> Vec<String> {
+        let mut commands = Vec::new();
+        for (number, spec) in self.breakpoint_specs.iter().enumerate() {
+            commands.push(format!("break {}", spec));
+            // `commands` without a number is for the breakpoint just set
+            if let Some(list) = self.breakpoint_commands.get(&number) {
+                commands.push(String::from("commands"));
+                commands.extend(list.iter().cloned());
+                commands.push(String::from("end"));
+            }
+        }
+        for tracepoint in &self.tracepoints {
+            let command = match tracepoint.action {
+                TraceAction::Printf { .. } => "dprintf",
+                TraceAction::Collect(_) => "trace",
+            };
+            commands.push(format!("{} {}", command, tracepoint.text));
+        }
+        for catchpoint in &self.catchpoints {
+            commands.push(match catchpoint {
+                Catchpoint::Syscall(numbers) => {
+                    let numbers: Vec<String> = numbers.iter().map(|number| number.to_string()).collect();
+                    format!("catch syscall {}", numbers.join(" ")).trim_end().to_string()
+                }
+                Catchpoint::Exec => String::from("catch exec"),
+                Catchpoint::Fork => String::from("catch fork"),
+            });
+        }
+        for display in &self.displays {
+            commands.push(format!("display {}", display.expression));
+        }
+        commands
+    }
+
+    /// Runs the commands in a file written by `save breakpoints`.
+    fn restore(&mut self, path: &Path) {
+        let commands = match session::read_commands(path) {
+            Ok(commands) => commands,
+            Err(err) => {
+                println!("Could not read '{}': {}", path.display(), err);
+                return;
+            }
+        };
+        for command in commands {
+            if self.add_command_list_line(&command) {
+                continue;
+            }
+            match DebuggerCommand::from_line(&command) {
+                Some(cmd) => {
+                    self.execute(cmd);
+                }
+                None => println!("Unrecognized command in '{}': {}", path.display(), command),
+            }
+        }
+    }
+
+    /// Restores what was saved in this target's session file when deet last exited, if anything.
+    /// Locations are looked up again, so they follow the code if the target has been rebuilt.
+    pub fn restore_session(&mut self) {
+        if let Some(path) = session::session_path(&self.target) {
+            if path.exists() {
+                println!("Restoring session from {}", path.display());
+                self.restore(&path);
+            }
+        }
+    }
+
+    /// Writes this target's session file. A session with nothing in it removes the file.
+    pub fn save_session(&self) {
+        let path = match session::session_path(&self.target) {
+            Some(path) => path,
+            None => return,
+        };
+        let commands = self.session_commands();
+        if commands.is_empty() {
+            fs::remove_file(&path).ok();
+        } else if let Err(err) = session::write_commands(&path, &self.target, &commands) {
+            println!("Warning: failed to save session file at {}: {}", path.display(), err);
+        }
+    }
+
+    /// Sets up `dprintf location,"format",expr...` (`printf` true) or
+    /// `trace location[,expr...]`.
+    fn add_tracepoint(&mut self, args: &str, printf: bool) {
+        let usage = if printf {
+            "Usage: dprintf location,\"format\",expr..."
+        } else {
+            "Usage: trace location[,expr...]"
+        };
+        let parts = match expr::split_arguments(args) {
+            Ok(parts) if !parts.is_empty() && !parts[0].is_empty() => parts,
+            Ok(_) => {
+                println!("{}", usage);
+                return;
+            }
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let action = if printf {
+            let format = match parts.get(1).map(|format| expr::parse(format)) {
+                Some(Ok(Expr::Str(format))) => String::from_utf8_lossy(&format).into_owned(),
+                _ => {
+                    println!("{}", usage);
+                    return;
+                }
+            };
+            let args = match parts[2..].iter().map(|arg| expr::parse(arg)).collect::<Result<Vec<_>, _>>() {
+                Ok(args) => args,
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            };
+            match printf::conversions(&format) {
+                Ok(conversions) if conversions.len() == args.len() => {}
+                Ok(conversions) => {
+                    println!("Format string takes {} arguments, but {} were given", conversions.len(), args.len());
+                    return;
+                }
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            }
+            TraceAction::Printf { format, args }
+        } else {
+            if let Some(Err(err)) = parts[1..].iter().map(|arg| expr::parse(arg)).find(|parsed| parsed.is_err()) {
+                println!("{}", err);
+                return;
+            }
+            TraceAction::Collect(parts[1..].to_vec())
+        };
+
+        let location = parts[0].clone();
+        let address = match self.resolve_location(&location) {
+            Some(address) => address,
+            None => {
+                println!("Location \"{}\" not found.", location);
+                return;
+            }
+        };
+        let number = self.next_tracepoint_number;
+        self.next_tracepoint_number += 1;
+        println!(
+            "{} {} at 0x{:x}",
+            if printf { "Dprintf" } else { "Tracepoint" },
+            number,
+            address
+        );
+        self.tracepoints.push(Tracepoint {
+            number,
+            location,
+            text: args.trim().to_string(),
+            address: None,
+            action,
+            hits: 0,
+        });
+        self.install_tracepoints();
+    }
+
+    /// Finds the address of a location: `*address`, a line number in the target, `file:line` or
+    /// a function name. Functions resolve to the end of their prologue, where the arguments
+    /// can be read.
+    fn resolve_location(&self, location: &str) -> Option<usize> {
+        if let Some(addr) = location.strip_prefix('*') {
+            return parse_address(addr);
+        }
+        if let Ok(line_number) = location.parse::<usize>() {
+            return self.modules.get_addr_for_line(None, line_number);
+        }
+        if let Some((file, line)) = location.rsplit_once(':') {
+            if let Ok(line_number) = line.parse::<usize>() {
+                return self.modules.get_addr_for_line(Some(file), line_number);
+            }
+        }
+        let addr = self.modules.get_addr_for_function(None, location)?;
+        Some(self.modules.get_addr_after_prologue(addr).unwrap_or(addr))
+    }
+
+    /// Puts a breakpoint under each tracepoint that isn't placed in the current inferior yet.
+    /// They are internal breakpoints, so stepping doesn't stop at them, unless the user also
+    /// set a breakpoint there.
+    fn install_tracepoints(&mut self) {
+        if self.inferior.is_none() {
+            return;
+        }
+        for i in 0..self.tracepoints.len() {
+            if self.tracepoints[i].address.is_some() {
+                continue;
+            }
+            if let Some(address) = self.resolve_location(&self.tracepoints[i].location) {
+                let placed = match self.break_list.get_mut(&address) {
+                    // A tbreak here mustn't take the breakpoint with it when it goes
+                    Some(bp) if !bp.permanent => {
+                        bp.internal = true;
+                        bp.permanent = true;
+                        true
+                    }
+                    Some(_) => true,
+                    None => self.set_temporary_breakpoint(address),
+                };
+                if placed {
+                    self.tracepoints[i].address = Some(address);
+                }
+            }
+        }
+    }
+
+    fn has_tracepoint(&self, addr: usize) -> bool {
+        self.tracepoints.iter().any(|tracepoint| tracepoint.address == Some(addr))
+    }
+
+    /// Runs the actions of the tracepoints at `addr`, which the inferior is stopped at.
+    fn run_tracepoints(&mut self, addr: usize) {
+        for i in 0..self.tracepoints.len() {
+            if self.tracepoints[i].address != Some(addr) {
+                continue;
+            }
+            self.tracepoints[i].hits += 1;
+            let number = self.tracepoints[i].number;
+            match self.tracepoints[i].action.clone() {
+                TraceAction::Printf { format, args } => match self.format_printf(&format, &args) {
+                    Ok(text) => {
+                        print!("{}", text);
+                        std::io::stdout().flush().ok();
+                    }
+                    Err(err) => println!("dprintf {}: {}", number, err),
+                },
+                TraceAction::Collect(expressions) => {
+                    let values = expressions
+                        .iter()
+                        .map(|text| {
+                            let value = match expr::parse(text).and_then(|expr| self.format_expression(&expr)) {
+                                Ok(value) => value.unwrap_or_else(|| String::from("void")),
+                                Err(err) => err,
+                            };
+                            (text.clone(), value)
+                        })
+                        .collect();
+                    let location = self.source_location();
+                    self.trace_frames.push(TraceFrame {
+                        tracepoint: number,
+                        address: addr,
+                        location,
+                        values,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Evaluates a dprintf's arguments and formats them. Strings are read from the inferior
+    /// for `%s`.
+    fn format_printf(&self, format: &str, args: &[Expr]) -> Result<String, String> {
+        let conversions = printf::conversions(format)?;
+        let mut values = Vec::new();
+        for (conversion, arg) in conversions.iter().zip(args) {
+            values.push(match (conversion, self.call_argument(arg)?) {
+                ('s', CallArgument::Integer(addr)) => PrintfArg::Str(
+                    self.inferior
+                        .as_ref()
+                        .unwrap()
+                        .read_string(addr as usize, MAX_PRINTF_STRING)
+                        .map_err(|_| format!("Cannot access memory at address 0x{:x}", addr))?,
+                ),
+                (_, CallArgument::Bytes(mut bytes)) => {
+                    bytes.pop();
+                    PrintfArg::Str(String::from_utf8_lossy(&bytes).into_owned())
+                }
+                (_, CallArgument::Integer(value)) => PrintfArg::Integer(value),
+                (_, CallArgument::Float(value)) => PrintfArg::Float(value),
+            });
+        }
+        printf::format(format, &values)
+    }
+
+    fn print_tracepoints(&self) {
+        if self.tracepoints.is_empty() {
+            println!("No dprintfs or tracepoints.");
+            return;
+        }
+        println!("Num     Type        Address             Hits  What");
+        for tracepoint in &self.tracepoints {
+            let kind = match tracepoint.action {
+                TraceAction::Printf { .. } => "dprintf",
+                TraceAction::Collect(_) => "tracepoint",
+            };
+            let address = match tracepoint.address {
+                Some(address) => format!("0x{:016x}", address),
+                None => String::from("<pending>"),
+            };
+            println!(
+                "{:<7} {:<11} {:<19} {:<5} {}",
+                tracepoint.number, kind, address, tracepoint.hits, tracepoint.text
+            );
+        }
+    }
+
+    /// `tdump`: everything the tracepoints collected in this run of the inferior.
+    fn print_trace_frames(&self) {
+        if self.trace_frames.is_empty() {
+            println!("No trace frames collected.");
+            return;
+        }
+        for (i, frame) in self.trace_frames.iter().enumerate() {
+            println!(
+                "Trace frame {}, tracepoint {} at 0x{:x}{}",
+                i,
+                frame.tracepoint,
+                frame.address,
+                frame.location.as_ref().map_or(String::new(), |location| format!(" in {}", location))
+            );
+            for (expression, value) in &frame.values {
+                println!("    {} = {}", expression, value);
+            }
+        }
+    }
+
+    fn add_catchpoint(&mut self, args: &[String]) {
+        let catchpoint = match args.get(0).map(|s| s.as_str()) {
+            Some("syscall") => {
+                let mut numbers = Vec::new();
+                for arg in &args[1..] {
+                    match arg.parse::<u64>().ok().or_else(|| syscalls::number(arg)) {
+                        Some(number) => numbers.push(number),
+                        None => {
+                            println!("Unknown syscall name '{}'.", arg);
+                            return;
+                        }
+                    }
+                }
+                Catchpoint::Syscall(numbers)
+            }
+            Some("exec") => Catchpoint::Exec,
+            Some("fork") => Catchpoint::Fork,
+            _ => {
+                println!("Usage: catch syscall [name|number ...] | catch exec | catch fork");
+                return;
+            }
+        };
+        let description = match &catchpoint {
+            Catchpoint::Syscall(numbers) if numbers.is_empty() => String::from("syscall"),
+            Catchpoint::Syscall(numbers) => {
+                let names: Vec<String> = numbers
+                    .iter()
+                    .map(|number| {
+                        format!("'{}' [{}]", syscalls::name(*number).unwrap_or("?"), number)
+                    })
+                    .collect();
+                let plural = if numbers.len() > 1 { "s" } else { "" };
+                format!("syscall{} {}", plural, names.join(" "))
+            }
+            Catchpoint::Exec => String::from("exec"),
+            Catchpoint::Fork => String::from("fork"),
+        };
+        self.catchpoints.push(catchpoint);
+        println!("Catchpoint {} ({})", self.catchpoints.len(), description);
+    }
+
+    /// The number of the first catchpoint that wants to stop for an event, if any.
+    fn catchpoint_for(&self, status: &Status) -> Option<usize> {
+        let index = self.catchpoints.iter().position(|catchpoint| match (catchpoint, status) {
+            (Catchpoint::Syscall(numbers), Status::SyscallEntry(number, _))
+            | (Catchpoint::Syscall(numbers), Status::SyscallExit(number, _)) => {
+                numbers.is_empty() || numbers.contains(number)
+            }
+            (Catchpoint::Exec, Status::Exec(_)) => true,
+            (Catchpoint::Fork, Status::Forked(_, _)) => true,
+            _ => false,
+        })?;
+        Some(index + 1)
+    }
+
+    /// Reports a stop for a catchpoint. Events no catchpoint asked for (an exec while stepping)
+    /// just show where the inferior is.
+    fn print_catch(&mut self, status: &Status) {
+        let number = self.catchpoint_for(status);
+        match (status, number) {
+            (_, None) => {}
+            (Status::SyscallEntry(syscall, args), Some(number)) => {
+                let call = syscalls::format_call(*syscall, args, self.inferior.as_ref().unwrap());
+                println!("Catchpoint {} (call to syscall {})", number, call);
+            }
+            (Status::SyscallExit(syscall, value), Some(number)) => println!(
+                "Catchpoint {} (returned from syscall {}) = {}",
+                number,
+                syscalls::name(*syscall).unwrap_or("?"),
+                syscalls::format_return(*syscall, *value)
+            ),
+            (Status::Exec(_), Some(number)) => {
+                let pid = self.inferior.as_ref().unwrap().pid();
+                let path = fs::read_link(format!("/proc/{}/exe", pid)).unwrap_or_default();
+                println!("Catchpoint {} (exec'd {})", number, path.display());
+            }
+            (Status::Forked(pid, _), Some(number)) => {
+                println!("Catchpoint {} (forked process {})", number, pid)
+            }
+            _ => return,
+        }
+        if let Some(regs) = self.registers() {
+            self.print_stop_location(regs.pc() as usize);
+        }
+    }
+
+    /// The inferior has replaced itself with a new program, so everything we knew about its
+    /// memory is gone. Start over with the new program's debug info. Breakpoints are kept
+    /// pending, and come back if their module is loaded again.
+    fn follow_exec(&mut self) {
+        let pid = self.inferior.as_ref().unwrap().pid();
+        let path = match fs::read_link(format!("/proc/{}/exe", pid)) {
+            Ok(path) => path.to_string_lossy().to_string(),
+            Err(e) => {
+                println!("Could not find the program process {} is executing: {}", pid, e);
+                return;
+            }
+        };
+        println!("process {} is executing new program: {}", pid, path);
+        self.unload_modules();
+        self.recording = None;
+        match ModuleList::new(&path) {
+            Ok(modules) => {
+                modules.main().print_warnings();
+                self.modules = modules;
+            }
+            Err(_) => println!("Could not load debugging symbols from {}", path),
+        }
+        self.load_modules();
+    }
+
+    /// Replaces the inferior with a fresh copy of a checkpoint. The old inferior is killed.
+    fn restart_checkpoint(&mut self, id: usize) {
+        let checkpoint = match self.checkpoints.iter_mut().find(|checkpoint| checkpoint.id == id) {
+            Some(checkpoint) => checkpoint,
+            None => {
+                println!("No checkpoint number {}.", id);
+                return;
+            }
+        };
+        let mut copy = match checkpoint.inferior.fork() {
+            Ok(copy) => copy,
+            Err(e) => {
+                println!("Could not restart checkpoint {}: {}", id, e);
+                return;
+            }
+        };
+        // The copy has the breakpoints the checkpoint was made with. Bring them in line with
+        // the ones set now.
+        for (addr, bp) in &checkpoint.breakpoints {
+            if !self.break_list.contains_key(addr) {
+                copy.write_bytes(*addr, &bp.orig_bytes).ok();
+            }
+        }
+        for addr in self.break_list.keys() {
+            if !checkpoint.breakpoints.contains_key(addr) {
+                copy.insert_breakpoint(*addr).ok();
+            }
+        }
+        if let Some(mut child) = self.inferior.take() {
+            child.kill().ok();
+            child.wait(None).ok();
+        }
+        println!("Switching to pid {}", copy.pid());
+        let instruction_ptr = checkpoint.instruction_ptr;
+        self.inferior = Some(copy);
+        self.recording = None;
+        self.load_modules();
+        self.print_stop_location(instruction_ptr);
+    }
+
+    fn print_checkpoints(&self) {
+        if self.checkpoints.is_empty() {
+            println!("No checkpoints.");
+            return;
+        }
+        for checkpoint in &self.checkpoints {
+            let function = self
+                .modules
+                .get_function_from_addr(checkpoint.instruction_ptr)
+                .unwrap_or_else(|| String::from("??"));
+            match self.modules.get_line_from_addr(checkpoint.instruction_ptr) {
+                Some(line) => println!(
+                    "  {} process {} at 0x{:x}, {} ({})",
+                    checkpoint.id,
+                    checkpoint.inferior.pid(),
+                    checkpoint.instruction_ptr,
+                    function,
+                    line
+                ),
+                None => println!(
+                    "  {} process {} at 0x{:x}, {}",
+                    checkpoint.id,
+                    checkpoint.inferior.pid(),
+                    checkpoint.instruction_ptr,
+                    function
+                ),
+            }
+        }
+    }
+
+    fn kill_checkpoints(&mut self) {
+        for mut checkpoint in self.checkpoints.drain(..) {
+            checkpoint.inferior.kill().ok();
+            checkpoint.inferior.wait(None).ok();
+        }
+        self.next_checkpoint_id = 1;
+    }
+
+    /// Reports where `run` (`started`) or `continue` stopped. The commands of the breakpoints it
+    /// stopped at are run, and if one of them continues, so does the program, and the next stop
+    /// is reported the same way. Where it stopped isn't shown if all their lists are `silent`.
+    fn report_resume(&mut self, mut result: Result<Status, Error>, mut started: bool) {
+        loop {
+            let commands = match result {
+                Ok(Status::Stopped(sig, instruction_ptr)) => {
+                    let commands = if sig == Signal::SIGTRAP {
+                        self.commands_at(instruction_ptr)
+                    } else {
+                        Vec::new()
+                    };
+                    if !Self::silent(&commands) {
+                        if started {
+                            println!("Child stopped by signal {}", sig);
+                        } else {
+                            println!("Child stopped signal {}", sig);
+                        }
+                        self.print_stop_location(instruction_ptr);
+                        self.print_fault(sig, instruction_ptr);
+                    }
+                    if self.running_commands {
+                        self.commands_stop = Some(instruction_ptr).filter(|_| sig == Signal::SIGTRAP);
+                        return;
+                    }
+                    commands
+                }
+                Ok(Status::Exited(code)) if started => return println!("Child exited (status {})", code),
+                Ok(Status::Exited(code)) => return println!("Continue Child exited (status {})", code),
+                Ok(Status::Signaled(sig)) if started => return println!("Child signaled signal {}", sig),
+                Ok(Status::Signaled(sig)) => return println!("Continue Child signaled signal {}", sig),
+                Ok(status) => return self.print_catch(&status),
+                Err(e) => return println!("err {}", e),
+            };
+            if !self.run_breakpoint_commands(commands) {
+                return;
+            }
+            result = self.continue_inferior();
+            started = false;
+        }
+    }
+
+    /// Reports where `until` or `advance` stopped, or why it couldn't start.
+    fn report_stop(&mut self, result: Result<Status, Error>) {
+        match result {
+            Err(err @ Error::NotRunning) | Err(err @ Error::NoLocation(_)) => println!("{}", err),
+            result => self.report_step(result),
+        }
+    }
+
+    /// Prints where a stepping command left the inferior. If that's at a breakpoint, its
+    /// commands are run as they are after `continue`.
+    fn report_step(&mut self, result: Result<Status, Error>) {
+        match result {
+            Ok(Status::Stopped(Signal::SIGTRAP, instruction_ptr)) => {
+                let commands = self.commands_at(instruction_ptr);
+                if !Self::silent(&commands) {
+                    self.print_stop_location(instruction_ptr);
+                }
+                if self.running_commands {
+                    self.commands_stop = Some(instruction_ptr);
+                } else if self.run_breakpoint_commands(commands) {
+                    let result = self.continue_inferior();
+                    self.report_resume(result, false);
+                }
+            }
+            Ok(Status::Stopped(sig, instruction_ptr)) => {
+                println!("Child stopped by signal {}", sig);
+                self.print_stop_location(instruction_ptr);
+                self.print_fault(sig, instruction_ptr);
+            }
+            Ok(Status::Exited(code)) => println!("Child exited (status {})", code),
+            Ok(Status::Signaled(sig)) => println!("Child signaled signal {}", sig),
+            Ok(status) => self.print_catch(&status),
+            Err(e) => println!("err {}", e),
+        }
+    }
+
+    /// Executes one instruction, through the recording if there is one.
+    fn step_instruction(&mut self) -> Result<Status, Error> {
+        let child = self.inferior.as_mut().unwrap();
+        let status = match self.recording.as_mut() {
+            Some(recording) => recording.step(child, &self.break_list)?,
+            None => child.step(&self.break_list)?,
+        };
+        match status {
+            Status::Stopped(Signal::SIGTRAP, instruction_ptr)
+                if Some(instruction_ptr) == self.shlib_event_addr =>
+            {
+                self.load_modules();
+            }
+            Status::Stopped(..) => {}
+            Status::Exec(_) => self.follow_exec(),
+            _ => self.recording = None,
+        }
+        Ok(status)
+    }
+
+    /// Steps until the inferior reaches the start of a different line, or the same line in
+    /// another frame (a recursive call, or back in the caller). Stops early at breakpoints and
+    /// signals. Calls into code without line information are always stepped over.
+    fn step_line(&mut self, kind: StepKind) -> Result<Status, Error> {
+        let regs = self.inferior.as_ref().unwrap().registers()?;
+        let start_line = self.modules.get_line_from_addr(regs.pc() as usize);
+        let start_cfa = self.frame_cfa(&regs);
+        let start_depth = self.modules.inline_depth(regs.pc() as usize);
+        loop {
+            let before = self.inferior.as_ref().unwrap().registers()?;
+            let mut status = self.step_instruction()?;
+            let instruction_ptr = match status {
+                Status::Stopped(Signal::SIGTRAP, instruction_ptr) => instruction_ptr,
+                _ => return Ok(status),
+            };
+            if self.breakpoint_stops_at(instruction_ptr) {
+                self.clear_one_shot(instruction_ptr);
+                return Ok(status);
+            }
+            let mut regs = self.inferior.as_ref().unwrap().registers()?;
+            let mut instruction_ptr = instruction_ptr;
+            if let Some(return_addr) = self.called_from(&before, &regs) {
+                let has_lines = self.modules.get_line_from_addr(instruction_ptr).is_some();
+                if kind == StepKind::Into && has_lines {
+                    return self.step_past_prologue(status);
+                }
+                // Back from the call, which may have been the last thing on its line, so the
+                // return address can be where the next line starts
+                match self.finish_call(return_addr, before.sp())? {
+                    Status::Stopped(Signal::SIGTRAP, stopped_at) if stopped_at == return_addr => {
+                        regs = self.inferior.as_ref().unwrap().registers()?;
+                        instruction_ptr = return_addr;
+                        status = Status::Stopped(Signal::SIGTRAP, return_addr);
+                    }
+                    status => return Ok(status),
+                }
+            }
+            if !self.modules.is_line_start(instruction_ptr) {
+                continue;
+            }
+            // Stepping over a line steps over the functions inlined into it, as it does calls
+            if kind == StepKind::Over
+                && self.modules.inline_depth(instruction_ptr) > start_depth
+                && self.frame_cfa(&regs) == start_cfa
+            {
+                continue;
+            }
+            let line = self.modules.get_line_from_addr(instruction_ptr);
+            let same_line = match (&line, &start_line) {
+                (Some(line), Some(start_line)) => {
+                    line.file == start_line.file && line.number == start_line.number
+                }
+                _ => false,
+            };
+            if line.is_some() && (!same_line || self.frame_cfa(&regs) != start_cfa) {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Having just stepped into a function, steps on through its prologue, so that we stop
+    /// where a breakpoint on the function would and its arguments can be printed.
+    fn step_past_prologue(&mut self, mut status: Status) -> Result<Status, Error> {
+        let entry = match status {
+            Status::Stopped(Signal::SIGTRAP, instruction_ptr) => instruction_ptr,
+            _ => return Ok(status),
+        };
+        let body = self.modules.get_addr_after_prologue(entry).unwrap_or(entry);
+        while let Status::Stopped(Signal::SIGTRAP, instruction_ptr) = status {
+            if !(entry..body).contains(&instruction_ptr) {
+                break;
+            }
+            status = self.step_instruction()?;
+        }
+        Ok(status)
+    }
+
+    /// If the instruction just executed was a call, returns the address it will return to.
+    fn called_from(&self, before: &Registers, after: &Registers) -> Option<usize> {
+        let child = self.inferior.as_ref()?;
+        let return_addr = child.arch().call_return_address(before, after, child)?;
+        Some(return_addr as usize)
+    }
+
+    /// Runs until the function just called returns to `return_addr`, with the stack back up to
+    /// `call_sp`, where it was before the call (so a recursive call returning there doesn't
+    /// count).
+    fn finish_call(&mut self, return_addr: usize, call_sp: u64) -> Result<Status, Error> {
+        loop {
+            let status = if self.recording.is_some() {
+                // Every instruction has to go through the recording, so no breakpoint here
+                let status = self.step_instruction()?;
+                match status {
+                    Status::Stopped(Signal::SIGTRAP, instruction_ptr)
+                        if instruction_ptr != return_addr && !self.breakpoint_stops_at(instruction_ptr) =>
+                    {
+                        continue;
+                    }
+                    _ => status,
+                }
+            } else {
+                let inserted = self.set_temporary_breakpoint(return_addr);
+                let status = self.continue_inferior();
+                if inserted {
+                    self.remove_temporary_breakpoint(return_addr);
+                }
+                status?
+            };
+            match status {
+                Status::Stopped(Signal::SIGTRAP, instruction_ptr) if instruction_ptr == return_addr => {
+                    let regs = self.inferior.as_ref().unwrap().registers()?;
+                    if regs.sp() >= call_sp {
+                        return Ok(status);
+                    }
+                }
+                _ => return Ok(status),
+            }
+        }
+    }
+
+    /// `until` with no location: steps over lines until one after the starting line in this
+    /// frame, or until the function returns.
+    fn until_next_line(&mut self) -> Result<Status, Error> {
+        let regs = self.inferior.as_ref().unwrap().registers()?;
+        let start_line = self.modules.get_line_from_addr(regs.pc() as usize);
+        let start_cfa = self.frame_cfa(&regs);
+        loop {
+            let status = self.step_line(StepKind::Over)?;
+            let instruction_ptr = match status {
+                Status::Stopped(Signal::SIGTRAP, instruction_ptr) => instruction_ptr,
+                _ => return Ok(status),
+            };
+            if self.breakpoint_stops_at(instruction_ptr) {
+                return Ok(status);
+            }
+            let regs = self.inferior.as_ref().unwrap().registers()?;
+            let line = self.modules.get_line_from_addr(instruction_ptr);
+            let jumped_back = match (&line, &start_line) {
+                (Some(line), Some(start_line)) => {
+                    line.file == start_line.file && line.number <= start_line.number
+                }
+                _ => false,
+            };
+            if !jumped_back || self.frame_cfa(&regs) != start_cfa {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// `until location` (`this_frame_only`) and `advance location`: continues with one-shot
+    /// breakpoints at the location and where the current function returns to, until one of them
+    /// is hit in the right frame. Whichever isn't hit is taken out again.
+    fn run_to(&mut self, location: &str, this_frame_only: bool) -> Result<Status, Error> {
+        let target = self
+            .resolve_location(location)
+            .ok_or_else(|| Error::NoLocation(location.to_string()))?;
+        let regs = self.inferior.as_ref().unwrap().registers()?;
+        let start_cfa = self.frame_cfa(&regs);
+        let return_addr = self
+            .caller_registers(&regs, regs.pc() as usize)
+            .map(|caller| caller.pc() as usize);
+        let mut stops: Vec<usize> = Vec::new();
+        for addr in std::iter::once(target).chain(return_addr) {
+            if self.set_one_shot_breakpoint(addr, true) {
+                stops.push(addr);
+            }
+        }
+        // The stops go whether we got there, stopped elsewhere, the program ended or we failed
+        let result = self.continue_to(target, return_addr, start_cfa, this_frame_only, &stops);
+        for addr in stops {
+            self.clear_one_shot(addr);
+        }
+        result
+    }
+
+    /// Continues until `run_to`'s target or return address is reached in the right frame, or
+    /// the inferior stops for some other reason.
+    fn continue_to(
+        &mut self,
+        target: usize,
+        return_addr: Option<usize>,
+        start_cfa: u64,
+        this_frame_only: bool,
+        stops: &[usize],
+    ) -> Result<Status, Error> {
+        loop {
+            let status = self.continue_inferior()?;
+            let instruction_ptr = match status {
+                Status::Stopped(Signal::SIGTRAP, instruction_ptr) => instruction_ptr,
+                _ => return Ok(status),
+            };
+            let regs = self.inferior.as_ref().unwrap().registers()?;
+            // Deeper frames have lower CFAs: this is a recursive call, or a call from this frame
+            // that returns to the same place, so carry on past it
+            let too_deep = if instruction_ptr == target {
+                this_frame_only && self.frame_cfa(&regs) < start_cfa
+            } else if Some(instruction_ptr) == return_addr {
+                regs.sp() < start_cfa
+            } else {
+                false
+            };
+            if !too_deep || self.breakpoint_stops_at(instruction_ptr) {
+                return Ok(status);
+            }
+            if stops.contains(&instruction_ptr) {
+                self.set_one_shot_breakpoint(instruction_ptr, true);
+            }
+        }
+    }
+
+    /// Undoes one recorded instruction. Returns false if there was no history left.
+    fn undo_instruction(&mut self) -> Result<bool, Error> {
+        let child = self.inferior.as_ref().unwrap();
+        self.recording.as_mut().unwrap().undo(child)
+    }
+
+    /// Goes back to the start of the previous line, the reverse of `step_line`. Returns false if
+    /// the recording ran out first.
+    fn reverse_step_line(&mut self, kind: StepKind) -> Result<bool, Error> {
+        let regs = self.inferior.as_ref().unwrap().registers()?;
+        let start_line = self.modules.get_line_from_addr(regs.pc() as usize);
+        let start_cfa = self.frame_cfa(&regs);
+        // First back out of the current line
+        let (line, cfa) = loop {
+            if !self.undo_instruction()? {
+                return Ok(false);
+            }
+            let regs = self.inferior.as_ref().unwrap().registers()?;
+            let instruction_ptr = regs.pc() as usize;
+            if self.breakpoint_stops_at(instruction_ptr) {
+                return Ok(true);
+            }
+            let cfa = self.frame_cfa(&regs);
+            let line = match self.modules.get_line_from_addr(instruction_ptr) {
+                Some(line) => line,
+                None => continue,
+            };
+            // A callee: next skips it entirely, step stops on its last line
+            if cfa < start_cfa && kind == StepKind::Over {
+                continue;
+            }
+            let same_line = start_line
+                .as_ref()
+                .map_or(false, |start| start.file == line.file && start.number == line.number);
+            if !same_line || cfa != start_cfa {
+                break (line, cfa);
+            }
+        };
+        // Then back to the first instruction of the line we ended up on, undoing any calls
+        // made from it along the way
+        loop {
+            let previous = match self.recording.as_ref().unwrap().previous_registers() {
+                Some(previous) => previous,
+                None => return Ok(true),
+            };
+            let previous_cfa = self.frame_cfa(&previous);
+            let same_line = self
+                .modules
+                .get_line_from_addr(previous.pc() as usize)
+                .map_or(false, |prev| prev.file == line.file && prev.number == line.number);
+            if previous_cfa >= cfa && !(same_line && previous_cfa == cfa) {
+                return Ok(true);
+            }
+            self.undo_instruction()?;
+        }
+    }
+
+    /// Goes back to the last time a breakpoint was hit. Returns false if the recording ran out
+    /// first.
+    fn reverse_continue(&mut self) -> Result<bool, Error> {
+        loop {
+            if !self.undo_instruction()? {
+                return Ok(false);
+            }
+            let regs = self.inferior.as_ref().unwrap().registers()?;
+            if self.breakpoint_stops_at(regs.pc() as usize) {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// The function and source line of the innermost frame that has line information. Inside a
+    /// library without debug info (say, a libc syscall wrapper) that's the program's own code
+    /// that called into it.
+    fn source_location(&self) -> Option<String> {
+        let mut regs = self.registers()?;
+        for depth in 0..MAX_UNWIND_DEPTH {
+            let pc = unwind_pc(&regs, depth);
+            if let Some(line) = self.modules.get_line_from_addr(pc) {
+                let function = self
+                    .modules
+                    .get_function_from_addr(pc)
+                    .unwrap_or_else(|| String::from("??"));
+                return Some(format!("{} ({})", function, line));
+            }
+            regs = self.caller_registers(&regs, pc)?;
+        }
+        None
+    }
+
+    /// Unwinds one frame using the call frame information: returns the registers as they were in
+    /// the caller, as far as the unwind rules at `pc` tell us. Registers the function didn't save
+    /// are assumed unchanged.
+    fn caller_registers(&self, regs: &Registers, pc: usize) -> Option<Registers> {
+        let module = self.modules.module_for_addr(pc)?;
+        let rule = module.debug_data.get_unwind_rule(pc.checked_sub(module.bias)?)?;
+        let cfa = regs.get(rule.cfa.0)?.wrapping_add(rule.cfa.1 as u64);
+        let child = self.inferior.as_ref()?;
+        let mut caller = regs.clone();
+        for (register, offset) in &rule.saved_registers {
+            let value = child.read_word(cfa.wrapping_add(*offset as u64) as usize).ok()?;
+            caller.set(*register, value as u64);
+        }
+        // The caller carries on from the return address, wherever it was kept
+        let return_address_register = child.arch().return_address_register();
+        if !rule.saved_registers.iter().any(|(register, _)| *register == return_address_register) {
+            return None;
+        }
+        caller.set_pc(caller.get(return_address_register)?);
+        caller.set_sp(cfa);
+        Some(caller)
+    }
+
+    /// Whether the inferior should stop for the user at `addr`: there is a breakpoint they set
+    /// there, or a one-shot stop.
+    fn breakpoint_stops_at(&self, addr: usize) -> bool {
+        self.break_list.get(&addr).map_or(false, |bp| !bp.internal || bp.one_shot)
+    }
+
+    /// The canonical frame address of the innermost frame, which tells frames apart while
+    /// stepping. Falls back on the stack pointer where there's no unwind information.
+    fn frame_cfa(&self, regs: &Registers) -> u64 {
+        self.cfa_at(regs, regs.pc() as usize).unwrap_or(regs.sp())
+    }
+
+    /// The canonical frame address of the frame with registers `regs`, by the unwind rule at
+    /// `pc` (see unwind_pc).
+    fn cfa_at(&self, regs: &Registers, pc: usize) -> Option<u64> {
+        let module = self.modules.module_for_addr(pc)?;
+        let (register, offset) = module.debug_data.get_cfa_rule(pc.checked_sub(module.bias)?)?;
+        Some(regs.get(register)?.wrapping_add(offset as u64))
+    }
+
+    /// Looks up a variable as seen from the innermost frame and reads it. `f` gets the
+    /// variable's type and where and what its bytes are (or why they couldn't be read), along
+    /// with what it needs to interpret them. Fails if there is no such variable.
+    fn with_variable<T, F>(&self, name: &str, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&Type, Result<(Vec<Piece>, Vec<u8>), EvalError>, &DwarfData, &FrameContext) -> T,
+    {
+        let child = self
+            .inferior
+            .as_ref()
+            .ok_or_else(|| "The program is not being run.".to_string())?;
+        let regs = child.registers().map_err(|e| format!("err {}", e))?;
+        let pc = regs.pc() as usize;
+        let found = self.modules.module_for_addr(pc).and_then(|module| {
+            let link_pc = pc.checked_sub(module.bias)?;
+            let (var, frame_base) = module.debug_data.get_variable(link_pc, name)?;
+            Some((module, link_pc, var, frame_base))
+        });
+        let (module, link_pc, var, frame_base) =
+            found.ok_or_else(|| format!("No symbol \"{}\" in current context.", name))?;
+
+        let cfa = module
+            .debug_data
+            .get_cfa_rule(link_pc)
+            .map(|_| self.frame_cfa(&regs));
+        let ctx = FrameContext {
+            inferior: child,
+            regs,
+            bias: module.bias,
+            cfa,
         };
+        let size = module.debug_data.type_size(&var.entity_type);
+        let value = dwarf_expr::evaluate_location(&var.location, link_pc, frame_base, &ctx)
+            .and_then(|pieces| {
+                let bytes = dwarf_expr::read_pieces(&pieces, size, &ctx)?;
+                Ok((pieces, bytes))
+            });
+        Ok(f(&var.entity_type, value, &module.debug_data, &ctx))
+    }
 
-        let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
-        let mut readline = Editor::<()>::new();
-        // Attempt to load history from ~/.deet_history if it exists
-        let _ = readline.load_history(&history_path);
+    /// Evaluates an expression for `print` or `call` and prints it. A function returning void
+    /// prints as `void` only for `print`.
+    fn print_expression(&mut self, text: &str, show_void: bool) {
+        let text = text.trim();
+        match expr::parse(text).and_then(|expr| self.format_expression(&expr)) {
+            Ok(Some(value)) => println!("{} = {}", text, value),
+            Ok(None) if show_void => println!("{} = void", text),
+            Ok(None) => {}
+            Err(err) => println!("{}", err),
+        }
+    }
 
-        debug_data.print();
+    /// Formats the value of an expression: a variable as seen from the innermost frame, a
+    /// literal, or what a call to a function in the inferior returns (None if that is void).
+    fn format_expression(&mut self, expr: &Expr) -> Result<Option<String>, String> {
+        match expr {
+            Expr::Variable(name) => self
+                .with_variable(name, |var_type, value, debug_data, ctx| match value {
+                    Ok((_, bytes)) => {
+                        pretty::format_value(&bytes, var_type, debug_data, ctx, &self.printers)
+                    }
+                    Err(err) => err.to_string(),
+                })
+                .map(Some),
+            Expr::Call(name, args) => self.call_function(name, args),
+            Expr::Integer(value) => Ok(Some(value.to_string())),
+            Expr::Float(value) => Ok(Some(value.to_string())),
+            Expr::Str(bytes) => Ok(Some(format!("{:?}", String::from_utf8_lossy(bytes)))),
+        }
+    }
 
-        Debugger {
-            target: target.to_string(),
-            history_path,
-            readline,
-            inferior: None,
-            debug_data:debug_data,
-            break_list:HashMap::new(),
+    /// Calls a function in the stopped inferior, returning its formatted return value, or None
+    /// if it returns void. The function returns to a temporary breakpoint on the program's
+    /// entry point, which nothing else runs once the program has started. If it stops anywhere
+    /// else first (a breakpoint, a signal), the call is abandoned. Either way the registers are
+    /// put back as they were, though anything the function wrote to memory stays written.
+    fn call_function(&mut self, name: &str, args: &[Expr]) -> Result<Option<String>, String> {
+        if self.inferior.is_none() {
+            return Err("You can't do that without a process to debug.".to_string());
+        }
+        let function = self
+            .modules
+            .get_addr_for_function(None, name)
+            .ok_or_else(|| format!("No symbol \"{}\" in current context.", name))?;
+        if let Some((module, Function { return_type: Some(return_type), .. })) =
+            self.modules.get_function(name)
+        {
+            // Those are returned through memory the caller provides, via a hidden argument
+            if let TypeKind::Struct { .. } | TypeKind::Array { .. } =
+                module.debug_data.strip_aliases(return_type).kind
+            {
+                return Err(format!("Calling {}, which returns a structure, isn't supported", name));
+            }
+        }
+        let args = args
+            .iter()
+            .map(|arg| self.call_argument(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let return_addr = self.modules.main().entry + self.modules.main().bias;
+        let inserted = self.set_temporary_breakpoint(return_addr);
+        let result = self.run_call(function, return_addr, &args);
+        if inserted {
+            self.remove_temporary_breakpoint(return_addr);
         }
+        let (rax, xmm0) = result.map_err(|err| err.to_string())?;
+        Ok(self.format_return_value(name, rax, xmm0))
     }
 
-    pub fn run(&mut self) {
-        loop {
-            // println!{"run loop"};
-            match self.get_next_command() {
-                DebuggerCommand::Run(args) => {
-                    if let Some(child)=self.inferior.as_mut(){      // kill any existing inferiors
-                        if let Ok(_)= child.kill(){
-                            println!("kill ok when re-run");
-                        }else{
-                            println!("kill failed when re-run");
-                        }
-                        child.wait(None).ok();
-                    }
-
-                    if let Some(inferior) = Inferior::new(&self.target, &args,&mut self.break_list) {
-                        // Create the inferior
-                        self.inferior = Some(inferior);
-                        // TODO (milestone 1): make the inferior run
-                        // You may use self.inferior.as_mut().unwrap() to get a mutable reference
-                        // to the Inferior object
-                        let child=self.inferior.as_mut().unwrap();
-                        match child.wakeup(&self.break_list){
-                            Ok(status)=> match status{
-                                Status::Stopped(sig,instruction_ptr)=> {
-                                    println!("Child stopped by signal {}",sig);
-                                    if let Some(filename_line) = self.debug_data.get_line_from_addr(instruction_ptr as usize){
-                                        if let Some(function_name)=self.debug_data.get_function_from_addr(instruction_ptr as usize){
-                                            println!("Stopped   {}    ({}:{}) ",function_name,filename_line.file,filename_line.number);
-                                        }
-                                    }
-                                },
-                                Status::Exited(code)=> {println!("Child exited (status {})",code);},
-                                Status::Signaled(sig) => {println!("Child signaled signal {}",sig);},
-                            },
-                            Err(e)=>{
-                                println!("err {}",e);
-                            },
+    fn run_call(
+        &mut self,
+        function: usize,
+        return_addr: usize,
+        args: &[CallArgument],
+    ) -> Result<(u64, [u8; 16]), Error> {
+        let child = self.inferior.as_mut().unwrap();
+        let saved = child.start_call(function, return_addr, args)?;
+        child.set_syscall_stops(false);
+        // wakeup would step over a breakpoint on the function's first instruction
+        let status = if self.breakpoint_stops_at(function) {
+            Ok(Status::Stopped(Signal::SIGTRAP, function))
+        } else {
+            loop {
+                match self.inferior.as_mut().unwrap().wakeup(&mut self.break_list) {
+                    Ok(Status::Stopped(Signal::SIGTRAP, instruction_ptr))
+                        if Some(instruction_ptr) == self.shlib_event_addr =>
+                    {
+                        self.load_modules();
+                    }
+                    Ok(Status::Stopped(Signal::SIGTRAP, instruction_ptr))
+                        if self.has_tracepoint(instruction_ptr)
+                            && !self.breakpoint_stops_at(instruction_ptr) =>
+                    {
+                        self.run_tracepoints(instruction_ptr);
+                    }
+                    Ok(Status::Forked(..)) => {}
+                    status => break status,
+                }
+            }
+        };
+        let result = match status {
+            Ok(Status::Stopped(Signal::SIGTRAP, instruction_ptr)) if instruction_ptr == return_addr => {
+                self.inferior.as_ref().unwrap().call_result()
+            }
+            // There is no process left to restore, so end_call is skipped
+            Ok(Status::Exited(code)) => {
+                return Err(Error::CallAbandoned(format!("exited (status {})", code), false));
+            }
+            Ok(Status::Signaled(sig)) => {
+                return Err(Error::CallAbandoned(format!("was killed by signal {}", sig), false));
+            }
+            Ok(Status::Stopped(Signal::SIGTRAP, instruction_ptr))
+                if self.breakpoint_stops_at(instruction_ptr) =>
+            {
+                Err(Error::CallAbandoned(
+                    format!("stopped at a breakpoint at 0x{:x}", instruction_ptr),
+                    true,
+                ))
+            }
+            Ok(Status::Stopped(sig, instruction_ptr)) => Err(Error::CallAbandoned(
+                format!("stopped (signal {}) at 0x{:x}", sig, instruction_ptr),
+                true,
+            )),
+            Ok(_) => Err(Error::CallAbandoned("stopped".to_string(), true)),
+            Err(err) => Err(err),
+        };
+        self.inferior.as_mut().unwrap().end_call(saved)?;
+        result
+    }
+
+    /// Turns an argument of `call` into what the calling convention passes: literals as they
+    /// are, and variables by value, arrays decaying to pointers like in C.
+    fn call_argument(&self, arg: &Expr) -> Result<CallArgument, String> {
+        match arg {
+            Expr::Integer(value) => Ok(CallArgument::Integer(*value as u64)),
+            Expr::Float(value) => Ok(CallArgument::Float(*value)),
+            Expr::Str(bytes) => {
+                let mut bytes = bytes.clone();
+                bytes.push(0);
+                Ok(CallArgument::Bytes(bytes))
+            }
+            Expr::Call(..) => Err("Function calls as arguments aren't supported".to_string()),
+            Expr::Variable(name) => self.with_variable(name, |var_type, value, debug_data, _| {
+                let (pieces, bytes) = value.map_err(|err| format!("{} = {}", name, err))?;
+                let var_type = debug_data.strip_aliases(var_type);
+                match var_type.kind {
+                    TypeKind::Array { .. } => match pieces.as_slice() {
+                        [Piece { location: PieceLocation::Memory(addr), .. }] => {
+                            Ok(CallArgument::Integer(*addr))
                         }
-                    } else {
-                        println!("Error starting subprocess");
+                        _ => Err(format!("Can't pass {}, an array that isn't in memory", name)),
+                    },
+                    TypeKind::Struct { .. } => {
+                        Err(format!("Passing {}, a structure, by value isn't supported", name))
                     }
-                },
-                DebuggerCommand::Quit => {
-                    if let Some(child)=self.inferior.as_mut(){
-                        if let Ok(_)= child.kill(){
-                            println!("kill exist child when quit");
+                    // float arguments are promoted to double, as for a function without a
+                    // prototype
+                    TypeKind::Base(Some(gimli::DW_ATE_float)) => match bytes.len() {
+                        4 => Ok(CallArgument::Float(
+                            f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                        )),
+                        8 => {
+                            let mut raw = [0; 8];
+                            raw.copy_from_slice(&bytes);
+                            Ok(CallArgument::Float(f64::from_le_bytes(raw)))
                         }
-                        child.wait(None).ok();
+                        _ => Err(format!("Passing {}, a long double, isn't supported", name)),
+                    },
+                    _ if bytes.len() <= 8 => {
+                        let signed = matches!(
+                            var_type.kind,
+                            TypeKind::Base(Some(gimli::DW_ATE_signed))
+                                | TypeKind::Base(Some(gimli::DW_ATE_signed_char))
+                        );
+                        let negative = signed && bytes.last().map_or(false, |b| b & 0x80 != 0);
+                        let mut raw = [if negative { 0xff } else { 0 }; 8];
+                        raw[..bytes.len()].copy_from_slice(&bytes);
+                        Ok(CallArgument::Integer(u64::from_le_bytes(raw)))
                     }
-                    println!("quit");
-                    return;
-                },
-                DebuggerCommand::ContinueRun => {
-                    if let Some(child)=self.inferior.as_mut(){
-                        match child.wakeup(&self.break_list){
-                            Ok(status)=> match status{
-                                Status::Stopped(sig,instruction_ptr)=> {
-                                    println!("Child stopped signal {}",sig);
-                                    if let Some(filename_line) = self.debug_data.get_line_from_addr(instruction_ptr as usize){
-                                        if let Some(function_name)=self.debug_data.get_function_from_addr(instruction_ptr as usize){
-                                            println!("Stopped   {}    ({}:{}) ",function_name,filename_line.file,filename_line.number);
-                                        }
-                                    }
-                                },
-                                Status::Exited(code)=> {println!("Continue Child exited (status {})",code);},
-                                Status::Signaled(sig) => {println!("Continue Child signaled signal {}",sig);},
-                            },
-                            Err(e)=>{
-                                println!("err {}",e);
-                            },
-                        };
-                    }else{
-                        println!("no inferior to continue!");
+                    _ => Err(format!("Can't pass {}, which is {} bytes", name, bytes.len())),
+                }
+            })?,
+        }
+    }
+
+    /// Formats what a called function returned, going by its DWARF return type. Functions
+    /// without debug info are assumed to return int, as C assumed of undeclared functions.
+    fn format_return_value(&self, name: &str, rax: u64, xmm0: [u8; 16]) -> Option<String> {
+        let (module, function) = match self.modules.get_function(name) {
+            Some(found) => found,
+            None => return Some((rax as i32).to_string()),
+        };
+        let return_type = function.return_type.as_ref()?;
+        let stripped = module.debug_data.strip_aliases(return_type);
+        let bytes = match stripped.kind {
+            TypeKind::Alias(None) => return None,
+            TypeKind::Base(Some(gimli::DW_ATE_float)) => xmm0[..stripped.size.min(16)].to_vec(),
+            _ => rax.to_le_bytes()[..stripped.size.min(8)].to_vec(),
+        };
+        let child = self.inferior.as_ref()?;
+        let ctx = FrameContext {
+            inferior: child,
+            regs: child.registers().ok()?,
+            bias: module.bias,
+            cfa: None,
+        };
+        Some(pretty::format_value(
+            &bytes,
+            return_type,
+            &module.debug_data,
+            &ctx,
+            &self.printers,
+        ))
+    }
+
+    /// Prints where the inferior stopped, followed by the `display` expressions.
+    fn print_stop_location(&mut self, instruction_ptr: usize) {
+        let function_name = self.modules.get_function_from_addr(instruction_ptr);
+        match (function_name, self.modules.get_line_from_addr(instruction_ptr)) {
+            (Some(function_name), Some(filename_line)) => {
+                println!("Stopped   {}    ({}:{}) ",function_name,filename_line.file,filename_line.number);
+            }
+            // No line information, e.g. in a stripped binary or a library without DWARF
+            (Some(function_name), None) => {
+                println!("Stopped   {}    (0x{:x}) ",function_name,instruction_ptr);
+            }
+            _ => {}
+        }
+        self.print_displays();
+    }
+
+    /// Explains a crash: the kind of fault and the address it happened at, from the signal's
+    /// siginfo, where that address is in the inferior's memory, the instruction that faulted
+    /// and a backtrace. Other signals are left alone.
+    fn print_fault(&self, signal: Signal, instruction_ptr: usize) {
+        if !fault::is_fault(signal) {
+            return;
+        }
+        let child = match self.inferior.as_ref() {
+            Some(child) => child,
+            None => return,
+        };
+        if let Ok(siginfo) = child.get_siginfo() {
+            let (code_name, meaning) = fault::describe_code(signal, siginfo.si_code);
+            let addr = unsafe { siginfo.si_addr() } as usize;
+            println!("Fault: {} ({})", code_name, meaning);
+            if fault::has_fault_addr(siginfo.si_code) {
+                let maps = procfs::read_maps(child.pid()).unwrap_or_default();
+                let kind = if fault::addr_is_instruction(signal) { "instruction" } else { "address" };
+                println!("Fault {}: 0x{:x}, {}", kind, addr, fault::locate(addr, &maps));
+            }
+        }
+        if let Some(line) = self.modules.get_line_from_addr(instruction_ptr) {
+            let text = fs::read_to_string(&line.file)
+                .ok()
+                .and_then(|source| Some(source.lines().nth(line.number.checked_sub(1)?)?.to_string()));
+            match text {
+                Some(text) => println!("Faulting line: {}\t{}", line.number, text.trim()),
+                None => println!("Faulting line: {}:{}", line.file, line.number),
+            }
+        }
+        // We have no disassembler, so show the bytes the way a kernel oops does, starting with
+        // the faulting instruction
+        if let Ok(mut bytes) = child.read_bytes(instruction_ptr, FAULT_CODE_BYTES) {
+            // Show the program's code rather than the breakpoint instructions written over it
+            for bp in self.break_list.values() {
+                for (i, byte) in bp.orig_bytes.iter().enumerate() {
+                    if let Some(offset) = (bp.address + i).checked_sub(instruction_ptr) {
+                        if let Some(slot) = bytes.get_mut(offset) {
+                            *slot = *byte;
+                        }
                     }
-                },
-                DebuggerCommand::BackTrace=>{
-                    if let Some(child)=self.inferior.as_mut(){
-                        match child.print_backtrace(&self.debug_data){
-                            Ok(_)=>{},
-                            Err(_)=>{},
-                        };
-                    }else{
-                        println!("no inferior when backtrace!");
+                }
+            }
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let symbol = query::symbolize(&self.modules, instruction_ptr)
+                .map_or(String::new(), |symbol| format!(" <{}>", symbol));
+            println!("Code at 0x{:x}{}: {}", instruction_ptr, symbol, bytes.join(" "));
+        }
+        println!("Backtrace:");
+        match child.backtrace(&self.modules) {
+            Ok(frames) => {
+                // Runaway recursion leaves thousands of identical frames
+                for frame in frames.iter().take(FAULT_BACKTRACE_FRAMES) {
+                    println!("{}", frame);
+                }
+                if frames.len() > FAULT_BACKTRACE_FRAMES {
+                    println!("({} more frames)", frames.len() - FAULT_BACKTRACE_FRAMES);
+                }
+            }
+            Err(err) => println!("err {}", err),
+        }
+    }
+
+    /// Shows each `display` expression. One naming a variable that doesn't exist where the
+    /// inferior stopped, e.g. a local of another function, is skipped.
+    fn print_displays(&mut self) {
+        for i in 0..self.displays.len() {
+            self.print_display(i, false);
+        }
+    }
+
+    fn print_display(&mut self, index: usize, show_missing: bool) {
+        let (number, text) = (self.displays[index].number, self.displays[index].expression.clone());
+        let expr = match expr::parse(&text) {
+            Ok(expr) => expr,
+            Err(err) => {
+                println!("{}: {} = {}", number, text, err);
+                return;
+            }
+        };
+        if let Expr::Variable(name) = &expr {
+            if !show_missing && !self.variable_in_scope(name) {
+                return;
+            }
+        }
+        match self.format_expression(&expr) {
+            Ok(Some(value)) => println!("{}: {} = {}", number, text, value),
+            Ok(None) => println!("{}: {} = void", number, text),
+            Err(err) => println!("{}: {} = <{}>", number, text, err),
+        }
+    }
+
+    fn variable_in_scope(&self, name: &str) -> bool {
+        let pc = match self.registers() {
+            Some(regs) => regs.pc() as usize,
+            None => return false,
+        };
+        self.modules.module_for_addr(pc).map_or(false, |module| {
+            pc.checked_sub(module.bias)
+                .and_then(|link_pc| module.debug_data.get_variable(link_pc, name))
+                .is_some()
+        })
+    }
+
+    /// `display expr`: adds an expression to show at every stop, and shows it now if the
+    /// inferior is stopped. Plain `display` shows them all.
+    fn add_display(&mut self, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            if self.inferior.is_some() {
+                self.print_displays();
+            }
+            return;
+        }
+        if let Err(err) = expr::parse(text) {
+            println!("{}", err);
+            return;
+        }
+        self.displays.push(Display {
+            number: self.next_display_number,
+            expression: text.to_string(),
+        });
+        self.next_display_number += 1;
+        if self.inferior.is_some() {
+            self.print_display(self.displays.len() - 1, true);
+        }
+    }
+
+    /// `undisplay N...`, or `undisplay` to remove them all.
+    fn remove_displays(&mut self, numbers: &[String]) {
+        if numbers.is_empty() {
+            self.displays.clear();
+            return;
+        }
+        for number in numbers {
+            match number.parse::<usize>() {
+                Ok(number) if self.displays.iter().any(|display| display.number == number) => {
+                    self.displays.retain(|display| display.number != number);
+                }
+                _ => println!("No display number {}.", number),
+            }
+        }
+    }
+
+    /// `printer load <file>` loads a printer file or plugin; anything else is a line of a printer
+    /// file.
+    fn define_printer(&mut self, args: &str) {
+        let args = args.trim();
+        if args.is_empty() {
+            println!("Usage: printer load <file or library.so>, or printer <shape> <pattern> ...");
+            return;
+        }
+        if let Some(path) = args.strip_prefix("load ") {
+            let path = path.trim();
+            match self.load_printers(Path::new(path)) {
+                Ok(count) => println!("Loaded {} pretty-printer(s) from {}", count, path),
+                Err(err) => println!("{}", err),
+            }
+            return;
+        }
+        if let Err(err) = self.printers.define(args, "command line") {
+            println!("{}", err);
+        }
+    }
+
+    pub(crate) fn load_printers(&mut self, path: &Path) -> Result<usize, Error> {
+        self.printers.load(path)
+    }
+
+    fn print_printers(&self) {
+        if self.printers.is_empty() {
+            println!("No pretty-printers.");
+            return;
+        }
+        println!("Pattern              Shape    From");
+        for printer in self.printers.iter() {
+            let shape = match printer.shape {
+                Shape::List { .. } => "list",
+                Shape::Map { .. } => "map",
+                Shape::Str { .. } => "string",
+                Shape::Template(_) => "format",
+                Shape::Plugin(_) => "plugin",
+            };
+            println!("{:<20} {:<8} {}", printer.pattern.as_str(), shape, printer.source);
+        }
+    }
+
+    /// Prints the value of each local variable and parameter of the innermost frame's function.
+    fn info_locals(&mut self) {
+        let child = match &self.inferior {
+            Some(child) => child,
+            None => {
+                println!("{}", Error::NotRunning);
+                return;
+            }
+        };
+        let pc = match child.registers() {
+            Ok(regs) => regs.pc() as usize,
+            Err(err) => {
+                println!("err {}", err);
+                return;
+            }
+        };
+        let names: Vec<String> = self
+            .modules
+            .module_for_addr(pc)
+            .and_then(|module| module.debug_data.get_function_containing(pc.checked_sub(module.bias)?))
+            .map(|func| func.variables.iter().map(|var| var.name.clone()).collect())
+            .unwrap_or_default();
+        if names.is_empty() {
+            println!("No locals.");
+        }
+        for name in names {
+            match self.format_expression(&Expr::Variable(name.clone())) {
+                Ok(Some(value)) => println!("{} = {}", name, value),
+                Ok(None) => {}
+                Err(err) => println!("{}: {}", name, err),
+            }
+        }
+    }
+
+    fn print_display_list(&self) {
+        if self.displays.is_empty() {
+            println!("There are no auto-display expressions now.");
+            return;
+        }
+        println!("Auto-display expressions now in effect:");
+        println!("Num Expression");
+        for display in &self.displays {
+            println!("{:<3} {}", display.number, display.expression);
+        }
+    }
+
+    fn set_breakpoint(&mut self, total_address: usize) {
+        // Breakpoints are numbered by the location they were set on, which is how `commands`
+        // refers to them; the numbers of internal and temporary breakpoints would come and go
+        let label=match self.breakpoint_number(total_address){
+            Some(number) => format!("Set breakpoint {} at 0x{:x}",number,total_address),
+            None => format!("Set breakpoint at 0x{:x}",total_address),
+        };
+        if let Some(bp)=self.break_list.get_mut(&total_address){ // already trapped, e.g. by a tbreak
+            bp.internal=false;
+            bp.permanent=true;
+            println!("{}",label);
+        }else if let Some(child)=self.inferior.as_mut(){ // inferior stopped ,insert directly
+            if let Some(orig_instr)=child.insert_breakpoint(total_address).ok(){
+                self.break_list.insert(total_address,BreakPoint::new(total_address,orig_instr,false));
+                println!("{}",label);
+            }else{
+                println!("Write Memory Error:inferior write byte on invalid address 0x{:x}",total_address);
+            }
+        }else{  // inferior not run ,insert once it has been started and its modules are loaded
+            self.break_list.insert(total_address,BreakPoint::new(total_address,Vec::new(),false));
+            println!("{}",label);
+        }
+    }
+
+    fn place_breakpoint(&mut self, addr: usize, one_shot: bool) {
+        if one_shot {
+            self.set_tbreak(addr);
+        } else {
+            self.set_breakpoint(addr);
+        }
+    }
+
+    /// `tbreak` at one address: a user breakpoint that goes once it is hit.
+    fn set_tbreak(&mut self, addr: usize) {
+        if self.breakpoint_stops_at(addr) {
+            println!("Note: the program already stops at 0x{:x}.", addr);
+            return;
+        }
+        // Only `break` breakpoints are numbered, so that `commands` can refer to them
+        if self.set_one_shot_breakpoint(addr, false) {
+            println!("Temporary breakpoint at 0x{:x}", addr);
+        } else {
+            println!("Write Memory Error:inferior write byte on invalid address 0x{:x}", addr);
+        }
+    }
+
+    /// Makes the inferior stop at `addr` the next time it gets there, and only then. Returns
+    /// whether it added a stop, which it doesn't if the program stops there anyway. Before the
+    /// program is started, the breakpoint is put in once it is.
+    fn set_one_shot_breakpoint(&mut self, addr: usize, internal: bool) -> bool {
+        match self.break_list.get_mut(&addr) {
+            Some(bp) if !bp.internal || bp.one_shot => false,
+            Some(bp) => {
+                bp.one_shot = true;
+                true
+            }
+            None => {
+                let orig_bytes = match self.inferior.as_mut() {
+                    Some(child) => match child.insert_breakpoint(addr) {
+                        Ok(orig_bytes) => orig_bytes,
+                        Err(_) => return false,
+                    },
+                    None => Vec::new(),
+                };
+                self.break_list.insert(addr, BreakPoint::one_shot(addr, orig_bytes, internal));
+                true
+            }
+        }
+    }
+
+    /// Takes out the one-shot stop at `addr` if it hasn't been used up, e.g. because `until`
+    /// stopped somewhere else first.
+    fn clear_one_shot(&mut self, addr: usize) {
+        match self.inferior.as_mut() {
+            Some(child) => child.clear_one_shot(&mut self.break_list, addr),
+            // Without a process there's no code to put back, only the stop to forget
+            None => match self.break_list.get_mut(&addr) {
+                Some(bp) if bp.one_shot && bp.permanent => bp.one_shot = false,
+                Some(bp) if bp.one_shot => {
+                    self.break_list.remove(&addr);
+                }
+                _ => {}
+            },
+        }
+    }
+
+    /// Continues the inferior until it stops for a reason the user cares about. Stops at the
+    /// dynamic linker's breakpoint are handled here: we pick up whatever libraries were just
+    /// loaded and keep going, as we do past events no catchpoint asked for. While recording, the
+    /// inferior is single-stepped the whole way.
+    fn continue_inferior(&mut self) -> Result<Status, Error> {
+        if self.recording.is_some() {
+            loop {
+                match self.step_instruction()? {
+                    Status::Stopped(Signal::SIGTRAP, instruction_ptr)
+                        if !self.breakpoint_stops_at(instruction_ptr) =>
+                    {
+                        self.run_tracepoints(instruction_ptr);
                     }
-                },
-                DebuggerCommand::Break(args)=>{
-                    let mut total_address=0;
-                    if &args[0..1]=="*"{
-                        if let Some(_address)=parse_address(&args[1..]){
-                            total_address=_address;
-                        }else{
-                            println!("Invalid Address");
-                        }   
-                    }else if let Some(_line_number)=args.parse::<usize>().ok(){
-                        if let Some(_address)=self.debug_data.get_addr_for_line(None,_line_number){
-                            println!("line number address {} {} ",_line_number,_address);
-                            total_address=_address;
-                        }
-                    }else{
-                        if let Some(_address)=self.debug_data.get_addr_for_function(None, &args){
-                            total_address=_address;
-                            println!("function number address {} {} ",args,_address);
-                        }else{
-                            println!("Invalid Address");
+                    status => {
+                        if let Status::Stopped(Signal::SIGTRAP, instruction_ptr) = status {
+                            self.run_tracepoints(instruction_ptr);
+                            self.clear_one_shot(instruction_ptr);
                         }
+                        return Ok(status);
+                    }
+                }
+            }
+        }
+        let syscall_stops = self
+            .catchpoints
+            .iter()
+            .any(|catchpoint| matches!(catchpoint, Catchpoint::Syscall(_)));
+        self.inferior.as_mut().unwrap().set_syscall_stops(syscall_stops);
+        loop {
+            // wakeup uses up a one-shot stop it makes, so note where they are beforehand
+            let one_shots: Vec<usize> = self
+                .break_list
+                .values()
+                .filter(|bp| bp.one_shot)
+                .map(|bp| bp.address)
+                .collect();
+            let status = self.inferior.as_mut().unwrap().wakeup(&mut self.break_list)?;
+            match status {
+                Status::Stopped(Signal::SIGTRAP, instruction_ptr)
+                    if Some(instruction_ptr) == self.shlib_event_addr =>
+                {
+                    self.load_modules();
+                }
+                Status::Stopped(Signal::SIGTRAP, instruction_ptr)
+                    if self.has_tracepoint(instruction_ptr) =>
+                {
+                    self.run_tracepoints(instruction_ptr);
+                    if self.breakpoint_stops_at(instruction_ptr) || one_shots.contains(&instruction_ptr) {
+                        return Ok(status);
+                    }
+                }
+                Status::Exec(_) => {
+                    self.follow_exec();
+                    if self.catchpoint_for(&status).is_some() {
+                        return Ok(status);
                     }
+                }
+                Status::SyscallEntry(..) | Status::SyscallExit(..) | Status::Forked(..)
+                    if self.catchpoint_for(&status).is_none() => {}
+                _ => return Ok(status),
+            }
+        }
+    }
+
+    /// Starts a new inferior, stopped before it runs anything, with breakpoints from the last
+    /// run put back where they belong. Any old inferior should already have been killed.
+    fn start_inferior(&mut self, args: &Vec<String>) -> Result<(), Error> {
+        self.unload_modules();
+        self.recording = None;
+        self.trace_frames.clear();
+        self.inferior = Some(Inferior::new(&self.target, args)?);
+        self.load_modules();
+        Ok(())
+    }
+
+    /// Forgets where modules were loaded in the previous inferior. Breakpoints are remembered
+    /// relative to the module they were in, and put back once that module is loaded again.
+    fn unload_modules(&mut self) {
+        // A one-shot stop left on an internal breakpoint is a tbreak sharing its address. One
+        // that was only ever there for a stop (until, advance) belongs to the command, not the
+        // next run.
+        let mut addresses: Vec<(usize, bool)> = self
+            .break_list
+            .values()
+            .filter(|bp| !bp.internal || (bp.one_shot && bp.permanent))
+            .map(|bp| (bp.address, bp.internal || !bp.permanent))
+            .collect();
+        addresses.sort();
+        for (addr, one_shot) in addresses {
+            match self.modules.to_relative(addr) {
+                Some((path, offset)) => self
+                    .pending_breaks
+                    .push(PendingBreakpoint::ModuleOffset(path, offset, one_shot)),
+                None => println!("Dropping breakpoint at 0x{:x}, which is outside any module", addr),
+            }
+        }
+        self.break_list.clear();
+        for tracepoint in self.tracepoints.iter_mut() {
+            tracepoint.address = None;
+        }
+        self.modules.unload_libraries();
+        self.r_debug_addr = None;
+        self.shlib_event_addr = None;
+    }
+
+    /// Works out where the target, the dynamic linker and every shared library are loaded in the
+    /// inferior, then places any breakpoints that were waiting on them.
+    fn load_modules(&mut self) {
+        let pid = match self.inferior.as_ref() {
+            Some(child) => child.pid(),
+            None => return,
+        };
+        let auxv = procfs::read_auxv(pid).unwrap_or_default();
+        let maps = procfs::read_maps(pid).unwrap_or_default();
+
+        // PIE executables are loaded at a bias; comparing the runtime and link-time entry points
+        // tells us what it is
+        if let Some(entry) = auxv.get(&libc::AT_ENTRY) {
+            let bias = (*entry as usize).wrapping_sub(self.modules.main().entry);
+            self.modules.set_main_bias(bias);
+        }
+
+        // The kernel maps the dynamic linker itself, before any library is loaded. Put a
+        // breakpoint where it reports library loads so that we hear about each one.
+        if self.shlib_event_addr.is_none() {
+            let base = auxv.get(&libc::AT_BASE).cloned().unwrap_or(0) as usize;
+            let interp = maps
+                .iter()
+                .find(|entry| entry.contains(base))
+                .and_then(|entry| entry.path.clone());
+            if let (true, Some(interp)) = (base != 0, interp) {
+                self.modules.add(&interp, base);
+                let r_debug = modules::find_dynamic_symbol(&interp, "_r_debug");
+                let debug_state = modules::find_dynamic_symbol(&interp, "_dl_debug_state");
+                if let (Some(r_debug), Some(debug_state)) = (r_debug, debug_state) {
+                    self.r_debug_addr = Some(base + r_debug);
+                    self.set_internal_breakpoint(base + debug_state);
+                }
+            }
+        }
+
+        if let Some(r_debug) = self.r_debug_addr {
+            let child = self.inferior.as_ref().unwrap();
+            if let Ok(Some(libraries)) = modules::read_link_map(child, r_debug) {
+                for (path, bias) in libraries {
+                    self.modules.add(&path, bias);
+                }
+            }
+        }
+        self.modules
+            .update_ranges(&procfs::read_maps(pid).unwrap_or(maps));
+        self.resolve_pending_breakpoints();
+        self.install_tracepoints();
+    }
+
+    fn set_internal_breakpoint(&mut self, addr: usize) {
+        let child = self.inferior.as_mut().unwrap();
+        if let Ok(orig_bytes) = child.insert_breakpoint(addr) {
+            self.shlib_event_addr = Some(addr);
+            self.break_list.insert(addr, BreakPoint::new(addr, orig_bytes, true));
+        }
+    }
+
+    /// Puts an internal breakpoint at `addr` unless there is already one there. Returns whether
+    /// it did, i.e. whether it should be removed again afterwards.
+    fn set_temporary_breakpoint(&mut self, addr: usize) -> bool {
+        if self.break_list.contains_key(&addr) {
+            return false;
+        }
+        let child = self.inferior.as_mut().unwrap();
+        match child.insert_breakpoint(addr) {
+            Ok(orig_bytes) => {
+                self.break_list.insert(addr, BreakPoint::new(addr, orig_bytes, true));
+                true
+            }
+            Err(_) => false,
+        }
+    }
 
-                    println!("0x{:x}",total_address);
-                    if let Some(child)=self.inferior.as_mut(){ // inferior stopped ,insert directly
-                        if let Some(orig_instr)=child.write_byte(total_address,0xcc as u8).ok(){
-                            let break_list_len=self.break_list.len();
-                            self.break_list.insert(total_address,BreakPoint{
-                                address:total_address,
-                                orig_byte:orig_instr,
-                            });
-                            println!("Set breakpoint {} at 0x{:x}",break_list_len,total_address);
-                        }else{
-                            println!("Write Memory Error:inferior write byte on invalid address 0x{:x}",total_address);
+    fn remove_temporary_breakpoint(&mut self, addr: usize) {
+        if let Some(bp) = self.break_list.remove(&addr) {
+            if let Some(child) = self.inferior.as_mut() {
+                child.write_bytes(addr, &bp.orig_bytes).ok();
+            }
+        }
+    }
+
+    fn resolve_pending_breakpoints(&mut self) {
+        let pending = std::mem::replace(&mut self.pending_breaks, Vec::new());
+        for bp in pending {
+            match &bp {
+                PendingBreakpoint::Function(name, one_shot) => {
+                    let addresses = self.function_breakpoint_addrs(name);
+                    if addresses.is_empty() {
+                        self.pending_breaks.push(bp);
+                    } else {
+                        println!("Pending breakpoint \"{}\" resolved", name);
+                        for addr in addresses {
+                            self.place_breakpoint(addr, *one_shot);
                         }
-                    }else{  // inferior not run ,insert when inferior::new()
-                        let break_list_len=self.break_list.len();
-                        self.break_list.insert(total_address,BreakPoint{
-                            address:total_address,
-                            orig_byte:0,
-                        });
-                        println!("Set breakpoint {} at 0x{:x}",break_list_len,total_address);
                     }
-                },
+                }
+                PendingBreakpoint::ModuleOffset(path, offset, one_shot) => {
+                    match self.modules.to_absolute(path, *offset) {
+                        Some(addr) => self.place_breakpoint(addr, *one_shot),
+                        None => self.pending_breaks.push(bp),
+                    }
+                }
+            }
+        }
+    }
+
+    // What api::Session is built on. These do what the commands of the same name do, but
+    // return what happened, or an error, rather than printing it.
+
+    /// `run`: starts the target afresh and lets it run until it stops.
+    pub(crate) fn start(&mut self, args: &Vec<String>) -> Result<Status, Error> {
+        self.kill_inferior();
+        self.start_inferior(args)?;
+        self.continue_inferior()
+    }
+
+    /// Kills the inferior and any checkpoints of it.
+    pub(crate) fn kill_inferior(&mut self) {
+        self.kill_checkpoints();
+        if let Some(mut child) = self.inferior.take() {
+            child.kill().ok();
+            child.wait(None).ok();
+        }
+    }
+
+    /// `break`: returns the addresses the breakpoint went in at, which are none if it is
+    /// pending on a function in a library that hasn't been loaded yet.
+    pub(crate) fn break_at(&mut self, location: &str) -> Result<Vec<usize>, Error> {
+        let addresses = self.breakpoint_addrs(location)?;
+        self.remember_breakpoint(location);
+        if addresses.is_empty() {
+            self.pending_breaks.push(PendingBreakpoint::Function(location.to_string(), false));
+        }
+        for addr in &addresses {
+            self.set_breakpoint(*addr);
+        }
+        Ok(addresses)
+    }
+
+    /// `tbreak`: like `break_at`, but each breakpoint goes the first time it is hit. Temporary
+    /// breakpoints aren't saved in the session file.
+    pub(crate) fn tbreak_at(&mut self, location: &str) -> Result<Vec<usize>, Error> {
+        let addresses = self.breakpoint_addrs(location)?;
+        if addresses.is_empty() {
+            self.pending_breaks.push(PendingBreakpoint::Function(location.to_string(), true));
+        }
+        for addr in &addresses {
+            self.set_tbreak(*addr);
+        }
+        Ok(addresses)
+    }
+
+    /// Where a breakpoint on `location` goes. Anything but `*address`, `line` and `file:line` is
+    /// a function, as for `break`, which may be found in no module yet.
+    fn breakpoint_addrs(&self, location: &str) -> Result<Vec<usize>, Error> {
+        let is_function = !(location.starts_with('*')
+            || location.parse::<usize>().is_ok()
+            || location
+                .rsplit_once(':')
+                .map_or(false, |(_, line)| line.parse::<usize>().is_ok()));
+        if is_function {
+            return Ok(self.function_breakpoint_addrs(location));
+        }
+        let addr = self
+            .resolve_location(location)
+            .ok_or_else(|| Error::NoLocation(location.to_string()))?;
+        Ok(vec![addr])
+    }
+
+    /// `until`: with no location, `next`, but without stopping at lines before the one it
+    /// started on in this frame, so it runs to the end of a loop rather than around it again.
+    /// With a location, like `advance`, except that it only stops there in this frame or its
+    /// callers.
+    pub(crate) fn until(&mut self, location: &str) -> Result<Status, Error> {
+        self.running()?;
+        if location.is_empty() {
+            self.until_next_line()
+        } else {
+            self.run_to(location, true)
+        }
+    }
+
+    /// `advance`: runs until `location` is reached, or the current function returns.
+    pub(crate) fn advance(&mut self, location: &str) -> Result<Status, Error> {
+        self.running()?;
+        self.run_to(location, false)
+    }
+
+    /// `continue`
+    pub(crate) fn resume(&mut self) -> Result<Status, Error> {
+        self.running()?;
+        self.continue_inferior()
+    }
+
+    /// `step`, or `next` if `over` is set
+    pub(crate) fn step_source_line(&mut self, over: bool) -> Result<Status, Error> {
+        self.running()?;
+        self.step_line(if over { StepKind::Over } else { StepKind::Into })
+    }
+
+    /// `stepi`
+    pub(crate) fn step_one_instruction(&mut self) -> Result<Status, Error> {
+        self.running()?;
+        self.step_instruction()
+    }
+
+    /// `print`: the value of a variable as seen from the innermost frame.
+    pub(crate) fn read_variable(&mut self, name: &str) -> Result<String, Error> {
+        self.running()?;
+        // Unlike `print`, a value that can't be read is an error rather than a message to show
+        self.with_variable(name, |var_type, value, debug_data, ctx| match value {
+            Ok((_, bytes)) => {
+                Ok(pretty::format_value(&bytes, var_type, debug_data, ctx, &self.printers))
             }
+            Err(err) => Err(err.to_string()),
+        })
+        .and_then(|value| value)
+        .map_err(Error::Evaluation)
+    }
+
+    /// `backtrace`
+    pub(crate) fn frames(&self) -> Result<Vec<Frame>, Error> {
+        self.running()?.frames(&self.modules)
+    }
+
+    pub(crate) fn modules(&self) -> &ModuleList {
+        &self.modules
+    }
+
+    fn running(&self) -> Result<&Inferior, Error> {
+        self.inferior.as_ref().ok_or(Error::NotRunning)
+    }
+
+    /// Returns the source line the inferior is currently stopped on, if there is a running
+    /// inferior and we have line information for its instruction pointer.
+    pub fn current_line(&self) -> Option<Line> {
+        let regs = self.inferior.as_ref()?.registers().ok()?;
+        self.modules.get_line_from_addr(regs.pc() as usize)
+    }
+
+    /// Returns the source lines that currently have a breakpoint on them.
+    pub fn breakpoint_lines(&self) -> Vec<Line> {
+        self.break_list
+            .values()
+            .filter(|bp| !bp.internal || bp.one_shot)
+            .filter_map(|bp| self.modules.get_line_from_addr(bp.address))
+            .collect()
+    }
+
+    pub fn registers(&self) -> Option<Registers> {
+        self.inferior.as_ref()?.registers().ok()
+    }
+
+    pub fn backtrace(&self) -> Vec<String> {
+        match self.inferior.as_ref() {
+            Some(child) => child.backtrace(&self.modules).unwrap_or_default(),
+            None => Vec::new(),
         }
     }
 
     /// This function prompts the user to enter a command, and continues re-prompting until the user
-    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
-    ///
-    /// You don't need to read, understand, or modify this function.
-    fn get_next_command(&mut self) -> DebuggerCommand {
+    /// enters a valid command. It uses DebuggerCommand::from_line to do the command parsing.
+    fn get_next_command(&mut self) -> Result<DebuggerCommand, Error> {
         // println!("get_next_command");
         loop {
             // Print prompt and get next line of user input
             // println!("get_next_command loop");
 
-            match self.readline.readline("(deet) ") {
+            let prompt = if self.command_list.is_some() { ">" } else { "(deet) " };
+            match self.readline.readline(prompt) {
                 Err(ReadlineError::Interrupted) => {
                     // User pressed ctrl+c. We're going to ignore it
                     println!("Type \"quit\" to exit");
@@ -199,28 +2862,28 @@ impl Debugger {
                 Err(ReadlineError::Eof) => {
                     // User pressed ctrl+d, which is the equivalent of "quit" for our purposes
                     // println!("Eof");
-                    return DebuggerCommand::Quit;
-                }
-                Err(err) => {
-                    panic!("Unexpected I/O error: {:?}", err);
+                    return Ok(DebuggerCommand::Quit);
                 }
+                Err(err) => return Err(err.into()),
                 Ok(line) => {
                     // println!("???????????????{}",line);
                     if line.trim().len() == 0 {
                         continue;
                     }
                     self.readline.add_history_entry(line.as_str());
-                    if let Err(err) = self.readline.save_history(&self.history_path) {
-                        println!(
-                            "Warning: failed to save history file at {}: {}",
-                            self.history_path, err
-                        );
+                    if let Some(history_path) = &self.history_path {
+                        if let Err(err) = self.readline.save_history(history_path) {
+                            println!(
+                                "Warning: failed to save history file at {}: {}",
+                                history_path, err
+                            );
+                        }
                     }
-                    let tokens: Vec<&str> = line.split_whitespace().collect();
-                    println!("{:?}",tokens);
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
-                        // println!("next command is {:?}",tokens);
-                        return cmd;
+                    if self.add_command_list_line(&line) {
+                        continue;
+                    }
+                    if let Some(cmd) = DebuggerCommand::from_line(&line) {
+                        return Ok(cmd);
                     } else {
                         println!("Unrecognized command.");
                     }
@@ -230,3 +2893,11 @@ impl Debugger {
     }
 }
 
+
+/// Where to look up the unwind rules and line of a frame `depth` calls out from the innermost.
+/// Past the innermost frame the pc is a return address, which may belong to the next line; the call
+/// is the instruction before it.
+fn unwind_pc(regs: &Registers, depth: usize) -> usize {
+    let pc = if depth == 0 { regs.pc() } else { regs.pc() - 1 };
+    pc as usize
+}
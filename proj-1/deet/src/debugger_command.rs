@@ -4,6 +4,49 @@ pub enum DebuggerCommand {
     ContinueRun,
     BackTrace,
     Break(String),
+    /// `tbreak <location>`
+    TBreak(String),
+    /// `until [location]`
+    Until(String),
+    /// `advance <location>`
+    Advance(String),
+    /// `commands [N]`, followed by the commands to run when breakpoint N is hit and `end`
+    Commands(String),
+    Print(String),
+    /// `ptype <variable or type>`
+    Ptype(String),
+    /// `call func(args)`
+    Call(String),
+    StepInstruction,
+    Step,
+    Next,
+    /// `record`, or `record stop` (false)
+    Record(bool),
+    ReverseStepInstruction,
+    ReverseStep,
+    ReverseNext,
+    ReverseContinue,
+    Checkpoint,
+    Restart(String),
+    /// `info <what>`
+    Info(String),
+    /// `catch syscall|exec|fork ...`
+    Catch(Vec<String>),
+    /// `dprintf location,"format",expr...`
+    Dprintf(String),
+    /// `trace location[,expr...]`
+    Trace(String),
+    TraceDump,
+    /// `display [expr]`
+    Display(String),
+    /// `undisplay [N...]`
+    Undisplay(Vec<String>),
+    /// `save breakpoints <file>`
+    Save(Vec<String>),
+    /// `restore [file]`, where no file means the target's session file
+    Restore(String),
+    /// `printer load <file>`, or `printer <shape> <pattern> ...` to define one
+    Printer(String),
 }
 
 pub fn parse_address(addr: &str) -> Option<usize> {
@@ -18,6 +61,19 @@ pub fn parse_address(addr: &str) -> Option<usize> {
 
 impl DebuggerCommand {
 
+    /// Parses a line of input. Commands whose argument is free text, like the format string of
+    /// dprintf, get the rest of the line as it was typed rather than its tokens joined back up.
+    pub fn from_line(line: &str) -> Option<DebuggerCommand> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let rest = line.trim_start()[tokens.first()?.len()..].trim().to_string();
+        match tokens[0] {
+            "dprintf" => Some(DebuggerCommand::Dprintf(rest)),
+            "trace" | "tp" => Some(DebuggerCommand::Trace(rest)),
+            "printer" => Some(DebuggerCommand::Printer(rest)),
+            _ => DebuggerCommand::from_tokens(&tokens),
+        }
+    }
+
     pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
@@ -38,9 +94,84 @@ impl DebuggerCommand {
                 //     None
                 // }
             } 
+            "tb" | "tbreak" => Some(DebuggerCommand::TBreak(tokens[1..].join(" "))),
+            "u" | "until" => Some(DebuggerCommand::Until(tokens[1..].join(" "))),
+            "advance" => Some(DebuggerCommand::Advance(tokens[1..].join(" "))),
+            "commands" => Some(DebuggerCommand::Commands(tokens[1..].join(" "))),
+            "p" | "print" => Some(DebuggerCommand::Print(tokens[1..].join(" "))),
+            "ptype" => Some(DebuggerCommand::Ptype(tokens[1..].join(" "))),
+            "call" => Some(DebuggerCommand::Call(tokens[1..].join(" "))),
+            "si" | "stepi" => Some(DebuggerCommand::StepInstruction),
+            "s" | "step" => Some(DebuggerCommand::Step),
+            "n" | "next" => Some(DebuggerCommand::Next),
+            "rec" | "record" => match tokens.get(1) {
+                None => Some(DebuggerCommand::Record(true)),
+                Some(&"stop") => Some(DebuggerCommand::Record(false)),
+                Some(_) => None,
+            },
+            "rsi" | "reverse-stepi" => Some(DebuggerCommand::ReverseStepInstruction),
+            "rs" | "reverse-step" => Some(DebuggerCommand::ReverseStep),
+            "rn" | "reverse-next" => Some(DebuggerCommand::ReverseNext),
+            "rc" | "reverse-continue" => Some(DebuggerCommand::ReverseContinue),
+            "checkpoint" => Some(DebuggerCommand::Checkpoint),
+            "restart" => Some(DebuggerCommand::Restart(tokens[1..].join(" "))),
+            "catch" => Some(DebuggerCommand::Catch(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "tdump" => Some(DebuggerCommand::TraceDump),
+            "display" => Some(DebuggerCommand::Display(tokens[1..].join(" "))),
+            "undisplay" => Some(DebuggerCommand::Undisplay(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "save" => Some(DebuggerCommand::Save(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "restore" => Some(DebuggerCommand::Restore(tokens[1..].join(" "))),
+            "i" | "info" => Some(DebuggerCommand::Info(tokens[1..].join(" "))),
             // Default case:
             _ => None,
         }
     }
 
+    /// Whether the command sets the program running, and so can stop it somewhere else.
+    pub fn resumes(&self) -> bool {
+        matches!(
+            self,
+            DebuggerCommand::Run(_)
+                | DebuggerCommand::ContinueRun
+                | DebuggerCommand::Until(_)
+                | DebuggerCommand::Advance(_)
+                | DebuggerCommand::StepInstruction
+                | DebuggerCommand::Step
+                | DebuggerCommand::Next
+                | DebuggerCommand::ReverseStepInstruction
+                | DebuggerCommand::ReverseStep
+                | DebuggerCommand::ReverseNext
+                | DebuggerCommand::ReverseContinue
+                | DebuggerCommand::Restart(_)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_free_text_as_typed() {
+        match DebuggerCommand::from_line("dprintf  foo.c:12 \"a  =  %d\\n\", a ") {
+            Some(DebuggerCommand::Dprintf(args)) => assert_eq!(args, "foo.c:12 \"a  =  %d\\n\", a"),
+            _ => panic!("Expected dprintf"),
+        }
+        match DebuggerCommand::from_line("tp main") {
+            Some(DebuggerCommand::Trace(args)) => assert_eq!(args, "main"),
+            _ => panic!("Expected trace"),
+        }
+        match DebuggerCommand::from_line("printer format ^point$ ( {x},  {y} )") {
+            Some(DebuggerCommand::Printer(args)) => assert_eq!(args, "format ^point$ ( {x},  {y} )"),
+            _ => panic!("Expected printer"),
+        }
+        assert!(matches!(DebuggerCommand::from_line("  "), None));
+        assert!(matches!(DebuggerCommand::from_line("next"), Some(DebuggerCommand::Next)));
+    }
 }
@@ -0,0 +1,155 @@
+//! Finds DWARF that doesn't live in the binary itself. Distributions strip their binaries and
+//! ship the debugging information in separate packages, found through the binary's
+//! `.note.gnu.build-id` or `.gnu_debuglink` sections.
+
+use object::Object;
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// Where we look for separate debug files. Can be overridden with a colon-separated list of
+/// directories in DEET_DEBUG_FILE_DIRECTORY, much like gdb's `set debug-file-directory`.
+const DEFAULT_DEBUG_FILE_DIRECTORY: &str = "/usr/lib/debug";
+
+/// Note type of the build ID note in `.note.gnu.build-id`
+const NT_GNU_BUILD_ID: u32 = 3;
+
+pub fn debug_file_directories() -> Vec<PathBuf> {
+    env::var("DEET_DEBUG_FILE_DIRECTORY")
+        .unwrap_or(DEFAULT_DEBUG_FILE_DIRECTORY.to_string())
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+pub fn has_dwarf(object: &object::File) -> bool {
+    object
+        .section_data_by_name(".debug_info")
+        .map(|data| !data.is_empty())
+        .unwrap_or(false)
+}
+
+/// Looks for a separate debug file for the binary at `path`: first by build ID under
+/// `<debug dir>/.build-id/`, then by the name in `.gnu_debuglink`, next to the binary, in its
+/// `.debug` subdirectory, or mirrored under each debug directory.
+pub fn find_separate_debug_file(path: &str, object: &object::File) -> Option<PathBuf> {
+    let dirs = debug_file_directories();
+
+    if let Some(id) = object
+        .section_data_by_name(".note.gnu.build-id")
+        .and_then(|data| parse_build_id_note(&data))
+    {
+        if id.len() >= 2 {
+            let hex: String = id.iter().map(|byte| format!("{:02x}", byte)).collect();
+            for dir in &dirs {
+                let candidate = dir
+                    .join(".build-id")
+                    .join(&hex[..2])
+                    .join(format!("{}.debug", &hex[2..]));
+                if candidate.exists() {
+                    return Some(candidate);
+                }
+            }
+        }
+    }
+
+    let (name, crc) = object
+        .section_data_by_name(".gnu_debuglink")
+        .and_then(|data| parse_debuglink(&data))?;
+    let binary = fs::canonicalize(path).ok()?;
+    let binary_dir = binary.parent()?;
+    let mut candidates = vec![binary_dir.join(&name), binary_dir.join(".debug").join(&name)];
+    for dir in &dirs {
+        let relative_dir = binary_dir.strip_prefix("/").unwrap_or(binary_dir);
+        candidates.push(dir.join(relative_dir).join(&name));
+    }
+    candidates
+        .into_iter()
+        .find(|candidate| *candidate != binary && crc_matches(candidate, crc))
+}
+
+fn crc_matches(path: &Path, crc: u32) -> bool {
+    match fs::read(path) {
+        Ok(data) => crc32(&data) == crc,
+        Err(_) => false,
+    }
+}
+
+/// Parses an ELF note (Elf64_Nhdr followed by its name and descriptor, each padded to 4 bytes)
+/// and returns the descriptor if it is a GNU build ID.
+fn parse_build_id_note(data: &[u8]) -> Option<Vec<u8>> {
+    let read_u32 = |offset: usize| -> Option<u32> {
+        Some(u32::from_ne_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+    };
+    let namesz = read_u32(0)? as usize;
+    let descsz = read_u32(4)? as usize;
+    if read_u32(8)? != NT_GNU_BUILD_ID {
+        return None;
+    }
+    let desc_start = 12 + align4(namesz);
+    Some(data.get(desc_start..desc_start + descsz)?.to_vec())
+}
+
+/// Parses `.gnu_debuglink`: a NUL-terminated file name, padded to 4 bytes, then a CRC-32 of the
+/// debug file.
+fn parse_debuglink(data: &[u8]) -> Option<(String, u32)> {
+    let nul = data.iter().position(|byte| *byte == 0)?;
+    let name = std::str::from_utf8(&data[..nul]).ok()?.to_string();
+    let crc_offset = align4(nul + 1);
+    let crc = u32::from_ne_bytes(data.get(crc_offset..crc_offset + 4)?.try_into().ok()?);
+    Some((name, crc))
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// The CRC-32 `.gnu_debuglink` uses (the same one as zlib).
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+        *entry = crc;
+    }
+    let mut crc = !0u32;
+    for byte in data {
+        crc = table[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_parse_build_id_note() {
+        let mut note = Vec::new();
+        note.extend_from_slice(&4u32.to_ne_bytes());
+        note.extend_from_slice(&3u32.to_ne_bytes());
+        note.extend_from_slice(&NT_GNU_BUILD_ID.to_ne_bytes());
+        note.extend_from_slice(b"GNU\0");
+        note.extend_from_slice(&[0xab, 0xcd, 0xef]);
+        assert_eq!(parse_build_id_note(&note), Some(vec![0xab, 0xcd, 0xef]));
+    }
+
+    #[test]
+    fn test_parse_debuglink() {
+        let mut link = b"prog.debug\0\0".to_vec();
+        link.extend_from_slice(&0x1234_5678u32.to_ne_bytes());
+        assert_eq!(
+            parse_debuglink(&link),
+            Some(("prog.debug".to_string(), 0x1234_5678))
+        );
+    }
+}
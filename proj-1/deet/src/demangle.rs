@@ -0,0 +1,90 @@
+//! Turns mangled symbol names back into source-level paths: Rust's legacy (`_ZN...17h<hash>E`)
+//! and v0 (`_R...`) schemes, and the Itanium C++ ABI (`_Z...`).
+
+/// Demangles a symbol name, or returns it unchanged if it isn't mangled (e.g. a C function).
+pub fn demangle(name: &str) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        // The alternate format leaves off the hash suffix
+        return format!("{:#}", demangled);
+    }
+    if name.starts_with("_Z") {
+        if let Ok(symbol) = cpp_demangle::Symbol::new(name) {
+            if let Ok(demangled) = symbol.demangle(&Default::default()) {
+                return demangled;
+            }
+        }
+    }
+    name.to_string()
+}
+
+/// Whether `query` names the symbol `name`: either exactly, or as its demangled path, or as the
+/// tail end of that path (`module::func` for `mycrate::module::func`). C++ parameter lists are
+/// ignored, so `ns::func` matches `ns::func(int)`.
+pub fn matches(name: &str, query: &str) -> bool {
+    if name == query {
+        return true;
+    }
+    let demangled = demangle(name);
+    let path = strip_parameters(&demangled);
+    path == query || path.ends_with(&format!("::{}", query))
+}
+
+/// Drops the parameter list (and any trailing `const`) from a demangled C++ name.
+fn strip_parameters(name: &str) -> &str {
+    let trimmed = name.trim_end_matches(" const");
+    if !trimmed.ends_with(')') {
+        return name;
+    }
+    // Walk back to the parenthesis matching the last one, since parameters can have
+    // parentheses of their own (function pointers)
+    let mut depth = 0;
+    for (i, c) in trimmed.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' => {
+                depth -= 1;
+                if depth == 0 {
+                    return &trimmed[..i];
+                }
+            }
+            _ => {}
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_demangle_rust_legacy() {
+        assert_eq!(
+            demangle("_ZN7mycrate6module4func17h0123456789abcdefE"),
+            "mycrate::module::func"
+        );
+    }
+
+    #[test]
+    fn test_demangle_rust_v0() {
+        assert_eq!(demangle("_RNvNtCs1234_7mycrate6module4func"), "mycrate::module::func");
+    }
+
+    #[test]
+    fn test_demangle_cpp() {
+        assert_eq!(demangle("_ZN2ns4funcEi"), "ns::func(int)");
+        assert_eq!(demangle("main"), "main");
+    }
+
+    #[test]
+    fn test_matches() {
+        let rust = "_ZN7mycrate6module4func17h0123456789abcdefE";
+        assert!(matches(rust, "mycrate::module::func"));
+        assert!(matches(rust, "module::func"));
+        assert!(matches(rust, "func"));
+        assert!(!matches(rust, "unc"));
+        assert!(matches("_ZN2ns4funcEi", "ns::func"));
+        assert!(matches("_ZN2ns4funcEPFviE", "ns::func"));
+        assert!(matches("main", "main"));
+    }
+}
@@ -1,150 +1,723 @@
+use crate::debuginfo;
+use crate::demangle;
 use crate::gimli_wrapper;
-use addr2line::Context;
-use object::Object;
-use std::convert::TryInto;
+use crate::Error;
+use object::{Object, SymbolKind};
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::HashMap;
 use std::{fmt, fs};
 
-#[derive(Debug)]
-pub enum Error {
-    ErrorOpeningFile,
-    DwarfFormatError(gimli_wrapper::Error),
+/// The debug info of one binary, with indexes for looking functions, lines and types up by name
+/// and address. Loading reads only the root DIE of each unit, which is enough to index units by
+/// file name and by the addresses of their code. The rest of a unit's DIEs are parsed the first
+/// time a lookup needs something from that unit, and its line table decoded the first time one
+/// needs that. Lookups by name first need to know which units define the name, which takes a
+/// pass over every unit reading names only; that is done once, at the first such lookup.
+pub struct DwarfData {
+    sections: gimli_wrapper::DebugSections,
+    /// The units, in .debug_info order
+    units: Vec<Unit>,
+    /// Function symbols from the ELF symbol tables, sorted by address. We fall back on these for
+    /// code we have no DWARF for.
+    symbols: Vec<Symbol>,
+    /// .eh_frame/.debug_frame, for finding the canonical frame address (DW_OP_call_frame_cfa)
+    call_frame_info: gimli_wrapper::CallFrameInfo,
+    /// Files by their full name and their base name, as indices into `units`
+    file_index: HashMap<String, Vec<usize>>,
+    /// The address ranges of units' code, sorted by start address, as (start, end, unit)
+    unit_ranges: Vec<(usize, usize, usize)>,
+    /// Units by the names of the functions, inlined functions, types and global variables they
+    /// define, and by the short names of those (`func` for `mycrate::module::func`)
+    name_index: OnceCell<HashMap<String, Vec<usize>>>,
+    /// Symbols by their short demangled name, as indices into `symbols`
+    symbol_index: HashMap<String, Vec<usize>>,
+    /// What couldn't be loaded: the DWARF as a whole, or some of its units
+    warnings: Vec<String>,
 }
 
-pub struct DwarfData {
-    files: Vec<File>,
-    addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+/// A unit of the DWARF: what was read of it at load, and the rest once it has been needed.
+struct Unit {
+    root: gimli_wrapper::UnitRoot,
+    contents: OnceCell<UnitContents>,
+    /// Decoding every line table up front is most of the work of loading a large binary, and
+    /// few are ever needed
+    line_table: OnceCell<gimli_wrapper::LineTable>,
 }
 
-impl fmt::Debug for DwarfData {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DwarfData {{files: {:?}}}", self.files)
+/// A unit's DIEs, with indexes for looking its functions up by name and address.
+#[derive(Default)]
+struct UnitContents {
+    file: Option<File>,
+    /// Every type the unit describes, keyed by .debug_info offset
+    types: HashMap<usize, Type>,
+    /// Functions with an address by their short name, as indices into `file.functions`.
+    /// Candidates are then checked with Function::matches.
+    function_index: HashMap<String, Vec<usize>>,
+    /// The address ranges of functions, sorted by start address, as (start, end, function)
+    function_ranges: Vec<(usize, usize, usize)>,
+    /// Inlined calls by the short name of the function they inlined, as (function, call)
+    /// indices
+    inline_index: HashMap<String, Vec<(usize, usize)>>,
+}
+
+impl UnitContents {
+    fn new(entries: gimli_wrapper::UnitEntries) -> UnitContents {
+        let mut contents = UnitContents {
+            types: entries.types,
+            ..Default::default()
+        };
+        if let Some(file) = &entries.file {
+            for (function_index, func) in file.functions.iter().enumerate() {
+                if func.address != 0 {
+                    contents
+                        .function_index
+                        .entry(short_name(&func.name))
+                        .or_insert_with(Vec::new)
+                        .push(function_index);
+                    contents.function_ranges.push((
+                        func.address,
+                        func.address + func.text_length,
+                        function_index,
+                    ));
+                }
+                for (call_index, call) in func.inlined_calls.iter().enumerate() {
+                    contents
+                        .inline_index
+                        .entry(short_name(&call.name))
+                        .or_insert_with(Vec::new)
+                        .push((function_index, call_index));
+                }
+            }
+        }
+        contents.function_ranges.sort();
+        contents.file = entries.file;
+        contents
+    }
+
+    /// The names the unit would be found by in the name index, for units parsed before it was
+    /// built.
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.types.values().map(|dtype| dtype.name.clone()).collect();
+        if let Some(file) = &self.file {
+            for func in &file.functions {
+                names.push(func.name.clone());
+                names.extend(func.inlined_calls.iter().map(|call| call.name.clone()));
+            }
+            names.extend(file.global_variables.iter().map(|var| var.name.clone()));
+        }
+        names
     }
 }
 
-impl From<gimli_wrapper::Error> for Error {
-    fn from(err: gimli_wrapper::Error) -> Self {
-        Error::DwarfFormatError(err)
+impl fmt::Debug for DwarfData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let units: Vec<_> = self.units.iter().map(|unit| &unit.root).collect();
+        write!(f, "DwarfData {{units: {:?}}}", units)
     }
 }
 
 impl DwarfData {
     pub fn from_file(path: &str) -> Result<DwarfData, Error> {
-        let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
-        let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
-        let object = object::File::parse(&*mmap)
-            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+        DwarfData::load(path, true)
+    }
+
+    fn load(path: &str, follow_debug_link: bool) -> Result<DwarfData, Error> {
+        let file = fs::File::open(path).map_err(|err| Error::OpeningFile(path.to_string(), err))?;
+        let mmap = unsafe {
+            memmap::Mmap::map(&file).map_err(|err| Error::OpeningFile(path.to_string(), err))?
+        };
+        let object = object::File::parse(&*mmap).map_err(|err| {
+            Error::DebugInfo(path.to_string(), gimli_wrapper::Error::ObjectError(err.to_string()))
+        })?;
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
-        Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
-        })
+
+        // Stripped binaries usually keep their DWARF in a separate debug file
+        if follow_debug_link && !debuginfo::has_dwarf(&object) {
+            if let Some(debug_path) = debuginfo::find_separate_debug_file(path, &object) {
+                if let Ok(mut debug_data) = DwarfData::load(&debug_path.to_string_lossy(), false) {
+                    debug_data.add_symbols(&object);
+                    // .eh_frame is loaded at runtime, so it stays behind in the stripped binary
+                    let call_frame_info = gimli_wrapper::CallFrameInfo::load(&object, endian);
+                    if !call_frame_info.is_empty() {
+                        debug_data.call_frame_info = call_frame_info;
+                    }
+                    return Ok(debug_data);
+                }
+            }
+        }
+
+        // Without usable DWARF the program can still be debugged by instruction, going by the
+        // ELF symbols
+        let sections = gimli_wrapper::DebugSections::load(&object, endian);
+        let (units, warnings) = match sections.load_units(path) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                let warning = format!("Could not read debugging symbols from {}: {}", path, err);
+                (Vec::new(), vec![warning])
+            }
+        };
+        let mut debug_data = DwarfData::with_units(
+            units
+                .into_iter()
+                .map(|(root, entries)| Unit {
+                    root,
+                    contents: entries.map_or_else(OnceCell::new, |entries| {
+                        OnceCell::from(UnitContents::new(entries))
+                    }),
+                    line_table: OnceCell::new(),
+                })
+                .collect(),
+        );
+        debug_data.sections = sections;
+        debug_data.call_frame_info = gimli_wrapper::CallFrameInfo::load(&object, endian);
+        debug_data.warnings = warnings;
+        debug_data.build_indexes();
+        debug_data.add_symbols(&object);
+        Ok(debug_data)
+    }
+
+    fn with_units(units: Vec<Unit>) -> DwarfData {
+        DwarfData {
+            sections: Default::default(),
+            units,
+            symbols: Vec::new(),
+            call_frame_info: Default::default(),
+            file_index: HashMap::new(),
+            unit_ranges: Vec::new(),
+            name_index: OnceCell::new(),
+            symbol_index: HashMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// A DwarfData with nothing in it but `types`, for testing code that formats values.
+    #[cfg(test)]
+    pub fn from_types(types: HashMap<usize, Type>) -> DwarfData {
+        DwarfData::with_units(vec![Unit {
+            root: Default::default(),
+            contents: OnceCell::from(UnitContents {
+                types,
+                ..Default::default()
+            }),
+            line_table: OnceCell::new(),
+        }])
+    }
+
+    fn build_indexes(&mut self) {
+        for (unit_index, unit) in self.units.iter().enumerate() {
+            for (start, end) in &unit.root.ranges {
+                self.unit_ranges.push((*start, *end, unit_index));
+            }
+            let name = match &unit.root.name {
+                Some(name) => name,
+                None => continue,
+            };
+            let base_name = name.rsplit('/').next().unwrap_or(name);
+            for name in &[name.as_str(), base_name] {
+                let units = self.file_index.entry(name.to_string()).or_insert_with(Vec::new);
+                if !units.contains(&unit_index) {
+                    units.push(unit_index);
+                }
+            }
+        }
+        self.unit_ranges.sort();
     }
 
-    #[allow(dead_code)]
-    fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+    fn add_symbols(&mut self, object: &object::File) {
+        for (_, symbol) in object.symbols().chain(object.dynamic_symbols()) {
+            if symbol.kind() != SymbolKind::Text || symbol.address() == 0 {
+                continue;
+            }
+            if let Some(name) = symbol.name() {
+                self.symbols.push(Symbol {
+                    name: name.to_string(),
+                    address: symbol.address() as usize,
+                    size: symbol.size() as usize,
+                });
+            }
+        }
+        self.symbols.sort_by_key(|symbol| symbol.address);
+        self.symbols
+            .dedup_by(|a, b| a.address == b.address && a.name == b.name);
+        self.symbol_index.clear();
+        for (index, symbol) in self.symbols.iter().enumerate() {
+            self.symbol_index
+                .entry(short_name(&demangle::demangle(&symbol.name)))
+                .or_insert_with(Vec::new)
+                .push(index);
+        }
+    }
+
+    /// A unit's contents, parsing its DIEs if this is the first time they're needed. A unit
+    /// whose DIEs can't be parsed is left empty, as is one whose line table can't be decoded.
+    fn contents(&self, unit: usize) -> &UnitContents {
+        let unit = &self.units[unit];
+        unit.contents.get_or_init(|| {
+            self.sections
+                .load_unit(&unit.root)
+                .map(UnitContents::new)
+                .unwrap_or_default()
         })
     }
 
-    #[allow(dead_code)]
-    pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
-        let target_file = match file {
-            Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+    fn file(&self, unit: usize) -> Option<&File> {
+        self.contents(unit).file.as_ref()
+    }
+
+    fn function(&self, unit: usize, func: usize) -> &Function {
+        &self.contents(unit).file.as_ref().unwrap().functions[func]
+    }
+
+    /// The units that define something called `name`, or something whose short name is `name`,
+    /// in .debug_info order. The first call reads the names of every unit.
+    fn units_named(&self, name: &str) -> &[usize] {
+        let name_index = self.name_index.get_or_init(|| {
+            let mut name_index: HashMap<String, Vec<usize>> = HashMap::new();
+            for (unit_index, unit) in self.units.iter().enumerate() {
+                let names = match unit.contents.get() {
+                    Some(contents) => contents.names(),
+                    None => self.sections.unit_names(&unit.root).unwrap_or_default(),
+                };
+                let mut add = |name: String| {
+                    let units = name_index.entry(name).or_insert_with(Vec::new);
+                    if units.last() != Some(&unit_index) {
+                        units.push(unit_index);
+                    }
+                };
+                for name in names {
+                    let short = short_name(&name);
+                    if short != name {
+                        add(short);
+                    }
+                    add(name);
+                }
+            }
+            name_index
+        });
+        name_index.get(name).map_or(&[], |units| units.as_slice())
+    }
+
+    /// Finds the function symbol covering an address, for code without DWARF.
+    pub fn get_symbol_from_addr(&self, curr_addr: usize) -> Option<&Symbol> {
+        let index = match self
+            .symbols
+            .binary_search_by_key(&curr_addr, |symbol| symbol.address)
+        {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
         };
-        Some(
-            target_file
-                .lines
+        let symbol = &self.symbols[index];
+        if curr_addr < symbol.address + symbol.size.max(1) {
+            Some(symbol)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the register and offset the canonical frame address is computed from while the
+    /// program counter is at `curr_addr`.
+    pub fn get_cfa_rule(&self, curr_addr: usize) -> Option<(u16, i64)> {
+        self.call_frame_info.cfa_rule(curr_addr)
+    }
+
+    pub fn get_unwind_rule(&self, curr_addr: usize) -> Option<gimli_wrapper::UnwindRule> {
+        self.call_frame_info.unwind_rule(curr_addr)
+    }
+
+    /// Looks up a variable visible at `curr_addr`: a local or parameter of the function
+    /// containing it, then a global from the same file, then a global from any file. Locals come
+    /// with their function's frame base.
+    pub fn get_variable(&self, curr_addr: usize, name: &str) -> Option<(&Variable, Option<&Location>)> {
+        if let Some((unit, func)) = self.function_at(curr_addr) {
+            let func = self.function(unit, func);
+            if let Some(var) = func.variables.iter().find(|var| var.name == name) {
+                return Some((var, func.frame_base.as_ref()));
+            }
+            let file = self.file(unit)?;
+            if let Some(var) = file.global_variables.iter().find(|var| var.name == name) {
+                return Some((var, None));
+            }
+        }
+        self.get_global_variable(name).map(|var| (var, None))
+    }
+
+    /// Looks up a global variable by name, in the first file that has one.
+    pub fn get_global_variable(&self, name: &str) -> Option<&Variable> {
+        self.units_named(name).iter().find_map(|unit| {
+            self.file(*unit)?
+                .global_variables
                 .iter()
-                .find(|line| line.number >= line_number)?
-                .address,
-        )
+                .find(|var| var.name == name)
+        })
     }
 
-    #[allow(dead_code)]
-    pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
+    /// Looks up a type by key, parsing the unit it is in if need be.
+    pub fn get_type(&self, key: usize) -> Option<&Type> {
+        let unit = match key / gimli_wrapper::SPLIT_UNIT_TYPE_BASE {
+            0 => self
+                .units
+                .partition_point(|unit| unit.root.offset <= key)
+                .checked_sub(1)?,
+            split_unit => split_unit - 1,
+        };
+        if unit >= self.units.len() {
+            return None;
+        }
+        self.contents(unit).types.get(&key)
+    }
+
+    /// Looks through typedefs and qualifiers to the type underneath.
+    pub fn strip_aliases<'a>(&'a self, mut dtype: &'a Type) -> &'a Type {
+        for _ in 0..16 {
+            match dtype.kind {
+                TypeKind::Alias(Some(key)) => match self.get_type(key) {
+                    Some(target) => dtype = target,
+                    None => break,
+                },
+                _ => break,
+            }
+        }
+        dtype
+    }
+
+    /// Size of a value of this type. Typedefs, qualifiers and arrays don't carry their own size.
+    pub fn type_size(&self, dtype: &Type) -> usize {
+        let dtype = self.strip_aliases(dtype);
+        match dtype.kind {
+            TypeKind::Array {
+                element,
+                count: Some(count),
+            } if dtype.size == 0 => self
+                .get_type(element)
+                .map_or(0, |element| self.type_size(element) * count),
+            TypeKind::Pointer(_) if dtype.size == 0 => std::mem::size_of::<usize>(),
+            _ => dtype.size,
+        }
+    }
+
+    /// The files a line number may be in: those with the given name (Rust crates are split
+    /// into several units named after the crate root), or else the first one. A name that is
+    /// neither a full name nor a base name, like `src/main.c`, matches the files whose names end
+    /// in it after a `/`.
+    fn target_files(&self, file: Option<&str>) -> Cow<'_, [usize]> {
         match file {
-            Some(filename) => Some(
-                self.get_target_file(filename)?
-                    .functions
+            Some(filename) => match self.file_index.get(filename) {
+                Some(files) => Cow::Borrowed(files),
+                None => Cow::Owned(
+                    self.units
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, unit)| {
+                            unit.root
+                                .name
+                                .as_ref()
+                                .map_or(false, |name| is_path_suffix(name, filename))
+                        })
+                        .map(|(index, _)| index)
+                        .collect(),
+                ),
+            },
+            None => Cow::Owned(
+                self.units
                     .iter()
-                    .find(|func| func.name == func_name)?
-                    .address,
+                    .position(|unit| unit.root.name.is_some())
+                    .into_iter()
+                    .collect(),
             ),
-            None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
-                    }
-                }
-                None
+        }
+    }
+
+    /// The (unit, function) indices of the function whose code contains `curr_addr`.
+    fn function_at(&self, curr_addr: usize) -> Option<(usize, usize)> {
+        let index = self
+            .unit_ranges
+            .partition_point(|(start, _, _)| *start <= curr_addr)
+            .checked_sub(1)?;
+        let (_, end, unit) = self.unit_ranges[index];
+        if curr_addr >= end {
+            return None;
+        }
+        let function_ranges = &self.contents(unit).function_ranges;
+        let index = function_ranges
+            .partition_point(|(start, _, _)| *start <= curr_addr)
+            .checked_sub(1)?;
+        let (_, end, func) = function_ranges[index];
+        if curr_addr < end {
+            Some((unit, func))
+        } else {
+            None
+        }
+    }
+
+    /// A unit's line table, decoding it if this is the first time it's needed.
+    fn line_table(&self, unit: usize) -> Option<&gimli_wrapper::LineTable> {
+        let unit = self.units.get(unit)?;
+        Some(unit.line_table.get_or_init(|| {
+            match (&unit.root.line_program, &unit.root.name) {
+                (Some(program), Some(name)) => self.sections.decode(program, name).ok(),
+                _ => None,
+            }
+            .unwrap_or_default()
+        }))
+    }
+
+    /// The closest line at or after `line_number` that has code, in any of the files `file` may
+    /// refer to, as (file, row) indices.
+    fn find_line(&self, file: Option<&str>, line_number: usize) -> Option<(usize, usize)> {
+        self.target_files(file)
+            .iter()
+            .filter_map(|file| {
+                let table = self.line_table(*file)?;
+                // The line table is in address order, which needn't be line order (think of an
+                // optimized function placed after main), so take the closest line rather than
+                // the first row that comes after it
+                table
+                    .own_rows
+                    .iter()
+                    .map(|row| (table.rows[*row].number, table.rows[*row].address, *file, *row))
+                    .filter(|(number, _, _, _)| *number >= line_number)
+                    .min()
+            })
+            .min()
+            .map(|(_, _, file, row)| (file, row))
+    }
+
+    /// What of the debug info couldn't be loaded, for the caller to pass on. Loading doesn't fail
+    /// for it, since what's missing can still be debugged by instruction with the ELF symbols.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// How many files there is debug info for, without parsing any.
+    pub fn file_count(&self) -> usize {
+        self.units.iter().filter(|unit| unit.root.name.is_some()).count()
+    }
+
+    /// Every file there is debug info for. This parses every unit.
+    pub fn files(&self) -> impl Iterator<Item = &File> {
+        (0..self.units.len()).filter_map(move |unit| self.file(unit))
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// The functions with an address whose short name is that of `func_name`, as (unit,
+    /// function), in the order they appear in the DWARF.
+    fn function_candidates<'a>(&'a self, func_name: &str) -> impl Iterator<Item = (usize, &'a Function)> + 'a {
+        let key = short_name(func_name);
+        self.units_named(&key).iter().flat_map(move |unit| {
+            let contents = self.contents(*unit);
+            contents
+                .function_index
+                .get(&key)
+                .map_or(&[][..], |candidates| candidates.as_slice())
+                .iter()
+                .map(move |func| (*unit, self.function(*unit, *func)))
+        })
+    }
+
+    /// The functions that `func_name` may refer to, in the order they appear in the DWARF.
+    fn functions_named<'a>(&'a self, func_name: &str) -> impl Iterator<Item = (usize, &'a Function)> + 'a {
+        let func_name = func_name.to_string();
+        self.function_candidates(&func_name)
+            .filter(move |(_, func)| func.matches(&func_name))
+    }
+
+    pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
+        let (file, row) = self.find_line(file, line_number)?;
+        Some(self.line_table(file)?.rows[row].address)
+    }
+
+    pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
+        match file {
+            Some(filename) => {
+                let target_files = self.target_files(Some(filename));
+                self.functions_named(func_name)
+                    .find(|(unit, _)| target_files.contains(unit))
+                    .map(|(_, func)| func.address)
             }
+            None => self
+                .get_function(func_name)
+                .map(|func| func.address)
+                .or_else(|| {
+                    self.symbol_index
+                        .get(&short_name(func_name))?
+                        .iter()
+                        .map(|index| &self.symbols[*index])
+                        .find(|symbol| demangle::matches(&symbol.name, func_name))
+                        .map(|symbol| symbol.address)
+                }),
         }
     }
 
-    #[allow(dead_code)]
+    /// Finds the definition of a function by name, for its debug info rather than just its
+    /// address.
+    pub fn get_function(&self, func_name: &str) -> Option<&Function> {
+        // Checking every candidate's demangled name is slow when many share a short name (think
+        // of `fmt` or `new` in Rust), so try their plain names first
+        self.function_candidates(func_name)
+            .map(|(_, func)| func)
+            .find(|func| func.name == func_name)
+            .or_else(|| self.functions_named(func_name).next().map(|(_, func)| func))
+    }
+
+    /// The function whose code contains `curr_addr`.
+    pub fn get_function_containing(&self, curr_addr: usize) -> Option<&Function> {
+        let (unit, func) = self.function_at(curr_addr)?;
+        Some(self.function(unit, func))
+    }
+
+    /// Finds a named type, e.g. for `ptype struct node`.
+    pub fn get_type_by_name(&self, name: &str) -> Option<&Type> {
+        // The same type is often described in many units, sometimes only declared. Prefer a
+        // definition, then the lowest key, so that lookups don't depend on hash order.
+        self.units_named(name)
+            .iter()
+            .flat_map(|unit| self.contents(*unit).types.iter())
+            .filter(|(_, dtype)| dtype.name == name)
+            .min_by_key(|(key, dtype)| (dtype.size == 0, **key))
+            .map(|(_, dtype)| dtype)
+    }
+
+    /// The addresses a line's code occupies: from the first row of the line table for it (or
+    /// the closest line after it with code) to the next row. Returns the line that was found,
+    /// which may not be the one asked for.
+    pub fn get_line_range(&self, file: Option<&str>, line_number: usize) -> Option<(Line, usize, usize)> {
+        let (file, row) = self.find_line(file, line_number)?;
+        let rows = &self.line_table(file)?.rows;
+        let start = &rows[row];
+        let end = rows[row..]
+            .iter()
+            .map(|line| line.address)
+            .find(|addr| *addr > start.address)
+            .unwrap_or(start.address);
+        Some((start.clone(), start.address, end))
+    }
+
+    /// The row of the line table covering `curr_addr`, if it's in a function we have debug
+    /// info for.
+    fn row_at(&self, curr_addr: usize) -> Option<&Line> {
+        let (file, _) = self.function_at(curr_addr)?;
+        let rows = &self.line_table(file)?.rows;
+        let index = rows
+            .partition_point(|line| line.address <= curr_addr)
+            .checked_sub(1)?;
+        Some(&rows[index])
+    }
+
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
-        let location = self
-            .addr2line
-            .find_location(curr_addr.try_into().unwrap())
-            .ok()??;
+        let line = self.row_at(curr_addr)?;
+        if line.number == 0 {
+            return None;
+        }
         Some(Line {
-            file: location.file?.to_string(),
-            number: location.line?.try_into().unwrap(),
+            file: line.file.clone(),
+            number: line.number,
             address: curr_addr,
         })
     }
 
-    #[allow(dead_code)]
+    /// Whether an address is the first instruction of a row in the line table. Stepping by line
+    /// stops at these, rather than part way through a line.
+    pub fn is_line_start(&self, curr_addr: usize) -> bool {
+        self.row_at(curr_addr)
+            .map_or(false, |line| line.address == curr_addr && line.number != 0)
+    }
+
+    /// Where the body of the function starting at `func_addr` begins, past the prologue that
+    /// sets up its frame and stores its arguments. Like gdb, we take the second row of the line
+    /// table within the function.
+    pub fn get_addr_after_prologue(&self, func_addr: usize) -> Option<usize> {
+        let (unit, func) = self.function_at(func_addr)?;
+        let func = self.function(unit, func);
+        if func.address != func_addr {
+            return None;
+        }
+        let rows = &self.line_table(unit)?.rows;
+        let index = rows.partition_point(|line| line.address <= func_addr);
+        rows.get(index)
+            .map(|line| line.address)
+            .filter(|addr| *addr < func_addr + func.text_length)
+    }
+
+    /// The name of the function executing at `curr_addr`. In code inlined from another
+    /// function, that's the inlined function.
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
-            .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
-            .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
-    }
-
-    #[allow(dead_code)]
-    pub fn print(&self) {
-        for file in &self.files {
-            println!("------");
-            println!("{}", file.name);
-            println!("------");
-
-            println!("Global variables:");
-            for var in &file.global_variables {
-                println!(
-                    "  * {} ({}, located at {}, declared at line {})",
-                    var.name, var.entity_type.name, var.location, var.line_number
-                );
-            }
-
-            println!("Functions:");
-            for func in &file.functions {
-                println!(
-                    "  * {} (declared on line {}, located at {:#x}, {} bytes long)",
-                    func.name, func.line_number, func.address, func.text_length
-                );
-                for var in &func.variables {
-                    println!(
-                        "    * Variable: {} ({}, located at {}, declared at line {})",
-                        var.name, var.entity_type.name, var.location, var.line_number
-                    );
-                }
-            }
+        let name = match self.get_function_containing(curr_addr) {
+            Some(func) => match inlined_calls_at(func, curr_addr).first() {
+                Some(call) => return Some(call.display_name()),
+                None => func.linkage_name.as_ref().unwrap_or(&func.name),
+            },
+            None => &self.get_symbol_from_addr(curr_addr)?.name,
+        };
+        Some(demangle::demangle(name))
+    }
+
+    /// How many inlined calls `curr_addr` is in the code of.
+    pub fn inline_depth(&self, curr_addr: usize) -> usize {
+        self.get_function_containing(curr_addr)
+            .map_or(0, |func| inlined_calls_at(func, curr_addr).len())
+    }
 
-            println!("Line numbers:");
-            for line in &file.lines {
-                println!("  * {} (at {:#x})", line.number, line.address);
+    /// The functions executing at `curr_addr`, innermost first: the functions inlined there,
+    /// then the function their code was inlined into. Each comes with the line it is at, which
+    /// for the callers of inlined functions is the line of the call.
+    pub fn get_frames_at(&self, curr_addr: usize) -> Vec<(String, Option<Line>)> {
+        let (unit, func) = match self.function_at(curr_addr) {
+            Some((unit, func)) => (unit, self.function(unit, func)),
+            None => {
+                return self
+                    .get_symbol_from_addr(curr_addr)
+                    .map(|symbol| (demangle::demangle(&symbol.name), None))
+                    .into_iter()
+                    .collect();
             }
+        };
+        let mut frames = Vec::new();
+        let mut line = self.get_line_from_addr(curr_addr);
+        for call in inlined_calls_at(func, curr_addr) {
+            frames.push((call.display_name(), line));
+            line = self
+                .line_table(unit)
+                .and_then(|table| table.files.get(&call.call_file))
+                .map(|path| Line {
+                    file: path.clone(),
+                    number: call.call_line,
+                    address: curr_addr,
+                });
         }
+        let name = func.linkage_name.as_ref().unwrap_or(&func.name);
+        frames.push((demangle::demangle(name), line));
+        frames
+    }
+
+    /// Where each inlined copy of a function starts, for setting breakpoints on it.
+    pub fn get_inlined_addrs(&self, func_name: &str) -> Vec<usize> {
+        let key = short_name(func_name);
+        let mut addrs: Vec<usize> = self
+            .units_named(&key)
+            .iter()
+            .flat_map(|unit| {
+                self.contents(*unit)
+                    .inline_index
+                    .get(&key)
+                    .map_or(&[][..], |calls| calls.as_slice())
+                    .iter()
+                    .map(move |(func, call)| &self.function(*unit, *func).inlined_calls[*call])
+            })
+            .filter(|call| call.matches(func_name))
+            .map(|call| call.entry_pc)
+            .collect();
+        addrs.sort();
+        addrs.dedup();
+        addrs
     }
 }
 
@@ -152,28 +725,94 @@ impl DwarfData {
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
 }
 
-impl Type {
-    pub fn new(name: String, size: usize) -> Self {
-        Type {
-            name: name,
-            size: size,
-        }
+/// What a type is made of. Other types are referred to by key, see DwarfData::get_type.
+#[derive(Debug, Clone)]
+pub enum TypeKind {
+    /// An integer, character, boolean or floating point type, with its DW_ATE_* encoding
+    Base(Option<gimli::DwAte>),
+    /// Pointers and references. None for `void *`.
+    Pointer(Option<usize>),
+    /// Structures, classes and unions. Rust enums are structures with a variant part.
+    Struct {
+        members: Vec<Member>,
+        /// Generic parameters, e.g. ("T", key of i32) for a Vec<i32>
+        template_params: Vec<(String, usize)>,
+        variant_part: Option<VariantPart>,
+    },
+    /// A C-style enumeration: the names and values of its enumerators
+    Enumeration(Vec<(String, i64)>),
+    Array {
+        element: usize,
+        count: Option<usize>,
+    },
+    /// Typedefs and qualifiers (const, volatile, ...). None for `void`.
+    Alias(Option<usize>),
+    /// Function types and anything else we don't know how to look inside
+    Opaque,
+}
+
+impl Default for TypeKind {
+    fn default() -> Self {
+        TypeKind::Opaque
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub type_key: usize,
+    /// Offset of the member from the start of the struct, in bytes
+    pub offset: usize,
+}
+
+/// The variants of a Rust enum, and where to find the discriminant that picks one.
+#[derive(Debug, Clone)]
+pub struct VariantPart {
+    /// None if there is only one variant
+    pub discriminant: Option<Member>,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Variant {
+    /// The discriminant value selecting this variant. None for the variant used when no other
+    /// one matches, e.g. `Some` in an Option<&T>, where any non-null pointer means `Some`.
+    pub discr_value: Option<u64>,
+    /// The variant's fields, as a member whose type is a struct named after the variant
+    pub member: Member,
+}
+
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
     FramePointerOffset(isize),
+    /// Anything that isn't a plain address or frame base offset, e.g. a register or a value
+    /// assembled from several pieces. Evaluated with dwarf_expr once the inferior is stopped.
+    Expression(Expression),
+    /// Where the variable lives depends on the program counter, as is common in optimized code.
+    /// Outside every range, the variable is optimized out.
+    List(Vec<LocationListEntry>),
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expr) => write!(f, "Expression({})", expr),
+            Location::List(entries) => {
+                write!(f, "List(")?;
+                for (i, entry) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "[{:#x}, {:#x}): {}", entry.begin, entry.end, entry.expression)?;
+                }
+                write!(f, ")")
+            }
         }
     }
 }
@@ -184,6 +823,32 @@ impl fmt::Debug for Location {
     }
 }
 
+/// A DWARF expression, copied out of the debug info so it can be evaluated later.
+#[derive(Clone)]
+pub struct Expression {
+    pub bytecode: Vec<u8>,
+    pub encoding: gimli::Encoding,
+    pub endian: gimli::RunTimeEndian,
+    /// Values of the DW_OP_addrx/DW_OP_constx operands, looked up in .debug_addr at load time
+    /// since the unit's address table isn't around any more when we evaluate
+    pub indexed_addresses: Vec<(usize, u64)>,
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", gimli_wrapper::format_expression(self))
+    }
+}
+
+/// One entry of a location list: the location of a variable while the program counter is in
+/// [begin, end).
+#[derive(Clone)]
+pub struct LocationListEntry {
+    pub begin: usize,
+    pub end: usize,
+    pub expression: Expression,
+}
+
 // For variables and formal parameters
 #[derive(Debug, Clone)]
 pub struct Variable {
@@ -200,6 +865,101 @@ pub struct Function {
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    /// Mangled name, for Rust and C++ functions
+    pub linkage_name: Option<String>,
+    /// DW_AT_frame_base, which DW_OP_fbreg offsets are relative to
+    pub frame_base: Option<Location>,
+    /// None for functions returning void
+    pub return_type: Option<Type>,
+    /// Calls to other functions that the compiler inlined into this one
+    pub inlined_calls: Vec<InlinedCall>,
+}
+
+/// A call the compiler replaced with a copy of the function's code. Inlined calls can be nested,
+/// when the inlined function had calls of its own inlined into it.
+#[derive(Debug, Default, Clone)]
+pub struct InlinedCall {
+    /// The function that was inlined
+    pub name: String,
+    pub linkage_name: Option<String>,
+    /// Where its copy of the code is: usually one range, but the optimizer may scatter it
+    pub ranges: Vec<(usize, usize)>,
+    /// The first instruction of the copy, which isn't necessarily the lowest address
+    pub entry_pc: usize,
+    /// Where the call was: a file number from the unit's line table, and a line
+    pub call_file: u64,
+    pub call_line: usize,
+    /// How many other inlined calls this one is nested in
+    pub depth: usize,
+}
+
+impl InlinedCall {
+    pub fn contains(&self, curr_addr: usize) -> bool {
+        self.ranges
+            .iter()
+            .any(|(start, end)| *start <= curr_addr && curr_addr < *end)
+    }
+
+    /// Like Function::matches
+    pub fn matches(&self, name: &str) -> bool {
+        self.name == name
+            || self
+                .linkage_name
+                .as_ref()
+                .map_or(false, |linkage_name| demangle::matches(linkage_name, name))
+    }
+
+    fn display_name(&self) -> String {
+        demangle::demangle(self.linkage_name.as_ref().unwrap_or(&self.name))
+    }
+}
+
+impl Function {
+    /// Whether `name` refers to this function, either by its plain name or by (a suffix of) its
+    /// demangled path, like `module::func` for `mycrate::module::func`.
+    pub fn matches(&self, name: &str) -> bool {
+        self.name == name
+            || self
+                .linkage_name
+                .as_ref()
+                .map_or(false, |linkage_name| demangle::matches(linkage_name, name))
+    }
+}
+
+/// The inlined calls in `func` whose code contains `curr_addr`, innermost first.
+fn inlined_calls_at(func: &Function, curr_addr: usize) -> Vec<&InlinedCall> {
+    let mut calls: Vec<&InlinedCall> = func
+        .inlined_calls
+        .iter()
+        .filter(|call| call.contains(curr_addr))
+        .collect();
+    calls.sort_by_key(|call| std::cmp::Reverse(call.depth));
+    calls
+}
+
+/// The last component of a path like `mycrate::module::func`, with any C++ parameter list and
+/// generic arguments left off. Function lookups go by this, since it's the part of the name
+/// every way of referring to the function has in common.
+fn short_name(name: &str) -> String {
+    let name = name.split('(').next().unwrap_or(name);
+    // Generic arguments can be paths too, as in `new<alloc::string::String>`
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in name.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' if depth > 0 => depth -= 1,
+            ':' if depth == 0 && name[i + 1..].starts_with(':') => start = i + 2,
+            _ => {}
+        }
+    }
+    let last = &name[start..];
+    last.split('<').next().unwrap_or(last).to_string()
+}
+
+/// Whether `suffix` is the end of the path `name`, starting at a directory boundary.
+fn is_path_suffix(name: &str, suffix: &str) -> bool {
+    name.strip_suffix(suffix).map_or(false, |rest| rest.ends_with('/'))
 }
 
 #[derive(Debug, Default, Clone)]
@@ -207,7 +967,14 @@ pub struct File {
     pub name: String,
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
-    pub lines: Vec<Line>,
+}
+
+/// A function symbol from the ELF symbol table
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: usize,
+    pub size: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -223,4 +990,42 @@ impl fmt::Display for Line {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        let names = ["/home/me/project/src/main.c", "/home/me/project/lib/main.c", "/usr/include/stdio.h"];
+        let mut data = DwarfData::with_units(
+            names
+                .iter()
+                .map(|name| Unit {
+                    root: gimli_wrapper::UnitRoot {
+                        name: Some(name.to_string()),
+                        ..Default::default()
+                    },
+                    contents: OnceCell::new(),
+                    line_table: OnceCell::new(),
+                })
+                .collect(),
+        );
+        data.build_indexes();
+        assert_eq!(&*data.target_files(Some("/usr/include/stdio.h")), &[2]);
+        assert_eq!(&*data.target_files(Some("main.c")), &[0, 1]);
+        assert_eq!(&*data.target_files(Some("src/main.c")), &[0]);
+        assert_eq!(&*data.target_files(Some("project/lib/main.c")), &[1]);
+        // Only whole directory names count
+        assert!(data.target_files(Some("rc/main.c")).is_empty());
+        assert!(data.target_files(Some("ain.c")).is_empty());
+        assert_eq!(&*data.target_files(None), &[0]);
+    }
 
+    #[test]
+    fn short_names() {
+        assert_eq!(short_name("mycrate::module::func"), "func");
+        assert_eq!(short_name("Stack::push(int)"), "push");
+        assert_eq!(short_name("new<alloc::string::String>"), "new");
+        assert_eq!(short_name("std::vector<std::pair<int, int>>::push_back"), "push_back");
+    }
+}
@@ -0,0 +1,362 @@
+//! Evaluates DWARF location descriptions (DW_AT_location, DW_AT_frame_base) against a stopped
+//! inferior. gimli does the actual stack machine work; whenever it needs something from the
+//! outside world (a register, memory, the frame base, the CFA) it stops and asks, and we answer
+//! through an EvalContext.
+
+use crate::dwarf_data::{Expression, Location};
+use std::convert::TryInto;
+use std::fmt;
+
+/// What an expression can ask about the frame it is evaluated in.
+pub trait EvalContext {
+    /// Value of a register, by DWARF register number
+    fn register(&self, register: u16) -> Option<u64>;
+    fn read_memory(&self, addr: u64, size: usize) -> Option<Vec<u8>>;
+    /// The canonical frame address, i.e. the stack pointer in the caller before the call
+    fn call_frame_cfa(&self) -> Option<u64>;
+    /// How far the module the expression came from was loaded from its link-time address
+    fn bias(&self) -> u64;
+}
+
+#[derive(Debug)]
+pub enum EvalError {
+    /// The variable doesn't exist at this point in the program, e.g. it is outside every range
+    /// of its location list
+    OptimizedOut,
+    /// We couldn't read a register or some memory the expression needs
+    Unavailable(&'static str),
+    Unsupported(String),
+    Dwarf(gimli::Error),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::OptimizedOut => write!(f, "<optimized out>"),
+            EvalError::Unavailable(what) => write!(f, "<unavailable: could not read {}>", what),
+            EvalError::Unsupported(what) => write!(f, "<unsupported: {}>", what),
+            EvalError::Dwarf(err) => write!(f, "<invalid DWARF expression: {}>", err),
+        }
+    }
+}
+
+impl From<gimli::Error> for EvalError {
+    fn from(err: gimli::Error) -> Self {
+        EvalError::Dwarf(err)
+    }
+}
+
+/// Where (part of) a variable lives.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PieceLocation {
+    Memory(u64),
+    Register(u16),
+    /// The variable has no storage, but we know its value (DW_OP_stack_value)
+    Value(u64),
+    /// Likewise, with the value spelled out in the expression (DW_OP_implicit_value)
+    Bytes(Vec<u8>),
+    /// This part of the variable was optimized away
+    Empty,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Piece {
+    pub location: PieceLocation,
+    /// None when the variable is a single piece that covers the whole object
+    pub size_in_bits: Option<u64>,
+}
+
+/// Works out where a variable lives while the program counter is at `pc`, a link-time address
+/// in the variable's module. `frame_base` is the DW_AT_frame_base of the enclosing function.
+pub fn evaluate_location(
+    location: &Location,
+    pc: usize,
+    frame_base: Option<&Location>,
+    ctx: &dyn EvalContext,
+) -> Result<Vec<Piece>, EvalError> {
+    match location {
+        Location::Address(addr) => Ok(vec![memory_piece(*addr as u64 + ctx.bias())]),
+        Location::FramePointerOffset(offset) => {
+            let base = frame_base_address(frame_base, pc, ctx)?;
+            Ok(vec![memory_piece(base.wrapping_add(*offset as u64))])
+        }
+        Location::Expression(expr) => evaluate(expr, pc, frame_base, ctx),
+        Location::List(entries) => {
+            let entry = entries
+                .iter()
+                .find(|entry| entry.begin <= pc && pc < entry.end)
+                .ok_or(EvalError::OptimizedOut)?;
+            evaluate(&entry.expression, pc, frame_base, ctx)
+        }
+    }
+}
+
+fn memory_piece(addr: u64) -> Piece {
+    Piece {
+        location: PieceLocation::Memory(addr),
+        size_in_bits: None,
+    }
+}
+
+/// Evaluates a function's DW_AT_frame_base. It usually comes out as a memory location (e.g.
+/// DW_OP_call_frame_cfa), whose address is the frame base, but older compilers name a register
+/// holding it instead (DW_OP_reg6).
+fn frame_base_address(
+    frame_base: Option<&Location>,
+    pc: usize,
+    ctx: &dyn EvalContext,
+) -> Result<u64, EvalError> {
+    let frame_base = frame_base
+        .ok_or_else(|| EvalError::Unsupported("DW_OP_fbreg without a frame base".to_string()))?;
+    let pieces = evaluate_location(frame_base, pc, None, ctx)?;
+    match pieces.first().map(|piece| &piece.location) {
+        Some(PieceLocation::Memory(addr)) => Ok(*addr),
+        Some(PieceLocation::Value(value)) => Ok(*value),
+        Some(PieceLocation::Register(register)) => {
+            ctx.register(*register).ok_or(EvalError::Unavailable("register"))
+        }
+        _ => Err(EvalError::Unsupported("frame base location".to_string())),
+    }
+}
+
+fn evaluate(
+    expr: &Expression,
+    pc: usize,
+    frame_base: Option<&Location>,
+    ctx: &dyn EvalContext,
+) -> Result<Vec<Piece>, EvalError> {
+    let bytecode = gimli::EndianSlice::new(&expr.bytecode, expr.endian);
+    let mut eval = gimli::Evaluation::new(bytecode, expr.encoding);
+    let mut result = eval.evaluate()?;
+    loop {
+        result = match result {
+            gimli::EvaluationResult::Complete => break,
+            gimli::EvaluationResult::RequiresMemory { address, size, .. } => {
+                let bytes = ctx
+                    .read_memory(address, size as usize)
+                    .ok_or(EvalError::Unavailable("memory"))?;
+                eval.resume_with_memory(gimli::Value::Generic(to_u64(&bytes, expr.endian)))?
+            }
+            gimli::EvaluationResult::RequiresRegister { register, .. } => {
+                let value = ctx
+                    .register(register.0)
+                    .ok_or(EvalError::Unavailable("register"))?;
+                eval.resume_with_register(gimli::Value::Generic(value))?
+            }
+            gimli::EvaluationResult::RequiresFrameBase => {
+                eval.resume_with_frame_base(frame_base_address(frame_base, pc, ctx)?)?
+            }
+            gimli::EvaluationResult::RequiresCallFrameCfa => {
+                let cfa = ctx.call_frame_cfa().ok_or(EvalError::Unavailable("CFA"))?;
+                eval.resume_with_call_frame_cfa(cfa)?
+            }
+            gimli::EvaluationResult::RequiresRelocatedAddress(address) => {
+                eval.resume_with_relocated_address(address + ctx.bias())?
+            }
+            gimli::EvaluationResult::RequiresIndexedAddress { index, relocate } => {
+                let address = expr
+                    .indexed_addresses
+                    .iter()
+                    .find(|(i, _)| *i == index.0)
+                    .map(|(_, address)| *address)
+                    .ok_or(EvalError::Unavailable(".debug_addr entry"))?;
+                let bias = if relocate { ctx.bias() } else { 0 };
+                eval.resume_with_indexed_address(address + bias)?
+            }
+            gimli::EvaluationResult::RequiresBaseType(_) => {
+                eval.resume_with_base_type(gimli::ValueType::Generic)?
+            }
+            // The value a register had on entry to the function. Once the function has moved
+            // on, we have no way of getting it back.
+            gimli::EvaluationResult::RequiresEntryValue(_) => return Err(EvalError::OptimizedOut),
+            gimli::EvaluationResult::RequiresTls(_) => {
+                return Err(EvalError::Unsupported("thread-local storage".to_string()))
+            }
+            gimli::EvaluationResult::RequiresAtLocation(_)
+            | gimli::EvaluationResult::RequiresParameterRef(_) => {
+                return Err(EvalError::Unsupported("DIE references".to_string()))
+            }
+        };
+    }
+
+    eval.result()
+        .into_iter()
+        .map(|piece| {
+            let location = match piece.location {
+                gimli::Location::Empty => PieceLocation::Empty,
+                gimli::Location::Register { register } => PieceLocation::Register(register.0),
+                gimli::Location::Address { address } => PieceLocation::Memory(address),
+                gimli::Location::Value { value } => PieceLocation::Value(value.to_u64(!0)?),
+                gimli::Location::Bytes { value } => PieceLocation::Bytes(value.slice().to_vec()),
+                gimli::Location::ImplicitPointer { .. } => {
+                    return Err(EvalError::Unsupported("implicit pointers".to_string()))
+                }
+            };
+            if piece.bit_offset.unwrap_or(0) != 0 {
+                return Err(EvalError::Unsupported("bit pieces".to_string()));
+            }
+            Ok(Piece {
+                location,
+                size_in_bits: piece.size_in_bits,
+            })
+        })
+        .collect()
+}
+
+/// Reads the `size` bytes of a variable, gathering them from each of its pieces.
+pub fn read_pieces(
+    pieces: &[Piece],
+    size: usize,
+    ctx: &dyn EvalContext,
+) -> Result<Vec<u8>, EvalError> {
+    let mut bytes = Vec::new();
+    for piece in pieces {
+        let piece_size = match piece.size_in_bits {
+            Some(bits) if bits % 8 != 0 => {
+                return Err(EvalError::Unsupported("bit pieces".to_string()))
+            }
+            Some(bits) => (bits / 8) as usize,
+            None => size,
+        };
+        let mut piece_bytes = match &piece.location {
+            PieceLocation::Memory(addr) => ctx
+                .read_memory(*addr, piece_size)
+                .ok_or(EvalError::Unavailable("memory"))?,
+            PieceLocation::Register(register) => ctx
+                .register(*register)
+                .ok_or(EvalError::Unavailable("register"))?
+                .to_le_bytes()
+                .to_vec(),
+            PieceLocation::Value(value) => value.to_le_bytes().to_vec(),
+            PieceLocation::Bytes(value) => value.clone(),
+            PieceLocation::Empty => return Err(EvalError::OptimizedOut),
+        };
+        piece_bytes.resize(piece_size, 0);
+        bytes.extend(piece_bytes);
+    }
+    bytes.truncate(size);
+    Ok(bytes)
+}
+
+/// Interprets up to 8 bytes as an integer.
+fn to_u64(bytes: &[u8], endian: gimli::RunTimeEndian) -> u64 {
+    let mut buf = [0u8; 8];
+    match endian {
+        gimli::RunTimeEndian::Little => {
+            buf[..bytes.len().min(8)].copy_from_slice(&bytes[..bytes.len().min(8)]);
+            u64::from_le_bytes(buf.try_into().unwrap())
+        }
+        gimli::RunTimeEndian::Big => {
+            let len = bytes.len().min(8);
+            buf[8 - len..].copy_from_slice(&bytes[..len]);
+            u64::from_be_bytes(buf)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A frame with rax = 42, rbp = 0x7000 and the CFA at 0x7010. Each byte of memory holds the
+    /// low byte of its own address.
+    struct TestFrame;
+
+    impl EvalContext for TestFrame {
+        fn register(&self, register: u16) -> Option<u64> {
+            match register {
+                0 => Some(42),
+                6 => Some(0x7000),
+                _ => None,
+            }
+        }
+
+        fn read_memory(&self, addr: u64, size: usize) -> Option<Vec<u8>> {
+            Some((0..size as u64).map(|i| (addr + i) as u8).collect())
+        }
+
+        fn call_frame_cfa(&self) -> Option<u64> {
+            Some(0x7010)
+        }
+
+        fn bias(&self) -> u64 {
+            0x1000
+        }
+    }
+
+    fn expression(bytecode: &[u8]) -> Location {
+        Location::Expression(Expression {
+            bytecode: bytecode.to_vec(),
+            encoding: gimli::Encoding {
+                format: gimli::Format::Dwarf32,
+                version: 4,
+                address_size: 8,
+            },
+            endian: gimli::RunTimeEndian::Little,
+            indexed_addresses: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn test_frame_base_offset() {
+        // DW_OP_call_frame_cfa
+        let frame_base = expression(&[0x9c]);
+        let pieces = evaluate_location(
+            &Location::FramePointerOffset(-20),
+            0,
+            Some(&frame_base),
+            &TestFrame,
+        )
+        .unwrap();
+        assert_eq!(pieces, vec![memory_piece(0x7010 - 20)]);
+    }
+
+    #[test]
+    fn test_register_and_breg() {
+        // DW_OP_reg0
+        let pieces = evaluate_location(&expression(&[0x50]), 0, None, &TestFrame).unwrap();
+        assert_eq!(read_pieces(&pieces, 4, &TestFrame).unwrap(), vec![42, 0, 0, 0]);
+        // DW_OP_breg6 -8
+        let pieces = evaluate_location(&expression(&[0x76, 0x78]), 0, None, &TestFrame).unwrap();
+        assert_eq!(pieces, vec![memory_piece(0x7000 - 8)]);
+    }
+
+    #[test]
+    fn test_address_is_relocated() {
+        let pieces = evaluate_location(&Location::Address(0x4010), 0, None, &TestFrame).unwrap();
+        assert_eq!(pieces, vec![memory_piece(0x5010)]);
+    }
+
+    #[test]
+    fn test_pieces() {
+        // DW_OP_reg0 DW_OP_piece 2, DW_OP_lit5 DW_OP_stack_value DW_OP_piece 2
+        let location = expression(&[0x50, 0x93, 0x02, 0x35, 0x9f, 0x93, 0x02]);
+        let pieces = evaluate_location(&location, 0, None, &TestFrame).unwrap();
+        assert_eq!(read_pieces(&pieces, 4, &TestFrame).unwrap(), vec![42, 0, 5, 0]);
+    }
+
+    #[test]
+    fn test_location_list() {
+        let entry = |begin, end, bytecode: &[u8]| crate::dwarf_data::LocationListEntry {
+            begin,
+            end,
+            expression: match expression(bytecode) {
+                Location::Expression(expr) => expr,
+                _ => unreachable!(),
+            },
+        };
+        // DW_OP_reg0 in [0x10, 0x20), DW_OP_lit7 DW_OP_stack_value in [0x20, 0x30)
+        let location = Location::List(vec![
+            entry(0x10, 0x20, &[0x50]),
+            entry(0x20, 0x30, &[0x37, 0x9f]),
+        ]);
+        let pieces = evaluate_location(&location, 0x18, None, &TestFrame).unwrap();
+        assert_eq!(pieces[0].location, PieceLocation::Register(0));
+        let pieces = evaluate_location(&location, 0x20, None, &TestFrame).unwrap();
+        assert_eq!(pieces[0].location, PieceLocation::Value(7));
+        match evaluate_location(&location, 0x30, None, &TestFrame) {
+            Err(EvalError::OptimizedOut) => {}
+            other => panic!("Expected the variable to be optimized out, got {:?}", other),
+        }
+    }
+}
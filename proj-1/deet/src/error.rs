@@ -0,0 +1,104 @@
+//! The error type shared by the whole debugger. Anything that can go wrong while loading a
+//! program or controlling the inferior ends up as one of these, so that callers (the command
+//! loop, the TUI, or another program using deet as a library) decide what to do about it rather
+//! than the debugger exiting or panicking.
+
+use crate::arch;
+use crate::gimli_wrapper;
+use rustyline::error::ReadlineError;
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum Error {
+    /// A file we were asked to load couldn't be opened
+    OpeningFile(String, io::Error),
+    /// A file isn't an object file we understand, or its debug info is malformed
+    DebugInfo(String, gimli_wrapper::Error),
+    /// The target program couldn't be started under ptrace
+    Spawn(String, io::Error),
+    /// The target is a program for another processor (named here) than the one deet runs on
+    WrongArchitecture(String, String),
+    /// A ptrace call, waitpid or another system call failed
+    Ptrace(nix::Error),
+    /// waitpid reported something about the inferior we don't know what to do with
+    UnexpectedStop(String),
+    /// Something needs a running inferior and there isn't one
+    NotRunning,
+    /// A breakpoint location names no line or address we can find
+    NoLocation(String),
+    /// A variable or expression couldn't be evaluated, e.g. because it isn't in scope
+    Evaluation(String),
+    /// A function called from deet didn't return: the program stopped, exited or was killed
+    /// first (says which), and whether its registers were put back as they were before the call
+    CallAbandoned(String, bool),
+    /// Something deet can't do on the architecture it is running on
+    Unsupported(String),
+    /// A pretty-printer file has a bad line, or a plugin couldn't be loaded
+    Printer(String),
+    Readline(ReadlineError),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::OpeningFile(path, err) => write!(f, "Could not open file {}: {}", path, err),
+            Error::DebugInfo(path, err) => {
+                write!(f, "Could not read debugging symbols from {}: {}", path, err)
+            }
+            Error::Spawn(target, err) => write!(f, "Could not start {}: {}", target, err),
+            Error::WrongArchitecture(target, arch) => write!(
+                f,
+                "{} is a program for {}, but deet can only run {} programs here",
+                target,
+                arch,
+                arch::native().name()
+            ),
+            Error::Ptrace(err) => write!(f, "{}", err),
+            Error::UnexpectedStop(status) => write!(f, "Unexpected status from waitpid: {}", status),
+            Error::NotRunning => write!(f, "The program is not being run."),
+            Error::NoLocation(location) => write!(f, "No location \"{}\" found.", location),
+            Error::Evaluation(message) => write!(f, "{}", message),
+            Error::CallAbandoned(what, restored) => {
+                write!(f, "The program being debugged {} while in a function called from deet.", what)?;
+                if *restored {
+                    write!(f, "\nIts state before the call has been restored.")?;
+                }
+                Ok(())
+            }
+            Error::Unsupported(what) => write!(f, "{} is not supported on this architecture.", what),
+            Error::Printer(message) => write!(f, "Could not load pretty-printers: {}", message),
+            Error::Readline(err) => write!(f, "Could not read input: {}", err),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::OpeningFile(_, err) | Error::Spawn(_, err) | Error::Io(err) => Some(err),
+            Error::Ptrace(err) => Some(err),
+            Error::Readline(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(err: nix::Error) -> Self {
+        Error::Ptrace(err)
+    }
+}
+
+impl From<ReadlineError> for Error {
+    fn from(err: ReadlineError) -> Self {
+        Error::Readline(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,175 @@
+//! The expressions `print` and `call` accept: a variable, a literal, or a function call whose
+//! arguments are themselves variables or literals, e.g. `dump_list(head, "after insert", 2)`.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Variable(String),
+    Integer(i64),
+    Float(f64),
+    /// A string literal, without its terminating NUL
+    Str(Vec<u8>),
+    Call(String, Vec<Expr>),
+}
+
+pub fn parse(text: &str) -> Result<Expr, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("Expected an expression".to_string());
+    }
+    if text.ends_with(')') {
+        if let Some(open) = text.find('(') {
+            let name = text[..open].trim();
+            if is_identifier(name) {
+                let args = split_arguments(&text[open + 1..text.len() - 1])?
+                    .iter()
+                    .map(|arg| parse(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                return Ok(Expr::Call(name.to_string(), args));
+            }
+        }
+    }
+    if text.starts_with('"') {
+        return parse_string(text).map(Expr::Str);
+    }
+    if text.starts_with('\'') {
+        return match parse_string(text)?.as_slice() {
+            [c] => Ok(Expr::Integer(*c as i64)),
+            _ => Err(format!("Invalid character constant {}", text)),
+        };
+    }
+    if let Some(value) = parse_integer(text) {
+        return Ok(Expr::Integer(value));
+    }
+    if let Ok(value) = text.parse::<f64>() {
+        return Ok(Expr::Float(value));
+    }
+    if is_identifier(text) {
+        return Ok(Expr::Variable(text.to_string()));
+    }
+    Err(format!("Can't parse expression \"{}\"", text))
+}
+
+/// Function and variable names, including Rust and C++ paths like `mycrate::dump`.
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn parse_integer(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()? as i64,
+        None => digits.parse::<i64>().ok()?,
+    };
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+/// Splits a comma-separated list, such as a call's arguments or those of `dprintf`, on the
+/// commas that aren't inside a string, character constant or nested call.
+pub fn split_arguments(text: &str) -> Result<Vec<String>, String> {
+    if text.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for c in text.chars() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    args.push(current.trim().to_string());
+                    current.clear();
+                    continue;
+                }
+                _ => {}
+            },
+        }
+        current.push(c);
+    }
+    if quote.is_some() || depth != 0 {
+        return Err(format!("Unbalanced quotes or parentheses in \"{}\"", text));
+    }
+    args.push(current.trim().to_string());
+    Ok(args)
+}
+
+/// Decodes a quoted string or character constant, with C escapes.
+fn parse_string(text: &str) -> Result<Vec<u8>, String> {
+    let quote = text.chars().next().unwrap();
+    if text.len() < 2 || !text.ends_with(quote) {
+        return Err(format!("Unterminated string {}", text));
+    }
+    let mut bytes = Vec::new();
+    let mut chars = text[1..text.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        bytes.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('\'') => b'\'',
+            Some('"') => b'"',
+            other => return Err(format!("Unknown escape \\{}", other.unwrap_or(' '))),
+        });
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_calls() {
+        assert_eq!(
+            parse("square(5)"),
+            Ok(Expr::Call("square".to_string(), vec![Expr::Integer(5)]))
+        );
+        assert_eq!(
+            parse("dump(list, \"a, b\", 'x', -0x10, 2.5)"),
+            Ok(Expr::Call(
+                "dump".to_string(),
+                vec![
+                    Expr::Variable("list".to_string()),
+                    Expr::Str(b"a, b".to_vec()),
+                    Expr::Integer('x' as i64),
+                    Expr::Integer(-16),
+                    Expr::Float(2.5),
+                ]
+            ))
+        );
+        assert_eq!(parse("getpid()"), Ok(Expr::Call("getpid".to_string(), vec![])));
+    }
+
+    #[test]
+    fn parses_variables_and_literals() {
+        assert_eq!(parse(" counter "), Ok(Expr::Variable("counter".to_string())));
+        assert_eq!(parse("\"hi\\n\""), Ok(Expr::Str(b"hi\n".to_vec())));
+        assert!(parse("f(\"unterminated)").is_err());
+        assert!(parse("1 + 2").is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Explaining why the inferior crashed: what the si_code of a SIGSEGV, SIGBUS, SIGFPE or SIGILL
+//! means, and where the faulting address lies relative to the inferior's memory map.
+
+use crate::procfs::MapEntry;
+use nix::sys::signal::Signal;
+
+/// si_code values from <asm-generic/siginfo.h>. The ones below 0 and SI_KERNEL apply to every
+/// signal; the rest depend on which signal it is.
+const SI_USER: i32 = 0;
+const SI_KERNEL: i32 = 0x80;
+const SI_QUEUE: i32 = -1;
+const SI_TKILL: i32 = -6;
+
+/// Addresses below this are never mapped (the default vm.mmap_min_addr), so a fault there is
+/// almost always a null pointer with an offset
+const NULL_PAGE_LIMIT: usize = 0x10000;
+
+/// How far below the stack a fault still looks like the stack outgrowing its limit
+const STACK_GUARD_DISTANCE: usize = 1 << 20;
+
+/// The signals that mean the inferior did something wrong, rather than being sent a signal
+pub fn is_fault(signal: Signal) -> bool {
+    match signal {
+        Signal::SIGSEGV | Signal::SIGBUS | Signal::SIGFPE | Signal::SIGILL => true,
+        _ => false,
+    }
+}
+
+/// Whether si_addr is the address of the instruction that faulted, rather than of the memory it
+/// touched.
+pub fn addr_is_instruction(signal: Signal) -> bool {
+    signal == Signal::SIGFPE || signal == Signal::SIGILL
+}
+
+/// Whether si_addr means anything for this si_code. Only the kernel fills it in, and not for a
+/// general protection fault; a signal sent with kill or sigqueue (a code of 0 or below) has
+/// none.
+pub fn has_fault_addr(code: i32) -> bool {
+    code > 0 && code != SI_KERNEL
+}
+
+/// The name of an si_code and what it means, e.g. ("SEGV_MAPERR", "address not mapped to
+/// object").
+pub fn describe_code(signal: Signal, code: i32) -> (&'static str, &'static str) {
+    match (signal, code) {
+        (_, SI_USER) => ("SI_USER", "sent by kill()"),
+        (_, SI_QUEUE) => ("SI_QUEUE", "sent by sigqueue()"),
+        (_, SI_TKILL) => ("SI_TKILL", "sent by tkill()"),
+        // x86-64 reports a general protection fault, e.g. from a non-canonical pointer, without
+        // an address
+        (Signal::SIGSEGV, SI_KERNEL) => ("SI_KERNEL", "general protection fault"),
+        (_, SI_KERNEL) => ("SI_KERNEL", "sent by the kernel"),
+        (Signal::SIGSEGV, 1) => ("SEGV_MAPERR", "address not mapped to object"),
+        (Signal::SIGSEGV, 2) => ("SEGV_ACCERR", "invalid permissions for mapped object"),
+        (Signal::SIGSEGV, 3) => ("SEGV_BNDERR", "failed address bound checks"),
+        (Signal::SIGSEGV, 4) => ("SEGV_PKUERR", "access denied by memory protection keys"),
+        (Signal::SIGBUS, 1) => ("BUS_ADRALN", "invalid address alignment"),
+        (Signal::SIGBUS, 2) => ("BUS_ADRERR", "nonexistent physical address"),
+        (Signal::SIGBUS, 3) => ("BUS_OBJERR", "object-specific hardware error"),
+        (Signal::SIGBUS, 4) => ("BUS_MCEERR_AR", "hardware memory error consumed on a machine check"),
+        (Signal::SIGBUS, 5) => ("BUS_MCEERR_AO", "hardware memory error detected in process but not consumed"),
+        (Signal::SIGFPE, 1) => ("FPE_INTDIV", "integer divide by zero"),
+        (Signal::SIGFPE, 2) => ("FPE_INTOVF", "integer overflow"),
+        (Signal::SIGFPE, 3) => ("FPE_FLTDIV", "floating-point divide by zero"),
+        (Signal::SIGFPE, 4) => ("FPE_FLTOVF", "floating-point overflow"),
+        (Signal::SIGFPE, 5) => ("FPE_FLTUND", "floating-point underflow"),
+        (Signal::SIGFPE, 6) => ("FPE_FLTRES", "floating-point inexact result"),
+        (Signal::SIGFPE, 7) => ("FPE_FLTINV", "floating-point invalid operation"),
+        (Signal::SIGFPE, 8) => ("FPE_FLTSUB", "subscript out of range"),
+        (Signal::SIGILL, 1) => ("ILL_ILLOPC", "illegal opcode"),
+        (Signal::SIGILL, 2) => ("ILL_ILLOPN", "illegal operand"),
+        (Signal::SIGILL, 3) => ("ILL_ILLADR", "illegal addressing mode"),
+        (Signal::SIGILL, 4) => ("ILL_ILLTRP", "illegal trap"),
+        (Signal::SIGILL, 5) => ("ILL_PRVOPC", "privileged opcode"),
+        (Signal::SIGILL, 6) => ("ILL_PRVREG", "privileged register"),
+        (Signal::SIGILL, 7) => ("ILL_COPROC", "coprocessor error"),
+        (Signal::SIGILL, 8) => ("ILL_BADSTK", "internal stack error"),
+        _ => ("unknown", "unknown si_code"),
+    }
+}
+
+/// Says where `addr` lies in the inferior's memory: which mapping it is in, or, for an address
+/// that isn't mapped, which mapping it is nearest to.
+pub fn locate(addr: usize, maps: &[MapEntry]) -> String {
+    if let Some(entry) = maps.iter().find(|entry| entry.contains(addr)) {
+        return format!(
+            "0x{:x} bytes into {} ({})",
+            addr - entry.start,
+            region_name(entry),
+            entry.perms
+        );
+    }
+    if addr < NULL_PAGE_LIMIT {
+        return format!("not mapped: a null pointer dereference (offset 0x{:x} from NULL)", addr);
+    }
+    let below = maps.iter().filter(|entry| entry.end <= addr).max_by_key(|entry| entry.end);
+    let above = maps.iter().filter(|entry| entry.start > addr).min_by_key(|entry| entry.start);
+    if let Some(stack) = above.filter(|entry| entry.path.as_deref() == Some("[stack]")) {
+        if stack.start - addr <= STACK_GUARD_DISTANCE {
+            return format!(
+                "not mapped: 0x{:x} bytes below [stack], probably a stack overflow",
+                stack.start - addr
+            );
+        }
+    }
+    match (below, above) {
+        (Some(below), Some(above)) if addr - below.end <= above.start - addr => format!(
+            "not mapped: 0x{:x} bytes past the end of {}",
+            addr - below.end,
+            region_name(below)
+        ),
+        (_, Some(above)) => format!(
+            "not mapped: 0x{:x} bytes before {}",
+            above.start - addr,
+            region_name(above)
+        ),
+        (Some(below), None) => format!(
+            "not mapped: 0x{:x} bytes past the end of {}",
+            addr - below.end,
+            region_name(below)
+        ),
+        (None, None) => String::from("not mapped"),
+    }
+}
+
+fn region_name(entry: &MapEntry) -> String {
+    match &entry.path {
+        Some(path) => path.clone(),
+        None => format!("an anonymous mapping at 0x{:x}-0x{:x}", entry.start, entry.end),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(start: usize, end: usize, perms: &str, path: Option<&str>) -> MapEntry {
+        MapEntry {
+            start,
+            end,
+            perms: perms.to_string(),
+            offset: 0,
+            path: path.map(|path| path.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_describe_code() {
+        assert_eq!(describe_code(Signal::SIGSEGV, 1).0, "SEGV_MAPERR");
+        assert_eq!(describe_code(Signal::SIGBUS, 1).0, "BUS_ADRALN");
+        assert_eq!(describe_code(Signal::SIGFPE, 1).1, "integer divide by zero");
+        assert_eq!(describe_code(Signal::SIGSEGV, 0x80).1, "general protection fault");
+        assert_eq!(describe_code(Signal::SIGSEGV, 0).0, "SI_USER");
+    }
+
+    #[test]
+    fn test_has_fault_addr() {
+        assert!(has_fault_addr(1));
+        assert!(!has_fault_addr(SI_KERNEL));
+        assert!(!has_fault_addr(SI_USER));
+        assert!(!has_fault_addr(SI_TKILL));
+    }
+
+    #[test]
+    fn test_locate() {
+        let maps = vec![
+            entry(0x400000, 0x401000, "r-xp", Some("/tmp/prog")),
+            entry(0x405000, 0x426000, "rw-p", Some("[heap]")),
+            entry(0x7ffc0000, 0x7ffe0000, "rw-p", Some("[stack]")),
+        ];
+        assert_eq!(locate(0x400010, &maps), "0x10 bytes into /tmp/prog (r-xp)");
+        assert!(locate(0x8, &maps).contains("null pointer"));
+        assert_eq!(locate(0x426008, &maps), "not mapped: 0x8 bytes past the end of [heap]");
+        assert_eq!(locate(0x404ff0, &maps), "not mapped: 0x10 bytes before [heap]");
+        assert!(locate(0x7ffbfff0, &maps).contains("stack overflow"));
+    }
+}
@@ -0,0 +1,313 @@
+//! `info frame`: where a frame of the inferior's stack is and what it holds. That means its
+//! canonical frame address (CFA), the registers it saved for its caller, the return address, and
+//! its locals by their offset from the frame pointer. Optionally the frame is also drawn, one
+//! stack slot per row, from the CFA down to the stack pointer.
+
+use crate::arch::Arch;
+use crate::dwarf_data::Line;
+
+/// What `info frame` found out about a frame.
+pub struct FrameInfo {
+    pub arch: &'static dyn Arch,
+    /// 0 for the innermost frame, counting outwards
+    pub level: usize,
+    pub function: String,
+    pub line: Option<Line>,
+    pub pc: u64,
+    /// The stack pointer's value in the caller, before the call. None without unwind information.
+    pub cfa: Option<u64>,
+    /// The unwind rule's register and offset the CFA is computed from
+    pub cfa_rule: Option<(u16, i64)>,
+    pub fp: u64,
+    pub sp: u64,
+    /// The CFAs of the frames that called this one and that this one called
+    pub caller_cfa: Option<u64>,
+    pub callee_cfa: Option<u64>,
+    /// The stack slot of the return address, and the address
+    pub return_address: Option<(u64, u64)>,
+    /// Registers saved for the caller: their DWARF number, stack slot and the value saved
+    pub saved_registers: Vec<(u16, u64, u64)>,
+    pub locals: Vec<Local>,
+}
+
+/// A local variable or parameter that lives in the frame.
+pub struct Local {
+    pub name: String,
+    pub type_name: String,
+    pub addr: u64,
+    pub size: u64,
+}
+
+impl FrameInfo {
+    fn register_name(&self, register: u16) -> String {
+        match self.arch.register_name(register) {
+            Some(name) => name.to_string(),
+            None => format!("r{}", register),
+        }
+    }
+
+    /// `addr` relative to the frame pointer, e.g. `rbp-20`
+    fn fp_offset(&self, addr: u64) -> String {
+        let fp = self.register_name(self.arch.fp_register());
+        match addr.wrapping_sub(self.fp) as i64 {
+            0 => fp,
+            offset => format!("{}{:+}", fp, offset),
+        }
+    }
+}
+
+pub fn print_frame(info: &FrameInfo) {
+    match info.cfa {
+        Some(cfa) => println!("Stack level {}, frame at 0x{:x}:", info.level, cfa),
+        None => println!("Stack level {}, frame with no unwind information:", info.level),
+    }
+    let location = match &info.line {
+        Some(line) => format!(" ({})", line),
+        None => String::new(),
+    };
+    let saved_pc = match info.return_address {
+        Some((_, addr)) => {
+            format!("; saved {} = 0x{:x}", info.register_name(info.arch.pc_register()), addr)
+        }
+        None => String::new(),
+    };
+    println!(
+        " {} = 0x{:x} in {}{}{}",
+        info.register_name(info.arch.pc_register()),
+        info.pc,
+        info.function,
+        location,
+        saved_pc
+    );
+    if let Some(cfa) = info.caller_cfa {
+        println!(" called by frame at 0x{:x}", cfa);
+    }
+    if let Some(cfa) = info.callee_cfa {
+        println!(" caller of frame at 0x{:x}", cfa);
+    }
+    if let Some((register, offset)) = info.cfa_rule {
+        println!(" CFA = {}{:+}", info.register_name(register), offset);
+    }
+    println!(
+        " {} = 0x{:x}, {} = 0x{:x}",
+        info.register_name(info.arch.fp_register()),
+        info.fp,
+        info.register_name(info.arch.sp_register()),
+        info.sp
+    );
+    let mut saved: Vec<String> = info
+        .saved_registers
+        .iter()
+        .map(|(register, slot, _)| format!("{} at 0x{:x}", info.register_name(*register), slot))
+        .collect();
+    if let Some((slot, _)) = info.return_address {
+        saved.push(format!("{} at 0x{:x}", info.register_name(info.arch.pc_register()), slot));
+    }
+    if !saved.is_empty() {
+        println!(" Saved registers:\n  {}", saved.join(", "));
+    }
+    if !info.locals.is_empty() {
+        println!(" Locals and arguments:");
+        for local in &info.locals {
+            println!(
+                "  {} at 0x{:x}, {} ({}, {} bytes)",
+                local.name,
+                local.addr,
+                info.fp_offset(local.addr),
+                local.type_name,
+                local.size
+            );
+        }
+    }
+}
+
+pub fn print_diagram(info: &FrameInfo) {
+    match info.cfa {
+        Some(_) => {
+            println!();
+            for line in diagram(info) {
+                println!("{}", line);
+            }
+        }
+        None => println!("Without unwind information there is no telling where the frame ends."),
+    }
+}
+
+/// A row of the diagram: a stack slot, or bytes between them
+struct Row {
+    addr: u64,
+    label: String,
+}
+
+/// Draws the frame from the CFA down, higher addresses first, as the stack grows. Each row is a
+/// stack slot, with its address and offset from the frame pointer; the bytes between slots, which
+/// the debug info doesn't account for, get rows of their own. Slots below the stack pointer are
+/// in the red zone, which leaf functions use without moving it.
+fn diagram(info: &FrameInfo) -> Vec<String> {
+    let cfa = match info.cfa {
+        Some(cfa) => cfa,
+        None => return Vec::new(),
+    };
+    let mut slots: Vec<(u64, u64, String)> = Vec::new();
+    if let Some((slot, addr)) = info.return_address {
+        slots.push((slot, 8, format!("return address 0x{:x}", addr)));
+    }
+    for (register, slot, value) in &info.saved_registers {
+        slots.push((*slot, 8, format!("saved {} 0x{:x}", info.register_name(*register), value)));
+    }
+    for local in &info.locals {
+        slots.push((local.addr, local.size.max(1), format!("{} ({})", local.name, local.type_name)));
+    }
+    slots.sort_by(|a, b| b.0.cmp(&a.0));
+
+    let mut rows = Vec::new();
+    let mut next = cfa;
+    for (addr, size, label) in slots {
+        gap_rows(&mut rows, addr + size, next, &[info.fp, info.sp]);
+        rows.push(Row { addr, label });
+        next = next.min(addr);
+    }
+    gap_rows(&mut rows, info.sp, next, &[info.fp]);
+
+    let width = rows.iter().map(|row| row.label.len()).max().unwrap_or(0).max(24);
+    let fp_name = info.register_name(info.arch.fp_register());
+    let sp_name = info.register_name(info.arch.sp_register());
+    let border = |fill: &str| format!("{:>18} {:>8} +{}+", "", "", fill.repeat(width + 2));
+    let mut lines = vec![
+        format!("{:>18} {:>8}", "address", "offset"),
+        border("-"),
+        format!("{:>18} {:>8} | {:<width$} |", "", "", "caller's frame", width = width),
+        format!(
+            "{:>18} {:>8} +{}+ <- CFA",
+            format!("0x{:x}", cfa),
+            info.fp_offset(cfa),
+            "=".repeat(width + 2)
+        ),
+    ];
+    for row in &rows {
+        let mut markers = Vec::new();
+        if row.addr == info.fp {
+            markers.push(fp_name.as_str());
+        }
+        if row.addr == info.sp {
+            markers.push(sp_name.as_str());
+        }
+        let marker = if !markers.is_empty() {
+            format!(" <- {}", markers.join(", "))
+        } else if row.addr < info.sp {
+            String::from(" (red zone)")
+        } else {
+            String::new()
+        };
+        lines.push(format!(
+            "{:>18} {:>8} | {:<width$} |{}",
+            format!("0x{:x}", row.addr),
+            info.fp_offset(row.addr),
+            row.label,
+            marker,
+            width = width
+        ));
+        lines.push(border("-"));
+    }
+    lines
+}
+
+/// Rows for the bytes from `start` up to `end` that no slot covers, split where one of the
+/// `pointers` points so that it gets a row to point at.
+fn gap_rows(rows: &mut Vec<Row>, start: u64, end: u64, pointers: &[u64]) {
+    let mut top = end;
+    let mut splits: Vec<u64> = pointers
+        .iter()
+        .cloned()
+        .filter(|pointer| start < *pointer && *pointer < end)
+        .collect();
+    splits.sort_by(|a, b| b.cmp(a));
+    splits.dedup();
+    for bottom in splits.into_iter().chain(std::iter::once(start)) {
+        if bottom < top {
+            rows.push(Row {
+                addr: bottom,
+                label: format!("({} bytes, no variable)", top - bottom),
+            });
+            top = bottom;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::X86_64;
+
+    /// A frame at -O0 with a frame pointer: rbp saved at CFA-16, an int and a pointer local,
+    /// and 4 bytes of padding down to rsp.
+    fn frame() -> FrameInfo {
+        FrameInfo {
+            arch: &X86_64,
+            level: 1,
+            function: String::from("func2"),
+            line: None,
+            pc: 0x401189,
+            cfa: Some(0x7fffffffe0e0),
+            cfa_rule: Some((6, 16)),
+            fp: 0x7fffffffe0d0,
+            sp: 0x7fffffffe0b0,
+            caller_cfa: Some(0x7fffffffe100),
+            callee_cfa: Some(0x7fffffffe0b0),
+            return_address: Some((0x7fffffffe0d8, 0x4011c4)),
+            saved_registers: vec![(6, 0x7fffffffe0d0, 0x7fffffffe0f0)],
+            locals: vec![
+                Local {
+                    name: String::from("sum"),
+                    type_name: String::from("int"),
+                    addr: 0x7fffffffe0cc,
+                    size: 4,
+                },
+                Local {
+                    name: String::from("p"),
+                    type_name: String::from("char *"),
+                    addr: 0x7fffffffe0b8,
+                    size: 8,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_fp_offset() {
+        let info = frame();
+        assert_eq!(info.fp_offset(0x7fffffffe0cc), "rbp-4");
+        assert_eq!(info.fp_offset(0x7fffffffe0d8), "rbp+8");
+        assert_eq!(info.fp_offset(0x7fffffffe0d0), "rbp");
+    }
+
+    #[test]
+    fn test_diagram() {
+        let lines = diagram(&frame());
+        let rows: Vec<&str> = lines
+            .iter()
+            .filter(|line| line.contains(" | "))
+            .map(|line| line.trim_start())
+            .collect();
+        assert!(rows[0].contains("caller's frame"));
+        assert!(rows[1].starts_with("0x7fffffffe0d8    rbp+8 | return address 0x4011c4"));
+        assert!(rows[2].contains("rbp | saved rbp 0x7fffffffe0f0") && rows[2].ends_with("<- rbp"));
+        assert!(rows[3].contains("rbp-4 | sum (int)"));
+        assert!(rows[4].contains("rbp-16 | (12 bytes, no variable)"));
+        assert!(rows[5].contains("rbp-24 | p (char *)"));
+        assert!(rows[6].contains("rbp-32 | (8 bytes, no variable)") && rows[6].ends_with("<- rsp"));
+        assert_eq!(rows.len(), 7);
+        assert!(lines[3].ends_with("<- CFA"));
+    }
+
+    #[test]
+    fn test_diagram_red_zone() {
+        // A leaf function that never moved rsp: its locals are below it
+        let mut info = frame();
+        info.sp = info.fp;
+        let lines = diagram(&info);
+        let sum = lines.iter().find(|line| line.contains("sum (int)")).unwrap();
+        assert!(sum.ends_with("(red zone)"));
+        assert!(lines.iter().any(|line| line.contains("saved rbp") && line.ends_with("<- rbp, rsp")));
+    }
+}
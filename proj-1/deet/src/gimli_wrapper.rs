@@ -6,241 +6,1211 @@
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
 use gimli;
-use gimli::{UnitOffset, UnitSectionOffset};
-use object::Object;
+use gimli::{ReaderOffset, Section, UnitOffset, UnitSectionOffset};
+use object::{Object, ObjectSection};
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    Expression, File, Function, InlinedCall, Line, Location, LocationListEntry, Member, Type,
+    TypeKind, Variable, Variant, VariantPart,
+};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
-use std::{io, path};
-
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
-    // Load a section and return as `Cow<[u8]>`.
-    let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
-        Ok(object
-            .section_data_by_name(id.name())
-            .unwrap_or(borrow::Cow::Borrowed(&[][..])))
-    };
-    // Load a supplementary section. We don't have a supplementary object file,
-    // so always return an empty slice.
-    let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
+use std::num::TryFromIntError;
+use std::{fmt, fs, io, path};
 
-    // Load all of the sections.
-    let dwarf_cow = gimli::Dwarf::load(&load_section, &load_section_sup)?;
+/// Split DWARF units each come from their own .dwo section, so their offsets overlap. Their
+/// types are keyed by offset plus a multiple of this: (index of the unit + 1) times it.
+pub const SPLIT_UNIT_TYPE_BASE: usize = 1 << 40;
 
-    // Borrow a `Cow<[u8]>` to create an `EndianSlice`.
-    let borrow_section: &dyn for<'a> Fn(
-        &'a borrow::Cow<[u8]>,
-    ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
+/// What loading reads of a unit up front, from its root DIE: enough to index it by file name
+/// and by address, and to find the rest of it when a lookup needs it.
+#[derive(Debug, Clone, Default)]
+pub struct UnitRoot {
+    /// Where the unit starts in .debug_info
+    pub offset: usize,
+    /// The name of the unit's source file. None for partial units and type units, which have
+    /// no File, and for split units whose .dwo couldn't be found.
+    pub name: Option<String>,
+    /// The addresses of the unit's code. Units without any (many of rustc's) have no code, only
+    /// declarations and types.
+    pub ranges: Vec<(usize, usize)>,
+    pub line_program: Option<LineProgram>,
+}
 
-    // Create `EndianSlice`s for all of the sections.
-    let dwarf = dwarf_cow.borrow(&borrow_section);
+/// What a unit's DIEs describe: its File, if it is a compile unit, and its types, keyed by
+/// .debug_info offset.
+#[derive(Debug, Default)]
+pub struct UnitEntries {
+    pub file: Option<File>,
+    pub types: HashMap<usize, Type>,
+}
 
-    // Define a mapping from type offsets to type structs
-    let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
+/// Where to find a unit's line program in .debug_line.
+#[derive(Debug, Clone)]
+pub struct LineProgram {
+    offset: gimli::DebugLineOffset<usize>,
+    address_size: u8,
+    comp_dir: Option<String>,
+    comp_name: Option<String>,
+}
 
-    let mut compilation_units: Vec<File> = Vec::new();
+/// The rows of a unit's line table.
+#[derive(Debug, Default)]
+pub struct LineTable {
+    /// Every row, in address order. Rows with a line number of 0 mark the end of a sequence of
+    /// instructions, or code the compiler didn't attribute to any line.
+    pub rows: Vec<Line>,
+    /// Indices of the rows for lines of the unit's own source file, rather than of the headers
+    /// it includes
+    pub own_rows: Vec<usize>,
+    /// The paths of the files the line program numbers, e.g. for DW_AT_call_file
+    pub files: HashMap<u64, String>,
+}
 
-    // Iterate over the compilation units.
-    let mut iter = dwarf.units();
-    while let Some(header) = iter.next()? {
+/// The DWARF sections, copied out of the binary so that units and line programs can be decoded
+/// long after it has been unmapped.
+#[derive(Default)]
+pub struct DebugSections {
+    endian: gimli::RunTimeEndian,
+    sections: gimli::Dwarf<Vec<u8>>,
+}
+
+impl DebugSections {
+    pub fn load(object: &object::File, endian: gimli::RunTimeEndian) -> DebugSections {
+        let load_section = |id: gimli::SectionId| -> Result<Vec<u8>, gimli::Error> {
+            Ok(object
+                .section_data_by_name(id.name())
+                .map_or_else(Vec::new, |data| data.into_owned()))
+        };
+        // We don't have a supplementary object file
+        let load_section_sup = |_| Ok(Vec::new());
+        DebugSections {
+            endian,
+            sections: gimli::Dwarf::load(load_section, load_section_sup).unwrap_or_default(),
+        }
+    }
+
+    fn dwarf(&self) -> gimli::Dwarf<gimli::EndianSlice<'_, gimli::RunTimeEndian>> {
+        self.sections
+            .borrow(|section| gimli::EndianSlice::new(section, self.endian))
+    }
+
+    /// Reads the root DIE of every unit: its name, the addresses of its code and where its line
+    /// program is. That's all the indexes of files and addresses need, so the rest of a unit is
+    /// left for load_unit, except for split DWARF units, whose DIEs (and name) are in a .dwo file.
+    /// Those are parsed here and come with their entries. Also returns warnings about units that
+    /// had to be left out, such as split DWARF whose .dwo file is missing.
+    pub fn load_units(&self, path: &str) -> Result<(Vec<(UnitRoot, Option<UnitEntries>)>, Vec<String>), Error> {
+        let dwarf = self.dwarf();
+        let mut units = Vec::new();
+        let mut warnings = Vec::new();
+
+        let mut iter = dwarf.units();
+        while let Some(header) = iter.next()? {
+            let offset = header.offset().0;
+            let unit = dwarf.unit(header)?;
+            let entry = match unit.entries().next_dfs()? {
+                Some((_, entry)) => entry.clone(),
+                None => continue,
+            };
+
+            // The line table is only decoded when something first looks at it (see
+            // DebugSections::decode), so just remember where it is. With split DWARF it stays
+            // in the binary, with the skeleton.
+            let line_program = unit.line_program.as_ref().map(|program| LineProgram {
+                offset: program.header().offset(),
+                address_size: program.header().address_size(),
+                comp_dir: unit.comp_dir.map(|dir| dir.to_string_lossy().into_owned()),
+                comp_name: unit.name.map(|name| name.to_string_lossy().into_owned()),
+            });
+            let mut root = UnitRoot {
+                offset,
+                name: None,
+                ranges: die_ranges(&entry, &unit, &dwarf)?,
+                line_program,
+            };
+
+            // With split DWARF (-gsplit-dwarf) the binary only holds a skeleton unit, and the
+            // DIEs describing the unit live in a .dwo file or a .dwp package
+            let entries = match get_skeleton(&unit, &dwarf)? {
+                Some(skeleton) => {
+                    let mut types = HashMap::new();
+                    let mut files = Vec::new();
+                    let found = load_split_unit(
+                        &dwarf,
+                        &skeleton,
+                        path,
+                        self.endian,
+                        (units.len() + 1) * SPLIT_UNIT_TYPE_BASE,
+                        &mut types,
+                        &mut files,
+                    )?;
+                    if !found {
+                        warnings.push(format!(
+                            "Could not find split DWARF {} (or a {}.dwp package)",
+                            skeleton.dwo_path.display(),
+                            path
+                        ));
+                    }
+                    Some(UnitEntries {
+                        file: files.pop(),
+                        types,
+                    })
+                }
+                None => {
+                    if entry.tag() == gimli::DW_TAG_compile_unit {
+                        root.name = Some(unit_file_name(&entry, &unit, &dwarf));
+                    }
+                    None
+                }
+            };
+            if let Some(file) = entries.as_ref().and_then(|entries| entries.file.as_ref()) {
+                root.name = Some(file.name.clone());
+            }
+            units.push((root, entries));
+        }
+        Ok((units, warnings))
+    }
+
+    /// Parses the DIEs of a unit that load_units left for later.
+    pub fn load_unit(&self, root: &UnitRoot) -> Result<UnitEntries, Error> {
+        let dwarf = self.dwarf();
+        let header = dwarf
+            .debug_info
+            .header_from_offset(gimli::DebugInfoOffset(root.offset))?;
+        load_unit_entries(&dwarf.unit(header)?, &dwarf)
+    }
+
+    /// The names of what a unit defines: its functions, the functions inlined into them, its
+    /// named types and its global variables, each as Function, InlinedCall, Type and Variable
+    /// would name it. Only names are read, so that finding the units a lookup by name needs
+    /// doesn't take parsing every unit.
+    pub fn unit_names(&self, root: &UnitRoot) -> Result<Vec<String>, Error> {
+        let dwarf = self.dwarf();
+        let header = dwarf
+            .debug_info
+            .header_from_offset(gimli::DebugInfoOffset(root.offset))?;
         let unit = dwarf.unit(header)?;
 
-        // Iterate over the Debugging Information Entries (DIEs) in the unit.
+        let mut names = Vec::new();
         let mut depth = 0;
+        // As in load_entries, to tell globals from locals
+        let mut function_depth: Option<isize> = None;
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
-            // Update the offset_to_type mapping for types
-            // Update the variable list for formal params/variables
-            match entry.tag() {
-                gimli::DW_TAG_compile_unit => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    compilation_units.push(File {
-                        name,
-                        global_variables: Vec::new(),
-                        functions: Vec::new(),
-                        lines: Vec::new(),
-                    });
+            if function_depth.map_or(false, |function_depth| depth <= function_depth) {
+                function_depth = None;
+            }
+            let name = match entry.tag() {
+                gimli::DW_TAG_subprogram => {
+                    function_depth = Some(depth);
+                    declared_name(entry, &unit, &dwarf)?
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
+                gimli::DW_TAG_inlined_subroutine => declared_name(entry, &unit, &dwarf)?,
+                gimli::DW_TAG_variable if function_depth.is_none() => attr_name(entry, &unit, &dwarf),
+                // An array's name has its length in it, which takes reading its subranges
+                gimli::DW_TAG_array_type => {
+                    let mut tree = unit.entries_tree(Some(entry.offset()))?;
+                    Some(load_type(tree.root()?, &unit, &dwarf, 0)?.name)
+                }
+                tag if is_type_tag(tag) => Some(type_name(entry, &unit, &dwarf, 0)),
+                _ => None,
+            };
+            names.extend(name.filter(|name| !name.is_empty()));
+        }
+        Ok(names)
+    }
+
+    /// Decodes a unit's line program. `file_name` is the unit's name, which tells the rows for
+    /// its own source file from those of its headers.
+    pub fn decode(&self, program: &LineProgram, file_name: &str) -> Result<LineTable, Error> {
+        let dwarf = self.dwarf();
+        let strings = (dwarf.debug_str, dwarf.debug_line_str);
+        let line_program = dwarf.debug_line.program(
+            program.offset,
+            program.address_size,
+            program
+                .comp_dir
+                .as_ref()
+                .map(|dir| gimli::EndianSlice::new(dir.as_bytes(), self.endian)),
+            program
+                .comp_name
+                .as_ref()
+                .map(|name| gimli::EndianSlice::new(name.as_bytes(), self.endian)),
+        )?;
+
+        // Work out each file's path once, rather than for every row. File numbers start at 1
+        // before DWARF 5, and header.file() takes care of that.
+        let header = line_program.header();
+        let files: HashMap<u64, String> = (0..=header.file_names().len() as u64)
+            .filter_map(|index| {
+                let file = header.file(index)?;
+                Some((index, file_path(header, file, program.comp_dir.as_deref(), &strings)))
+            })
+            .collect();
+        let is_own = |path: &str| path == file_name || path.ends_with(&format!("/{}", file_name));
+        let mut rows = Vec::new();
+        let mut program_rows = line_program.rows();
+        while let Some((_, row)) = program_rows.next_row()? {
+            let path = files.get(&row.file_index()).map_or("", |path| path.as_str());
+            let own = is_own(path);
+            let number = match row.line() {
+                Some(number) if !row.end_sequence() => number as usize,
+                _ => 0,
+            };
+            let line = Line {
+                file: path.to_string(),
+                number,
+                address: row.address() as usize,
+            };
+            rows.push((line, own && number != 0));
+        }
+
+        // Sequences can come in any order. An end of sequence goes before a row at the same
+        // address, which starts the next one.
+        rows.sort_by_key(|(line, _)| (line.address, line.number != 0));
+        let own_rows = rows
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, own))| *own)
+            .map(|(index, _)| index)
+            .collect();
+        Ok(LineTable {
+            rows: rows.into_iter().map(|(line, _)| line).collect(),
+            own_rows,
+            files,
+        })
+    }
+}
+
+type LineStrings<'a> = (
+    gimli::DebugStr<gimli::EndianSlice<'a, gimli::RunTimeEndian>>,
+    gimli::DebugLineStr<gimli::EndianSlice<'a, gimli::RunTimeEndian>>,
+);
+
+/// The full path of a file in a line program's header. Relative directories are relative to
+/// the unit's compilation directory.
+fn file_path<'a>(
+    header: &gimli::LineProgramHeader<gimli::EndianSlice<'a, gimli::RunTimeEndian>>,
+    file: &gimli::FileEntry<gimli::EndianSlice<'a, gimli::RunTimeEndian>>,
+    comp_dir: Option<&str>,
+    strings: &LineStrings<'a>,
+) -> String {
+    let string = |value| -> Option<String> {
+        let string = match value {
+            gimli::AttributeValue::String(string) => string,
+            gimli::AttributeValue::DebugStrRef(offset) => strings.0.get_str(offset).ok()?,
+            gimli::AttributeValue::DebugLineStrRef(offset) => strings.1.get_str(offset).ok()?,
+            _ => return None,
+        };
+        Some(string.to_string_lossy().into_owned())
+    };
+    let mut path = path::PathBuf::new();
+    if let Some(comp_dir) = comp_dir {
+        path.push(comp_dir);
+    }
+    // Pushing an absolute path replaces what's there
+    if let Some(dir) = file.directory(header).and_then(string) {
+        path.push(dir);
+    }
+    if let Some(name) = string(file.path_name()) {
+        path.push(name);
+    }
+    path.to_string_lossy().into_owned()
+}
+
+fn load_unit_entries<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<UnitEntries, Error> {
+    let mut types = HashMap::new();
+    let mut files = Vec::new();
+    load_entries(unit, dwarf, 0, &mut types, &mut files)?;
+    Ok(UnitEntries {
+        file: files.pop(),
+        types,
+    })
+}
+
+/// The name of a compile unit's File, from its root DIE.
+fn unit_file_name<R: Reader>(
+    root: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> String {
+    let name = attr_name(root, unit, dwarf).unwrap_or_else(|| "<unknown>".to_string());
+    // rustc names units "<crate root>/@/<codegen unit>"
+    match name.find("/@/") {
+        Some(i) => name[..i].to_string(),
+        None => name,
+    }
+}
+
+fn load_entries<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    type_base: usize,
+    offset_to_type: &mut HashMap<usize, Type>,
+    compilation_units: &mut Vec<File>,
+) -> Result<(), Error> {
+    // Variables may refer to types declared further down, so read all the types first
+    load_types(unit, dwarf, type_base, offset_to_type)?;
+
+    // Iterate over the Debugging Information Entries (DIEs) in the unit.
+    let mut depth = 0;
+    // Depth of the function we're in, if any. Functions can be nested in namespaces (Rust, C++),
+    // so depth alone doesn't tell locals from globals.
+    let mut function_depth: Option<isize> = None;
+    // Depths of the inlined calls enclosing the current entry, innermost last
+    let mut inline_depths: Vec<isize> = Vec::new();
+    // The File for this unit. Partial units (from dwz) and type units don't get one, so what's
+    // in them is skipped.
+    let mut unit_file: Option<usize> = None;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        if function_depth.map_or(false, |function_depth| depth <= function_depth) {
+            function_depth = None;
+        }
+        // Update the offset_to_type mapping for types
+        // Update the variable list for formal params/variables
+        match entry.tag() {
+            gimli::DW_TAG_compile_unit => {
+                compilation_units.push(File {
+                    name: unit_file_name(entry, unit, dwarf),
+                    global_variables: Vec::new(),
+                    functions: Vec::new(),
+                });
+                unit_file = Some(compilation_units.len() - 1);
+            }
+            gimli::DW_TAG_subprogram => {
+                function_depth = Some(depth);
+                inline_depths.clear();
+                let mut func: Function = Default::default();
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, unit, dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.name = name;
+                            }
                         }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
+                        gimli::DW_AT_high_pc => {
+                            if let Ok(DebugValue::Uint(high_pc)) = val {
+                                func.text_length = high_pc.try_into()?;
+                            }
                         }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
-                gimli::DW_TAG_subprogram => {
-                    let mut func: Function = Default::default();
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(name)) = val {
-                                    func.name = name;
-                                }
+                        gimli::DW_AT_low_pc => {
+                            //println!("low pc {:?}", attr.value());
+                            if let Ok(DebugValue::Uint(low_pc)) = val {
+                                func.address = low_pc.try_into()?;
                             }
-                            gimli::DW_AT_high_pc => {
-                                if let Ok(DebugValue::Uint(high_pc)) = val {
-                                    func.text_length = high_pc.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(line_number)) = val {
+                                func.line_number = line_number.try_into()?;
                             }
-                            gimli::DW_AT_low_pc => {
-                                //println!("low pc {:?}", attr.value());
-                                if let Ok(DebugValue::Uint(low_pc)) = val {
-                                    func.address = low_pc.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                func.return_type = offset_to_type.get(&(offset + type_base)).cloned();
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(line_number)) = val {
-                                    func.line_number = line_number.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_frame_base => {
+                            func.frame_base = get_location(&attr, unit, dwarf);
+                        }
+                        gimli::DW_AT_linkage_name | gimli::DW_AT_MIPS_linkage_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.linkage_name = Some(name);
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
-                    compilation_units.last_mut().unwrap().functions.push(func);
                 }
-                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
-                    let mut name = String::new();
-                    let mut entity_type: Option<Type> = None;
-                    let mut location: Option<Location> = None;
-                    let mut line_number = 0;
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(attr_name)) = val {
-                                    name = attr_name;
-                                }
+                // Out-of-line definitions of methods (and concrete instances of inlined
+                // functions) leave their names on the declaration they point to
+                if func.name.is_empty() {
+                    if let Some(decl) = get_declaration(entry, unit)? {
+                        func.name = attr_name(&decl, unit, dwarf).unwrap_or_default();
+                        if func.linkage_name.is_none() {
+                            func.linkage_name = attr_linkage_name(&decl, unit, dwarf);
+                        }
+                        if func.return_type.is_none() {
+                            func.return_type = attr_type_key(&decl, unit, type_base)
+                                .and_then(|key| offset_to_type.get(&key).cloned());
+                        }
+                    }
+                }
+                if let Some(file) = unit_file {
+                    compilation_units[file].functions.push(func);
+                }
+            }
+            gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
+                let mut name = String::new();
+                let mut entity_type: Option<Type> = None;
+                let mut location: Option<Location> = None;
+                let mut line_number = 0;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, unit, dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(attr_name)) = val {
+                                name = attr_name;
                             }
-                            gimli::DW_AT_type => {
-                                if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
-                                    }
+                        }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                if let Some(dtype) = offset_to_type.get(&(offset + type_base)) {
+                                    entity_type = Some(dtype.clone());
                                 }
                             }
-                            gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
-                                    location = Some(loc);
-                                }
+                        }
+                        gimli::DW_AT_location => {
+                            if let Some(loc) = get_location(&attr, unit, dwarf) {
+                                location = Some(loc);
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(num)) = val {
-                                    line_number = num;
-                                }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(num)) = val {
+                                line_number = num;
                             }
-                            _ => {}
                         }
+                        _ => {}
+                    }
+                }
+                if let (Some(entity_type), Some(location), Some(file)) = (entity_type, location, unit_file) {
+                    let var = Variable {
+                        name,
+                        entity_type,
+                        location,
+                        line_number: line_number.try_into()?,
+                    };
+                    let file = &mut compilation_units[file];
+                    if function_depth.is_none() {
+                        file.global_variables.push(var);
+                    } else if let Some(func) = file.functions.last_mut() {
+                        func.variables.push(var);
                     }
-                    if entity_type.is_some() && location.is_some() {
-                        let var = Variable {
-                            name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
-                            line_number: line_number.try_into().unwrap(),
-                        };
-                        if depth == 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .global_variables
-                                .push(var);
-                        } else if depth > 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .functions
-                                .last_mut()
-                                .unwrap()
-                                .variables
-                                .push(var);
+                }
+            }
+            gimli::DW_TAG_inlined_subroutine if function_depth.is_some() => {
+                while inline_depths.last().map_or(false, |outer| *outer >= depth) {
+                    inline_depths.pop();
+                }
+                let call = load_inlined_call(entry, unit, dwarf, inline_depths.len())?;
+                inline_depths.push(depth);
+                let func = unit_file.and_then(|file| compilation_units[file].functions.last_mut());
+                if let (Some(call), Some(func)) = (call, func) {
+                    func.inlined_calls.push(call);
+                }
+            }
+            // NOTE: :You may consider supporting other types by extending this
+            // match statement
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Reads a DW_TAG_inlined_subroutine: which function was inlined, where its code ended up, and
+/// where it was called from. None if the call was optimized away entirely.
+fn load_inlined_call<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    depth: usize,
+) -> Result<Option<InlinedCall>, Error> {
+    let ranges = die_ranges(entry, unit, dwarf)?;
+    let low_pc = match ranges.iter().map(|(start, _)| *start).min() {
+        Some(low_pc) => low_pc,
+        None => return Ok(None),
+    };
+    let mut call = InlinedCall {
+        entry_pc: low_pc,
+        ranges,
+        depth,
+        ..Default::default()
+    };
+    let mut attrs = entry.attrs();
+    while let Some(attr) = attrs.next()? {
+        match (attr.name(), attr.value()) {
+            (gimli::DW_AT_entry_pc, gimli::AttributeValue::Addr(pc)) => call.entry_pc = pc as usize,
+            (gimli::DW_AT_entry_pc, gimli::AttributeValue::DebugAddrIndex(index)) => {
+                call.entry_pc = dwarf.address(unit, index)? as usize
+            }
+            // As a constant, DWARF 5 makes it an offset from the start of the code
+            (gimli::DW_AT_entry_pc, value) => {
+                if let Some(offset) = value.udata_value() {
+                    call.entry_pc = low_pc + offset as usize;
+                }
+            }
+            (gimli::DW_AT_call_file, gimli::AttributeValue::FileIndex(index)) => call.call_file = index,
+            (gimli::DW_AT_call_file, value) => call.call_file = value.udata_value().unwrap_or(0),
+            (gimli::DW_AT_call_line, value) => {
+                call.call_line = value.udata_value().unwrap_or(0) as usize
+            }
+            _ => {}
+        }
+    }
+    // The name is on the abstract instance of the function, or on the declaration that
+    // points to
+    let mut origin = get_declaration(entry, unit)?;
+    for _ in 0..2 {
+        let decl = match origin {
+            Some(decl) => decl,
+            None => break,
+        };
+        if call.name.is_empty() {
+            call.name = attr_name(&decl, unit, dwarf).unwrap_or_default();
+        }
+        if call.linkage_name.is_none() {
+            call.linkage_name = attr_linkage_name(&decl, unit, dwarf);
+        }
+        origin = get_declaration(&decl, unit)?;
+    }
+    Ok(Some(call))
+}
+
+/// The address ranges of a DIE, from DW_AT_low_pc and DW_AT_high_pc or from DW_AT_ranges.
+fn die_ranges<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Vec<(usize, usize)>, Error> {
+    let mut ranges = Vec::new();
+    let mut low_pc = None;
+    let mut high_pc = None;
+    let mut length = None;
+    let mut attrs = entry.attrs();
+    while let Some(attr) = attrs.next()? {
+        match attr.name() {
+            gimli::DW_AT_low_pc => {
+                if let Ok(DebugValue::Uint(pc)) = get_attr_value(&attr, unit, dwarf) {
+                    low_pc = Some(pc);
+                }
+            }
+            // An address, or (usually) the length of the code
+            gimli::DW_AT_high_pc => match attr.value() {
+                gimli::AttributeValue::Addr(pc) => high_pc = Some(pc),
+                gimli::AttributeValue::DebugAddrIndex(index) => {
+                    high_pc = Some(dwarf.address(unit, index)?)
+                }
+                value => length = value.udata_value(),
+            },
+            gimli::DW_AT_ranges => {
+                if let Some(offset) = dwarf.attr_ranges_offset(unit, attr.value())? {
+                    let mut iter = dwarf.ranges(unit, offset)?;
+                    while let Some(range) = iter.next()? {
+                        if range.begin < range.end {
+                            ranges.push((range.begin as usize, range.end as usize));
                         }
                     }
                 }
-                // NOTE: :You may consider supporting other types by extending this
-                // match statement
-                _ => {}
             }
+            _ => {}
         }
+    }
+    if let Some(low_pc) = low_pc {
+        if let Some(high_pc) = high_pc.or_else(|| Some(low_pc + length?)) {
+            if low_pc < high_pc {
+                ranges.push((low_pc as usize, high_pc as usize));
+            }
+        }
+    }
+    Ok(ranges)
+}
+
+fn is_type_tag(tag: gimli::DwTag) -> bool {
+    match tag {
+        gimli::DW_TAG_base_type
+        | gimli::DW_TAG_pointer_type
+        | gimli::DW_TAG_reference_type
+        | gimli::DW_TAG_rvalue_reference_type
+        | gimli::DW_TAG_structure_type
+        | gimli::DW_TAG_class_type
+        | gimli::DW_TAG_union_type
+        | gimli::DW_TAG_enumeration_type
+        | gimli::DW_TAG_array_type
+        | gimli::DW_TAG_typedef
+        | gimli::DW_TAG_const_type
+        | gimli::DW_TAG_volatile_type
+        | gimli::DW_TAG_restrict_type
+        | gimli::DW_TAG_atomic_type
+        | gimli::DW_TAG_subroutine_type
+        | gimli::DW_TAG_unspecified_type => true,
+        _ => false,
+    }
+}
 
-        // Get line numbers
-        if let Some(program) = unit.line_program.clone() {
-            // Iterate over the line program rows.
-            let mut rows = program.rows();
-            while let Some((header, row)) = rows.next_row()? {
-                if !row.end_sequence() {
-                    // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
-                    if let Some(file) = row.file(header) {
-                        if let Some(dir) = file.directory(header) {
-                            path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
+/// Reads every type in a unit into `offset_to_type`.
+fn load_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    type_base: usize,
+    offset_to_type: &mut HashMap<usize, Type>,
+) -> Result<(), Error> {
+    let mut entries = unit.entries();
+    while let Some((_, entry)) = entries.next_dfs()? {
+        if is_type_tag(entry.tag()) {
+            let mut tree = unit.entries_tree(Some(entry.offset()))?;
+            let dtype = load_type(tree.root()?, unit, dwarf, type_base)?;
+            offset_to_type.insert(type_key(unit, entry.offset(), type_base), dtype);
+        }
+    }
+    Ok(())
+}
+
+fn load_type<R: Reader>(
+    node: gimli::EntriesTreeNode<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    type_base: usize,
+) -> Result<Type, Error> {
+    let entry = node.entry().clone();
+    let target = attr_type_key(&entry, unit, type_base);
+    let kind = match entry.tag() {
+        gimli::DW_TAG_base_type => match entry.attr_value(gimli::DW_AT_encoding)? {
+            Some(gimli::AttributeValue::Encoding(encoding)) => TypeKind::Base(Some(encoding)),
+            _ => TypeKind::Base(None),
+        },
+        gimli::DW_TAG_pointer_type
+        | gimli::DW_TAG_reference_type
+        | gimli::DW_TAG_rvalue_reference_type => TypeKind::Pointer(target),
+        gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type | gimli::DW_TAG_union_type => {
+            let mut members = Vec::new();
+            let mut template_params = Vec::new();
+            let mut variant_part = None;
+            let mut children = node.children();
+            while let Some(child) = children.next()? {
+                let child_entry = child.entry();
+                match child_entry.tag() {
+                    // Static members are declarations without any storage in the struct
+                    gimli::DW_TAG_member
+                        if child_entry.attr(gimli::DW_AT_declaration)?.is_none() =>
+                    {
+                        members.extend(load_member(child_entry, unit, dwarf, type_base));
+                    }
+                    gimli::DW_TAG_template_type_parameter => {
+                        let name = attr_name(child_entry, unit, dwarf).unwrap_or_default();
+                        if let Some(param) = attr_type_key(child_entry, unit, type_base) {
+                            template_params.push((name, param));
                         }
-                        path.push(
-                            dwarf
-                                .attr_string(&unit, file.path_name())?
-                                .to_string_lossy()
-                                .as_ref(),
-                        );
                     }
+                    gimli::DW_TAG_variant_part => {
+                        variant_part = Some(load_variant_part(child, unit, dwarf, type_base)?);
+                    }
+                    _ => {}
+                }
+            }
+            TypeKind::Struct {
+                members,
+                template_params,
+                variant_part,
+            }
+        }
+        gimli::DW_TAG_enumeration_type => {
+            let mut enumerators = Vec::new();
+            let mut children = node.children();
+            while let Some(child) = children.next()? {
+                let child_entry = child.entry();
+                if child_entry.tag() != gimli::DW_TAG_enumerator {
+                    continue;
+                }
+                let name = attr_name(child_entry, unit, dwarf).unwrap_or_default();
+                if let Some(attr) = child_entry.attr(gimli::DW_AT_const_value)? {
+                    enumerators.push((name, attr_constant(&attr).unwrap_or(0) as i64));
+                }
+            }
+            TypeKind::Enumeration(enumerators)
+        }
+        gimli::DW_TAG_array_type => {
+            // Multi-dimensional arrays have a subrange per dimension; we flatten them
+            let mut count = None;
+            let mut children = node.children();
+            while let Some(child) = children.next()? {
+                let child_entry = child.entry();
+                if child_entry.tag() != gimli::DW_TAG_subrange_type {
+                    continue;
+                }
+                let dimension = match child_entry.attr(gimli::DW_AT_count)? {
+                    Some(attr) => attr.udata_value(),
+                    None => child_entry
+                        .attr(gimli::DW_AT_upper_bound)?
+                        .and_then(|attr| attr.udata_value())
+                        .map(|upper_bound| upper_bound + 1),
+                };
+                count = match (count, dimension) {
+                    (None, Some(dimension)) => Some(dimension as usize),
+                    (Some(count), Some(dimension)) => Some(count * dimension as usize),
+                    (_, None) => None,
+                };
+            }
+            match target {
+                Some(element) => TypeKind::Array { element, count },
+                None => TypeKind::Opaque,
+            }
+        }
+        gimli::DW_TAG_typedef
+        | gimli::DW_TAG_const_type
+        | gimli::DW_TAG_volatile_type
+        | gimli::DW_TAG_restrict_type
+        | gimli::DW_TAG_atomic_type => TypeKind::Alias(target),
+        _ => TypeKind::Opaque,
+    };
+    let size = entry
+        .attr(gimli::DW_AT_byte_size)?
+        .and_then(|attr| attr.udata_value())
+        .unwrap_or(0);
+    let mut name = type_name(&entry, unit, dwarf, 0);
+    if let TypeKind::Array {
+        count: Some(count), ..
+    } = kind
+    {
+        name = name.replace("[]", &format!("[{}]", count));
+    }
+    Ok(Type {
+        name,
+        size: size.try_into().unwrap_or(0),
+        kind,
+    })
+}
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
-
-                    // Determine line/column. DWARF line/column is never 0, so we use that
-                    // but other applications may want to display this differently.
-                    let line = row.line().unwrap_or(0);
-
-                    if let Some(file) = file {
-                        file.lines.push(Line {
-                            file: file.name.clone(),
-                            number: line.try_into().unwrap(),
-                            address: row.address().try_into().unwrap(),
-                        });
+fn load_member<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    type_base: usize,
+) -> Option<Member> {
+    let offset = match entry.attr(gimli::DW_AT_data_member_location).ok()? {
+        Some(attr) => attr.udata_value()?,
+        // Union members don't have one
+        None => 0,
+    };
+    Some(Member {
+        name: attr_name(entry, unit, dwarf).unwrap_or_default(),
+        type_key: attr_type_key(entry, unit, type_base)?,
+        offset: offset.try_into().ok()?,
+    })
+}
+
+/// Reads the variants of a Rust enum. The variant part names the member holding the
+/// discriminant, and each variant is a member (of a struct type named after the variant) tagged
+/// with the discriminant value that selects it.
+fn load_variant_part<R: Reader>(
+    node: gimli::EntriesTreeNode<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    type_base: usize,
+) -> Result<VariantPart, Error> {
+    let discr = match node.entry().attr_value(gimli::DW_AT_discr)? {
+        Some(gimli::AttributeValue::UnitRef(offset)) => Some(offset),
+        _ => None,
+    };
+    let mut discriminant = None;
+    let mut variants = Vec::new();
+    let mut children = node.children();
+    while let Some(child) = children.next()? {
+        let child_entry = child.entry().clone();
+        match child_entry.tag() {
+            gimli::DW_TAG_member if Some(child_entry.offset()) == discr => {
+                discriminant = load_member(&child_entry, unit, dwarf, type_base);
+            }
+            gimli::DW_TAG_variant => {
+                let discr_value = match child_entry.attr(gimli::DW_AT_discr_value)? {
+                    Some(attr) => attr_constant(&attr),
+                    None => None,
+                };
+                let mut members = child.children();
+                while let Some(member) = members.next()? {
+                    if member.entry().tag() == gimli::DW_TAG_member {
+                        if let Some(member) = load_member(member.entry(), unit, dwarf, type_base) {
+                            variants.push(Variant {
+                                discr_value,
+                                member,
+                            });
+                        }
+                        break;
                     }
                 }
             }
+            _ => {}
+        }
+    }
+    Ok(VariantPart {
+        discriminant,
+        variants,
+    })
+}
+
+/// Types are keyed by their offset in .debug_info (or .debug_types), which is what DW_AT_type
+/// references resolve to.
+fn type_key<R: Reader>(unit: &gimli::Unit<R>, offset: UnitOffset, type_base: usize) -> usize {
+    type_base
+        + match offset.to_unit_section_offset(unit) {
+            UnitSectionOffset::DebugInfoOffset(offset) => offset.0,
+            UnitSectionOffset::DebugTypesOffset(offset) => offset.0,
+        }
+}
+
+fn attr_type_key<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    type_base: usize,
+) -> Option<usize> {
+    match entry.attr_value(gimli::DW_AT_type).ok()? {
+        Some(gimli::AttributeValue::UnitRef(offset)) => Some(type_key(unit, offset, type_base)),
+        Some(gimli::AttributeValue::DebugInfoRef(offset)) => Some(type_base + offset.0),
+        _ => None,
+    }
+}
+
+fn attr_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    match get_attr_value(&entry.attr(gimli::DW_AT_name).ok()??, unit, dwarf) {
+        Ok(DebugValue::Str(name)) => Some(name),
+        _ => None,
+    }
+}
+
+fn attr_linkage_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    let attr = entry
+        .attr(gimli::DW_AT_linkage_name)
+        .ok()?
+        .or(entry.attr(gimli::DW_AT_MIPS_linkage_name).ok()?)?;
+    match get_attr_value(&attr, unit, dwarf) {
+        Ok(DebugValue::Str(name)) => Some(name),
+        _ => None,
+    }
+}
+
+/// Reads a constant attribute (a discriminant or enumerator value) as raw bits.
+fn attr_constant<R: Reader>(attr: &gimli::Attribute<R>) -> Option<u64> {
+    match attr.value() {
+        gimli::AttributeValue::Sdata(value) => Some(value as u64),
+        _ => attr.udata_value(),
+    }
+}
+
+/// Follows DW_AT_specification or DW_AT_abstract_origin to the DIE declaring this one.
+fn get_declaration<'a, R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<'a, 'a, R>,
+    unit: &'a gimli::Unit<R>,
+) -> Result<Option<gimli::DebuggingInformationEntry<'a, 'a, R>>, Error> {
+    for name in &[gimli::DW_AT_specification, gimli::DW_AT_abstract_origin] {
+        if let Some(gimli::AttributeValue::UnitRef(offset)) = entry.attr_value(*name)? {
+            return Ok(Some(unit.entry(offset)?));
+        }
+    }
+    Ok(None)
+}
+
+/// The name of a function or inlined call: its own, or else that of the declaration or
+/// abstract instance it points to (or the declaration that one points to).
+fn declared_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Option<String>, Error> {
+    if let Some(name) = attr_name(entry, unit, dwarf) {
+        return Ok(Some(name));
+    }
+    let mut origin = get_declaration(entry, unit)?;
+    for _ in 0..2 {
+        let decl = match origin {
+            Some(decl) => decl,
+            None => break,
+        };
+        if let Some(name) = attr_name(&decl, unit, dwarf) {
+            return Ok(Some(name));
+        }
+        origin = get_declaration(&decl, unit)?;
+    }
+    Ok(None)
+}
+
+/// Names a type the way C would spell it. Pointers, arrays and qualified types have no
+/// DW_AT_name of their own, so their names are built from the types they refer to.
+fn type_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    depth: usize,
+) -> String {
+    if let Some(name) = attr_name(entry, unit, dwarf) {
+        return name;
+    }
+    let target = match entry.attr_value(gimli::DW_AT_type) {
+        Ok(Some(gimli::AttributeValue::UnitRef(offset))) if depth < 8 => match unit.entry(offset) {
+            Ok(target) => type_name(&target, unit, dwarf, depth + 1),
+            Err(_) => "<unknown>".to_string(),
+        },
+        _ => "void".to_string(),
+    };
+    match entry.tag() {
+        gimli::DW_TAG_pointer_type => format!("{} *", target),
+        gimli::DW_TAG_reference_type => format!("{} &", target),
+        gimli::DW_TAG_rvalue_reference_type => format!("{} &&", target),
+        gimli::DW_TAG_const_type => format!("const {}", target),
+        gimli::DW_TAG_volatile_type => format!("volatile {}", target),
+        gimli::DW_TAG_array_type => format!("{} []", target),
+        gimli::DW_TAG_subroutine_type => format!("{} ()", target),
+        gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type => "struct {...}".to_string(),
+        gimli::DW_TAG_union_type => "union {...}".to_string(),
+        gimli::DW_TAG_enumeration_type => "enum {...}".to_string(),
+        _ => target,
+    }
+}
+
+/// What we need from a split DWARF skeleton unit to find and read the unit's DIEs.
+struct Skeleton {
+    /// Path of the .dwo file, from DW_AT_(GNU_)dwo_name and the unit's DW_AT_comp_dir
+    dwo_path: path::PathBuf,
+    /// Identifies the unit in a .dwp package
+    dwo_id: Option<u64>,
+    /// The split unit's addresses are indices into the binary's .debug_addr, starting here
+    addr_base: gimli::DebugAddrBase<usize>,
+}
+
+fn get_skeleton<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Option<Skeleton>, Error> {
+    let mut entries = unit.entries();
+    let root = match entries.next_dfs()? {
+        Some((_, root)) => root,
+        None => return Ok(None),
+    };
+    let mut dwo_name = None;
+    let mut dwo_id = None;
+    let mut addr_base = unit.addr_base;
+    let mut attrs = root.attrs();
+    while let Some(attr) = attrs.next()? {
+        match attr.name() {
+            gimli::DW_AT_GNU_dwo_name | gimli::DW_AT_dwo_name => {
+                let name = dwarf.attr_string(unit, attr.value())?;
+                dwo_name = Some(name.to_string_lossy()?.into_owned());
+            }
+            gimli::DW_AT_GNU_dwo_id => dwo_id = attr.udata_value(),
+            gimli::DW_AT_GNU_addr_base => match attr.value() {
+                gimli::AttributeValue::DebugAddrBase(base) => addr_base = base,
+                gimli::AttributeValue::SecOffset(offset) => {
+                    addr_base = gimli::DebugAddrBase(offset)
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    let dwo_name = match dwo_name {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    // DWARF 5 moved the id from an attribute into the unit header
+    if let UnitSectionOffset::DebugInfoOffset(offset) = unit.offset {
+        if let Some(id) = header_dwo_id(dwarf.debug_info.reader().clone(), offset.0)? {
+            dwo_id = Some(id);
+        }
+    }
+    let mut dwo_path = path::PathBuf::new();
+    if let Some(ref comp_dir) = unit.comp_dir {
+        dwo_path.push(comp_dir.to_string_lossy()?.as_ref());
+    }
+    dwo_path.push(dwo_name);
+    Ok(Some(Skeleton {
+        dwo_path,
+        dwo_id,
+        addr_base,
+    }))
+}
+
+/// The dwo_id in a DWARF 5 skeleton or split compile unit's header, which comes after the fields
+/// every unit header has (length, version, unit type, address size and abbreviations offset).
+/// `debug_info` is the .debug_info section, and the unit starts `offset` bytes in.
+fn header_dwo_id<R: Reader>(mut header: R, offset: R::Offset) -> Result<Option<u64>, Error> {
+    header.skip(offset)?;
+    let (_, format) = header.read_initial_length()?;
+    if header.read_u16()? != 5 {
+        return Ok(None);
+    }
+    match gimli::DwUt(header.read_u8()?) {
+        gimli::DW_UT_skeleton | gimli::DW_UT_split_compile => {
+            header.read_u8()?;
+            header.skip(R::Offset::from_u8(format.word_size()))?;
+            Ok(Some(header.read_u64()?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Loads the DIEs for a split unit, from its .dwo file if that exists, or else from the .dwp
+/// package next to the binary. Returns false if neither has it.
+fn load_split_unit(
+    dwarf: &gimli::Dwarf<gimli::EndianSlice<gimli::RunTimeEndian>>,
+    skeleton: &Skeleton,
+    binary_path: &str,
+    endian: gimli::RunTimeEndian,
+    type_base: usize,
+    offset_to_type: &mut HashMap<usize, Type>,
+    compilation_units: &mut Vec<File>,
+) -> Result<bool, Error> {
+    if let Ok(data) = fs::read(&skeleton.dwo_path) {
+        let object = object::File::parse(&data).or_else(|e| Err(Error::ObjectError(e.to_string())))?;
+        let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
+            Ok(id
+                .dwo_name()
+                .and_then(|name| object.section_data_by_name(name))
+                .unwrap_or(borrow::Cow::Borrowed(&[][..])))
+        };
+        load_dwo(
+            dwarf,
+            skeleton,
+            load_section,
+            endian,
+            type_base,
+            offset_to_type,
+            compilation_units,
+        )?;
+        return Ok(true);
+    }
+
+    let dwp_path = format!("{}.dwp", binary_path);
+    if let (Ok(data), Some(dwo_id)) = (fs::read(&dwp_path), skeleton.dwo_id) {
+        let object = object::File::parse(&data).or_else(|e| Err(Error::ObjectError(e.to_string())))?;
+        let index = object
+            .section_data_by_name(".debug_cu_index")
+            .unwrap_or(borrow::Cow::Borrowed(&[][..]));
+        if let Some(contributions) = find_dwp_unit(&index, dwo_id, endian) {
+            // A package holds many units back to back; hand gimli just this unit's piece of
+            // each section
+            let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
+                let data = id
+                    .dwo_name()
+                    .and_then(|name| object.section_data_by_name(name))
+                    .unwrap_or(borrow::Cow::Borrowed(&[][..]));
+                Ok(match contributions.get(&id) {
+                    Some((offset, size)) => borrow::Cow::Owned(
+                        data.get(*offset..*offset + *size).unwrap_or(&[]).to_vec(),
+                    ),
+                    None => data,
+                })
+            };
+            load_dwo(
+                dwarf,
+                skeleton,
+                load_section,
+                endian,
+                type_base,
+                offset_to_type,
+                compilation_units,
+            )?;
+            return Ok(true);
         }
     }
-    Ok(compilation_units)
+
+    Ok(false)
+}
+
+fn load_dwo<'input, F>(
+    dwarf: &gimli::Dwarf<gimli::EndianSlice<'input, gimli::RunTimeEndian>>,
+    skeleton: &Skeleton,
+    load_section: F,
+    endian: gimli::RunTimeEndian,
+    type_base: usize,
+    offset_to_type: &mut HashMap<usize, Type>,
+    compilation_units: &mut Vec<File>,
+) -> Result<(), Error>
+where
+    F: Fn(gimli::SectionId) -> Result<borrow::Cow<'input, [u8]>, gimli::Error>,
+{
+    let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
+    let dwo_cow = gimli::Dwarf::load(&load_section, &load_section_sup)?;
+    let borrow_section: &dyn for<'a> Fn(
+        &'a borrow::Cow<[u8]>,
+    ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
+        &|section| gimli::EndianSlice::new(&*section, endian);
+    let mut dwo = dwo_cow.borrow(&borrow_section);
+    // Addresses stay in the binary, since they need relocating at link time
+    dwo.debug_addr = dwarf.debug_addr.clone();
+
+    let mut iter = dwo.units();
+    while let Some(header) = iter.next()? {
+        let mut unit = dwo.unit(header)?;
+        unit.addr_base = skeleton.addr_base;
+        load_entries(&unit, &dwo, type_base, offset_to_type, compilation_units)?;
+    }
+    Ok(())
+}
+
+/// Looks a unit up by DWO id in a .dwp package's .debug_cu_index, and returns the offset and
+/// size of its contribution to each section.
+fn find_dwp_unit(
+    index: &[u8],
+    dwo_id: u64,
+    endian: gimli::RunTimeEndian,
+) -> Option<HashMap<gimli::SectionId, (usize, usize)>> {
+    let read_u32 = |offset: usize| -> Option<usize> {
+        let bytes = index.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match endian {
+            gimli::RunTimeEndian::Little => u32::from_le_bytes(bytes),
+            gimli::RunTimeEndian::Big => u32::from_be_bytes(bytes),
+        } as usize)
+    };
+    let read_u64 = |offset: usize| -> Option<u64> {
+        let bytes = index.get(offset..offset + 8)?.try_into().ok()?;
+        Some(match endian {
+            gimli::RunTimeEndian::Little => u64::from_le_bytes(bytes),
+            gimli::RunTimeEndian::Big => u64::from_be_bytes(bytes),
+        })
+    };
+
+    // Version 5 packages have a 2-byte version followed by 2 bytes of padding
+    let version = read_u32(0)? & 0xffff;
+    let column_count = read_u32(4)?;
+    let unit_count = read_u32(8)?;
+    let slot_count = read_u32(12)?;
+    let hash_table = 16;
+    let index_table = hash_table + 8 * slot_count;
+    let column_headers = index_table + 4 * slot_count;
+    let offsets = column_headers + 4 * column_count;
+    let sizes = offsets + 4 * column_count * unit_count;
+
+    let row = (0..slot_count)
+        .find(|slot| read_u64(hash_table + 8 * slot) == Some(dwo_id))
+        .and_then(|slot| read_u32(index_table + 4 * slot))
+        .filter(|row| *row != 0)?;
+
+    let mut contributions = HashMap::new();
+    for column in 0..column_count {
+        let section = match (version, read_u32(column_headers + 4 * column)?) {
+            (_, 1) => gimli::SectionId::DebugInfo,
+            (2, 2) => gimli::SectionId::DebugTypes,
+            (_, 3) => gimli::SectionId::DebugAbbrev,
+            (_, 4) => gimli::SectionId::DebugLine,
+            (2, 5) => gimli::SectionId::DebugLoc,
+            (5, 5) => gimli::SectionId::DebugLocLists,
+            (_, 6) => gimli::SectionId::DebugStrOffsets,
+            (2, 8) | (5, 7) => gimli::SectionId::DebugMacro,
+            (5, 8) => gimli::SectionId::DebugRngLists,
+            _ => continue,
+        };
+        let cell = 4 * ((row - 1) * column_count + column);
+        contributions.insert(section, (read_u32(offsets + cell)?, read_u32(sizes + cell)?));
+    }
+    Some(contributions)
 }
 
 #[derive(Debug, Clone)]
@@ -255,20 +1225,32 @@ pub enum DebugValue {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     GimliError(gimli::Error),
-    Addr2lineError(addr2line::gimli::Error),
     ObjectError(String),
     IoError,
+    /// An attribute's value doesn't fit in an address on this machine
+    ValueOutOfRange,
 }
 
-impl From<gimli::Error> for Error {
-    fn from(err: gimli::Error) -> Self {
-        Error::GimliError(err)
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::GimliError(err) => write!(f, "{}", err),
+            Error::ObjectError(err) => write!(f, "{}", err),
+            Error::IoError => write!(f, "I/O error"),
+            Error::ValueOutOfRange => write!(f, "attribute value out of range"),
+        }
     }
 }
 
-impl From<addr2line::gimli::Error> for Error {
-    fn from(err: addr2line::gimli::Error) -> Self {
-        Error::Addr2lineError(err)
+impl From<TryFromIntError> for Error {
+    fn from(_: TryFromIntError) -> Self {
+        Error::ValueOutOfRange
+    }
+}
+
+impl From<gimli::Error> for Error {
+    fn from(err: gimli::Error) -> Self {
+        Error::GimliError(err)
     }
 }
 
@@ -291,25 +1273,189 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
-    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
-        let mut pc = data.0.clone();
-        if pc.len() > 0 {
+/// Reads a DW_AT_location or DW_AT_frame_base attribute: either an expression, or (for
+/// DW_FORM_sec_offset and DWARF 5's DW_FORM_loclistx) a location list. A single DW_OP_addr or
+/// DW_OP_fbreg is decoded right away, since that covers almost every unoptimized variable.
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    let offset = match attr.value() {
+        gimli::AttributeValue::Exprloc(ref data) => {
+            let encoding = unit.encoding();
+            let mut pc = data.0.clone();
             if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
+                if pc.len() == 0 {
+                    match op {
+                        gimli::Operation::FrameOffset { offset } => {
+                            return Some(Location::FramePointerOffset(offset.try_into().ok()?));
+                        }
+                        gimli::Operation::Address { address } => {
+                            return Some(Location::Address(address.try_into().ok()?));
+                        }
+                        _ => {}
                     }
-                    _ => {}
                 }
             }
+            return Some(Location::Expression(get_expression(data, unit, dwarf)?));
+        }
+        gimli::AttributeValue::LocationListsRef(offset) => offset,
+        gimli::AttributeValue::DebugLocListsIndex(index) => {
+            dwarf.locations_offset(unit, index).ok()?
+        }
+        _ => return None,
+    };
+
+    let mut locations = dwarf.locations(unit, offset).ok()?;
+    let mut entries = Vec::new();
+    while let Some(entry) = locations.next().ok()? {
+        entries.push(LocationListEntry {
+            begin: entry.range.begin.try_into().ok()?,
+            end: entry.range.end.try_into().ok()?,
+            expression: get_expression(&entry.data, unit, dwarf)?,
+        });
+    }
+    Some(Location::List(entries))
+}
+
+/// Copies an expression out of the DWARF sections, resolving any indices into .debug_addr.
+fn get_expression<R: Reader>(
+    data: &gimli::Expression<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Expression> {
+    let encoding = unit.encoding();
+    let mut indexed_addresses = Vec::new();
+    let mut pc = data.0.clone();
+    while pc.len() != 0 {
+        match gimli::Operation::parse(&mut pc, encoding).ok()? {
+            gimli::Operation::AddressIndex { index } | gimli::Operation::ConstantIndex { index } => {
+                indexed_addresses.push((index.0, dwarf.address(unit, index).ok()?));
+            }
+            _ => {}
         }
     }
-    None
+    Some(Expression {
+        bytecode: data.0.to_slice().ok()?.into_owned(),
+        encoding,
+        endian: if gimli::Endianity::is_big_endian(data.0.endian()) {
+            gimli::RunTimeEndian::Big
+        } else {
+            gimli::RunTimeEndian::Little
+        },
+        indexed_addresses,
+    })
+}
+
+/// Disassembles an expression into something like "DW_OP_breg6 -20 DW_OP_stack_value".
+pub fn format_expression(expr: &Expression) -> String {
+    let mut buf = String::new();
+    let data = gimli::Expression(gimli::EndianSlice::new(&expr.bytecode, expr.endian));
+    if dump_exprloc(&mut buf, expr.encoding, &data).is_err() {
+        buf.push_str("<invalid expression>");
+    }
+    buf.trim_end().to_string()
+}
+
+/// Call frame information from .eh_frame (or .debug_frame, for the rare binary built without
+/// unwind tables), which tells us how to find each frame's canonical frame address.
+#[derive(Default)]
+pub struct CallFrameInfo {
+    eh_frame: Vec<u8>,
+    eh_frame_addr: u64,
+    debug_frame: Vec<u8>,
+    text_addr: u64,
+    endian: gimli::RunTimeEndian,
+}
+
+/// One row of the unwind table: where the canonical frame address is, and where the function
+/// has saved any of its caller's registers.
+pub struct UnwindRule {
+    /// Register and offset the CFA is computed from
+    pub cfa: (u16, i64),
+    /// Registers saved on the stack, with their offset from the CFA
+    pub saved_registers: Vec<(u16, i64)>,
+}
+
+impl CallFrameInfo {
+    pub fn load(object: &object::File, endian: gimli::RunTimeEndian) -> CallFrameInfo {
+        let section_addr = |name: &str| {
+            object
+                .section_by_name(name)
+                .map(|section| section.address())
+                .unwrap_or(0)
+        };
+        let section_data = |name: &str| {
+            object
+                .section_data_by_name(name)
+                .map(|data| data.into_owned())
+                .unwrap_or_default()
+        };
+        CallFrameInfo {
+            eh_frame: section_data(".eh_frame"),
+            eh_frame_addr: section_addr(".eh_frame"),
+            debug_frame: section_data(".debug_frame"),
+            text_addr: section_addr(".text"),
+            endian,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.eh_frame.is_empty() && self.debug_frame.is_empty()
+    }
+
+    /// Returns the register and offset the CFA is computed from at `addr`. CFAs defined by a
+    /// DWARF expression aren't supported; compilers only emit those for unusual stack layouts.
+    pub fn cfa_rule(&self, addr: usize) -> Option<(u16, i64)> {
+        self.unwind_rule(addr).map(|rule| rule.cfa)
+    }
+
+    /// Returns how to recover the caller's frame at `addr`.
+    pub fn unwind_rule(&self, addr: usize) -> Option<UnwindRule> {
+        let bases = gimli::BaseAddresses::default()
+            .set_eh_frame(self.eh_frame_addr)
+            .set_text(self.text_addr);
+        let row = self
+            .unwind_row(
+                &gimli::EhFrame::new(&self.eh_frame, self.endian),
+                &bases,
+                addr,
+            )
+            .or_else(|| {
+                self.unwind_row(
+                    &gimli::DebugFrame::new(&self.debug_frame, self.endian),
+                    &bases,
+                    addr,
+                )
+            })?;
+        let cfa = match *row.cfa() {
+            gimli::CfaRule::RegisterAndOffset { register, offset } => (register.0, offset),
+            gimli::CfaRule::Expression(_) => return None,
+        };
+        let saved_registers = (0..=16)
+            .filter_map(|register| match row.register(gimli::Register(register)) {
+                gimli::RegisterRule::Offset(offset) => Some((register, offset)),
+                _ => None,
+            })
+            .collect();
+        Some(UnwindRule {
+            cfa,
+            saved_registers,
+        })
+    }
+
+    fn unwind_row<'a, S: gimli::UnwindSection<gimli::EndianSlice<'a, gimli::RunTimeEndian>>>(
+        &self,
+        section: &S,
+        bases: &gimli::BaseAddresses,
+        addr: usize,
+    ) -> Option<gimli::UnwindTableRow<gimli::EndianSlice<'a, gimli::RunTimeEndian>>> {
+        let mut ctx = gimli::UninitializedUnwindContext::new();
+        section
+            .unwind_info_for_address(bases, &mut ctx, addr as u64, S::cie_from_offset)
+            .ok()
+    }
 }
 
 // based on dwarf_dump.rs
@@ -344,6 +1490,13 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
+        gimli::AttributeValue::DebugStrOffsetsIndex(_) | gimli::AttributeValue::DebugLineStrRef(_) => {
+            let s = dwarf.attr_string(unit, value)?;
+            Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
+        }
+        gimli::AttributeValue::DebugAddrIndex(index) => {
+            Ok(DebugValue::Uint(dwarf.address(unit, index)?))
+        }
         gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
@@ -612,3 +1765,24 @@ fn dump_op<R: Reader, W: Write>(
     };
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header_dwo_id() {
+        // A 32-bit DWARF 5 skeleton unit header, then a DWARF 4 one, which has no dwo_id
+        let skeleton = [
+            0x14, 0, 0, 0, 5, 0, 0x04, 8, 0, 0, 0, 0, 0xef, 0xcd, 0xab, 0x89, 0x67, 0x45, 0x23,
+            0x01,
+        ];
+        let mut section = vec![0xff; 3];
+        section.extend_from_slice(&skeleton);
+        let reader = gimli::EndianSlice::new(&section, gimli::LittleEndian);
+        assert_eq!(header_dwo_id(reader, 3).unwrap(), Some(0x0123_4567_89ab_cdef));
+        let compile = [0x07, 0, 0, 0, 4, 0, 0, 0, 0, 0, 8];
+        let reader = gimli::EndianSlice::new(&compile, gimli::LittleEndian);
+        assert_eq!(header_dwo_id(reader, 0).unwrap(), None);
+    }
+}
@@ -1,15 +1,29 @@
 use nix::sys::ptrace;
 use nix::sys::signal;
+use nix::errno::Errno;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use std::process::Child;
 use std::process::Command;
 use std::os::unix::process::CommandExt;
-use std::io;
-use crate::dwarf_data::{DwarfData, /*Error as DwarfError*/};
+use crate::arch::{self, Arch, Memory, Registers};
+use crate::dwarf_data::Line;
+use crate::dwarf_expr::EvalContext;
+use crate::modules::ModuleList;
+use crate::Error;
 use std::mem::size_of;
 use std::collections::HashMap;
 
+/// Signals that programs get in the normal course of things, e.g. when a child exits or the
+/// terminal is resized. Like gdb, we don't stop for these, but hand them straight on.
+const PASS_SIGNALS: [signal::Signal; 7] = [
+    signal::Signal::SIGALRM,
+    signal::Signal::SIGURG,
+    signal::Signal::SIGCHLD,
+    signal::Signal::SIGIO,
+    signal::Signal::SIGVTALRM,
+    signal::Signal::SIGPROF,
+    signal::Signal::SIGWINCH,
+];
 
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
@@ -22,6 +36,36 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior stopped on its way into a system call. Contains the syscall number
+    /// and its six argument registers. Only reported while syscall stops are turned on.
+    SyscallEntry(u64, [u64; 6]),
+
+    /// Indicates the inferior stopped on its way back from a system call. Contains the syscall
+    /// number and its return value.
+    SyscallExit(u64, i64),
+
+    /// Indicates the inferior replaced itself with a new program. Contains the new program's
+    /// instruction pointer.
+    Exec(usize),
+
+    /// Indicates the inferior forked. Contains the new process's pid, which has been detached to
+    /// run on its own, and the instruction pointer.
+    Forked(Pid, usize),
+
+    /// Indicates the inferior started a thread, with its thread id and the instruction pointer.
+    /// Only `wait` returns this: wakeup and step let the thread go and carry on.
+    Cloned(Pid, usize),
+}
+
+/// A frame of the inferior's stack, as found by Inferior::frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub line: Option<Line>,
+    /// Where the frame is executing: the instruction pointer for the innermost frame, and the
+    /// return address into the others
+    pub address: usize,
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
@@ -34,128 +78,854 @@ fn child_traceme() -> Result<(), std::io::Error> {
 }
 
 pub struct Inferior {
-    child: Child,
+    /// Not necessarily our child: checkpoints are forked from the inferior itself
+    pid: Pid,
+    /// Resume with PTRACE_SYSCALL rather than PTRACE_CONT, to stop at every syscall
+    syscall_stops: bool,
+    /// Whether the last syscall stop was an entry, so the next one is its exit. The kernel
+    /// doesn't tell the two apart.
+    in_syscall: bool,
+    /// A signal the inferior stopped with, to be delivered when it resumes
+    pending_signal: Option<signal::Signal>,
+    /// Whether the last fork reported was a vfork, whose child borrows the inferior's memory
+    /// until it execs or exits
+    vforked: bool,
+    /// Threads besides the main one, which run on their own. See `release_thread`.
+    threads: Vec<Pid>,
+    arch: &'static dyn Arch,
 }
 
 #[derive(Clone)]
 pub struct BreakPoint{
     pub address:usize,
-    pub orig_byte:u8,
+    /// The code the breakpoint instruction was written over
+    pub orig_bytes:Vec<u8>,
+    /// Set by deet itself (e.g. to hear about shared library loads) rather than by the user
+    pub internal:bool,
+    /// A one-shot stop (`tbreak`, `until`, `advance`) is waiting here. The inferior stops here
+    /// even if the breakpoint is internal, and wakeup clears this when it does.
+    pub one_shot:bool,
+    /// Whether the breakpoint stays once its one-shot stop is used up. It doesn't if it was
+    /// only ever there for the one-shot stop.
+    pub permanent:bool,
+}
+
+impl BreakPoint{
+    /// A breakpoint that stays until it is removed.
+    pub fn new(address:usize, orig_bytes:Vec<u8>, internal:bool) -> BreakPoint{
+        BreakPoint{ address, orig_bytes, internal, one_shot:false, permanent:true }
+    }
+
+    /// A breakpoint that is taken out the first time it is hit.
+    pub fn one_shot(address:usize, orig_bytes:Vec<u8>, internal:bool) -> BreakPoint{
+        BreakPoint{ address, orig_bytes, internal, one_shot:true, permanent:false }
+    }
+}
+
+/// personality(2) flag that turns off address space randomization. We start the inferior with it
+/// so that libraries (and PIE executables) land at the same addresses on every run, like gdb.
+const ADDR_NO_RANDOMIZE: libc::c_ulong = 0x0040000;
+
+fn child_disable_aslr() -> Result<(), std::io::Error> {
+    unsafe { libc::personality(ADDR_NO_RANDOMIZE) };
+    Ok(())
+}
+
+/// Options set on every inferior. TRACESYSGOOD marks syscall stops so they can't be mistaken for
+/// breakpoints, and TRACEEXEC reports exec as an event rather than a plain SIGTRAP. TRACEFORK and
+/// TRACEVFORK let us take our breakpoints out of new processes before they run, and
+/// TRACEVFORKDONE tells us when a vfork child is done with the memory it shares with the
+/// inferior. TRACECLONE keeps threads traced, so that one hitting a breakpoint isn't killed.
+fn trace_options() -> ptrace::Options {
+    ptrace::Options::PTRACE_O_TRACESYSGOOD
+        | ptrace::Options::PTRACE_O_TRACEEXEC
+        | ptrace::Options::PTRACE_O_TRACEFORK
+        | ptrace::Options::PTRACE_O_TRACEVFORK
+        | ptrace::Options::PTRACE_O_TRACEVFORKDONE
+        | ptrace::Options::PTRACE_O_TRACECLONE
+}
+
+/// Runs `parent` until the child of its vfork has let go of its memory. Returns false if the
+/// parent died instead.
+fn wait_for_vfork_done(parent: Pid) -> Result<bool, Error> {
+    let mut signal = None;
+    loop {
+        ptrace::cont(parent, signal.take())?;
+        match waitpid(parent, Some(WaitPidFlag::__WALL))? {
+            WaitStatus::PtraceEvent(_pid, _signal, libc::PTRACE_EVENT_VFORK_DONE) => return Ok(true),
+            WaitStatus::Stopped(_pid, stop_signal) => signal = Some(stop_signal),
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+/// How many integer and floating point arguments the System V AMD64 calling convention passes
+/// in registers (rdi, rsi, rdx, rcx, r8, r9 and xmm0-xmm7). The rest go on the stack.
+#[cfg(target_arch = "x86_64")]
+const INTEGER_ARGUMENT_REGISTERS: usize = 6;
+#[cfg(target_arch = "x86_64")]
+const FLOAT_ARGUMENT_REGISTERS: usize = 8;
+
+/// An argument to a function called with Inferior::start_call.
+pub enum CallArgument {
+    /// Integers, characters and pointers, passed in a general purpose register
+    Integer(u64),
+    /// A double, passed in an SSE register
+    Float(f64),
+    /// Data such as a string literal, copied onto the inferior's stack and passed as a pointer
+    Bytes(Vec<u8>),
+}
+
+/// The inferior's state from before Inferior::start_call, for Inferior::end_call to put back.
+#[cfg(target_arch = "x86_64")]
+pub struct SavedCall {
+    regs: libc::user_regs_struct,
+    fpregs: libc::user_fpregs_struct,
+    pending_signal: Option<signal::Signal>,
 }
 
+/// Calls are only injected following the System V AMD64 convention, so elsewhere there is never
+/// one to save.
+#[cfg(not(target_arch = "x86_64"))]
+pub enum SavedCall {}
+
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
 }
 
 impl Inferior {
-    /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
-    /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>,break_list:&mut HashMap<usize,BreakPoint>) -> Option<Inferior> {
-        // TODO: implement me!
-        // println!("new inter {:?} {:?}",target,args);
+    /// Attempts to start a new inferior process. The inferior is left stopped right after exec,
+    /// before the dynamic linker has run, so that breakpoints can be installed.
+    pub fn new(target: &str, args: &Vec<String>) -> Result<Inferior, Error> {
         let mut child_no_spawn_args= Command::new(target);
         child_no_spawn_args.args(args);
         unsafe{
+            child_no_spawn_args.pre_exec(child_disable_aslr);
             child_no_spawn_args.pre_exec(child_traceme);
         }
 
-        let child_spawn=child_no_spawn_args.spawn().ok()?;
-        let mut _ret=Inferior{
-            child:child_spawn,
-        };
+        let child_spawn=child_no_spawn_args.spawn().map_err(|err| Error::Spawn(target.to_string(), err))?;
+        let mut _ret=Inferior::traced(Pid::from_raw(child_spawn.id() as i32));
  
-        let status=_ret.wait(None).ok()?;
-        match status{
-            Status::Stopped(sign,_)=>{
-                match sign{
-                    signal::Signal::SIGTRAP => {
-                        let bp = break_list.clone();
-                        for addr in bp.keys() {
-                            match _ret.write_byte(*addr, 0xcc as u8).ok() {
-                                Some(orig_instr) => { break_list.insert(*addr, BreakPoint{ address: *addr, orig_byte: orig_instr }); },
-                                None => {println!("Write Memory Error:inferior write byte on invalid address 0x{:x}",*addr);},
-                            }
-                        }          
-                        return Some(_ret);}
-                    _=>{return None;}
-                };
+        match _ret.wait(None)? {
+            Status::Stopped(signal::Signal::SIGTRAP,_)=>{
+                ptrace::setoptions(_ret.pid(), trace_options())?;
+                Ok(_ret)
             }
-            _=>{return None;}
+            Status::Stopped(sign,_)=>Err(Error::UnexpectedStop(format!("stopped by {} before exec", sign))),
+            Status::Exited(code)=>Err(Error::UnexpectedStop(format!("exited with status {} before exec", code))),
+            Status::Signaled(sign)=>Err(Error::UnexpectedStop(format!("killed by {} before exec", sign))),
+            _=>Err(Error::UnexpectedStop(String::from("unexpected event before exec"))),
         }
     }
-    pub fn print_backtrace(&self,debug_data:&DwarfData) -> Result<(), nix::Error>{
-        if let Ok(_r)= ptrace::getregs(self.getpid()){
 
-            let mut instruction_ptr=_r.rip;
-            let mut base_ptr=_r.rbp;
-            loop{
-                // println!("%rip register: {:#x}", _rip);
-                if let Some(filename_line) = debug_data.get_line_from_addr(instruction_ptr as usize){
-                    if let Some(function_name)=debug_data.get_function_from_addr(instruction_ptr as usize){
-                        println!("{}    ({}:{}) ",function_name,filename_line.file,filename_line.number);
-                        if function_name==String::from("main"){
-                            break;
-                        }
-                        instruction_ptr=ptrace::read(self.pid(), (base_ptr+8) as ptrace::AddressType)? as u64;
-                        base_ptr = ptrace::read(self.pid(), base_ptr as ptrace::AddressType)? as u64;
-                    }
+    pub fn print_backtrace(&self,debug_data:&ModuleList) -> Result<(), Error>{
+        for frame in self.backtrace(debug_data)? {
+            println!("{}",frame);
+        }
+        Ok(())
+    }
+
+    /// One formatted line per frame of frames(), innermost first.
+    pub fn backtrace(&self,debug_data:&ModuleList) -> Result<Vec<String>, Error>{
+        Ok(self.frames(debug_data)?.iter().map(|frame| match &frame.line{
+            Some(filename_line)=>format!("{}    ({}:{}) ",frame.function,filename_line.file,filename_line.number),
+            None=>format!("{}    (0x{:x}) ",frame.function,frame.address),
+        }).collect())
+    }
+
+    /// Walks the frame pointer chain and returns the frames on the stack, innermost first. Code
+    /// inlined into a function gets a frame of its own, above the function's. Stops at main, or
+    /// at the first frame we can't even find a function name for.
+    pub fn frames(&self,debug_data:&ModuleList) -> Result<Vec<Frame>, Error>{
+        let mut frames=Vec::new();
+        let mut regs=self.registers()?;
+        let mut innermost=true;
+        loop{
+            let instruction_ptr=regs.pc();
+            // A return address is the instruction after the call, which may already be past
+            // the code of an inlined call it was made from
+            let lookup_ptr=if innermost {instruction_ptr} else {instruction_ptr-1};
+            innermost=false;
+            let inline_frames=debug_data.get_frames_at(lookup_ptr as usize);
+            if inline_frames.is_empty(){
+                break;
+            }
+            let mut function_name=String::new();
+            for (name,line) in inline_frames{
+                function_name=name.clone();
+                frames.push(Frame{function:name,line,address:instruction_ptr as usize});
+            }
+            if function_name==String::from("main"){
+                break;
+            }
+            regs=match self.arch.caller_registers(&regs, self){
+                Some(caller)=>caller,
+                None=>break,
+            };
+        }
+        Ok(frames)
+    }
+
+    /// What the kernel said about the signal the inferior last stopped with, such as the
+    /// address a SIGSEGV faulted on.
+    pub fn get_siginfo(&self) -> Result<libc::siginfo_t, Error>{
+        Ok(ptrace::getsiginfo(self.pid())?)
+    }
+
+    /// The registers as x86-64's `user_regs_struct`, for calling functions the way its ABI says.
+    /// Everything else goes through `registers`.
+    #[cfg(target_arch = "x86_64")]
+    fn get_registers(&self) -> Result<libc::user_regs_struct, Error>{
+        Ok(ptrace::getregs(self.pid())?)
+    }
+
+    /// The architecture the inferior runs on.
+    pub fn arch(&self) -> &'static dyn Arch{
+        self.arch
+    }
+
+    /// The general purpose registers, read with PTRACE_GETREGSET so that they come out the same
+    /// way on every architecture.
+    pub fn registers(&self) -> Result<Registers, Error>{
+        let prstatus=self.get_register_set()?;
+        self.arch.decode_registers(&prstatus).ok_or_else(|| {
+            Error::UnexpectedStop(String::from("short NT_PRSTATUS register set"))
+        })
+    }
+
+    /// Moves the program counter.
+    pub fn set_pc(&self, pc: u64) -> Result<(), Error>{
+        let mut prstatus=self.get_register_set()?;
+        self.arch.encode_register(&mut prstatus, self.arch.pc_register(), pc);
+        self.set_register_set(&mut prstatus)
+    }
+
+    /// The NT_PRSTATUS register set as the kernel hands it out, for putting back as it was with
+    /// `set_register_set`.
+    pub fn get_register_set(&self) -> Result<Vec<u8>, Error>{
+        let mut prstatus=vec![0u8; self.arch.prstatus_size()];
+        let mut iov=libc::iovec{
+            iov_base: prstatus.as_mut_ptr() as *mut libc::c_void,
+            iov_len: prstatus.len(),
+        };
+        let ret = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETREGSET,
+                self.pid().as_raw(),
+                arch::NT_PRSTATUS,
+                &mut iov as *mut libc::iovec,
+            )
+        };
+        Errno::result(ret)?;
+        prstatus.truncate(iov.iov_len);
+        Ok(prstatus)
+    }
+
+    pub fn set_register_set(&self, prstatus: &mut [u8]) -> Result<(), Error>{
+        let mut iov=libc::iovec{
+            iov_base: prstatus.as_mut_ptr() as *mut libc::c_void,
+            iov_len: prstatus.len(),
+        };
+        let ret = unsafe {
+            libc::ptrace(
+                libc::PTRACE_SETREGSET,
+                self.pid().as_raw(),
+                arch::NT_PRSTATUS,
+                &mut iov as *mut libc::iovec,
+            )
+        };
+        Ok(Errno::result(ret).map(drop)?)
+    }
+
+    /// Writes a breakpoint instruction at `addr`, and returns the code it replaced.
+    pub fn insert_breakpoint(&mut self, addr: usize) -> Result<Vec<u8>, Error>{
+        let instruction=self.arch.breakpoint_instruction();
+        let orig_bytes=self.read_bytes(addr, instruction.len())?;
+        self.write_bytes(addr, instruction)?;
+        Ok(orig_bytes)
+    }
+
+    /// An Inferior for a process we are already tracing.
+    fn traced(pid: Pid) -> Inferior {
+        Inferior {
+            pid,
+            syscall_stops: false,
+            in_syscall: false,
+            pending_signal: None,
+            vforked: false,
+            threads: Vec::new(),
+            arch: arch::native(),
+        }
+    }
+
+    /// Turns stopping at every syscall entry and exit on or off, from the next time the inferior
+    /// is continued.
+    pub fn set_syscall_stops(&mut self, on: bool) {
+        self.syscall_stops = on;
+    }
+
+    pub fn getpid(&self) -> Pid{
+        self.pid
+    }
+
+    /// Continues the inferior until its next stop, delivering the signal it last stopped with. If
+    /// it is sitting on a breakpoint, the original instruction is stepped over first. When it
+    /// stops at a breakpoint, the pc is moved back onto the breakpoint instruction if it went past
+    /// it, so the returned address is the breakpoint's own, and a one-shot stop there is used up.
+    pub fn wakeup(&mut self,break_list:&mut HashMap<usize,BreakPoint>) -> Result<Status, Error>{
+        let instruction_ptr=self.registers()?.pc() as usize;
+        if break_list.contains_key(&instruction_ptr){
+            match self.step(break_list)? {
+                Status::Stopped(_, _) => {},
+                status => return Ok(status),
+            }
+        }
+        let status=loop{
+            let signal=self.pending_signal.take();
+            if self.syscall_stops {
+                ptrace::syscall(self.getpid(), signal)?;
+            } else {
+                self.in_syscall=false;
+                ptrace::cont(self.getpid(), signal)?;
+            }
+            match self.wait_main_thread(break_list)? {
+                Status::Cloned(tid, _) => self.release_thread(tid)?,
+                // The signal is delivered as it goes on
+                Status::Stopped(signal, _) if PASS_SIGNALS.contains(&signal) => {}
+                status => break status,
+            }
+        };
+        match status {
+            Status::Stopped(signal::Signal::SIGTRAP, instruction_ptr)
+                if break_list.contains_key(&self.breakpoint_address(instruction_ptr)) =>
+            {
+                let addr=self.breakpoint_address(instruction_ptr);
+                if addr!=instruction_ptr{
+                    self.set_pc(addr as u64)?;
                 }
+                self.clear_one_shot(break_list, addr);
+                Ok(Status::Stopped(signal::Signal::SIGTRAP, addr))
+            }
+            Status::Forked(pid, instruction_ptr) => {
+                self.detach_fork(self.pid(), pid, self.vforked, break_list)?;
+                Ok(Status::Forked(pid, instruction_ptr))
+            }
+            status => Ok(status),
+        }
+    }
+
+    /// Where the breakpoint the inferior trapped on is, given the pc it stopped with.
+    fn breakpoint_address(&self, instruction_ptr: usize) -> usize{
+        self.arch.breakpoint_address(instruction_ptr as u64) as usize
+    }
+
+    /// Executes a single instruction. A breakpoint on that instruction is lifted for the step and
+    /// put back afterwards. Stepping onto a breakpoint stops before its breakpoint instruction
+    /// runs. A signal the inferior last stopped with is delivered first, so the step may land in
+    /// its handler.
+    pub fn step(&mut self,break_list:&HashMap<usize,BreakPoint>) -> Result<Status, Error>{
+        let instruction_ptr=self.registers()?.pc() as usize;
+        let breakpoint=break_list.get(&instruction_ptr);
+        if let Some(breakpoint)=breakpoint{
+            self.write_bytes(breakpoint.address, &breakpoint.orig_bytes)?;
+        }
+        self.in_syscall=false;
+        let status=loop{
+            let stop_at=self.start_step()?;
+            let status=self.wait(None)?;
+            match self.end_step(stop_at, status)? {
+                // The instruction was a fork, which hasn't finished yet
+                Status::Forked(pid, _) => self.detach_fork(self.pid(), pid, self.vforked, break_list)?,
+                Status::Cloned(tid, _) => self.release_thread(tid)?,
+                // The step didn't happen; it is tried again with the signal delivered
+                Status::Stopped(signal, _) if PASS_SIGNALS.contains(&signal) => {}
+                status => break status,
+            }
+        };
+        if let (Some(breakpoint), Status::Stopped(_, _))=(breakpoint, &status){
+            self.write_bytes(breakpoint.address, self.arch.breakpoint_instruction())?;
+        }
+        Ok(status)
+    }
+
+    /// Sets the process off on one instruction. Where the kernel can't single-step, the
+    /// instruction is decoded to see where it goes, and a breakpoint put there instead. That
+    /// breakpoint and the code under it are returned for `end_step` to take out again.
+    fn start_step(&mut self) -> Result<Option<(usize, Vec<u8>)>, Error>{
+        let signal=self.pending_signal.take();
+        let err=match ptrace::step(self.pid(), signal){
+            Ok(()) => return Ok(None),
+            Err(err) => err,
+        };
+        let next=match self.arch.next_pc(&self.registers()?, self){
+            Some(next) if matches!(err, nix::Error::Sys(Errno::EIO)) => next as usize,
+            _ => return Err(err.into()),
+        };
+        let orig_bytes=self.insert_breakpoint(next)?;
+        ptrace::cont(self.pid(), signal)?;
+        Ok(Some((next, orig_bytes)))
+    }
+
+    /// Takes out the breakpoint `start_step` stepped with, if it needed one, leaving the pc on it
+    /// if that's where the process stopped.
+    fn end_step(&mut self, stop_at: Option<(usize, Vec<u8>)>, status: Status) -> Result<Status, Error>{
+        let (addr, orig_bytes)=match stop_at{
+            Some(stop_at) => stop_at,
+            None => return Ok(status),
+        };
+        // The process may be gone, and its code with it
+        self.write_bytes(addr, &orig_bytes).ok();
+        match status{
+            Status::Stopped(signal::Signal::SIGTRAP, instruction_ptr)
+                if self.breakpoint_address(instruction_ptr)==addr =>
+            {
+                self.set_pc(addr as u64)?;
+                Ok(Status::Stopped(signal::Signal::SIGTRAP, addr))
+            }
+            status => Ok(status),
+        }
+    }
+
+    /// Uses up the one-shot stop at `addr`, if there is one. A breakpoint that was only there for
+    /// it is taken out, and the original instruction put back if the process is still there to
+    /// put it back in. Others at the same address, set with `break` or by deet itself, stay.
+    pub fn clear_one_shot(&mut self,break_list:&mut HashMap<usize,BreakPoint>,addr:usize){
+        match break_list.get_mut(&addr){
+            Some(breakpoint) if breakpoint.one_shot && breakpoint.permanent => breakpoint.one_shot=false,
+            Some(breakpoint) if breakpoint.one_shot => {
+                self.write_bytes(addr, &breakpoint.orig_bytes).ok();
+                break_list.remove(&addr);
+            }
+            _ => {}
+        }
+    }
+
+    /// Lets a process `parent` forked run on its own, once our breakpoints are out of its copy
+    /// of the inferior's memory. The child of a vfork has no copy: it borrows the inferior's
+    /// memory, so the breakpoints are out of the inferior too until the child execs or exits.
+    /// The parent is suspended until then anyway, and they go back in afterwards.
+    fn detach_fork(&self, parent: Pid, pid: Pid, vforked: bool, break_list:&HashMap<usize,BreakPoint>) -> Result<(), Error>{
+        // It starts out stopped, but we have to collect that stop before touching it
+        waitpid(pid, Some(WaitPidFlag::__WALL))?;
+        let mut forked=Inferior::traced(pid);
+        for breakpoint in break_list.values(){
+            forked.write_bytes(breakpoint.address, &breakpoint.orig_bytes).ok();
+        }
+        ptrace::detach(pid, None)?;
+        if vforked && wait_for_vfork_done(parent)? {
+            let mut parent=Inferior::traced(parent);
+            for breakpoint in break_list.values().filter(|breakpoint| !breakpoint.orig_bytes.is_empty()){
+                parent.write_bytes(breakpoint.address, self.arch.breakpoint_instruction())?;
             }
         }
         Ok(())
     }
 
-    pub fn getpid(&self) -> Pid{
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
-    }
-
-    pub fn wakeup(&mut self,break_list:&HashMap<usize,BreakPoint>) -> Result<Status, nix::Error>{
-        if let Ok(mut _r)= ptrace::getregs(self.getpid()){
-            let instruction_ptr=_r.rip as usize;
-            if let Some(breakpoint)= break_list.get(&(instruction_ptr-1)){
-                self.write_byte(breakpoint.address, breakpoint.orig_byte).ok();
-                _r.rip=(_r.rip-1) as u64;
-                ptrace::setregs(self.pid(), _r).ok();
-                ptrace::step(self.pid(),None).ok();
-                
-                match self.wait(None).ok().unwrap() {
-                    Status::Exited(exit_code) => return Ok(Status::Exited(exit_code)),
-                    Status::Signaled(signal) => return Ok(Status::Signaled(signal)),
-                    Status::Stopped(_, _) => {
-                        self.write_byte(instruction_ptr - 1, 0xcc as u8).ok();
+    /// Lets a thread the inferior started run. Threads share the inferior's memory, breakpoints
+    /// and all, so unlike forked processes they can't be cleaned up and detached: the SIGTRAP of
+    /// a breakpoint would kill the whole process. They stay traced instead, but only the main
+    /// thread is followed. The others are seen to while it runs, by `wait_main_thread`.
+    fn release_thread(&mut self, tid: Pid) -> Result<(), Error>{
+        waitpid(tid, Some(WaitPidFlag::__WALL))?;
+        self.threads.push(tid);
+        Ok(ptrace::cont(tid, None)?)
+    }
+
+    /// Waits for the main thread like `wait` does, meanwhile letting the other threads carry on
+    /// from their stops. Those stopped by a signal get it delivered, and those at one of our
+    /// breakpoints are stepped past it.
+    fn wait_main_thread(&mut self, break_list:&HashMap<usize,BreakPoint>) -> Result<Status, Error>{
+        let options=WaitPidFlag::__WALL | WaitPidFlag::WNOHANG;
+        // There is no waiting on a set of threads short of all our children, which would take
+        // in checkpoints too, so go round them
+        while !self.threads.is_empty() {
+            match waitpid(self.pid(), Some(options))? {
+                WaitStatus::StillAlive => {}
+                wait_status => return self.status(wait_status),
+            }
+            let mut stopped=false;
+            for tid in self.threads.clone(){
+                match waitpid(tid, Some(options)) {
+                    Ok(WaitStatus::StillAlive) => {}
+                    Ok(wait_status) => {
+                        self.resume_thread(tid, wait_status, break_list)?;
+                        stopped=true;
                     }
+                    Err(_) => self.threads.retain(|&thread| thread != tid),
                 }
             }
-
+            if !stopped {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
         }
-        ptrace::cont(self.getpid(), None).ok();
         self.wait(None)
     }
 
+    /// Continues a thread other than the main one from a stop.
+    fn resume_thread(&mut self, tid: Pid, wait_status: WaitStatus, break_list:&HashMap<usize,BreakPoint>) -> Result<(), Error>{
+        let signal=match wait_status {
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                self.threads.retain(|&thread| thread != tid);
+                return Ok(());
+            }
+            WaitStatus::Stopped(_tid, signal::Signal::SIGTRAP) => {
+                self.step_thread_past_breakpoint(tid, break_list)?;
+                None
+            }
+            WaitStatus::Stopped(_tid, signal::Signal::SIGSTOP) => None,
+            WaitStatus::Stopped(_tid, signal) => Some(signal),
+            WaitStatus::PtraceEvent(_tid, _signal, libc::PTRACE_EVENT_CLONE) => {
+                self.release_thread(Pid::from_raw(ptrace::getevent(tid)? as i32))?;
+                None
+            }
+            WaitStatus::PtraceEvent(_tid, _signal, event @ libc::PTRACE_EVENT_FORK)
+            | WaitStatus::PtraceEvent(_tid, _signal, event @ libc::PTRACE_EVENT_VFORK) => {
+                let pid=Pid::from_raw(ptrace::getevent(tid)? as i32);
+                self.detach_fork(tid, pid, event == libc::PTRACE_EVENT_VFORK, break_list)?;
+                None
+            }
+            _ => None,
+        };
+        // The thread may have been killed meanwhile
+        ptrace::cont(tid, signal).ok();
+        Ok(())
+    }
+
+    /// Moves a thread that trapped on one of our breakpoints past it: the original instruction
+    /// goes back in for a single step. The other threads keep running meanwhile, and one of
+    /// them can run through the breakpoint without stopping.
+    fn step_thread_past_breakpoint(&self, tid: Pid, break_list:&HashMap<usize,BreakPoint>) -> Result<(), Error>{
+        let mut thread=Inferior::traced(tid);
+        let addr=self.breakpoint_address(thread.registers()?.pc() as usize);
+        let breakpoint=match break_list.get(&addr){
+            Some(breakpoint) => breakpoint,
+            None => return Ok(()),
+        };
+        thread.set_pc(addr as u64)?;
+        thread.write_bytes(addr, &breakpoint.orig_bytes)?;
+        ptrace::step(tid, None)?;
+        waitpid(tid, Some(WaitPidFlag::__WALL))?;
+        thread.write_bytes(addr, self.arch.breakpoint_instruction())?;
+        Ok(())
+    }
+
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    pub fn kill(&mut self) -> Result<(), Error> {
+        signal::kill(self.pid, signal::Signal::SIGKILL)?;
+        // The main thread's exit isn't reported until the other threads have been collected
+        for tid in self.threads.drain(..) {
+            waitpid(tid, Some(WaitPidFlag::__WALL)).ok();
+        }
+        Ok(())
+    }
+
+    /// Makes the stopped inferior fork, by pointing it at a fork syscall, and returns the new
+    /// process. Both are left stopped exactly where this one was, and the copy has the same
+    /// memory, breakpoints included.
+    pub fn fork(&mut self) -> Result<Inferior, Error> {
+        let mut saved = self.get_register_set()?;
+        let addr = self.registers()?.pc() as usize;
+        let syscall = self.arch.syscall_instruction();
+        let orig_bytes = self.read_bytes(addr, syscall.len())?;
+        self.write_bytes(addr, syscall)?;
+        let mut syscall_regs = saved.clone();
+        let (number, args) = self.arch.fork_syscall();
+        self.arch.encode_register(&mut syscall_regs, self.arch.syscall_number_register(), number);
+        for (register, value) in self.arch.syscall_argument_registers().iter().zip(args) {
+            self.arch.encode_register(&mut syscall_regs, *register, *value);
+        }
+        self.set_register_set(&mut syscall_regs)?;
+        // PTRACE_O_TRACEFORK has the kernel trace the new process too, and stop it before it runs
+        // anything
+        let forked = self.step_through_fork();
+        self.write_bytes(addr, &orig_bytes)?;
+        self.set_register_set(&mut saved)?;
+
+        // The copy is stopped just past our syscall, so it needs putting back the same way
+        let mut forked = forked?;
+        forked.write_bytes(addr, &orig_bytes)?;
+        forked.set_register_set(&mut saved)?;
+        Ok(forked)
+    }
+
+    fn step_through_fork(&self) -> Result<Inferior, Error> {
+        let pid = loop {
+            ptrace::step(self.pid(), None)?;
+            match waitpid(self.pid(), None)? {
+                WaitStatus::PtraceEvent(_pid, _signal, libc::PTRACE_EVENT_FORK) => {
+                    break Pid::from_raw(ptrace::getevent(self.pid())? as i32);
+                }
+                // The syscall returned without forking, with -errno as its result
+                WaitStatus::Stopped(_pid, signal::Signal::SIGTRAP) => {
+                    let regs = self.registers()?;
+                    let result = regs.get(self.arch.syscall_result_register()).unwrap_or(0) as i64;
+                    return Err(nix::Error::Sys(Errno::from_i32(-result as i32)).into());
+                }
+                // A signal arrived first (e.g. a checkpoint hearing that its child exited).
+                // Discard it, since the process shouldn't run anything but the syscall.
+                WaitStatus::Stopped(_pid, _signal) => {}
+                _ => return Err(nix::Error::Sys(Errno::ESRCH).into()),
+            }
+        };
+        // Finish the syscall in the parent, and collect the stop the new process starts with
+        ptrace::step(self.pid(), None)?;
+        waitpid(self.pid(), None)?;
+        waitpid(pid, Some(WaitPidFlag::__WALL))?;
+        Ok(Inferior::traced(pid))
+    }
+
+    /// Sets the stopped inferior up to call the function at `function` with `args`, following the
+    /// System V AMD64 calling convention, and to return to `return_addr`. Continuing it then runs
+    /// the function, and once it comes back to `return_addr` (where the caller should have put a
+    /// breakpoint), call_result has the return value and end_call restores everything.
+    #[cfg(target_arch = "x86_64")]
+    pub fn start_call(
+        &mut self,
+        function: usize,
+        return_addr: usize,
+        args: &[CallArgument],
+    ) -> Result<SavedCall, Error> {
+        let saved = SavedCall {
+            regs: self.get_registers()?,
+            fpregs: self.get_fp_registers()?,
+            pending_signal: self.pending_signal.take(),
+        };
+        let mut regs = saved.regs;
+        let mut fpregs = saved.fpregs;
+        // The function may be interrupting a leaf function that keeps data in the red zone
+        let mut sp = saved.regs.rsp - self.arch.red_zone_size();
+
+        let mut integers = Vec::new();
+        let mut floats = Vec::new();
+        let mut stack = Vec::new();
+        for arg in args {
+            match arg {
+                CallArgument::Integer(value) if integers.len() < INTEGER_ARGUMENT_REGISTERS => {
+                    integers.push(*value)
+                }
+                CallArgument::Float(value) if floats.len() < FLOAT_ARGUMENT_REGISTERS => {
+                    floats.push(*value)
+                }
+                CallArgument::Integer(value) => stack.push(*value),
+                CallArgument::Float(value) => stack.push(value.to_bits()),
+                CallArgument::Bytes(bytes) => {
+                    sp = (sp - bytes.len() as u64) & !0xf;
+                    self.write_bytes(sp as usize, bytes)?;
+                    if integers.len() < INTEGER_ARGUMENT_REGISTERS {
+                        integers.push(sp);
+                    } else {
+                        stack.push(sp);
+                    }
+                }
+            }
+        }
+
+        // The stack must be 16-byte aligned at the call, i.e. just before the return address is
+        // pushed, with the stack arguments above it in order
+        sp = (sp - 8 * stack.len() as u64) & !0xf;
+        for (i, value) in stack.iter().enumerate() {
+            self.write_bytes(sp as usize + 8 * i, &value.to_le_bytes())?;
+        }
+        sp -= 8;
+        self.write_bytes(sp as usize, &(return_addr as u64).to_le_bytes())?;
+
+        for (register, value) in [
+            &mut regs.rdi,
+            &mut regs.rsi,
+            &mut regs.rdx,
+            &mut regs.rcx,
+            &mut regs.r8,
+            &mut regs.r9,
+        ]
+        .iter_mut()
+        .zip(integers)
+        {
+            **register = value;
+        }
+        for (i, value) in floats.iter().enumerate() {
+            // Each xmm register is four u32s, the double going in the low two
+            let bits = value.to_bits();
+            fpregs.xmm_space[4 * i] = bits as u32;
+            fpregs.xmm_space[4 * i + 1] = (bits >> 32) as u32;
+        }
+        // For variadic functions, al holds how many vector registers are used
+        regs.rax = floats.len() as u64;
+        regs.rsp = sp;
+        regs.rip = function as u64;
+        // Don't let the kernel restart a syscall we were stopped in at the function's address
+        regs.orig_rax = u64::MAX;
+        ptrace::setregs(self.pid(), regs)?;
+        self.set_fp_registers(&fpregs)?;
+        Ok(saved)
+    }
+
+    /// The values a called function returned: rax, and the contents of xmm0.
+    #[cfg(target_arch = "x86_64")]
+    pub fn call_result(&self) -> Result<(u64, [u8; 16]), Error> {
+        let rax = self.get_registers()?.rax;
+        let fpregs = self.get_fp_registers()?;
+        let mut xmm0 = [0; 16];
+        for (i, word) in fpregs.xmm_space[..4].iter().enumerate() {
+            xmm0[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        Ok((rax, xmm0))
+    }
+
+    /// Puts the inferior back the way it was before start_call, whether or not the function
+    /// returned.
+    #[cfg(target_arch = "x86_64")]
+    pub fn end_call(&mut self, saved: SavedCall) -> Result<(), Error> {
+        ptrace::setregs(self.pid(), saved.regs)?;
+        self.set_fp_registers(&saved.fpregs)?;
+        self.pending_signal = saved.pending_signal;
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    pub fn start_call(
+        &mut self,
+        _function: usize,
+        _return_addr: usize,
+        _args: &[CallArgument],
+    ) -> Result<SavedCall, Error> {
+        Err(Error::Unsupported("Calling functions in the program".to_string()))
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    pub fn call_result(&self) -> Result<(u64, [u8; 16]), Error> {
+        Err(Error::Unsupported("Calling functions in the program".to_string()))
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    pub fn end_call(&mut self, saved: SavedCall) -> Result<(), Error> {
+        match saved {}
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn get_fp_registers(&self) -> Result<libc::user_fpregs_struct, Error> {
+        let mut fpregs: libc::user_fpregs_struct = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETFPREGS,
+                self.pid().as_raw(),
+                std::ptr::null_mut::<libc::c_void>(),
+                &mut fpregs as *mut libc::user_fpregs_struct,
+            )
+        };
+        Ok(Errno::result(ret).map(|_| fpregs)?)
     }
 
-    pub fn kill(&mut self) -> io::Result<()> {
-        self.child.kill()
+    #[cfg(target_arch = "x86_64")]
+    fn set_fp_registers(&self, fpregs: &libc::user_fpregs_struct) -> Result<(), Error> {
+        let ret = unsafe {
+            libc::ptrace(
+                libc::PTRACE_SETFPREGS,
+                self.pid().as_raw(),
+                std::ptr::null_mut::<libc::c_void>(),
+                fpregs as *const libc::user_fpregs_struct,
+            )
+        };
+        Ok(Errno::result(ret).map(drop)?)
     }
 
     /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
     /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
+    pub fn wait(&mut self, options: Option<WaitPidFlag>) -> Result<Status, Error> {
+        let wait_status = waitpid(self.pid(), options)?;
+        self.status(wait_status)
+    }
+
+    /// The Status for what waitpid said about the main thread.
+    fn status(&mut self, wait_status: WaitStatus) -> Result<Status, Error> {
+        Ok(match wait_status {
             WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
             WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
             WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
+                let pc = self.registers()?.pc();
+                // Like gdb, hand signals on to the program when it continues, except the ones
+                // that are only there to stop it for us
+                self.pending_signal = match signal {
+                    signal::Signal::SIGTRAP | signal::Signal::SIGINT | signal::Signal::SIGSTOP => None,
+                    signal => Some(signal),
+                };
+                Status::Stopped(signal, pc as usize)
+            }
+            WaitStatus::PtraceSyscall(_pid) => {
+                let prstatus = self.get_register_set()?;
+                let (number, args, result) = self.arch.decode_syscall(&prstatus).ok_or_else(|| {
+                    Error::UnexpectedStop(String::from("short NT_PRSTATUS register set"))
+                })?;
+                self.in_syscall = !self.in_syscall;
+                if self.in_syscall {
+                    Status::SyscallEntry(number, args)
+                } else {
+                    Status::SyscallExit(number, result)
+                }
+            }
+            WaitStatus::PtraceEvent(_pid, _signal, libc::PTRACE_EVENT_EXEC) => {
+                Status::Exec(self.registers()?.pc() as usize)
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
+            WaitStatus::PtraceEvent(_pid, _signal, event @ libc::PTRACE_EVENT_FORK)
+            | WaitStatus::PtraceEvent(_pid, _signal, event @ libc::PTRACE_EVENT_VFORK) => {
+                self.vforked = event == libc::PTRACE_EVENT_VFORK;
+                let pid = Pid::from_raw(ptrace::getevent(self.pid())? as i32);
+                Status::Forked(pid, self.registers()?.pc() as usize)
+            }
+            WaitStatus::PtraceEvent(_pid, _signal, libc::PTRACE_EVENT_CLONE) => {
+                let tid = Pid::from_raw(ptrace::getevent(self.pid())? as i32);
+                Status::Cloned(tid, self.registers()?.pc() as usize)
+            }
+            other => return Err(Error::UnexpectedStop(format!("{:?}", other))),
         })
     }
 
-    pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
+    pub fn read_word(&self, addr: usize) -> Result<usize, Error> {
+        Ok(ptrace::read(self.pid(), addr as ptrace::AddressType)? as usize)
+    }
+
+    pub fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::with_capacity(len);
+        let mut curr_addr = align_addr_to_word(addr);
+        let skip = addr - curr_addr;
+        while bytes.len() < skip + len {
+            bytes.extend_from_slice(&self.read_word(curr_addr)?.to_ne_bytes());
+            curr_addr += size_of::<usize>();
+        }
+        Ok(bytes[skip..skip + len].to_vec())
+    }
+
+    /// Reads a NUL-terminated string out of the inferior's memory, stopping after `max_len` bytes
+    /// if there is no NUL by then.
+    pub fn read_string(&self, addr: usize, max_len: usize) -> Result<String, Error> {
+        let mut bytes = Vec::new();
+        let mut curr_addr = addr;
+        while bytes.len() < max_len {
+            let word = self.read_word(curr_addr)?;
+            for byte in word.to_ne_bytes().iter() {
+                if *byte == 0 || bytes.len() == max_len {
+                    return Ok(String::from_utf8_lossy(&bytes).to_string());
+                }
+                bytes.push(*byte);
+            }
+            curr_addr += size_of::<usize>();
+        }
+        Ok(String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    pub fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<(), Error> {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_byte(addr + i, *byte)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, Error> {
         let aligned_addr = align_addr_to_word(addr);
         let byte_offset = addr - aligned_addr;
         let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
@@ -170,3 +940,37 @@ impl Inferior {
         Ok(orig_byte as u8)
     }
 }
+
+impl Memory for Inferior {
+    fn read_word(&self, addr: u64) -> Option<u64> {
+        ptrace::read(self.pid(), addr as ptrace::AddressType).ok().map(|word| word as u64)
+    }
+}
+
+/// The innermost frame of a stopped inferior, as seen by DWARF expressions.
+pub struct FrameContext<'a> {
+    pub inferior: &'a Inferior,
+    pub regs: Registers,
+    /// Load bias of the module whose debug info is being evaluated
+    pub bias: usize,
+    pub cfa: Option<u64>,
+}
+
+impl EvalContext for FrameContext<'_> {
+    fn register(&self, register: u16) -> Option<u64> {
+        self.regs.get(register)
+    }
+
+    fn read_memory(&self, addr: u64, size: usize) -> Option<Vec<u8>> {
+        self.inferior.read_bytes(addr as usize, size).ok()
+    }
+
+    fn call_frame_cfa(&self) -> Option<u64> {
+        self.cfa
+    }
+
+    fn bias(&self) -> u64 {
+        self.bias as u64
+    }
+}
+
@@ -0,0 +1,42 @@
+//! deet, a debugger for Linux x86-64 programs, with the architecture-specific parts of driving the
+//! inferior also written for aarch64 (see `arch`). The command-line binary in `main.rs` is a thin
+//! wrapper around Debugger. Session drives the same debugger from code, and the debug info
+//! readers are public too, so that the benchmarks in `benches/` can load binaries the same way
+//! it does.
+//!
+//! Nothing in here exits the process or panics on a bad target, a failed ptrace call or broken
+//! input: those come back as Error.
+
+pub mod arch;
+pub mod debuginfo;
+pub mod demangle;
+pub mod dwarf_data;
+pub mod gimli_wrapper;
+pub mod printers;
+pub mod ui;
+
+mod api;
+mod debugger;
+mod debugger_command;
+mod dwarf_expr;
+mod error;
+mod expr;
+mod fault;
+mod frameinfo;
+mod inferior;
+mod modules;
+mod pretty;
+mod printf;
+mod procfs;
+mod procinfo;
+mod query;
+mod record;
+mod session;
+mod syscalls;
+
+pub use api::{Session, StopEvent, StopLocation};
+pub use debugger::Debugger;
+pub use debugger_command::DebuggerCommand;
+pub use dwarf_data::DwarfData;
+pub use error::Error;
+pub use inferior::{Frame, Inferior, Status};
@@ -1,25 +1,51 @@
-mod debugger;
-mod debugger_command;
-mod inferior;
-
-mod dwarf_data;
-mod gimli_wrapper;
-
-use crate::debugger::Debugger;
+use deet::{ui, Debugger};
 use nix::sys::signal::{signal, SigHandler, Signal};
 use std::env;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
+    if args.len() >= 3 && args[1] == "--trace-syscalls" {
+        let mut debugger = new_debugger(&args[2]);
+        let code = debugger.trace_syscalls(&args[3..].to_vec());
+        std::process::exit(code);
+    }
+    let use_tui = args.len() == 3 && args[1] == "--tui";
+    if args.len() != 2 && !use_tui {
+        println!("Usage: {} [--tui] <target program>", args[0]);
+        println!("       {} --trace-syscalls <target program> [args...]", args[0]);
         std::process::exit(1);
     }
-    let target = &args[1];
+    let target = &args[args.len() - 1];
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
-    unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
+    if let Err(err) = unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) } {
+        println!("Error disabling SIGINT handling: {}", err);
+        std::process::exit(1);
+    }
 
-    Debugger::new(target).run();
+    let mut debugger = new_debugger(target);
+    debugger.restore_session();
+    let result = if use_tui {
+        ui::run(&mut debugger).map_err(|err| format!("Error running the TUI: {}", err))
+    } else {
+        debugger.run().map_err(|err| err.to_string())
+    };
+    // Whether it ended with quit, the end of input or an error, keep the session for next time
+    debugger.save_session();
+    if let Err(err) = result {
+        println!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Loads the target, or explains why it can't be debugged and exits.
+fn new_debugger(target: &str) -> Debugger {
+    match Debugger::new(target) {
+        Ok(debugger) => debugger,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,313 @@
+//! Keeps track of every ELF module mapped into the inferior: the target executable, the dynamic
+//! linker and each shared library, along with the bias it was loaded at. DwarfData only knows
+//! link-time addresses; adding a module's bias turns them into runtime addresses. For a
+//! `-no-pie` executable the bias is 0, so nothing changes.
+
+use crate::dwarf_data::{DwarfData, Function, Line};
+use crate::inferior::Inferior;
+use crate::procfs::MapEntry;
+use crate::Error;
+use object::Object;
+use std::{fs, path};
+
+/// `r_state` value meaning the link_map list is consistent and safe to walk.
+const RT_CONSISTENT: usize = 0;
+
+// Field offsets in the dynamic linker's structures, as laid out on a 64-bit (LP64) target:
+//   struct r_debug { int r_version; struct link_map *r_map; ElfW(Addr) r_brk; int r_state; ... }
+//   struct link_map { ElfW(Addr) l_addr; char *l_name; ElfW(Dyn) *l_ld; struct link_map *l_next, *l_prev; }
+const R_MAP_OFFSET: usize = 8;
+const R_STATE_OFFSET: usize = 24;
+const L_ADDR_OFFSET: usize = 0;
+const L_NAME_OFFSET: usize = 8;
+const L_NEXT_OFFSET: usize = 24;
+
+pub struct Module {
+    pub path: String,
+    /// `path` with symlinks and `..` resolved, for comparing it with other spellings of the path
+    canonical_path: path::PathBuf,
+    pub bias: usize,
+    /// Link-time entry point, used to work out the executable's bias from AT_ENTRY.
+    pub entry: usize,
+    /// Address ranges this module is mapped at. Empty until the inferior has been started.
+    pub ranges: Vec<(usize, usize)>,
+    pub debug_data: DwarfData,
+}
+
+impl Module {
+    fn load(path: &str, bias: usize) -> Result<Module, Error> {
+        Ok(Module {
+            path: path.to_string(),
+            canonical_path: canonical(path),
+            bias,
+            entry: with_object(path, |object| Some(object.entry() as usize)).unwrap_or(0),
+            ranges: Vec::new(),
+            debug_data: DwarfData::from_file(path)?,
+        })
+    }
+
+    /// Prints what of the module's debug info couldn't be loaded.
+    pub fn print_warnings(&self) {
+        for warning in self.debug_data.warnings() {
+            println!("{}", warning);
+        }
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.ranges.iter().any(|(start, end)| *start <= addr && addr < *end)
+    }
+}
+
+pub struct ModuleList {
+    /// modules[0] is always the target executable
+    modules: Vec<Module>,
+}
+
+impl ModuleList {
+    pub fn new(target: &str) -> Result<ModuleList, Error> {
+        Ok(ModuleList {
+            modules: vec![Module::load(target, 0)?],
+        })
+    }
+
+    pub fn main(&self) -> &Module {
+        &self.modules[0]
+    }
+
+    /// The target executable, then the dynamic linker and libraries in the order they loaded.
+    pub fn iter(&self) -> std::slice::Iter<'_, Module> {
+        self.modules.iter()
+    }
+
+    pub fn set_main_bias(&mut self, bias: usize) {
+        self.modules[0].bias = bias;
+    }
+
+    /// Loads the module at `path` unless it is already loaded. Returns true if it was added.
+    pub fn add(&mut self, path: &str, bias: usize) -> bool {
+        if self.find(path).is_some() {
+            return false;
+        }
+        match Module::load(path, bias) {
+            Ok(module) => {
+                module.print_warnings();
+                self.modules.push(module);
+                true
+            }
+            Err(err) => {
+                println!("Could not load symbols from {}: {}", path, err);
+                false
+            }
+        }
+    }
+
+    /// Drops every module except the target executable, e.g. because the inferior is being
+    /// restarted.
+    pub fn unload_libraries(&mut self) {
+        self.modules.truncate(1);
+        self.modules[0].ranges.clear();
+    }
+
+    /// Records where each module is mapped, going by the file backing each mapping.
+    pub fn update_ranges(&mut self, maps: &[MapEntry]) {
+        let paths: Vec<Option<path::PathBuf>> =
+            maps.iter().map(|entry| entry.path.as_deref().map(canonical)).collect();
+        for module in self.modules.iter_mut() {
+            module.ranges = maps
+                .iter()
+                .zip(&paths)
+                .filter(|(_, path)| path.as_ref() == Some(&module.canonical_path))
+                .map(|(entry, _)| (entry.start, entry.end))
+                .collect();
+        }
+    }
+
+    /// The module loaded from `path`, however the path is spelled.
+    pub fn find(&self, path: &str) -> Option<&Module> {
+        let path = canonical(path);
+        self.modules.iter().find(|module| module.canonical_path == path)
+    }
+
+    pub fn module_for_addr(&self, addr: usize) -> Option<&Module> {
+        // Before the inferior starts we have no mappings, and everything belongs to the target
+        if self.modules.len() == 1 && self.modules[0].ranges.is_empty() {
+            return self.modules.get(0);
+        }
+        self.modules.iter().find(|module| module.contains(addr))
+    }
+
+    /// Converts a runtime address to a (module path, link-time address) pair that survives the
+    /// module being loaded somewhere else.
+    pub fn to_relative(&self, addr: usize) -> Option<(String, usize)> {
+        let module = self.module_for_addr(addr)?;
+        Some((module.path.clone(), addr.checked_sub(module.bias)?))
+    }
+
+    pub fn to_absolute(&self, path: &str, addr: usize) -> Option<usize> {
+        Some(self.find(path)?.bias + addr)
+    }
+
+    pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
+        let module = self.module_for_addr(curr_addr)?;
+        let mut line = module
+            .debug_data
+            .get_line_from_addr(curr_addr.checked_sub(module.bias)?)?;
+        line.address = curr_addr;
+        Some(line)
+    }
+
+    pub fn is_line_start(&self, curr_addr: usize) -> bool {
+        match self.module_for_addr(curr_addr) {
+            Some(module) => curr_addr
+                .checked_sub(module.bias)
+                .map_or(false, |addr| module.debug_data.is_line_start(addr)),
+            None => false,
+        }
+    }
+
+    pub fn get_addr_after_prologue(&self, func_addr: usize) -> Option<usize> {
+        let module = self.module_for_addr(func_addr)?;
+        Some(
+            module
+                .debug_data
+                .get_addr_after_prologue(func_addr.checked_sub(module.bias)?)?
+                + module.bias,
+        )
+    }
+
+    pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
+        let module = self.module_for_addr(curr_addr)?;
+        module
+            .debug_data
+            .get_function_from_addr(curr_addr.checked_sub(module.bias)?)
+    }
+
+    pub fn inline_depth(&self, curr_addr: usize) -> usize {
+        match self.module_for_addr(curr_addr) {
+            Some(module) => curr_addr
+                .checked_sub(module.bias)
+                .map_or(0, |addr| module.debug_data.inline_depth(addr)),
+            None => 0,
+        }
+    }
+
+    /// See DwarfData::get_frames_at.
+    pub fn get_frames_at(&self, curr_addr: usize) -> Vec<(String, Option<Line>)> {
+        let module = match self.module_for_addr(curr_addr) {
+            Some(module) => module,
+            None => return Vec::new(),
+        };
+        let mut frames = match curr_addr.checked_sub(module.bias) {
+            Some(addr) => module.debug_data.get_frames_at(addr),
+            None => return Vec::new(),
+        };
+        for (_, line) in frames.iter_mut() {
+            if let Some(line) = line {
+                line.address += module.bias;
+            }
+        }
+        frames
+    }
+
+    /// Where each inlined copy of a function starts, in every module.
+    pub fn get_inlined_addrs(&self, func_name: &str) -> Vec<usize> {
+        self.modules
+            .iter()
+            .flat_map(|module| {
+                module
+                    .debug_data
+                    .get_inlined_addrs(func_name)
+                    .into_iter()
+                    .map(move |addr| addr + module.bias)
+            })
+            .collect()
+    }
+
+    /// Without a file name, looks up the line in the target executable, like
+    /// DwarfData::get_addr_for_line does. Otherwise the file may belong to any module.
+    pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
+        match file {
+            None => {
+                let main = self.main();
+                Some(main.debug_data.get_addr_for_line(None, line_number)? + main.bias)
+            }
+            Some(_) => self.modules.iter().find_map(|module| {
+                Some(module.debug_data.get_addr_for_line(file, line_number)? + module.bias)
+            }),
+        }
+    }
+
+    pub fn get_function(&self, func_name: &str) -> Option<(&Module, &Function)> {
+        self.modules
+            .iter()
+            .find_map(|module| Some((module, module.debug_data.get_function(func_name)?)))
+    }
+
+    /// Like get_addr_for_line, but returns the whole range of addresses the line occupies.
+    pub fn get_line_range(&self, file: Option<&str>, line_number: usize) -> Option<(Line, usize, usize)> {
+        let modules = match file {
+            None => &self.modules[..1],
+            Some(_) => &self.modules[..],
+        };
+        modules.iter().find_map(|module| {
+            let (mut line, start, end) = module.debug_data.get_line_range(file, line_number)?;
+            line.address += module.bias;
+            Some((line, start + module.bias, end + module.bias))
+        })
+    }
+
+    pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
+        self.modules.iter().find_map(|module| {
+            Some(module.debug_data.get_addr_for_function(file, func_name)? + module.bias)
+        })
+    }
+}
+
+fn canonical(path: &str) -> path::PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path::PathBuf::from(path))
+}
+
+fn with_object<T, F>(path: &str, f: F) -> Option<T>
+where
+    F: FnOnce(&object::File) -> Option<T>,
+{
+    let file = fs::File::open(path).ok()?;
+    let mmap = unsafe { memmap::Mmap::map(&file).ok()? };
+    let object = object::File::parse(&*mmap).ok()?;
+    f(&object)
+}
+
+/// Looks up a symbol in a module's dynamic symbol table, returning its link-time address. We use
+/// this to find the dynamic linker's `_r_debug` and `_dl_debug_state`, which have no DWARF.
+pub fn find_dynamic_symbol(path: &str, name: &str) -> Option<usize> {
+    with_object(path, |object| {
+        object
+            .dynamic_symbols()
+            .find(|(_, symbol)| symbol.name() == Some(name))
+            .map(|(_, symbol)| symbol.address() as usize)
+    })
+}
+
+/// Walks the dynamic linker's link_map list, starting from its `r_debug` structure, and returns
+/// the path and load bias of each shared library. Returns None while the dynamic linker is in the
+/// middle of adding or removing libraries.
+pub fn read_link_map(inferior: &Inferior, r_debug: usize) -> Result<Option<Vec<(String, usize)>>, Error> {
+    let r_state = inferior.read_word(r_debug + R_STATE_OFFSET)? & 0xffff_ffff;
+    if r_state != RT_CONSISTENT {
+        return Ok(None);
+    }
+    let mut libraries = Vec::new();
+    let mut link_map = inferior.read_word(r_debug + R_MAP_OFFSET)?;
+    while link_map != 0 {
+        let l_addr = inferior.read_word(link_map + L_ADDR_OFFSET)?;
+        let l_name = inferior.read_word(link_map + L_NAME_OFFSET)?;
+        let name = if l_name != 0 { inferior.read_string(l_name, libc::PATH_MAX as usize)? } else { String::new() };
+        // The target executable itself comes first, with an empty name, and the vDSO has a name
+        // but no file behind it
+        if !name.is_empty() && path::Path::new(&name).exists() {
+            libraries.push((name, l_addr));
+        }
+        link_map = inferior.read_word(link_map + L_NEXT_OFFSET)?;
+    }
+    Ok(Some(libraries))
+}
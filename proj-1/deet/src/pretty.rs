@@ -0,0 +1,874 @@
+//! Formats variable values for `print`, following their DWARF types. Rust's String, Vec, &str
+//! and slices are shown the way their Debug impls would show them rather than as the structs
+//! they are made of, and Rust enums (Option, Result, ...) are shown as their active variant.
+//! The user's own pretty-printers (see `printers`) come first.
+
+use crate::dwarf_data::{DwarfData, Member, Type, TypeKind, VariantPart};
+use crate::dwarf_expr::EvalContext;
+use crate::printers::{self, PrettyPrinter, Printers, Shape};
+use std::collections::HashSet;
+
+/// How deep we follow nested structs and references before giving up
+const MAX_DEPTH: usize = 8;
+/// How many elements of an array, slice or Vec we show
+const MAX_ELEMENTS: usize = 200;
+/// How many bytes of a string we show
+const MAX_STRING_LENGTH: usize = 1000;
+/// How many buckets of a hash table we look through
+const MAX_BUCKETS: usize = 4096;
+
+/// Formats `bytes`, the contents of a value of type `dtype`. `ctx` is used to read whatever the
+/// value points to.
+pub fn format_value(
+    bytes: &[u8],
+    dtype: &Type,
+    debug_data: &DwarfData,
+    ctx: &dyn EvalContext,
+    printers: &Printers,
+) -> String {
+    Printer {
+        debug_data,
+        ctx,
+        printers,
+    }
+    .format(bytes, dtype, 0)
+}
+
+struct Printer<'a> {
+    debug_data: &'a DwarfData,
+    ctx: &'a dyn EvalContext,
+    printers: &'a Printers,
+}
+
+impl<'a> Printer<'a> {
+    fn format(&self, bytes: &[u8], dtype: &Type, depth: usize) -> String {
+        if depth > MAX_DEPTH {
+            return "...".to_string();
+        }
+        if let Some(formatted) = self.format_user(bytes, dtype, depth) {
+            return formatted;
+        }
+        self.format_builtin(bytes, dtype, depth)
+    }
+
+    fn format_builtin(&self, bytes: &[u8], dtype: &Type, depth: usize) -> String {
+        let dtype = self.debug_data.strip_aliases(dtype);
+        if let Some(formatted) = self.format_rust(bytes, dtype, depth) {
+            return formatted;
+        }
+        match &dtype.kind {
+            TypeKind::Base(encoding) => format_base(bytes, *encoding),
+            TypeKind::Pointer(target) => self.format_pointer(bytes, dtype, *target, depth),
+            TypeKind::Struct {
+                members,
+                variant_part: Some(variant_part),
+                ..
+            } if members.is_empty() => self
+                .format_enum(bytes, variant_part, depth)
+                .unwrap_or_else(|| format!("{} {{...}}", dtype.name)),
+            TypeKind::Struct { members, .. } => self.format_struct(bytes, &dtype.name, members, depth),
+            TypeKind::Enumeration(enumerators) => {
+                let value = to_i64(bytes);
+                match enumerators.iter().find(|(_, enumerator)| *enumerator == value) {
+                    Some((name, _)) => name.clone(),
+                    None => value.to_string(),
+                }
+            }
+            TypeKind::Array { element, count } => match self.debug_data.get_type(*element) {
+                Some(element) => {
+                    let element_size = self.debug_data.type_size(element);
+                    let count = count.unwrap_or(0).min(MAX_ELEMENTS);
+                    let elements = bytes
+                        .chunks(element_size.max(1))
+                        .take(count)
+                        .map(|chunk| chunk.to_vec())
+                        .collect::<Vec<_>>();
+                    self.format_elements(&elements, element, depth)
+                }
+                None => format_bytes(bytes),
+            },
+            _ => format_bytes(bytes),
+        }
+    }
+
+    /// Formats a pointer as its address. C strings are shown too, and so is what the pointer
+    /// points to if the user has a pretty-printer for it. Rust references are followed, like
+    /// Rust's Debug does.
+    fn format_pointer(&self, bytes: &[u8], dtype: &Type, target: Option<usize>, depth: usize) -> String {
+        let addr = to_u64(bytes);
+        let pointee = match target.and_then(|key| self.debug_data.get_type(key)) {
+            Some(pointee) => pointee,
+            None => return format!("{:#x}", addr),
+        };
+        let target = self.debug_data.strip_aliases(pointee);
+        if addr == 0 {
+            return "0x0".to_string();
+        }
+        if is_char(target) {
+            return format!("{:#x} {}", addr, self.read_c_string(addr));
+        }
+        if self.user_printer(pointee).is_some() {
+            if let Some(value) = self.ctx.read_memory(addr, self.debug_data.type_size(target)) {
+                return format!("{:#x} {}", addr, self.format(&value, pointee, depth + 1));
+            }
+        }
+        if dtype.name.starts_with('&') {
+            let size = self.debug_data.type_size(target);
+            return match self.ctx.read_memory(addr, size) {
+                Some(pointee) => format!("&{}", self.format(&pointee, target, depth + 1)),
+                None => format!("{:#x}", addr),
+            };
+        }
+        format!("{:#x}", addr)
+    }
+
+    fn format_struct(&self, bytes: &[u8], name: &str, members: &[Member], depth: usize) -> String {
+        // Anonymous structs get a made-up name from gimli_wrapper::type_name
+        let name = if name.contains("{...}") { "" } else { name };
+        let fields: Vec<(String, String)> = members
+            .iter()
+            .map(|member| (member.name.clone(), self.format_member(bytes, member, depth)))
+            .collect();
+        // Tuples, and tuple structs and variants, have fields named __0, __1, ...
+        let is_tuple = !fields.is_empty() && fields.iter().all(|(name, _)| name.starts_with("__"));
+        let values: Vec<String> = fields.iter().map(|(_, value)| value.clone()).collect();
+        if name.starts_with('(') {
+            format!("({})", values.join(", "))
+        } else if fields.is_empty() {
+            name.to_string()
+        } else if is_tuple {
+            format!("{}({})", name, values.join(", "))
+        } else {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value))
+                .collect();
+            format!("{} {{ {} }}", name, fields.join(", ")).trim_start().to_string()
+        }
+    }
+
+    fn format_member(&self, bytes: &[u8], member: &Member, depth: usize) -> String {
+        match self.debug_data.get_type(member.type_key) {
+            Some(dtype) => {
+                let size = self.debug_data.type_size(dtype);
+                match bytes.get(member.offset..member.offset + size) {
+                    Some(member_bytes) => self.format(member_bytes, dtype, depth + 1),
+                    None => "<unavailable>".to_string(),
+                }
+            }
+            None => "<unknown type>".to_string(),
+        }
+    }
+
+    /// Shows the active variant of a Rust enum. The variant's fields are a struct named after
+    /// the variant, so `Some(5)` comes out of formatting that struct.
+    fn format_enum(&self, bytes: &[u8], variant_part: &VariantPart, depth: usize) -> Option<String> {
+        let variant = match &variant_part.discriminant {
+            Some(discriminant) => {
+                let dtype = self.debug_data.get_type(discriminant.type_key)?;
+                let size = self.debug_data.type_size(dtype);
+                let value = to_u64(bytes.get(discriminant.offset..discriminant.offset + size)?);
+                let mask = if size >= 8 { !0 } else { (1u64 << (8 * size)) - 1 };
+                variant_part
+                    .variants
+                    .iter()
+                    .find(|variant| variant.discr_value.map(|discr| discr & mask) == Some(value))
+                    .or_else(|| variant_part.variants.iter().find(|variant| variant.discr_value.is_none()))?
+            }
+            None => variant_part.variants.first()?,
+        };
+        Some(self.format_member(bytes, &variant.member, depth))
+    }
+
+    fn format_elements(&self, elements: &[Vec<u8>], element: &Type, depth: usize) -> String {
+        let element = self.debug_data.strip_aliases(element);
+        if is_char(element) {
+            let end = elements.iter().position(|chunk| chunk.iter().all(|byte| *byte == 0));
+            let bytes: Vec<u8> = elements[..end.unwrap_or(elements.len())]
+                .iter()
+                .map(|chunk| chunk[0])
+                .collect();
+            return format!("{:?}", String::from_utf8_lossy(&bytes));
+        }
+        let values: Vec<String> = elements
+            .iter()
+            .map(|chunk| self.format(chunk, element, depth + 1))
+            .collect();
+        format!("[{}]", values.join(", "))
+    }
+
+    /// Pretty-printers for Rust's standard library types. Returns None for anything else (or if
+    /// the type doesn't have the layout we expect), to fall back on the generic formatting.
+    fn format_rust(&self, bytes: &[u8], dtype: &Type, depth: usize) -> Option<String> {
+        let name = dtype.name.as_str();
+        if name == "&str" || name == "&mut str" {
+            let (addr, len) = self.read_fat_pointer(bytes, dtype)?;
+            return Some(self.read_rust_string(addr, len));
+        }
+        if name.starts_with("&[") || name.starts_with("&mut [") {
+            let (addr, len) = self.read_fat_pointer(bytes, dtype)?;
+            let element = self.pointee(self.member_type(dtype, "data_ptr")?)?;
+            return Some(self.format_array(addr, len, element, depth));
+        }
+        if name == "String" {
+            let vec = self.find_member(dtype, "vec")?;
+            let vec_type = self.debug_data.get_type(vec.type_key)?;
+            let (addr, len) = self.read_vec(bytes.get(vec.offset..)?, vec_type)?;
+            return Some(self.read_rust_string(addr, len));
+        }
+        if name.starts_with("Vec<") {
+            let (addr, len) = self.read_vec(bytes, dtype)?;
+            let element = match &dtype.kind {
+                TypeKind::Struct { template_params, .. } => template_params
+                    .iter()
+                    .find(|(name, _)| name == "T")
+                    .and_then(|(_, key)| self.debug_data.get_type(*key))?,
+                _ => return None,
+            };
+            return Some(format!("vec!{}", self.format_array(addr, len, element, depth)));
+        }
+        None
+    }
+
+    /// Reads the (pointer, length) pair making up a &str or a slice.
+    fn read_fat_pointer(&self, bytes: &[u8], dtype: &Type) -> Option<(u64, usize)> {
+        let data_ptr = self.find_member(dtype, "data_ptr")?;
+        let length = self.find_member(dtype, "length")?;
+        Some((
+            to_u64(bytes.get(data_ptr.offset..data_ptr.offset + 8)?),
+            to_u64(bytes.get(length.offset..length.offset + 8)?) as usize,
+        ))
+    }
+
+    /// Reads the buffer address and length of a Vec. The buffer pointer is buried a few
+    /// structs deep (RawVec, Unique, NonNull, ...), and the nesting changes between Rust
+    /// versions, so we look for the first pointer inside `buf`.
+    fn read_vec(&self, bytes: &[u8], dtype: &Type) -> Option<(u64, usize)> {
+        let len = self.find_member(dtype, "len")?;
+        let len = to_u64(bytes.get(len.offset..len.offset + 8)?) as usize;
+        let buf = self.find_member(dtype, "buf")?;
+        let addr = self.first_pointer(bytes, buf.offset, self.debug_data.get_type(buf.type_key)?, 0)?;
+        Some((addr, len))
+    }
+
+    fn first_pointer(&self, bytes: &[u8], offset: usize, dtype: &Type, depth: usize) -> Option<u64> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        match &self.debug_data.strip_aliases(dtype).kind {
+            TypeKind::Pointer(_) => Some(to_u64(bytes.get(offset..offset + 8)?)),
+            TypeKind::Struct { members, .. } => members.iter().find_map(|member| {
+                let member_type = self.debug_data.get_type(member.type_key)?;
+                self.first_pointer(bytes, offset + member.offset, member_type, depth + 1)
+            }),
+            _ => None,
+        }
+    }
+
+    fn format_array(&self, addr: u64, len: usize, element: &Type, depth: usize) -> String {
+        let element_size = self.debug_data.type_size(element);
+        let count = len.min(MAX_ELEMENTS);
+        let bytes = match self.ctx.read_memory(addr, element_size * count) {
+            Some(bytes) => bytes,
+            None => return format!("<unreadable memory at {:#x}>", addr),
+        };
+        let elements: Vec<Vec<u8>> = bytes
+            .chunks(element_size.max(1))
+            .take(count)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+        let formatted = self.format_elements(&elements, element, depth);
+        if len <= count {
+            return formatted;
+        }
+        // Arrays of characters come out as a string, without the brackets
+        match formatted.strip_suffix(']') {
+            Some(elements) => format!("{}...]", elements),
+            None => format!("{}...", formatted),
+        }
+    }
+
+    fn read_rust_string(&self, addr: u64, len: usize) -> String {
+        match self.ctx.read_memory(addr, len.min(MAX_STRING_LENGTH)) {
+            Some(bytes) if len > MAX_STRING_LENGTH => {
+                format!("{:?}...", String::from_utf8_lossy(&bytes))
+            }
+            Some(bytes) => format!("{:?}", String::from_utf8_lossy(&bytes)),
+            None => format!("<unreadable memory at {:#x}>", addr),
+        }
+    }
+
+    fn read_c_string(&self, addr: u64) -> String {
+        let mut bytes = Vec::new();
+        while bytes.len() < MAX_STRING_LENGTH {
+            match self.ctx.read_memory(addr + bytes.len() as u64, 1) {
+                Some(byte) if byte[0] != 0 => bytes.push(byte[0]),
+                Some(_) => return format!("{:?}", String::from_utf8_lossy(&bytes)),
+                None => return format!("<unreadable memory at {:#x}>", addr),
+            }
+        }
+        format!("{:?}...", String::from_utf8_lossy(&bytes))
+    }
+
+    /// The user's pretty-printer for `dtype`, or for a typedef it is (the closest one wins), and
+    /// the name of the type it matched.
+    fn user_printer(&self, dtype: &Type) -> Option<(&'a PrettyPrinter, String)> {
+        if self.printers.is_empty() {
+            return None;
+        }
+        let mut dtype = dtype;
+        for _ in 0..MAX_DEPTH {
+            if let Some(printer) = self.printers.find(&dtype.name) {
+                return Some((printer, dtype.name.clone()));
+            }
+            match dtype.kind {
+                TypeKind::Alias(Some(key)) => dtype = self.debug_data.get_type(key)?,
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    /// Formats a value with the user's pretty-printer for its type. Returns None if there is
+    /// none, or if the value doesn't have the fields the printer names, to fall back on the
+    /// built-in formatting.
+    fn format_user(&self, bytes: &[u8], dtype: &Type, depth: usize) -> Option<String> {
+        let (printer, type_name) = self.user_printer(dtype)?;
+        match &printer.shape {
+            Shape::List { head, next, value } => {
+                self.format_list(bytes, dtype, head.as_deref(), next, value.as_deref(), depth)
+            }
+            Shape::Map {
+                buckets,
+                size,
+                next,
+                key,
+                value,
+            } => self.format_map(bytes, dtype, buckets, size, next, (key, value), depth),
+            Shape::Str { data, len } => {
+                let (data, data_type) = self.field(bytes, dtype, data)?;
+                let (addr, _) = self.follow(data, data_type, 0)?;
+                Some(match len {
+                    Some(len) => {
+                        let len = to_u64(self.field(bytes, dtype, len)?.0) as usize;
+                        self.read_rust_string(addr, len)
+                    }
+                    None => self.read_c_string(addr),
+                })
+            }
+            Shape::Template(template) => Some(self.expand_template(bytes, dtype, template, depth)),
+            Shape::Plugin(formatter) => printers::call_plugin(*formatter, &type_name, bytes, self.ctx),
+        }
+    }
+
+    /// A linked list's values, in order. It starts at the value itself, which is a node or a
+    /// pointer to one, or with `head` at the node that field points to.
+    fn format_list(
+        &self,
+        bytes: &[u8],
+        dtype: &Type,
+        head: Option<&str>,
+        next: &str,
+        value: Option<&str>,
+        depth: usize,
+    ) -> Option<String> {
+        let (mut node, node_type) = match head {
+            Some(head) => {
+                let (head, head_type) = self.field(bytes, dtype, head)?;
+                let (addr, node_type) = self.follow(head, head_type, 0)?;
+                (self.read_node(addr, node_type), node_type)
+            }
+            None => match self.follow(bytes, dtype, 0) {
+                Some((addr, node_type)) if self.is_pointer(dtype) => {
+                    (self.read_node(addr, node_type), node_type)
+                }
+                _ => (Some(Ok(bytes.to_vec())), self.debug_data.strip_aliases(dtype)),
+            },
+        };
+        let mut values = Vec::new();
+        let mut seen = HashSet::new();
+        while let Some(result) = node {
+            let bytes = match result {
+                Ok(bytes) => bytes,
+                Err(addr) => {
+                    values.push(format!("<unreadable memory at {:#x}>", addr));
+                    break;
+                }
+            };
+            if values.len() == MAX_ELEMENTS {
+                values.push(String::from("..."));
+                break;
+            }
+            values.push(match value {
+                Some(value) => {
+                    let (value, value_type) = self.field(&bytes, node_type, value)?;
+                    self.format_element(value, value_type, depth + 1)
+                }
+                None => self.format_builtin(&bytes, node_type, depth + 1),
+            });
+            let (next, next_type) = self.field(&bytes, node_type, next)?;
+            let (addr, _) = self.follow(next, next_type, 0)?;
+            if !seen.insert(addr) {
+                values.push(String::from("<cycle>"));
+                break;
+            }
+            node = self.read_node(addr, node_type);
+        }
+        Some(format!("[{}]", values.join(", ")))
+    }
+
+    /// Reads the node of a linked list at `addr`: None at the null pointer that ends the list,
+    /// and the address back if it can't be read.
+    fn read_node(&self, addr: u64, node_type: &Type) -> Option<Result<Vec<u8>, u64>> {
+        if addr == 0 {
+            return None;
+        }
+        Some(self.ctx.read_memory(addr, self.debug_data.type_size(node_type)).ok_or(addr))
+    }
+
+    /// A chained hash table's entries, `{key: value, ...}`, bucket by bucket.
+    #[allow(clippy::too_many_arguments)]
+    fn format_map(
+        &self,
+        bytes: &[u8],
+        dtype: &Type,
+        buckets: &str,
+        size: &str,
+        next: &str,
+        (key, value): (&str, &str),
+        depth: usize,
+    ) -> Option<String> {
+        let count = match size.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => to_u64(self.field(bytes, dtype, size)?.0) as usize,
+        };
+        let count = count.min(MAX_BUCKETS);
+        // The buckets are an array in the table, or somewhere else it points to
+        let (buckets, buckets_type) = self.field(bytes, dtype, buckets)?;
+        let (slots, slot_type) = match self.debug_data.strip_aliases(buckets_type).kind {
+            TypeKind::Array { element, .. } => (buckets.to_vec(), self.debug_data.get_type(element)?),
+            TypeKind::Pointer(Some(slot_type)) => {
+                let slot_type = self.debug_data.get_type(slot_type)?;
+                let size = self.debug_data.type_size(slot_type) * count;
+                (self.ctx.read_memory(to_u64(buckets), size)?, slot_type)
+            }
+            _ => return None,
+        };
+        let mut entries = Vec::new();
+        'buckets: for slot in slots.chunks(self.debug_data.type_size(slot_type).max(1)).take(count) {
+            let (mut addr, entry_type) = self.follow(slot, slot_type, 0)?;
+            let mut seen = HashSet::new();
+            while addr != 0 && seen.insert(addr) {
+                if entries.len() == MAX_ELEMENTS {
+                    entries.push(String::from("..."));
+                    break 'buckets;
+                }
+                let entry = match self.ctx.read_memory(addr, self.debug_data.type_size(entry_type)) {
+                    Some(entry) => entry,
+                    None => {
+                        entries.push(format!("<unreadable memory at {:#x}>", addr));
+                        break;
+                    }
+                };
+                let (key, key_type) = self.field(&entry, entry_type, key)?;
+                let (value, value_type) = self.field(&entry, entry_type, value)?;
+                entries.push(format!(
+                    "{}: {}",
+                    self.format_element(key, key_type, depth + 1),
+                    self.format_element(value, value_type, depth + 1)
+                ));
+                let (next, next_type) = self.field(&entry, entry_type, next)?;
+                addr = self.follow(next, next_type, 0)?.0;
+            }
+        }
+        Some(format!("{{{}}}", entries.join(", ")))
+    }
+
+    /// Formats an element of a container. A C string is shown without its address, which is
+    /// noise among the others.
+    fn format_element(&self, bytes: &[u8], dtype: &Type, depth: usize) -> String {
+        if let TypeKind::Pointer(Some(target)) = self.debug_data.strip_aliases(dtype).kind {
+            let addr = to_u64(bytes);
+            let is_string = self
+                .debug_data
+                .get_type(target)
+                .map_or(false, |target| is_char(self.debug_data.strip_aliases(target)));
+            if is_string && addr != 0 {
+                return self.read_c_string(addr);
+            }
+        }
+        self.format(bytes, dtype, depth)
+    }
+
+    /// Replaces each `{field}` or `{field.field...}` in a `format` printer's template with the
+    /// value of that field.
+    fn expand_template(&self, bytes: &[u8], dtype: &Type, template: &str, depth: usize) -> String {
+        let mut formatted = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            // An unterminated `{` is left as it is, along with the text before it
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            formatted.push_str(&rest[..start]);
+            let path = &rest[start + 1..end];
+            formatted.push_str(&match self.field_path(bytes, dtype, path) {
+                Some((value, value_type)) => self.format(&value, value_type, depth + 1),
+                None => format!("<no field {}>", path),
+            });
+            rest = &rest[end + 1..];
+        }
+        formatted.push_str(rest);
+        formatted
+    }
+
+    /// The value and type of `a.b.c` in a struct, going through pointers on the way.
+    fn field_path(&self, bytes: &[u8], dtype: &Type, path: &str) -> Option<(Vec<u8>, &'a Type)> {
+        let mut names = path.split('.');
+        let (value, mut value_type) = self.field(bytes, dtype, names.next()?.trim())?;
+        let mut value = value.to_vec();
+        for name in names {
+            if self.is_pointer(value_type) {
+                let (addr, target) = self.follow(&value, value_type, 0)?;
+                value = self.ctx.read_memory(addr, self.debug_data.type_size(target))?;
+                value_type = target;
+            }
+            let (member, member_type) = self.field(&value, value_type, name.trim())?;
+            value = member.to_vec();
+            value_type = member_type;
+        }
+        Some((value, value_type))
+    }
+
+    /// The bytes and type of the member `name` of a struct value.
+    fn field<'b>(&self, bytes: &'b [u8], dtype: &Type, name: &str) -> Option<(&'b [u8], &'a Type)> {
+        let member = self.find_member(self.debug_data.strip_aliases(dtype), name)?;
+        let member_type = self.debug_data.get_type(member.type_key)?;
+        let size = self.debug_data.type_size(member_type);
+        Some((bytes.get(member.offset..member.offset + size)?, member_type))
+    }
+
+    /// Where a pointer, or something wrapping one (a Box, an Option of one, a smart pointer
+    /// struct, ...), points, and the type it points to.
+    fn follow(&self, bytes: &[u8], dtype: &Type, depth: usize) -> Option<(u64, &'a Type)> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        match &self.debug_data.strip_aliases(dtype).kind {
+            TypeKind::Pointer(Some(key)) => {
+                Some((to_u64(bytes.get(..8)?), self.debug_data.get_type(*key)?))
+            }
+            TypeKind::Struct {
+                members,
+                variant_part,
+                ..
+            } => members
+                .iter()
+                .chain(variant_part.iter().flat_map(|part| {
+                    part.variants.iter().map(|variant| &variant.member)
+                }))
+                .find_map(|member| {
+                    let member_type = self.debug_data.get_type(member.type_key)?;
+                    self.follow(bytes.get(member.offset..)?, member_type, depth + 1)
+                }),
+            _ => None,
+        }
+    }
+
+    fn is_pointer(&self, dtype: &Type) -> bool {
+        match self.debug_data.strip_aliases(dtype).kind {
+            TypeKind::Pointer(_) => true,
+            _ => false,
+        }
+    }
+
+    fn find_member<'b>(&self, dtype: &'b Type, name: &str) -> Option<&'b Member> {
+        match &dtype.kind {
+            TypeKind::Struct { members, .. } => members.iter().find(|member| member.name == name),
+            _ => None,
+        }
+    }
+
+    fn member_type(&self, dtype: &Type, name: &str) -> Option<&Type> {
+        self.debug_data.get_type(self.find_member(dtype, name)?.type_key)
+    }
+
+    fn pointee(&self, dtype: &Type) -> Option<&Type> {
+        match self.debug_data.strip_aliases(dtype).kind {
+            TypeKind::Pointer(Some(key)) => self.debug_data.get_type(key),
+            _ => None,
+        }
+    }
+}
+
+fn is_char(dtype: &Type) -> bool {
+    match dtype.kind {
+        TypeKind::Base(Some(encoding)) => {
+            dtype.size == 1
+                && (encoding == gimli::DW_ATE_signed_char || encoding == gimli::DW_ATE_unsigned_char)
+        }
+        _ => false,
+    }
+}
+
+/// Formats an integer, character, boolean or floating point number according to its DW_ATE_*
+/// encoding.
+fn format_base(bytes: &[u8], encoding: Option<gimli::DwAte>) -> String {
+    if bytes.is_empty() {
+        return "<no value>".to_string();
+    }
+    let raw = to_u64(bytes);
+    match encoding {
+        Some(gimli::DW_ATE_boolean) => (raw != 0).to_string(),
+        Some(gimli::DW_ATE_float) if bytes.len() == 4 => f32::from_bits(raw as u32).to_string(),
+        Some(gimli::DW_ATE_float) if bytes.len() == 8 => f64::from_bits(raw).to_string(),
+        Some(gimli::DW_ATE_signed_char) => {
+            format!("{} '{}'", to_i64(bytes), (raw as u8 as char).escape_default())
+        }
+        Some(gimli::DW_ATE_unsigned_char) => {
+            format!("{} '{}'", raw, (raw as u8 as char).escape_default())
+        }
+        // Rust's char is a Unicode scalar value
+        Some(gimli::DW_ATE_UTF) => match std::char::from_u32(raw as u32) {
+            Some(c) => format!("{:?}", c),
+            None => raw.to_string(),
+        },
+        Some(gimli::DW_ATE_unsigned) => raw.to_string(),
+        _ => to_i64(bytes).to_string(),
+    }
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("<{}>", hex.join(" "))
+}
+
+/// Interprets up to 8 little-endian bytes as an unsigned integer.
+fn to_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(buf)
+}
+
+/// Interprets up to 8 little-endian bytes as a signed integer.
+fn to_i64(bytes: &[u8]) -> i64 {
+    let len = bytes.len().min(8);
+    if len == 0 {
+        return 0;
+    }
+    let shift = 64 - 8 * len as u32;
+    ((to_u64(bytes) << shift) as i64) >> shift
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dwarf_data::Variant;
+    use std::collections::HashMap;
+
+    const INT: usize = 1;
+    const CHAR: usize = 2;
+    const U8: usize = 3;
+    const U64: usize = 4;
+
+    /// Memory starting at `0`, and nothing else.
+    struct Memory(u64, Vec<u8>);
+
+    impl EvalContext for Memory {
+        fn register(&self, _register: u16) -> Option<u64> {
+            None
+        }
+
+        fn read_memory(&self, addr: u64, size: usize) -> Option<Vec<u8>> {
+            let start = addr.checked_sub(self.0)? as usize;
+            self.1.get(start..start + size).map(|bytes| bytes.to_vec())
+        }
+
+        fn call_frame_cfa(&self) -> Option<u64> {
+            None
+        }
+
+        fn bias(&self) -> u64 {
+            0
+        }
+    }
+
+    fn base(name: &str, size: usize, encoding: gimli::DwAte) -> Type {
+        Type {
+            name: name.to_string(),
+            size,
+            kind: TypeKind::Base(Some(encoding)),
+        }
+    }
+
+    fn member(name: &str, type_key: usize, offset: usize) -> Member {
+        Member {
+            name: name.to_string(),
+            type_key,
+            offset,
+        }
+    }
+
+    fn structure(name: &str, size: usize, members: Vec<Member>) -> Type {
+        Type {
+            name: name.to_string(),
+            size,
+            kind: TypeKind::Struct {
+                members,
+                template_params: Vec::new(),
+                variant_part: None,
+            },
+        }
+    }
+
+    fn types(extra: Vec<(usize, Type)>) -> DwarfData {
+        let mut types: HashMap<usize, Type> = vec![
+            (INT, base("int", 4, gimli::DW_ATE_signed)),
+            (CHAR, base("char", 1, gimli::DW_ATE_signed_char)),
+            (U8, base("u8", 1, gimli::DW_ATE_unsigned_char)),
+            (U64, base("usize", 8, gimli::DW_ATE_unsigned)),
+        ]
+        .into_iter()
+        .collect();
+        types.extend(extra);
+        DwarfData::from_types(types)
+    }
+
+    fn format(bytes: &[u8], dtype: &Type, debug_data: &DwarfData, memory: &Memory) -> String {
+        format_value(bytes, dtype, debug_data, memory, &Printers::default())
+    }
+
+    fn array(element: usize, count: usize) -> Type {
+        Type {
+            name: String::new(),
+            size: 0,
+            kind: TypeKind::Array {
+                element,
+                count: Some(count),
+            },
+        }
+    }
+
+    fn words(values: &[u64]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_arrays() {
+        let debug_data = types(Vec::new());
+        let memory = Memory(0, Vec::new());
+        let ints: Vec<u8> = [1i32, -2, 3].iter().flat_map(|int| int.to_le_bytes().to_vec()).collect();
+        assert_eq!(format(&ints, &array(INT, 3), &debug_data, &memory), "[1, -2, 3]");
+        assert_eq!(format(b"hi\0\0", &array(CHAR, 4), &debug_data, &memory), "\"hi\"");
+
+        // Slices longer than we show are cut short, strings or not
+        let printer = Printer {
+            debug_data: &debug_data,
+            ctx: &Memory(0x1000, vec![b'x'; MAX_ELEMENTS]),
+            printers: &Printers::default(),
+        };
+        let chars = printer.format_array(0x1000, MAX_ELEMENTS + 1, debug_data.get_type(U8).unwrap(), 0);
+        assert!(chars.starts_with("\"xxx") && chars.ends_with("\"..."), "{}", chars);
+        let printer = Printer {
+            debug_data: &debug_data,
+            ctx: &Memory(0x1000, vec![0; 4 * MAX_ELEMENTS]),
+            printers: &Printers::default(),
+        };
+        let ints = printer.format_array(0x1000, MAX_ELEMENTS + 1, debug_data.get_type(INT).unwrap(), 0);
+        assert!(ints.starts_with("[0, 0") && ints.ends_with("0...]"), "{}", ints);
+    }
+
+    #[test]
+    fn test_strings() {
+        let string = structure("String", 24, vec![member("vec", 10, 0)]);
+        let vec = structure(
+            "Vec<u8>",
+            24,
+            vec![member("buf", 11, 0), member("len", U64, 16)],
+        );
+        let raw_vec = structure("RawVec<u8>", 16, vec![member("ptr", 12, 0), member("cap", U64, 8)]);
+        let pointer = Type {
+            name: String::from("*const u8"),
+            size: 8,
+            kind: TypeKind::Pointer(Some(U8)),
+        };
+        let c_string = Type {
+            name: String::from("char *"),
+            size: 8,
+            kind: TypeKind::Pointer(Some(CHAR)),
+        };
+        let debug_data = types(vec![(10, vec), (11, raw_vec), (12, pointer)]);
+        let memory = Memory(0x1000, b"hello, world\0".to_vec());
+        assert_eq!(
+            format(&words(&[0x1000, 16, 5]), &string, &debug_data, &memory),
+            "\"hello\""
+        );
+        // A String cut short doesn't panic, but falls back on showing its fields
+        assert!(!format(&words(&[0x1000]), &string, &debug_data, &memory).is_empty());
+        assert_eq!(
+            format(&words(&[0x1007]), &c_string, &debug_data, &memory),
+            "0x1007 \"world\""
+        );
+        assert_eq!(
+            format(&words(&[0x2000]), &c_string, &debug_data, &memory),
+            "0x2000 <unreadable memory at 0x2000>"
+        );
+    }
+
+    #[test]
+    fn test_templates() {
+        let point = structure("point", 8, vec![member("x", INT, 0), member("y", INT, 4)]);
+        let debug_data = types(Vec::new());
+        let printer = Printer {
+            debug_data: &debug_data,
+            ctx: &Memory(0, Vec::new()),
+            printers: &Printers::default(),
+        };
+        let bytes = [1, 0, 0, 0, 2, 0, 0, 0];
+        assert_eq!(printer.expand_template(&bytes, &point, "({x}, {y})", 0), "(1, 2)");
+        assert_eq!(printer.expand_template(&bytes, &point, "{z}", 0), "<no field z>");
+        assert_eq!(printer.expand_template(&bytes, &point, "a {x", 0), "a {x");
+        assert_eq!(printer.expand_template(&bytes, &point, "{x} {y", 0), "1 {y");
+    }
+
+    #[test]
+    fn test_enums() {
+        // Option<i32>: a u32 discriminant, then the value in Some
+        let option = Type {
+            name: String::from("Option<i32>"),
+            size: 8,
+            kind: TypeKind::Struct {
+                members: Vec::new(),
+                template_params: Vec::new(),
+                variant_part: Some(VariantPart {
+                    discriminant: Some(member("", INT, 0)),
+                    variants: vec![
+                        Variant {
+                            discr_value: Some(0),
+                            member: member("None", 20, 0),
+                        },
+                        Variant {
+                            discr_value: Some(1),
+                            member: member("Some", 21, 0),
+                        },
+                    ],
+                }),
+            },
+        };
+        let none = structure("None", 8, Vec::new());
+        let some = structure("Some", 8, vec![member("__0", INT, 4)]);
+        let color = Type {
+            name: String::from("color"),
+            size: 4,
+            kind: TypeKind::Enumeration(vec![(String::from("RED"), 0), (String::from("BLUE"), 2)]),
+        };
+        let debug_data = types(vec![(20, none), (21, some)]);
+        let memory = Memory(0, Vec::new());
+        assert_eq!(format(&[1, 0, 0, 0, 42, 0, 0, 0], &option, &debug_data, &memory), "Some(42)");
+        assert_eq!(format(&[0; 8], &option, &debug_data, &memory), "None");
+        assert_eq!(format(&[2, 0, 0, 0], &color, &debug_data, &memory), "BLUE");
+        assert_eq!(format(&[7, 0, 0, 0], &color, &debug_data, &memory), "7");
+    }
+}
@@ -0,0 +1,421 @@
+//! Pretty-printers for the user's own types, which `print`, `display` and `info locals` try before
+//! the built-in formatting in `pretty`. Each one is chosen by a regular expression on the type's
+//! name, or the name of a typedef of it. They are read from printer files, one per line, which
+//! describe the usual shapes of data structure by their field names:
+//!
+//! ```text
+//! # A linked list, from a node or from a struct with a pointer to the first node
+//! list   ^node$   next=next value=value
+//! list   ^list$   head=head next=next value=value
+//! # A hash table of chained entries: `size` buckets, each a pointer to the first entry
+//! map    ^table$  buckets=buckets size=capacity next=next key=key value=value
+//! # A string with its length kept alongside; without `len` it is NUL-terminated
+//! string ^str$    data=chars len=length
+//! # Anything else, as a template of its fields
+//! format ^point$  ({x}, {y})
+//! ```
+//!
+//! A pattern with spaces in it goes in double quotes. `~/.deet/printers` is read at startup, and
+//! `printer load <file>` reads more. Printers defined later win over earlier ones for the same
+//! type.
+//!
+//! Formatters that need more than that come from plugins: shared libraries, written in C or in
+//! Rust as a `cdylib`, that export `deet_register_printers` (see PluginEntry) and register
+//! functions with it. They are loaded with `printer load <library>.so`.
+
+use crate::dwarf_expr::EvalContext;
+use crate::Error;
+use regex::Regex;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+
+/// A value handed to a plugin's formatter.
+#[repr(C)]
+pub struct PluginValue {
+    /// The name of the type the formatter was registered for, NUL-terminated
+    pub type_name: *const c_char,
+    /// The value's bytes, as laid out in the inferior
+    pub bytes: *const u8,
+    pub len: usize,
+    /// Reads `len` bytes of the inferior's memory at `addr` into `buf`. Returns false if they
+    /// can't be read.
+    pub read_memory: extern "C" fn(context: *const c_void, addr: u64, buf: *mut u8, len: usize) -> bool,
+    /// To be passed to read_memory
+    pub context: *const c_void,
+}
+
+/// A formatter in a plugin. It writes the formatted value to `out`, as UTF-8 without a NUL, and
+/// returns its length. If that is more than `out_len`, only `out_len` bytes are written, and it is
+/// called once more with room for the rest; if it still doesn't fit, the value is left to the
+/// built-in formatting, as it is on a negative return.
+pub type PluginFormatter =
+    extern "C" fn(value: *const PluginValue, out: *mut c_char, out_len: usize) -> isize;
+
+/// What a plugin calls to register a formatter for the types whose names match `pattern`, a
+/// NUL-terminated regular expression. `registry` is what deet_register_printers was given.
+pub type PluginRegister =
+    extern "C" fn(registry: *mut c_void, pattern: *const c_char, formatter: PluginFormatter);
+
+/// `deet_register_printers`, which a plugin exports and which is called once it is loaded.
+/// Plugins are never unloaded.
+pub type PluginEntry = extern "C" fn(registry: *mut c_void, register: PluginRegister);
+
+const PLUGIN_ENTRY: &[u8] = b"deet_register_printers\0";
+
+/// How much room a plugin's formatter gets on the first try
+const PLUGIN_OUTPUT_SIZE: usize = 256;
+
+/// What a pretty-printer does with a value of its type. Fields are named as they are in the
+/// struct.
+#[derive(Debug, Clone)]
+pub enum Shape {
+    /// A linked list: `value` of each node, following `next` until it is null. The value is the
+    /// first node, or a pointer to it, or with `head` a struct pointing to it.
+    List {
+        head: Option<String>,
+        next: String,
+        value: Option<String>,
+    },
+    /// A hash table with chaining: `buckets` points to an array of `size` pointers to entries,
+    /// each holding a `key`, a `value` and the `next` entry in its bucket. `size` is a field or
+    /// a number.
+    Map {
+        buckets: String,
+        size: String,
+        next: String,
+        key: String,
+        value: String,
+    },
+    /// A string made of a pointer to its bytes and, unless they are NUL-terminated, a length
+    Str { data: String, len: Option<String> },
+    /// Text in which `{field}` is replaced with that field's value. `{a.b}` looks inside a field,
+    /// going through pointers.
+    Template(String),
+    /// A function from a plugin
+    Plugin(PluginFormatter),
+}
+
+pub struct PrettyPrinter {
+    pub pattern: Regex,
+    pub shape: Shape,
+    /// Where it came from: a printer file and line, or a plugin
+    pub source: String,
+}
+
+/// The pretty-printers that have been loaded, in the order they were.
+#[derive(Default)]
+pub struct Printers {
+    printers: Vec<PrettyPrinter>,
+}
+
+impl Printers {
+    /// The printer for a type named `type_name`, if there is one. Later printers win.
+    pub fn find(&self, type_name: &str) -> Option<&PrettyPrinter> {
+        self.printers.iter().rev().find(|printer| printer.pattern.is_match(type_name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PrettyPrinter> {
+        self.printers.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.printers.is_empty()
+    }
+
+    /// Adds the printer defined by a line of a printer file. Returns whether the line defined
+    /// one, rather than being blank or a comment.
+    pub fn define(&mut self, line: &str, source: &str) -> Result<bool, String> {
+        match parse_line(line)? {
+            Some((pattern, shape)) => {
+                self.printers.push(PrettyPrinter {
+                    pattern,
+                    shape,
+                    source: source.to_string(),
+                });
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Loads a printer file, or a plugin if `path` is a shared library. Returns how many printers
+    /// it added. A file with a bad line adds none.
+    pub fn load(&mut self, path: &Path) -> Result<usize, Error> {
+        let name = path.to_string_lossy();
+        if name.ends_with(".so") || name.contains(".so.") {
+            return self.load_plugin(path);
+        }
+        let text = fs::read_to_string(path).map_err(|err| Error::OpeningFile(name.to_string(), err))?;
+        let mut loaded = Printers::default();
+        for (number, line) in text.lines().enumerate() {
+            let source = format!("{}:{}", name, number + 1);
+            loaded
+                .define(line, &source)
+                .map_err(|err| Error::Printer(format!("{}: {}", source, err)))?;
+        }
+        let count = loaded.printers.len();
+        self.printers.extend(loaded.printers);
+        Ok(count)
+    }
+
+    fn load_plugin(&mut self, path: &Path) -> Result<usize, Error> {
+        let name = path.to_string_lossy().to_string();
+        let c_path = CString::new(name.as_bytes())
+            .map_err(|_| Error::Printer(format!("{}: bad file name", name)))?;
+        let (entry, handle) = unsafe {
+            let handle = libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if handle.is_null() {
+                return Err(Error::Printer(dl_error()));
+            }
+            (libc::dlsym(handle, PLUGIN_ENTRY.as_ptr() as *const c_char), handle)
+        };
+        if entry.is_null() {
+            unsafe { libc::dlclose(handle) };
+            return Err(Error::Printer(format!("{}: no deet_register_printers function", name)));
+        }
+        let entry: PluginEntry = unsafe { std::mem::transmute(entry) };
+        let mut registration = Registration {
+            source: name,
+            printers: Vec::new(),
+            errors: Vec::new(),
+        };
+        entry(&mut registration as *mut Registration as *mut c_void, register_plugin_printer);
+        if let Some(err) = registration.errors.into_iter().next() {
+            return Err(Error::Printer(err));
+        }
+        let count = registration.printers.len();
+        self.printers.extend(registration.printers);
+        Ok(count)
+    }
+}
+
+/// Where `~/.deet/printers`, the printer file read at startup, would be.
+pub fn default_path() -> Option<PathBuf> {
+    let home = std::env::var("HOME").ok()?;
+    Some(Path::new(&home).join(".deet").join("printers"))
+}
+
+/// Parses a line of a printer file: `<shape> <pattern> <arguments>`.
+fn parse_line(line: &str) -> Result<Option<(Regex, Shape)>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (kind, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim_start();
+    let (pattern, rest) = match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"').ok_or("unterminated \" in the pattern")?,
+        None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+    };
+    if pattern.is_empty() {
+        return Err(String::from("missing type pattern"));
+    }
+    let pattern = Regex::new(pattern).map_err(|err| format!("invalid pattern: {}", err))?;
+    let rest = rest.trim();
+    if kind == "format" {
+        return Ok(Some((pattern, Shape::Template(rest.to_string()))));
+    }
+
+    let mut args = Vec::new();
+    for arg in rest.split_whitespace() {
+        match arg.split_once('=') {
+            Some((key, value)) if !value.is_empty() => args.push((key, value)),
+            _ => return Err(format!("expected field=name, found \"{}\"", arg)),
+        }
+    }
+    let known: &[&str] = match kind {
+        "list" => &["head", "next", "value"],
+        "map" => &["buckets", "size", "next", "key", "value"],
+        "string" => &["data", "len"],
+        _ => return Err(format!("unknown printer \"{}\": expected list, map, string or format", kind)),
+    };
+    if let Some((key, _)) = args.iter().find(|(key, _)| !known.contains(key)) {
+        return Err(format!("{} printers don't take {}", kind, key));
+    }
+    let get = |key: &str| args.iter().find(|(k, _)| *k == key).map(|(_, value)| value.to_string());
+    let require = |key: &str| get(key).ok_or_else(|| format!("{} printers need {}=", kind, key));
+    let shape = match kind {
+        "list" => Shape::List {
+            head: get("head"),
+            next: require("next")?,
+            value: get("value"),
+        },
+        "map" => Shape::Map {
+            buckets: require("buckets")?,
+            size: require("size")?,
+            next: require("next")?,
+            key: require("key")?,
+            value: require("value")?,
+        },
+        _ => Shape::Str {
+            data: require("data")?,
+            len: get("len"),
+        },
+    };
+    Ok(Some((pattern, shape)))
+}
+
+/// What a plugin registers into while deet_register_printers runs
+struct Registration {
+    source: String,
+    printers: Vec<PrettyPrinter>,
+    errors: Vec<String>,
+}
+
+extern "C" fn register_plugin_printer(
+    registry: *mut c_void,
+    pattern: *const c_char,
+    formatter: PluginFormatter,
+) {
+    let registration = unsafe { &mut *(registry as *mut Registration) };
+    if pattern.is_null() {
+        return registration.errors.push(format!("{}: null pattern", registration.source));
+    }
+    let pattern = unsafe { CStr::from_ptr(pattern) }.to_string_lossy();
+    match Regex::new(&pattern) {
+        Ok(regex) => registration.printers.push(PrettyPrinter {
+            pattern: regex,
+            shape: Shape::Plugin(formatter),
+            source: registration.source.clone(),
+        }),
+        Err(err) => registration
+            .errors
+            .push(format!("{}: invalid pattern {}: {}", registration.source, pattern, err)),
+    }
+}
+
+fn dl_error() -> String {
+    let err = unsafe { libc::dlerror() };
+    if err.is_null() {
+        String::from("dlopen failed")
+    } else {
+        unsafe { CStr::from_ptr(err) }.to_string_lossy().into_owned()
+    }
+}
+
+/// Runs a plugin's formatter on a value of type `type_name`, letting it read the inferior's memory
+/// through `ctx`. None if it declined.
+pub fn call_plugin(
+    formatter: PluginFormatter,
+    type_name: &str,
+    bytes: &[u8],
+    ctx: &dyn EvalContext,
+) -> Option<String> {
+    let type_name = CString::new(type_name).ok()?;
+    let value = PluginValue {
+        type_name: type_name.as_ptr(),
+        bytes: bytes.as_ptr(),
+        len: bytes.len(),
+        read_memory: read_plugin_memory,
+        context: &ctx as *const &dyn EvalContext as *const c_void,
+    };
+    let mut out = vec![0u8; PLUGIN_OUTPUT_SIZE];
+    let mut len = usize::try_from(formatter(&value, out.as_mut_ptr() as *mut c_char, out.len())).ok()?;
+    if len > out.len() {
+        // Only once: a formatter that wants more room every time would keep us here forever
+        out.resize(len, 0);
+        len = usize::try_from(formatter(&value, out.as_mut_ptr() as *mut c_char, out.len())).ok()?;
+    }
+    if len > out.len() {
+        return None;
+    }
+    out.truncate(len);
+    Some(String::from_utf8_lossy(&out).into_owned())
+}
+
+extern "C" fn read_plugin_memory(context: *const c_void, addr: u64, buf: *mut u8, len: usize) -> bool {
+    let ctx = unsafe { *(context as *const &dyn EvalContext) };
+    match ctx.read_memory(addr, len) {
+        Some(bytes) if bytes.len() == len => {
+            unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), buf, len) };
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let (pattern, shape) = parse_line("list ^node$ next=next value=value").unwrap().unwrap();
+        assert!(pattern.is_match("node"));
+        match shape {
+            Shape::List { head, next, value } => {
+                assert_eq!((head, next, value), (None, "next".to_string(), Some("value".to_string())))
+            }
+            other => panic!("Expected a list, got {:?}", other),
+        }
+        let (pattern, shape) = parse_line("format \"^struct point$\" ({x}, {y})").unwrap().unwrap();
+        assert!(pattern.is_match("struct point"));
+        assert!(matches!(shape, Shape::Template(template) if template == "({x}, {y})"));
+        assert!(matches!(
+            parse_line("string ^str$ data=chars").unwrap().unwrap().1,
+            Shape::Str { len: None, .. }
+        ));
+        assert!(parse_line("  # comment").unwrap().is_none());
+        assert!(parse_line("").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_line_errors() {
+        assert!(parse_line("tree ^node$ left=left").unwrap_err().contains("unknown printer"));
+        assert!(parse_line("list ^node$ value=value").unwrap_err().contains("need next="));
+        assert!(parse_line("list ^node$ next=next prev=prev").unwrap_err().contains("prev"));
+        assert!(parse_line("list ^node( next=next").unwrap_err().contains("invalid pattern"));
+        assert!(parse_line("map \"^table next=next").unwrap_err().contains("unterminated"));
+        assert!(parse_line("string ^str$ data").unwrap_err().contains("field=name"));
+    }
+
+    struct NoMemory;
+
+    impl EvalContext for NoMemory {
+        fn register(&self, _register: u16) -> Option<u64> {
+            None
+        }
+
+        fn read_memory(&self, _addr: u64, _size: usize) -> Option<Vec<u8>> {
+            None
+        }
+
+        fn call_frame_cfa(&self) -> Option<u64> {
+            None
+        }
+
+        fn bias(&self) -> u64 {
+            0
+        }
+    }
+
+    /// Writes 300 x's, which don't fit on the first try
+    extern "C" fn long(_value: *const PluginValue, out: *mut c_char, out_len: usize) -> isize {
+        let len = 300.min(out_len);
+        unsafe { std::ptr::write_bytes(out, b'x', len) };
+        300
+    }
+
+    /// Always wants more room than it was given
+    extern "C" fn greedy(_value: *const PluginValue, _out: *mut c_char, out_len: usize) -> isize {
+        out_len as isize + 1
+    }
+
+    #[test]
+    fn test_call_plugin() {
+        assert_eq!(call_plugin(long, "t", &[], &NoMemory), Some("x".repeat(300)));
+        assert_eq!(call_plugin(greedy, "t", &[], &NoMemory), None);
+    }
+
+    #[test]
+    fn test_find() {
+        let mut printers = Printers::default();
+        printers.define("list ^node$ next=next", "first").unwrap();
+        printers.define("format ^(node|point)$ {x}", "second").unwrap();
+        assert_eq!(printers.find("node").map(|printer| printer.source.as_str()), Some("second"));
+        assert!(printers.find("node *").is_none());
+    }
+}
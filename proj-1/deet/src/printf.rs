@@ -0,0 +1,346 @@
+//! C printf-style formatting, for `dprintf`. Supports the usual conversions (`d i u x X o c s
+//! f F e E g G p %`) with flags, width, precision and length modifiers.
+
+pub enum PrintfArg {
+    /// Integers, characters and pointers, as their raw 64 bits. The conversion's length
+    /// modifier picks how many of them count.
+    Integer(u64),
+    Float(f64),
+    Str(String),
+}
+
+struct Spec {
+    left_align: bool,
+    zero_pad: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+    /// Size of the argument in bytes according to the length modifier (hh, h, l, ...)
+    size: usize,
+    conversion: char,
+}
+
+enum Segment {
+    Literal(String),
+    Conversion(Spec),
+}
+
+/// The conversion characters in a format string, one per argument it takes.
+pub fn conversions(format: &str) -> Result<Vec<char>, String> {
+    Ok(parse(format)?
+        .iter()
+        .filter_map(|segment| match segment {
+            Segment::Conversion(spec) => Some(spec.conversion),
+            Segment::Literal(_) => None,
+        })
+        .collect())
+}
+
+pub fn format(format: &str, args: &[PrintfArg]) -> Result<String, String> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    for segment in parse(format)? {
+        let spec = match segment {
+            Segment::Literal(text) => {
+                out.push_str(&text);
+                continue;
+            }
+            Segment::Conversion(spec) => spec,
+        };
+        let arg = args
+            .next()
+            .ok_or_else(|| "Missing argument in format string".to_string())?;
+        let body = match (spec.conversion, arg) {
+            ('d', PrintfArg::Integer(value)) | ('i', PrintfArg::Integer(value)) => {
+                let value = sign_extend(*value, spec.size);
+                let digits = with_precision(value.unsigned_abs().to_string(), spec.precision);
+                let sign = if value < 0 {
+                    "-"
+                } else if spec.plus {
+                    "+"
+                } else if spec.space {
+                    " "
+                } else {
+                    ""
+                };
+                pad_number(&mut out, &spec, sign, &digits);
+                continue;
+            }
+            ('u', PrintfArg::Integer(value)) => {
+                with_precision(truncate(*value, spec.size).to_string(), spec.precision)
+            }
+            ('x', PrintfArg::Integer(value)) | ('X', PrintfArg::Integer(value)) => {
+                let value = truncate(*value, spec.size);
+                let digits = with_precision(format!("{:x}", value), spec.precision);
+                let prefix = if spec.alternate && value != 0 { "0x" } else { "" };
+                if spec.conversion == 'X' {
+                    pad_number(&mut out, &spec, &prefix.to_uppercase(), &digits.to_uppercase());
+                } else {
+                    pad_number(&mut out, &spec, prefix, &digits);
+                }
+                continue;
+            }
+            ('o', PrintfArg::Integer(value)) => {
+                let digits = with_precision(format!("{:o}", truncate(*value, spec.size)), spec.precision);
+                if spec.alternate && !digits.starts_with('0') {
+                    format!("0{}", digits)
+                } else {
+                    digits
+                }
+            }
+            ('p', PrintfArg::Integer(value)) => format!("0x{:x}", value),
+            ('c', PrintfArg::Integer(value)) => (*value as u8 as char).to_string(),
+            ('s', PrintfArg::Str(text)) => match spec.precision {
+                Some(precision) => text.chars().take(precision).collect(),
+                None => text.clone(),
+            },
+            ('f', PrintfArg::Float(value))
+            | ('F', PrintfArg::Float(value))
+            | ('e', PrintfArg::Float(value))
+            | ('E', PrintfArg::Float(value))
+            | ('g', PrintfArg::Float(value))
+            | ('G', PrintfArg::Float(value)) => {
+                let digits = format_float(value.abs(), &spec);
+                let sign = if value.is_sign_negative() {
+                    "-"
+                } else if spec.plus {
+                    "+"
+                } else if spec.space {
+                    " "
+                } else {
+                    ""
+                };
+                pad_number(&mut out, &spec, sign, &digits);
+                continue;
+            }
+            (conversion, _) => {
+                return Err(format!("Argument doesn't match conversion %{}", conversion));
+            }
+        };
+        pad(&mut out, &spec, "", &body, false);
+    }
+    if args.next().is_some() {
+        return Err("Too many arguments for format string".to_string());
+    }
+    Ok(out)
+}
+
+/// Pads a number, which unlike other conversions can be padded with zeros (unless an integer
+/// has a precision).
+fn pad_number(out: &mut String, spec: &Spec, sign: &str, digits: &str) {
+    let is_float = "fFeEgG".contains(spec.conversion);
+    let zero_pad = spec.zero_pad && (is_float || spec.precision.is_none());
+    pad(out, spec, sign, digits, zero_pad);
+}
+
+fn pad(out: &mut String, spec: &Spec, prefix: &str, body: &str, zero_pad: bool) {
+    let len = prefix.chars().count() + body.chars().count();
+    let fill = spec.width.unwrap_or(0).saturating_sub(len);
+    if spec.left_align {
+        out.push_str(prefix);
+        out.push_str(body);
+        out.extend(std::iter::repeat(' ').take(fill));
+    } else if zero_pad {
+        out.push_str(prefix);
+        out.extend(std::iter::repeat('0').take(fill));
+        out.push_str(body);
+    } else {
+        out.extend(std::iter::repeat(' ').take(fill));
+        out.push_str(prefix);
+        out.push_str(body);
+    }
+}
+
+fn with_precision(digits: String, precision: Option<usize>) -> String {
+    match precision {
+        Some(0) if digits == "0" => String::new(),
+        Some(precision) if digits.len() < precision => {
+            format!("{}{}", "0".repeat(precision - digits.len()), digits)
+        }
+        _ => digits,
+    }
+}
+
+fn truncate(value: u64, size: usize) -> u64 {
+    if size >= 8 {
+        value
+    } else {
+        value & ((1 << (8 * size)) - 1)
+    }
+}
+
+fn sign_extend(value: u64, size: usize) -> i64 {
+    let shift = 64 - 8 * size as u32;
+    ((value << shift) as i64) >> shift
+}
+
+/// Formats a non-negative float for %f, %e or %g.
+fn format_float(value: f64, spec: &Spec) -> String {
+    if !value.is_finite() {
+        let text = if value.is_nan() { "nan" } else { "inf" };
+        return if spec.conversion.is_ascii_uppercase() {
+            text.to_uppercase()
+        } else {
+            text.to_string()
+        };
+    }
+    let precision = spec.precision.unwrap_or(6);
+    let text = match spec.conversion.to_ascii_lowercase() {
+        'f' => format!("{:.*}", precision, value),
+        'e' => format_exponent(value, precision),
+        _ => {
+            // %g uses %e when the exponent is below -4 or at least the precision, and drops
+            // trailing zeros
+            let precision = precision.max(1);
+            let exponent = exponent_of(value, precision - 1);
+            if exponent < -4 || exponent >= precision as i32 {
+                let text = format_exponent(value, precision - 1);
+                let (mantissa, exponent) = text.split_at(text.find('e').unwrap());
+                format!("{}{}", strip_fraction_zeros(mantissa, spec.alternate), exponent)
+            } else {
+                let decimals = (precision as i32 - 1 - exponent) as usize;
+                strip_fraction_zeros(&format!("{:.*}", decimals, value), spec.alternate)
+            }
+        }
+    };
+    if spec.conversion.is_ascii_uppercase() {
+        text.to_uppercase()
+    } else {
+        text
+    }
+}
+
+/// The decimal exponent of `value` once rounded to `precision` digits after the point.
+fn exponent_of(value: f64, precision: usize) -> i32 {
+    let text = format!("{:.*e}", precision, value);
+    text[text.find('e').unwrap() + 1..].parse().unwrap_or(0)
+}
+
+/// Rust's `{:e}` writes `1.5e2`, where C writes `1.500000e+02`.
+fn format_exponent(value: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = text.split_at(text.find('e').unwrap());
+    let exponent: i32 = exponent[1..].parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+fn strip_fraction_zeros(text: &str, keep: bool) -> String {
+    if keep || !text.contains('.') {
+        return text.to_string();
+    }
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn parse(format: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            literal.push('%');
+            continue;
+        }
+        let mut spec = Spec {
+            left_align: false,
+            zero_pad: false,
+            plus: false,
+            space: false,
+            alternate: false,
+            width: None,
+            precision: None,
+            size: 4,
+            conversion: ' ',
+        };
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left_align = true,
+                '0' => spec.zero_pad = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        spec.width = parse_number(&mut chars);
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(parse_number(&mut chars).unwrap_or(0));
+        }
+        loop {
+            match chars.peek() {
+                Some('h') => spec.size = if spec.size == 2 { 1 } else { 2 },
+                Some('l') | Some('j') | Some('z') | Some('t') | Some('L') | Some('q') => {
+                    spec.size = 8
+                }
+                _ => break,
+            }
+            chars.next();
+        }
+        spec.conversion = match chars.next() {
+            Some(c) if "diuxXocspfFeEgG".contains(c) => c,
+            Some(c) => return Err(format!("Unsupported conversion %{} in format string", c)),
+            None => return Err("Incomplete conversion at the end of the format string".to_string()),
+        };
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(std::mem::replace(&mut literal, String::new())));
+        }
+        segments.push(Segment::Conversion(spec));
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+fn parse_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<usize> {
+    let mut number = None;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        number = Some(number.unwrap_or(0) * 10 + digit as usize);
+        chars.next();
+    }
+    number
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn int(value: i64) -> PrintfArg {
+        PrintfArg::Integer(value as u64)
+    }
+
+    #[test]
+    fn formats_integers() {
+        assert_eq!(
+            format("%d|%#x|%-4d|%u\n", &[int(-5), int(255), int(7), int(0xffff_ffff)]).unwrap(),
+            "-5|0xff|7   |4294967295\n"
+        );
+        assert_eq!(format("%03d %+d %c", &[int(7), int(3), int(65)]).unwrap(), "007 +3 A");
+        assert_eq!(format("%hhd %ld %X", &[int(0xff), int(-1), int(0xbeef)]).unwrap(), "-1 -1 BEEF");
+        assert_eq!(format("%d %d", &[int(1)]), Err("Missing argument in format string".to_string()));
+    }
+
+    #[test]
+    fn formats_floats_and_strings() {
+        assert_eq!(format("%f", &[PrintfArg::Float(1.5)]).unwrap(), "1.500000");
+        assert_eq!(format("%.2e", &[PrintfArg::Float(1234.5)]).unwrap(), "1.23e+03");
+        assert_eq!(
+            format("%g %g %g", &[PrintfArg::Float(0.0001), PrintfArg::Float(1e20), PrintfArg::Float(2.5)])
+                .unwrap(),
+            "0.0001 1e+20 2.5"
+        );
+        assert_eq!(format("%8.3s|", &[PrintfArg::Str("banana".to_string())]).unwrap(), "     ban|");
+        assert_eq!(format("100%%", &[]).unwrap(), "100%");
+        assert!(format("%s", &[int(1)]).is_err());
+        assert_eq!(conversions("%d and %-10s%%").unwrap(), vec!['d', 's']);
+    }
+}
@@ -0,0 +1,185 @@
+//! Helpers for reading information about the inferior out of /proc.
+
+use nix::unistd::Pid;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::{fs, io};
+
+/// One line of /proc/<pid>/maps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapEntry {
+    pub start: usize,
+    pub end: usize,
+    pub perms: String,
+    pub offset: usize,
+    pub path: Option<String>,
+}
+
+impl MapEntry {
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+pub fn read_maps(pid: Pid) -> io::Result<Vec<MapEntry>> {
+    let contents = fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    Ok(contents.lines().filter_map(parse_maps_line).collect())
+}
+
+fn parse_maps_line(line: &str) -> Option<MapEntry> {
+    let mut rest = line;
+    let mut next_field = || {
+        let trimmed = rest.trim_start();
+        let (field, after) = trimmed.split_once(char::is_whitespace).unwrap_or((trimmed, ""));
+        rest = after;
+        Some(field).filter(|field| !field.is_empty())
+    };
+    let range = next_field()?;
+    let perms = next_field()?.to_string();
+    let offset = usize::from_str_radix(next_field()?, 16).ok()?;
+    let _dev = next_field()?;
+    let _inode = next_field()?;
+    // The path is the rest of the line, and may itself contain spaces, even several in a row
+    let path = rest.trim_start().to_string();
+
+    let mut bounds = range.split('-');
+    let start = usize::from_str_radix(bounds.next()?, 16).ok()?;
+    let end = usize::from_str_radix(bounds.next()?, 16).ok()?;
+    Some(MapEntry {
+        start,
+        end,
+        perms,
+        offset,
+        path: if !path.is_empty() { Some(path) } else { None },
+    })
+}
+
+/// Reads the auxiliary vector the kernel handed the inferior at exec time (AT_ENTRY, AT_BASE,
+/// ...), as a map from entry type to value.
+pub fn read_auxv(pid: Pid) -> io::Result<HashMap<u64, u64>> {
+    let bytes = fs::read(format!("/proc/{}/auxv", pid))?;
+    let mut auxv = HashMap::new();
+    for entry in bytes.chunks_exact(16) {
+        let key = u64::from_ne_bytes(entry[0..8].try_into().unwrap());
+        let value = u64::from_ne_bytes(entry[8..16].try_into().unwrap());
+        if key == libc::AT_NULL {
+            break;
+        }
+        auxv.insert(key, value);
+    }
+    Ok(auxv)
+}
+
+/// An open file descriptor, from /proc/<pid>/fd and /proc/<pid>/fdinfo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FdEntry {
+    pub fd: i32,
+    /// What the descriptor refers to: a path, or something like `pipe:[1234]` or
+    /// `socket:[5678]`
+    pub target: String,
+    pub pos: u64,
+    /// The flags it was opened with, as the kernel reports them (octal in fdinfo)
+    pub flags: i32,
+}
+
+/// The inferior's open file descriptors, in numerical order.
+pub fn read_fds(pid: Pid) -> io::Result<Vec<FdEntry>> {
+    let mut fds = Vec::new();
+    for dir_entry in fs::read_dir(format!("/proc/{}/fd", pid))? {
+        let dir_entry = dir_entry?;
+        let fd = match dir_entry.file_name().to_str().and_then(|name| name.parse::<i32>().ok()) {
+            Some(fd) => fd,
+            None => continue,
+        };
+        // The descriptor may be closed between listing the directory and reading it
+        let target = match fs::read_link(dir_entry.path()) {
+            Ok(target) => target.to_string_lossy().into_owned(),
+            Err(_) => continue,
+        };
+        let (pos, flags) = fs::read_to_string(format!("/proc/{}/fdinfo/{}", pid, fd))
+            .ok()
+            .and_then(|contents| parse_fdinfo(&contents))
+            .unwrap_or((0, 0));
+        fds.push(FdEntry { fd, target, pos, flags });
+    }
+    fds.sort_by_key(|entry| entry.fd);
+    Ok(fds)
+}
+
+/// Pulls the position and flags out of a /proc/<pid>/fdinfo/<fd> file.
+fn parse_fdinfo(contents: &str) -> Option<(u64, i32)> {
+    let mut pos = None;
+    let mut flags = None;
+    for line in contents.lines() {
+        match line.split_once(':') {
+            Some(("pos", value)) => pos = value.trim().parse::<u64>().ok(),
+            Some(("flags", value)) => flags = i32::from_str_radix(value.trim(), 8).ok(),
+            _ => {}
+        }
+    }
+    Some((pos?, flags?))
+}
+
+/// The fields of /proc/<pid>/status, in the order the kernel lists them.
+pub fn read_status(pid: Pid) -> io::Result<Vec<(String, String)>> {
+    let contents = fs::read_to_string(format!("/proc/{}/status", pid))?;
+    Ok(parse_status(&contents))
+}
+
+fn parse_status(contents: &str) -> Vec<(String, String)> {
+    contents
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.to_string(), value.trim().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_maps_line() {
+        let entry = parse_maps_line(
+            "7f2c1a600000-7f2c1a628000 r--p 00000000 08:01 1835042    /usr/lib/libc.so.6",
+        )
+        .expect("Expected a map entry");
+        assert_eq!(entry.start, 0x7f2c1a600000);
+        assert_eq!(entry.end, 0x7f2c1a628000);
+        assert_eq!(entry.perms, "r--p");
+        assert_eq!(entry.offset, 0);
+        assert_eq!(entry.path, Some("/usr/lib/libc.so.6".to_string()));
+        assert!(entry.contains(0x7f2c1a600000));
+        assert!(!entry.contains(0x7f2c1a628000));
+    }
+
+    #[test]
+    fn test_parse_anonymous_maps_line() {
+        let entry = parse_maps_line("7ffd2e1b2000-7ffd2e1d3000 rw-p 00000000 00:00 0")
+            .expect("Expected a map entry");
+        assert_eq!(entry.path, None);
+        assert!(parse_maps_line("garbage").is_none());
+    }
+
+    #[test]
+    fn test_parse_maps_line_with_spaces() {
+        let entry =
+            parse_maps_line("00400000-00401000 r-xp 00000000 08:01 42   /tmp/my  prog (deleted)")
+                .expect("Expected a map entry");
+        assert_eq!(entry.path, Some("/tmp/my  prog (deleted)".to_string()));
+    }
+
+    #[test]
+    fn test_parse_fdinfo() {
+        let contents = "pos:\t12\nflags:\t0100002\nmnt_id:\t25\nino:\t3\n";
+        assert_eq!(parse_fdinfo(contents), Some((12, 0o100002)));
+        assert_eq!(parse_fdinfo("mnt_id:\t25\n"), None);
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let status = parse_status("Name:\tcount\nState:\tt (tracing stop)\nPid:\t4242\n");
+        assert_eq!(status.len(), 3);
+        assert_eq!(status[1], ("State".to_string(), "t (tracing stop)".to_string()));
+    }
+}
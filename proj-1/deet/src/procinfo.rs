@@ -0,0 +1,109 @@
+//! `info proc`: what the kernel says about the inferior, read from /proc while it is stopped. The
+//! memory map is annotated with the modules we have loaded, so that an address can be traced back
+//! to a library and its debug info.
+
+use crate::modules::{Module, ModuleList};
+use crate::procfs;
+use crate::syscalls;
+use nix::unistd::Pid;
+use std::fs;
+
+/// The kernel's O_LARGEFILE, which fdinfo reports for every file opened by a 64-bit process even
+/// though the C library defines the flag as 0
+const KERNEL_O_LARGEFILE: i32 = 0o100000;
+
+/// `info proc`: the process id, its command line, working directory and executable.
+pub fn print_summary(pid: Pid) {
+    println!("process {}", pid);
+    if let Ok(cmdline) = fs::read(format!("/proc/{}/cmdline", pid)) {
+        let args: Vec<String> = cmdline
+            .split(|byte| *byte == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        println!("cmdline = '{}'", args.join(" "));
+    }
+    for link in &["cwd", "exe"] {
+        if let Ok(target) = fs::read_link(format!("/proc/{}/{}", pid, link)) {
+            println!("{} = '{}'", link, target.display());
+        }
+    }
+}
+
+/// `info proc mappings`: the memory map, with the module each mapping belongs to and what debug
+/// info we have for it.
+pub fn print_mappings(pid: Pid, modules: &ModuleList) {
+    let maps = match procfs::read_maps(pid) {
+        Ok(maps) => maps,
+        Err(err) => return println!("Could not read /proc/{}/maps: {}", pid, err),
+    };
+    println!("Mapped address spaces:\n");
+    println!(
+        "{:>18} {:>18} {:>10} {:>10} {:<5}  {}",
+        "Start Addr", "End Addr", "Size", "Offset", "Perms", "objfile"
+    );
+    for entry in &maps {
+        let module = entry.path.as_deref().and_then(|path| modules.find(path));
+        println!(
+            "{:>18} {:>18} {:>10} {:>10} {:<5}  {}{}",
+            format!("0x{:x}", entry.start),
+            format!("0x{:x}", entry.end),
+            format!("0x{:x}", entry.end - entry.start),
+            format!("0x{:x}", entry.offset),
+            entry.perms,
+            entry.path.as_deref().unwrap_or(""),
+            module.map(describe_module).unwrap_or_default()
+        );
+    }
+}
+
+/// What we know about a module, e.g. ` [bias 0x7f1c2a400000, debug info for 3 files]`.
+fn describe_module(module: &Module) -> String {
+    let files = module.debug_data.file_count();
+    let symbols = module.debug_data.symbols().len();
+    let debug_info = match (files, symbols) {
+        (0, 0) => String::from("no symbols"),
+        (0, _) => format!("symbols only, {} symbols", symbols),
+        (1, _) => String::from("debug info for 1 file"),
+        _ => format!("debug info for {} files", files),
+    };
+    format!(" [bias 0x{:x}, {}]", module.bias, debug_info)
+}
+
+/// `info proc fds`: each open file descriptor, where it points and how it was opened.
+pub fn print_fds(pid: Pid) {
+    let fds = match procfs::read_fds(pid) {
+        Ok(fds) => fds,
+        Err(err) => return println!("Could not read /proc/{}/fd: {}", pid, err),
+    };
+    println!("{:>4} {:>10}  {:<28} {}", "fd", "pos", "flags", "target");
+    for entry in &fds {
+        println!(
+            "{:>4} {:>10}  {:<28} {}",
+            entry.fd,
+            entry.pos,
+            format_fd_flags(entry.flags),
+            entry.target
+        );
+    }
+}
+
+fn format_fd_flags(flags: i32) -> String {
+    if flags & KERNEL_O_LARGEFILE != 0 && libc::O_LARGEFILE != KERNEL_O_LARGEFILE {
+        format!("{}|O_LARGEFILE", syscalls::format_open_flags(flags & !KERNEL_O_LARGEFILE))
+    } else {
+        syscalls::format_open_flags(flags)
+    }
+}
+
+/// `info proc status`: /proc/<pid>/status, one field per line.
+pub fn print_status(pid: Pid) {
+    let status = match procfs::read_status(pid) {
+        Ok(status) => status,
+        Err(err) => return println!("Could not read /proc/{}/status: {}", pid, err),
+    };
+    let width = status.iter().map(|(key, _)| key.len()).max().unwrap_or(0) + 1;
+    for (key, value) in &status {
+        println!("{:<width$} {}", format!("{}:", key), value, width = width);
+    }
+}
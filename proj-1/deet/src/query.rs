@@ -0,0 +1,294 @@
+//! Symbol and type queries: `info functions`, `info variables`, `info scope`, `info line`,
+//! `info symbol` and `ptype`. These only look at the debug info (and ELF symbols) of the
+//! loaded modules, so most of them work before the program is started.
+
+use crate::demangle;
+use crate::dwarf_data::{DwarfData, Function, Location, Type, TypeKind};
+use crate::modules::ModuleList;
+use regex::Regex;
+
+/// `info functions [regex]`: functions with debug info grouped by source file, then the
+/// symbols of functions without any.
+pub fn print_functions(modules: &ModuleList, pattern: Option<&Regex>) {
+    print_heading("functions", pattern);
+    let matches = |name: &str| pattern.map_or(true, |pattern| pattern.is_match(name));
+    let mut non_debugging = Vec::new();
+    for module in modules.iter() {
+        let debug_data = &module.debug_data;
+        let mut files: Vec<_> = debug_data.files().collect();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        for file in files {
+            let mut functions: Vec<(String, &Function)> = file
+                .functions
+                .iter()
+                .filter(|func| func.address != 0)
+                .map(|func| (function_name(func), func))
+                .filter(|(name, _)| matches(name))
+                .collect();
+            if functions.is_empty() {
+                continue;
+            }
+            functions.sort_by(|a, b| a.0.cmp(&b.0));
+            functions.dedup_by(|a, b| a.0 == b.0);
+            println!("\nFile {}:", file.name);
+            for (name, func) in functions {
+                let return_type = func
+                    .return_type
+                    .as_ref()
+                    .map_or("void", |dtype| dtype.name.as_str());
+                println!("{}:\t{} {}();", func.line_number, return_type, name);
+            }
+        }
+        for symbol in debug_data.symbols() {
+            let name = demangle::demangle(&symbol.name);
+            if matches(&name) && debug_data.get_function_containing(symbol.address).is_none() {
+                non_debugging.push((symbol.address + module.bias, name));
+            }
+        }
+    }
+    if !non_debugging.is_empty() {
+        non_debugging.sort();
+        non_debugging.dedup();
+        println!("\nNon-debugging symbols:");
+        for (address, name) in non_debugging {
+            println!("0x{:016x}  {}", address, name);
+        }
+    }
+}
+
+/// `info variables [regex]`: global and static variables, grouped by source file.
+pub fn print_variables(modules: &ModuleList, pattern: Option<&Regex>) {
+    print_heading("variables", pattern);
+    for module in modules.iter() {
+        let mut files: Vec<_> = module.debug_data.files().collect();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        for file in files {
+            let mut variables: Vec<_> = file
+                .global_variables
+                .iter()
+                .filter(|var| pattern.map_or(true, |pattern| pattern.is_match(&var.name)))
+                .collect();
+            if variables.is_empty() {
+                continue;
+            }
+            variables.sort_by(|a, b| a.name.cmp(&b.name));
+            println!("\nFile {}:", file.name);
+            for var in variables {
+                println!("{}:\t{};", var.line_number, declaration(&var.entity_type.name, &var.name));
+            }
+        }
+    }
+}
+
+fn print_heading(what: &str, pattern: Option<&Regex>) {
+    match pattern {
+        Some(pattern) => println!("All {} matching regular expression \"{}\":", what, pattern),
+        None => println!("All defined {}:", what),
+    }
+}
+
+/// `info scope`: the parameters and locals of the function containing `addr`, and where each
+/// one lives.
+pub fn print_scope(modules: &ModuleList, addr: usize, location: &str) {
+    let found = modules.module_for_addr(addr).and_then(|module| {
+        let func = module
+            .debug_data
+            .get_function_containing(addr.checked_sub(module.bias)?)?;
+        Some((module, func))
+    });
+    let (module, func) = match found {
+        Some(found) => found,
+        None => {
+            println!("No function contains specified address.");
+            return;
+        }
+    };
+    if func.variables.is_empty() {
+        println!("Scope for {}:\nSymbol table contains no locals.", location);
+        return;
+    }
+    println!("Scope for {}:", location);
+    for var in &func.variables {
+        let size = module.debug_data.type_size(&var.entity_type);
+        let place = match &var.location {
+            Location::FramePointerOffset(offset) => format!("a variable at frame base offset {}", offset),
+            Location::Address(addr) => format!("static storage at address 0x{:x}", addr + module.bias),
+            Location::Expression(expr) => format!("a variable computed by the DWARF expression {}", expr),
+            Location::List(_) => String::from("a variable whose location depends on the program counter"),
+        };
+        println!(
+            "Symbol {} is {}, length {}, type {}.",
+            var.name, place, size, var.entity_type.name
+        );
+    }
+}
+
+/// `info line`: the range of addresses the code for a line occupies.
+pub fn print_line(modules: &ModuleList, file: Option<&str>, line_number: usize) {
+    match modules.get_line_range(file, line_number) {
+        Some((line, start, end)) if end > start => println!(
+            "Line {} of \"{}\" starts at address 0x{:x} {} and ends at 0x{:x} {}.",
+            line.number,
+            line.file,
+            start,
+            symbolize(modules, start).map_or(String::new(), |symbol| format!("<{}>", symbol)),
+            end,
+            symbolize(modules, end).map_or(String::new(), |symbol| format!("<{}>", symbol)),
+        ),
+        Some((line, start, _)) => println!(
+            "Line {} of \"{}\" is at address 0x{:x} but contains no code.",
+            line.number, line.file, start
+        ),
+        None => match file {
+            Some(file) => println!("No line {} in file \"{}\".", line_number, file),
+            None => println!("No line {} in the current file.", line_number),
+        },
+    }
+}
+
+/// `info symbol`: which function an address is in, and where.
+pub fn print_symbol(modules: &ModuleList, addr: usize) {
+    match (symbolize(modules, addr), modules.module_for_addr(addr)) {
+        (Some(symbol), Some(module)) => {
+            println!("{} in {}", symbol.replace('+', " + "), module.path)
+        }
+        _ => println!("No symbol matches 0x{:x}.", addr),
+    }
+}
+
+/// Describes an address as `function+offset`.
+pub fn symbolize(modules: &ModuleList, addr: usize) -> Option<String> {
+    let module = modules.module_for_addr(addr)?;
+    let link_addr = addr.checked_sub(module.bias)?;
+    let (name, start) = match module.debug_data.get_function_containing(link_addr) {
+        Some(func) => (function_name(func), func.address),
+        None => {
+            let symbol = module.debug_data.get_symbol_from_addr(link_addr)?;
+            (demangle::demangle(&symbol.name), symbol.address)
+        }
+    };
+    Some(match link_addr - start {
+        0 => name,
+        offset => format!("{}+{}", name, offset),
+    })
+}
+
+fn function_name(func: &Function) -> String {
+    match &func.linkage_name {
+        Some(linkage_name) => demangle::demangle(linkage_name),
+        None => func.name.clone(),
+    }
+}
+
+/// `ptype`: the type of a variable visible at `pc` (or a global when the program isn't
+/// running), or else the definition of a named type or the signature of a function.
+pub fn print_type(modules: &ModuleList, pc: Option<usize>, name: &str) {
+    let variable = match pc.and_then(|pc| Some((pc, modules.module_for_addr(pc)?))) {
+        Some((pc, module)) => module
+            .debug_data
+            .get_variable(pc - module.bias, name)
+            .map(|(var, _)| (module, &var.entity_type)),
+        None => None,
+    };
+    let variable = variable.or_else(|| {
+        modules.iter().find_map(|module| {
+            let var = module.debug_data.get_global_variable(name)?;
+            Some((module, &var.entity_type))
+        })
+    });
+    if let Some((module, dtype)) = variable {
+        println!("type = {}", describe_type(&module.debug_data, dtype));
+        return;
+    }
+    let type_name = ["struct ", "union ", "enum ", "class "]
+        .iter()
+        .find_map(|keyword| name.strip_prefix(keyword))
+        .unwrap_or(name)
+        .trim();
+    for module in modules.iter() {
+        if let Some(dtype) = module.debug_data.get_type_by_name(type_name) {
+            println!("type = {}", describe_type(&module.debug_data, dtype));
+            return;
+        }
+    }
+    match modules.get_function(name) {
+        Some((_, func)) => {
+            let return_type = func
+                .return_type
+                .as_ref()
+                .map_or("void", |dtype| dtype.name.as_str());
+            println!("type = {} ()", return_type);
+        }
+        None => println!("No symbol \"{}\" in current context.", name),
+    }
+}
+
+/// Spells out a type for `ptype`: the members of structures and the enumerators of enums,
+/// looking through typedefs.
+pub fn describe_type(debug_data: &DwarfData, dtype: &Type) -> String {
+    let dtype = debug_data.strip_aliases(dtype);
+    match &dtype.kind {
+        TypeKind::Struct {
+            members,
+            variant_part,
+            ..
+        } => {
+            // Unions are structures whose members all start at the beginning
+            let keyword = if members.len() > 1 && members.iter().all(|member| member.offset == 0) {
+                "union"
+            } else {
+                "struct"
+            };
+            let mut text = format!("{} {} {{\n", keyword, dtype.name);
+            for member in members {
+                let type_name = debug_data
+                    .get_type(member.type_key)
+                    .map_or("<unknown>", |member_type| member_type.name.as_str());
+                text.push_str(&format!("    {};\n", declaration(type_name, &member.name)));
+            }
+            if let Some(variant_part) = variant_part {
+                for variant in &variant_part.variants {
+                    text.push_str(&format!("    {},\n", variant.member.name));
+                }
+            }
+            text.push('}');
+            text
+        }
+        TypeKind::Enumeration(enumerators) => {
+            let names: Vec<String> = enumerators
+                .iter()
+                .enumerate()
+                .map(|(i, (name, value))| {
+                    if *value == i as i64 {
+                        name.clone()
+                    } else {
+                        format!("{} = {}", name, value)
+                    }
+                })
+                .collect();
+            format!("enum {} {{{}}}", dtype.name, names.join(", "))
+        }
+        _ => dtype.name.clone(),
+    }
+}
+
+/// A C declaration of `name` with the given type, e.g. `int counts[4]` for `int [4]`.
+fn declaration(type_name: &str, name: &str) -> String {
+    match type_name.find(" [") {
+        Some(i) => format!("{} {}{}", &type_name[..i], name, &type_name[i + 1..]),
+        None if type_name.ends_with('*') => format!("{}{}", type_name, name),
+        None => format!("{} {}", type_name, name),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn declares_arrays_and_pointers() {
+        assert_eq!(declaration("int", "x"), "int x");
+        assert_eq!(declaration("char *", "name"), "char *name");
+        assert_eq!(declaration("int [4]", "counts"), "int counts[4]");
+    }
+}
@@ -0,0 +1,216 @@
+//! Recording for reverse execution (`record`, `reverse-stepi`, `reverse-continue`, ...).
+//!
+//! While recording, the inferior is single-stepped. Before each instruction we save its
+//! registers, and after it we compare the inferior's writable memory against a shadow copy to
+//! find the bytes the instruction changed, keeping their old values. Undoing an instruction puts
+//! the registers and those bytes back. This needs no knowledge of what each instruction does,
+//! at the cost of a memory comparison per step, so it suits small programs and short stretches
+//! of execution.
+//!
+//! Going forward again after reversing simply runs the inferior from the restored state, so
+//! anything outside its memory (output already written, file offsets) isn't rolled back.
+
+use crate::arch::{Arch, Registers};
+use crate::inferior::{BreakPoint, Inferior, Status};
+use crate::procfs;
+use crate::Error;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+/// We refuse to record a process with more writable memory than this, since every step
+/// compares all of it.
+const MAX_RECORDED_MEMORY: usize = 64 * 1024 * 1024;
+
+/// What we need to undo one instruction.
+struct StepRecord {
+    /// The register set before the instruction ran, as the kernel hands it out
+    registers: Vec<u8>,
+    /// The bytes the instruction changed, with their values before it ran
+    memory: Vec<(usize, Vec<u8>)>,
+}
+
+/// A writable mapping and our copy of its contents as of the last recorded instruction.
+struct ShadowRegion {
+    start: usize,
+    bytes: Vec<u8>,
+}
+
+impl ShadowRegion {
+    fn end(&self) -> usize {
+        self.start + self.bytes.len()
+    }
+}
+
+pub struct Recording {
+    arch: &'static dyn Arch,
+    history: Vec<StepRecord>,
+    shadow: Vec<ShadowRegion>,
+    /// /proc/<pid>/mem, which is much faster than ptrace for reading the whole shadow at once
+    mem: fs::File,
+}
+
+impl Recording {
+    pub fn start(inferior: &Inferior) -> io::Result<Recording> {
+        let mem = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!("/proc/{}/mem", inferior.pid()))?;
+        let mut recording = Recording {
+            arch: inferior.arch(),
+            history: Vec::new(),
+            shadow: Vec::new(),
+            mem,
+        };
+        recording.refresh_mappings(inferior)?;
+        let total: usize = recording.shadow.iter().map(|region| region.bytes.len()).sum();
+        if total > MAX_RECORDED_MEMORY {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("the process has {} bytes of writable memory, too many to record", total),
+            ));
+        }
+        Ok(recording)
+    }
+
+    /// The registers `reverse-stepi` would take us back to, if there is any history left.
+    pub fn previous_registers(&self) -> Option<Registers> {
+        self.arch.decode_registers(&self.history.last()?.registers)
+    }
+
+    /// Single-steps the inferior, recording how to undo the step.
+    pub fn step(
+        &mut self,
+        inferior: &mut Inferior,
+        break_list: &HashMap<usize, BreakPoint>,
+    ) -> Result<Status, Error> {
+        let registers = inferior.get_register_set()?;
+        let regs = inferior.registers()?;
+        // A syscall can map or unmap memory, so we look at the memory map again after one
+        let syscall = self.arch.syscall_instruction();
+        let is_syscall = self
+            .read(regs.pc() as usize, syscall.len())
+            .map_or(false, |bytes| bytes == syscall);
+        let status = inferior.step(break_list)?;
+        if let Status::Stopped(..) = status {
+            // The stack grows without a syscall, when the instruction touches memory below it.
+            // Anything written below the red zone means we may have missed part of the stack.
+            let stack_low = (regs.sp() as usize).wrapping_sub(2 * self.arch.red_zone_size() as usize);
+            if is_syscall || self.shadow_for(stack_low).is_none() {
+                self.refresh_mappings(inferior).ok();
+            }
+            let memory = self.diff();
+            self.history.push(StepRecord { registers, memory });
+        }
+        Ok(status)
+    }
+
+    /// Undoes the last recorded instruction. Returns false if there is nothing left to undo.
+    pub fn undo(&mut self, inferior: &Inferior) -> Result<bool, Error> {
+        let mut record = match self.history.pop() {
+            Some(record) => record,
+            None => return Ok(false),
+        };
+        for (addr, old) in &record.memory {
+            self.mem.write_all_at(old, *addr as u64)?;
+            if let Some(region) = self.shadow_for(*addr) {
+                let offset = addr - region.start;
+                region.bytes[offset..offset + old.len()].copy_from_slice(old);
+            }
+        }
+        inferior.set_register_set(&mut record.registers)?;
+        Ok(true)
+    }
+
+    /// Re-reads the memory map, shadowing any new writable mappings and forgetting ones that
+    /// are gone. Mappings we already know keep their shadow, so changes in them still show up
+    /// in the next diff.
+    fn refresh_mappings(&mut self, inferior: &Inferior) -> io::Result<()> {
+        let maps = procfs::read_maps(inferior.pid())?;
+        let mut shadow = Vec::new();
+        for entry in maps.iter().filter(|entry| is_recorded(entry)) {
+            let len = entry.end - entry.start;
+            // The stack keeps its end as it grows down, other mappings keep their start
+            let existing = self
+                .shadow
+                .iter()
+                .position(|region| region.start == entry.start || region.end() == entry.end);
+            let region = match existing {
+                Some(i) if self.shadow[i].bytes.len() == len => self.shadow.swap_remove(i),
+                _ => {
+                    let mut bytes = match self.read(entry.start, len) {
+                        Some(bytes) => bytes,
+                        None => continue,
+                    };
+                    // Keep what we knew of the part that was already mapped, so that the next
+                    // diff still sees what changed there
+                    if let Some(i) = existing {
+                        let old = self.shadow.swap_remove(i);
+                        let overlap_start = old.start.max(entry.start);
+                        let overlap_end = old.end().min(entry.end);
+                        if overlap_start < overlap_end {
+                            bytes[overlap_start - entry.start..overlap_end - entry.start]
+                                .copy_from_slice(
+                                    &old.bytes[overlap_start - old.start..overlap_end - old.start],
+                                );
+                        }
+                    }
+                    ShadowRegion {
+                        start: entry.start,
+                        bytes,
+                    }
+                }
+            };
+            shadow.push(region);
+        }
+        self.shadow = shadow;
+        Ok(())
+    }
+
+    /// Finds the bytes that changed since the last step, updating the shadow as it goes and
+    /// returning the old values.
+    fn diff(&mut self) -> Vec<(usize, Vec<u8>)> {
+        let mut changes = Vec::new();
+        for i in 0..self.shadow.len() {
+            let start = self.shadow[i].start;
+            let current = match self.read(start, self.shadow[i].bytes.len()) {
+                Some(current) => current,
+                None => continue,
+            };
+            let region = &mut self.shadow[i];
+            let mut offset = 0;
+            while offset < current.len() {
+                if current[offset] == region.bytes[offset] {
+                    offset += 1;
+                    continue;
+                }
+                let run_start = offset;
+                while offset < current.len() && current[offset] != region.bytes[offset] {
+                    offset += 1;
+                }
+                changes.push((start + run_start, region.bytes[run_start..offset].to_vec()));
+            }
+            region.bytes = current;
+        }
+        changes
+    }
+
+    fn shadow_for(&mut self, addr: usize) -> Option<&mut ShadowRegion> {
+        self.shadow
+            .iter_mut()
+            .find(|region| region.start <= addr && addr < region.end())
+    }
+
+    fn read(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        let mut bytes = vec![0; len];
+        self.mem.read_exact_at(&mut bytes, addr as u64).ok()?;
+        Some(bytes)
+    }
+}
+
+/// Whether we keep a shadow of a mapping: anything the process can write to, except the
+/// kernel's vvar page, which changes on its own.
+fn is_recorded(entry: &procfs::MapEntry) -> bool {
+    entry.perms.starts_with("rw") && entry.path.as_deref() != Some("[vvar]")
+}
@@ -0,0 +1,88 @@
+//! Saving breakpoints and the like between runs of deet. They are written out as the deet
+//! commands that recreate them (`break main`, `dprintf 12,"x=%d\n",x`, ...), naming locations
+//! the way the user gave them rather than by address, so that restoring them against a rebuilt
+//! binary finds the same functions and lines in its new debug info.
+//!
+//! Besides `save breakpoints <file>`, each target gets a session file that is written when deet
+//! exits, however it exits, and restored when it starts debugging the same target again.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Where the session file for `target` lives: `~/.deet/sessions/`, named after the target's
+/// full path.
+pub fn session_path(target: &str) -> Option<PathBuf> {
+    let home = std::env::var("HOME").ok()?;
+    Some(session_file(Path::new(&home), target))
+}
+
+fn session_file(home: &Path, target: &str) -> PathBuf {
+    let target = fs::canonicalize(target).unwrap_or_else(|_| PathBuf::from(target));
+    let name = target.to_string_lossy().trim_start_matches('/').replace('/', "%");
+    home.join(".deet").join("sessions").join(name)
+}
+
+pub fn write_commands(path: &Path, target: &str, commands: &[String]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::File::create(path)?;
+    writeln!(file, "# deet session for {}", target)?;
+    for command in commands {
+        writeln!(file, "{}", command)?;
+    }
+    Ok(())
+}
+
+/// The commands in a file written by write_commands (or by hand), skipping blank lines and
+/// `#` comments.
+pub fn read_commands(path: &Path) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_file() {
+        assert_eq!(
+            session_file(Path::new("/home/me"), "/no/such/dir/prog"),
+            Path::new("/home/me/.deet/sessions/no%such%dir%prog")
+        );
+    }
+
+    #[test]
+    fn test_write_and_read_commands() {
+        let path = std::env::temp_dir()
+            .join(format!("deet-session-test-{}", std::process::id()))
+            .join("session");
+        let commands = vec![
+            String::from("break main"),
+            String::from("commands"),
+            String::from("silent"),
+            String::from("print x"),
+            String::from("end"),
+            String::from("dprintf count.c:6,\"i = %d\\n\",i"),
+        ];
+        write_commands(&path, "/tmp/prog", &commands).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().starts_with("# deet session for /tmp/prog\n"));
+        assert_eq!(read_commands(&path).unwrap(), commands);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_read_commands_skips_comments_and_blank_lines() {
+        let path = std::env::temp_dir().join(format!("deet-session-comments-{}", std::process::id()));
+        fs::write(&path, "# saved by hand\n\n  break main  \n\t# another\ncatch exec\n").unwrap();
+        assert_eq!(read_commands(&path).unwrap(), vec!["break main", "catch exec"]);
+        fs::remove_file(&path).unwrap();
+        assert!(read_commands(&path).is_err());
+    }
+}
@@ -0,0 +1,576 @@
+//! x86-64 Linux system call names, and formatting of calls and results for catchpoints.
+
+use crate::inferior::Inferior;
+use nix::errno::Errno;
+
+/// (number, name, arguments). Each character of the argument string says how to show one
+/// argument, see `format_argument`. None means we don't know the arguments, and show all six.
+const SYSCALLS: &[(u64, &str, Option<&str>)] = &[
+    (0, "read", Some("fpu")),
+    (1, "write", Some("fbu")),
+    (2, "open", Some("sOo")),
+    (3, "close", Some("f")),
+    (4, "stat", Some("sp")),
+    (5, "fstat", Some("fp")),
+    (6, "lstat", Some("sp")),
+    (7, "poll", Some("pui")),
+    (8, "lseek", Some("fii")),
+    (9, "mmap", Some("puPMfi")),
+    (10, "mprotect", Some("puP")),
+    (11, "munmap", Some("pu")),
+    (12, "brk", Some("p")),
+    (13, "rt_sigaction", Some("ippu")),
+    (14, "rt_sigprocmask", Some("ippu")),
+    (15, "rt_sigreturn", Some("")),
+    (16, "ioctl", Some("fxp")),
+    (17, "pread64", Some("fpui")),
+    (18, "pwrite64", Some("fbui")),
+    (19, "readv", Some("fpi")),
+    (20, "writev", Some("fpi")),
+    (21, "access", Some("so")),
+    (22, "pipe", Some("p")),
+    (23, "select", Some("ipppp")),
+    (24, "sched_yield", Some("")),
+    (25, "mremap", Some("puuxp")),
+    (26, "msync", Some("pux")),
+    (27, "mincore", None),
+    (28, "madvise", Some("pui")),
+    (29, "shmget", None),
+    (30, "shmat", None),
+    (31, "shmctl", None),
+    (32, "dup", Some("f")),
+    (33, "dup2", Some("ff")),
+    (34, "pause", Some("")),
+    (35, "nanosleep", Some("pp")),
+    (36, "getitimer", None),
+    (37, "alarm", None),
+    (38, "setitimer", None),
+    (39, "getpid", Some("")),
+    (40, "sendfile", Some("ffpu")),
+    (41, "socket", Some("iii")),
+    (42, "connect", Some("fpu")),
+    (43, "accept", Some("fpp")),
+    (44, "sendto", Some("fbuxpu")),
+    (45, "recvfrom", Some("fpuxpp")),
+    (46, "sendmsg", Some("fpx")),
+    (47, "recvmsg", Some("fpx")),
+    (48, "shutdown", Some("fi")),
+    (49, "bind", Some("fpu")),
+    (50, "listen", Some("fi")),
+    (51, "getsockname", Some("fpp")),
+    (52, "getpeername", Some("fpp")),
+    (53, "socketpair", Some("iiip")),
+    (54, "setsockopt", Some("fiipu")),
+    (55, "getsockopt", Some("fiipp")),
+    (56, "clone", Some("xpppp")),
+    (57, "fork", Some("")),
+    (58, "vfork", Some("")),
+    (59, "execve", Some("spp")),
+    (60, "exit", Some("i")),
+    (61, "wait4", Some("ipxp")),
+    (62, "kill", Some("ii")),
+    (63, "uname", Some("p")),
+    (64, "semget", None),
+    (65, "semop", None),
+    (66, "semctl", None),
+    (67, "shmdt", None),
+    (68, "msgget", None),
+    (69, "msgsnd", None),
+    (70, "msgrcv", None),
+    (71, "msgctl", None),
+    (72, "fcntl", Some("fix")),
+    (73, "flock", Some("fx")),
+    (74, "fsync", Some("f")),
+    (75, "fdatasync", Some("f")),
+    (76, "truncate", Some("si")),
+    (77, "ftruncate", Some("fi")),
+    (78, "getdents", Some("fpu")),
+    (79, "getcwd", Some("pu")),
+    (80, "chdir", Some("s")),
+    (81, "fchdir", Some("f")),
+    (82, "rename", Some("ss")),
+    (83, "mkdir", Some("so")),
+    (84, "rmdir", Some("s")),
+    (85, "creat", Some("so")),
+    (86, "link", Some("ss")),
+    (87, "unlink", Some("s")),
+    (88, "symlink", Some("ss")),
+    (89, "readlink", Some("spu")),
+    (90, "chmod", Some("so")),
+    (91, "fchmod", Some("fo")),
+    (92, "chown", Some("sii")),
+    (93, "fchown", Some("fii")),
+    (94, "lchown", Some("sii")),
+    (95, "umask", Some("o")),
+    (96, "gettimeofday", Some("pp")),
+    (97, "getrlimit", Some("ip")),
+    (98, "getrusage", Some("ip")),
+    (99, "sysinfo", Some("p")),
+    (100, "times", Some("p")),
+    (101, "ptrace", None),
+    (102, "getuid", Some("")),
+    (103, "syslog", None),
+    (104, "getgid", Some("")),
+    (105, "setuid", Some("i")),
+    (106, "setgid", Some("i")),
+    (107, "geteuid", Some("")),
+    (108, "getegid", Some("")),
+    (109, "setpgid", Some("ii")),
+    (110, "getppid", Some("")),
+    (111, "getpgrp", Some("")),
+    (112, "setsid", Some("")),
+    (113, "setreuid", None),
+    (114, "setregid", None),
+    (115, "getgroups", None),
+    (116, "setgroups", None),
+    (117, "setresuid", None),
+    (118, "getresuid", None),
+    (119, "setresgid", None),
+    (120, "getresgid", None),
+    (121, "getpgid", None),
+    (122, "setfsuid", None),
+    (123, "setfsgid", None),
+    (124, "getsid", None),
+    (125, "capget", None),
+    (126, "capset", None),
+    (127, "rt_sigpending", None),
+    (128, "rt_sigtimedwait", None),
+    (129, "rt_sigqueueinfo", None),
+    (130, "rt_sigsuspend", None),
+    (131, "sigaltstack", None),
+    (132, "utime", None),
+    (133, "mknod", None),
+    (134, "uselib", None),
+    (135, "personality", None),
+    (136, "ustat", None),
+    (137, "statfs", None),
+    (138, "fstatfs", None),
+    (139, "sysfs", None),
+    (140, "getpriority", None),
+    (141, "setpriority", None),
+    (142, "sched_setparam", None),
+    (143, "sched_getparam", None),
+    (144, "sched_setscheduler", None),
+    (145, "sched_getscheduler", None),
+    (146, "sched_get_priority_max", None),
+    (147, "sched_get_priority_min", None),
+    (148, "sched_rr_get_interval", None),
+    (149, "mlock", None),
+    (150, "munlock", None),
+    (151, "mlockall", None),
+    (152, "munlockall", None),
+    (153, "vhangup", None),
+    (154, "modify_ldt", None),
+    (155, "pivot_root", None),
+    (156, "_sysctl", None),
+    (157, "prctl", Some("ixxxx")),
+    (158, "arch_prctl", Some("ix")),
+    (159, "adjtimex", None),
+    (160, "setrlimit", Some("ip")),
+    (161, "chroot", None),
+    (162, "sync", None),
+    (163, "acct", None),
+    (164, "settimeofday", None),
+    (165, "mount", None),
+    (166, "umount2", None),
+    (167, "swapon", None),
+    (168, "swapoff", None),
+    (169, "reboot", None),
+    (170, "sethostname", None),
+    (171, "setdomainname", None),
+    (172, "iopl", None),
+    (173, "ioperm", None),
+    (174, "create_module", None),
+    (175, "init_module", None),
+    (176, "delete_module", None),
+    (177, "get_kernel_syms", None),
+    (178, "query_module", None),
+    (179, "quotactl", None),
+    (180, "nfsservctl", None),
+    (181, "getpmsg", None),
+    (182, "putpmsg", None),
+    (183, "afs_syscall", None),
+    (184, "tuxcall", None),
+    (185, "security", None),
+    (186, "gettid", Some("")),
+    (187, "readahead", None),
+    (188, "setxattr", None),
+    (189, "lsetxattr", None),
+    (190, "fsetxattr", None),
+    (191, "getxattr", None),
+    (192, "lgetxattr", None),
+    (193, "fgetxattr", None),
+    (194, "listxattr", None),
+    (195, "llistxattr", None),
+    (196, "flistxattr", None),
+    (197, "removexattr", None),
+    (198, "lremovexattr", None),
+    (199, "fremovexattr", None),
+    (200, "tkill", None),
+    (201, "time", Some("p")),
+    (202, "futex", Some("pxippi")),
+    (203, "sched_setaffinity", None),
+    (204, "sched_getaffinity", Some("iup")),
+    (205, "set_thread_area", None),
+    (206, "io_setup", None),
+    (207, "io_destroy", None),
+    (208, "io_getevents", None),
+    (209, "io_submit", None),
+    (210, "io_cancel", None),
+    (211, "get_thread_area", None),
+    (212, "lookup_dcookie", None),
+    (213, "epoll_create", None),
+    (214, "epoll_ctl_old", None),
+    (215, "epoll_wait_old", None),
+    (216, "remap_file_pages", None),
+    (217, "getdents64", Some("fpu")),
+    (218, "set_tid_address", Some("p")),
+    (219, "restart_syscall", None),
+    (220, "semtimedop", None),
+    (221, "fadvise64", None),
+    (222, "timer_create", None),
+    (223, "timer_settime", None),
+    (224, "timer_gettime", None),
+    (225, "timer_getoverrun", None),
+    (226, "timer_delete", None),
+    (227, "clock_settime", None),
+    (228, "clock_gettime", Some("ip")),
+    (229, "clock_getres", None),
+    (230, "clock_nanosleep", Some("ixpp")),
+    (231, "exit_group", Some("i")),
+    (232, "epoll_wait", Some("fpii")),
+    (233, "epoll_ctl", Some("fifp")),
+    (234, "tgkill", Some("iii")),
+    (235, "utimes", None),
+    (236, "vserver", None),
+    (237, "mbind", None),
+    (238, "set_mempolicy", None),
+    (239, "get_mempolicy", None),
+    (240, "mq_open", None),
+    (241, "mq_unlink", None),
+    (242, "mq_timedsend", None),
+    (243, "mq_timedreceive", None),
+    (244, "mq_notify", None),
+    (245, "mq_getsetattr", None),
+    (246, "kexec_load", None),
+    (247, "waitid", None),
+    (248, "add_key", None),
+    (249, "request_key", None),
+    (250, "keyctl", None),
+    (251, "ioprio_set", None),
+    (252, "ioprio_get", None),
+    (253, "inotify_init", None),
+    (254, "inotify_add_watch", None),
+    (255, "inotify_rm_watch", None),
+    (256, "migrate_pages", None),
+    (257, "openat", Some("fsOo")),
+    (258, "mkdirat", Some("fso")),
+    (259, "mknodat", None),
+    (260, "fchownat", None),
+    (261, "futimesat", None),
+    (262, "newfstatat", Some("fspx")),
+    (263, "unlinkat", Some("fsx")),
+    (264, "renameat", Some("fsfs")),
+    (265, "linkat", None),
+    (266, "symlinkat", None),
+    (267, "readlinkat", Some("fspu")),
+    (268, "fchmodat", Some("fso")),
+    (269, "faccessat", Some("fso")),
+    (270, "pselect6", None),
+    (271, "ppoll", None),
+    (272, "unshare", None),
+    (273, "set_robust_list", Some("pu")),
+    (274, "get_robust_list", None),
+    (275, "splice", None),
+    (276, "tee", None),
+    (277, "sync_file_range", None),
+    (278, "vmsplice", None),
+    (279, "move_pages", None),
+    (280, "utimensat", None),
+    (281, "epoll_pwait", None),
+    (282, "signalfd", None),
+    (283, "timerfd_create", None),
+    (284, "eventfd", None),
+    (285, "fallocate", None),
+    (286, "timerfd_settime", None),
+    (287, "timerfd_gettime", None),
+    (288, "accept4", Some("fppx")),
+    (289, "signalfd4", None),
+    (290, "eventfd2", Some("ux")),
+    (291, "epoll_create1", Some("x")),
+    (292, "dup3", Some("ffx")),
+    (293, "pipe2", Some("px")),
+    (294, "inotify_init1", Some("x")),
+    (295, "preadv", None),
+    (296, "pwritev", None),
+    (297, "rt_tgsigqueueinfo", None),
+    (298, "perf_event_open", None),
+    (299, "recvmmsg", None),
+    (300, "fanotify_init", None),
+    (301, "fanotify_mark", None),
+    (302, "prlimit64", Some("iipp")),
+    (303, "name_to_handle_at", None),
+    (304, "open_by_handle_at", None),
+    (305, "clock_adjtime", None),
+    (306, "syncfs", None),
+    (307, "sendmmsg", None),
+    (308, "setns", None),
+    (309, "getcpu", None),
+    (310, "process_vm_readv", None),
+    (311, "process_vm_writev", None),
+    (312, "kcmp", None),
+    (313, "finit_module", None),
+    (314, "sched_setattr", None),
+    (315, "sched_getattr", None),
+    (316, "renameat2", None),
+    (317, "seccomp", None),
+    (318, "getrandom", Some("pux")),
+    (319, "memfd_create", None),
+    (320, "kexec_file_load", None),
+    (321, "bpf", None),
+    (322, "execveat", Some("fsppx")),
+    (323, "userfaultfd", None),
+    (324, "membarrier", None),
+    (325, "mlock2", None),
+    (326, "copy_file_range", None),
+    (327, "preadv2", None),
+    (328, "pwritev2", None),
+    (329, "pkey_mprotect", None),
+    (330, "pkey_alloc", None),
+    (331, "pkey_free", None),
+    (332, "statx", Some("fsxxp")),
+    (333, "io_pgetevents", None),
+    (334, "rseq", Some("puxx")),
+    (424, "pidfd_send_signal", None),
+    (425, "io_uring_setup", None),
+    (426, "io_uring_enter", None),
+    (427, "io_uring_register", None),
+    (428, "open_tree", None),
+    (429, "move_mount", None),
+    (430, "fsopen", None),
+    (431, "fsconfig", None),
+    (432, "fsmount", None),
+    (433, "fspick", None),
+    (434, "pidfd_open", None),
+    (435, "clone3", Some("pu")),
+    (436, "close_range", Some("ffx")),
+    (437, "openat2", None),
+    (438, "pidfd_getfd", None),
+    (439, "faccessat2", Some("fsox")),
+    (440, "process_madvise", None),
+    (441, "epoll_pwait2", None),
+    (442, "mount_setattr", None),
+    (443, "quotactl_fd", None),
+    (444, "landlock_create_ruleset", None),
+    (445, "landlock_add_rule", None),
+    (446, "landlock_restrict_self", None),
+    (447, "memfd_secret", None),
+    (448, "process_mrelease", None),
+    (449, "futex_waitv", None),
+    (450, "set_mempolicy_home_node", None),
+];
+
+/// Syscalls that return an address rather than a number
+const RETURNS_ADDRESS: &[&str] = &["mmap", "mremap", "brk", "shmat"];
+
+/// How much of a string or buffer argument we show before cutting it off
+const MAX_STRING_ARGUMENT: usize = 32;
+
+/// `AT_FDCWD`, passed as a directory fd to mean the current directory
+const AT_FDCWD: i32 = -100;
+
+/// Flags for open and openat, other than the access mode. O_SYNC comes before O_DSYNC, which is
+/// part of it.
+const OPEN_FLAGS: &[(&str, i32)] = &[
+    ("O_CREAT", libc::O_CREAT),
+    ("O_EXCL", libc::O_EXCL),
+    ("O_NOCTTY", libc::O_NOCTTY),
+    ("O_TRUNC", libc::O_TRUNC),
+    ("O_APPEND", libc::O_APPEND),
+    ("O_NONBLOCK", libc::O_NONBLOCK),
+    ("O_SYNC", libc::O_SYNC),
+    ("O_DSYNC", libc::O_DSYNC),
+    ("O_DIRECT", libc::O_DIRECT),
+    ("O_LARGEFILE", libc::O_LARGEFILE),
+    ("O_DIRECTORY", libc::O_DIRECTORY),
+    ("O_NOFOLLOW", libc::O_NOFOLLOW),
+    ("O_NOATIME", libc::O_NOATIME),
+    ("O_CLOEXEC", libc::O_CLOEXEC),
+    ("O_PATH", libc::O_PATH),
+];
+
+const PROT_FLAGS: &[(&str, i32)] = &[
+    ("PROT_READ", libc::PROT_READ),
+    ("PROT_WRITE", libc::PROT_WRITE),
+    ("PROT_EXEC", libc::PROT_EXEC),
+];
+
+const MAP_FLAGS: &[(&str, i32)] = &[
+    ("MAP_SHARED", libc::MAP_SHARED),
+    ("MAP_PRIVATE", libc::MAP_PRIVATE),
+    ("MAP_FIXED", libc::MAP_FIXED),
+    ("MAP_ANONYMOUS", libc::MAP_ANONYMOUS),
+    ("MAP_GROWSDOWN", libc::MAP_GROWSDOWN),
+    ("MAP_DENYWRITE", libc::MAP_DENYWRITE),
+    ("MAP_NORESERVE", libc::MAP_NORESERVE),
+    ("MAP_POPULATE", libc::MAP_POPULATE),
+    ("MAP_STACK", libc::MAP_STACK),
+];
+
+pub fn name(number: u64) -> Option<&'static str> {
+    SYSCALLS
+        .iter()
+        .find(|(num, _, _)| *num == number)
+        .map(|(_, name, _)| *name)
+}
+
+pub fn number(name: &str) -> Option<u64> {
+    SYSCALLS
+        .iter()
+        .find(|(_, syscall, _)| *syscall == name)
+        .map(|(number, _, _)| *number)
+}
+
+/// Formats a call the way strace does, e.g. `openat(AT_FDCWD, "/etc/passwd", 0x80000)`. String
+/// arguments are read out of the inferior's memory.
+pub fn format_call(number: u64, args: &[u64; 6], inferior: &Inferior) -> String {
+    let entry = SYSCALLS.iter().find(|(num, _, _)| *num == number);
+    let (name, kinds) = match entry {
+        Some((_, name, Some(kinds))) => (name.to_string(), *kinds),
+        Some((_, name, None)) => (name.to_string(), "xxxxxx"),
+        None => (format!("syscall_{}", number), "xxxxxx"),
+    };
+    let formatted: Vec<String> = kinds
+        .chars()
+        .enumerate()
+        .map(|(i, kind)| format_argument(kind, args[i], args.get(i + 1).cloned(), inferior))
+        .collect();
+    format!("{}({})", name, formatted.join(", "))
+}
+
+/// Formats one argument:
+///   i: signed int, u: unsigned, x: hex flags, o: octal mode, p: pointer, f: file descriptor,
+///   s: NUL-terminated string, b: buffer whose length is the next argument,
+///   O: open flags, P: mmap protection, M: mmap flags
+fn format_argument(kind: char, value: u64, next: Option<u64>, inferior: &Inferior) -> String {
+    match kind {
+        'O' => format_open_flags(value as i32),
+        'P' => format_flags(value as i32, PROT_FLAGS, "PROT_NONE"),
+        'M' => format_flags(value as i32, MAP_FLAGS, "0"),
+        'i' => format!("{}", value as i32),
+        'u' => format!("{}", value),
+        'o' if value != 0 => format!("0{:o}", value),
+        'o' => String::from("0"),
+        'f' if value as i32 == AT_FDCWD => String::from("AT_FDCWD"),
+        'f' => format!("{}", value as i32),
+        'p' if value == 0 => String::from("NULL"),
+        's' if value != 0 => match inferior.read_string(value as usize, MAX_STRING_ARGUMENT + 1) {
+            Ok(text) => format_string(text.as_bytes()),
+            Err(_) => format!("0x{:x}", value),
+        },
+        'b' if value != 0 => {
+            let len = next.unwrap_or(0).min(MAX_STRING_ARGUMENT as u64 + 1) as usize;
+            match inferior.read_bytes(value as usize, len) {
+                Ok(bytes) => format_string(&bytes),
+                Err(_) => format!("0x{:x}", value),
+            }
+        }
+        _ => format!("0x{:x}", value),
+    }
+}
+
+/// Formats open(2) flags as `O_WRONLY|O_CREAT|O_TRUNC`.
+pub fn format_open_flags(value: i32) -> String {
+    let access_mode = match value & libc::O_ACCMODE {
+        libc::O_RDONLY => "O_RDONLY",
+        libc::O_WRONLY => "O_WRONLY",
+        _ => "O_RDWR",
+    };
+    match format_flags(value & !libc::O_ACCMODE, OPEN_FLAGS, "") {
+        flags if flags.is_empty() => access_mode.to_string(),
+        flags => format!("{}|{}", access_mode, flags),
+    }
+}
+
+/// Formats a bitmask as `A|B|0x40`, naming the bits it can and showing any left over in hex.
+fn format_flags(value: i32, names: &[(&str, i32)], none: &str) -> String {
+    let mut remaining = value;
+    let mut parts = Vec::new();
+    for (name, bits) in names {
+        if *bits != 0 && remaining & bits == *bits {
+            parts.push(name.to_string());
+            remaining &= !bits;
+        }
+    }
+    if remaining != 0 {
+        parts.push(format!("0x{:x}", remaining));
+    }
+    if parts.is_empty() {
+        none.to_string()
+    } else {
+        parts.join("|")
+    }
+}
+
+/// Quotes and escapes a string argument, with `...` after it if it was cut off.
+fn format_string(bytes: &[u8]) -> String {
+    let shown = &bytes[..bytes.len().min(MAX_STRING_ARGUMENT)];
+    let quoted = format!("{:?}", String::from_utf8_lossy(shown));
+    if bytes.len() > MAX_STRING_ARGUMENT {
+        format!("{}...", quoted)
+    } else {
+        quoted
+    }
+}
+
+/// Formats a syscall's result: errors as `-1 ENOENT (No such file or directory)`.
+pub fn format_return(number: u64, value: i64) -> String {
+    if (-4095..0).contains(&value) {
+        let errno = Errno::from_i32(-value as i32);
+        return format!("-1 {:?} ({})", errno, errno.desc());
+    }
+    match name(number) {
+        Some(name) if RETURNS_ADDRESS.contains(&name) => format!("0x{:x}", value),
+        _ => format!("{}", value),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(name(0), Some("read"));
+        assert_eq!(name(59), Some("execve"));
+        assert_eq!(number("openat"), Some(257));
+        assert_eq!(number("no_such_syscall"), None);
+    }
+
+    #[test]
+    fn test_format_return() {
+        assert_eq!(format_return(1, 9), "9");
+        assert_eq!(format_return(12, 0x555555559000), "0x555555559000");
+        assert_eq!(format_return(257, -2), "-1 ENOENT (No such file or directory)");
+    }
+
+    #[test]
+    fn test_format_flags() {
+        assert_eq!(format_open_flags(libc::O_RDONLY | libc::O_CLOEXEC), "O_RDONLY|O_CLOEXEC");
+        assert_eq!(
+            format_open_flags(libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC),
+            "O_WRONLY|O_CREAT|O_TRUNC"
+        );
+        assert_eq!(format_flags(0, PROT_FLAGS, "PROT_NONE"), "PROT_NONE");
+        assert_eq!(
+            format_flags(libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | 0x40000000, MAP_FLAGS, "0"),
+            "MAP_PRIVATE|MAP_ANONYMOUS|0x40000000"
+        );
+    }
+
+    #[test]
+    fn test_format_string() {
+        assert_eq!(format_string(b"total 14\n"), "\"total 14\\n\"");
+        assert_eq!(format_string(&[b'a'; 40]), format!("\"{}\"...", "a".repeat(32)));
+    }
+}
@@ -0,0 +1,313 @@
+//! Split-screen terminal UI (`deet --tui`).
+//!
+//! Commands typed into the command pane are parsed with `DebuggerCommand::from_line` and run
+//! through `Debugger::execute`, exactly like the line-oriented `Debugger::run` loop, so every
+//! command works the same way in both modes. Whatever the commands (or the inferior) print is
+//! captured and shown in the command pane.
+
+use crate::arch;
+use crate::debugger::Debugger;
+use crate::debugger_command::DebuggerCommand;
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use nix::unistd::{close, dup, dup2, pipe};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tui::backend::{Backend, CrosstermBackend};
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Paragraph};
+use tui::{Frame, Terminal};
+
+/// Number of output lines kept around for the command pane.
+const MAX_OUTPUT_LINES: usize = 1000;
+
+/// Redirects our stdout and stderr into a pipe and collects everything written to it. The
+/// inferior inherits these file descriptors, so its output lands in the command pane too instead
+/// of scribbling over the screen.
+struct OutputCapture {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    saved_stdout: RawFd,
+    saved_stderr: RawFd,
+}
+
+impl OutputCapture {
+    fn start() -> Result<OutputCapture, nix::Error> {
+        let saved_stdout = dup(1)?;
+        let saved_stderr = dup(2)?;
+        let (read_fd, write_fd) = pipe()?;
+        dup2(write_fd, 1)?;
+        dup2(write_fd, 2)?;
+        close(write_fd)?;
+
+        let lines = Arc::new(Mutex::new(VecDeque::new()));
+        let thread_lines = lines.clone();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(unsafe { fs::File::from_raw_fd(read_fd) });
+            let mut buf = Vec::new();
+            loop {
+                buf.clear();
+                match reader.read_until(b'\n', &mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        let line = String::from_utf8_lossy(&buf).trim_end().to_string();
+                        let mut lines = thread_lines.lock().unwrap();
+                        lines.push_back(line);
+                        if lines.len() > MAX_OUTPUT_LINES {
+                            lines.pop_front();
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(OutputCapture {
+            lines,
+            saved_stdout,
+            saved_stderr,
+        })
+    }
+
+    /// Returns a handle to the real terminal, which is what the UI draws on.
+    fn terminal(&self) -> Result<fs::File, nix::Error> {
+        Ok(unsafe { fs::File::from_raw_fd(dup(self.saved_stdout)?) })
+    }
+
+    /// Returns the last `n` lines of captured output.
+    fn tail(&self, n: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+        lines.iter().skip(lines.len().saturating_sub(n)).cloned().collect()
+    }
+
+    fn stop(self) {
+        io::stdout().flush().ok();
+        dup2(self.saved_stdout, 1).ok();
+        dup2(self.saved_stderr, 2).ok();
+        close(self.saved_stdout).ok();
+        close(self.saved_stderr).ok();
+    }
+}
+
+/// Runs the debugger with the split-screen UI until the user quits.
+pub fn run(debugger: &mut Debugger) -> Result<(), Box<dyn Error>> {
+    let capture = OutputCapture::start()?;
+    let mut out = capture.terminal()?;
+    enable_raw_mode()?;
+    execute!(out, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(out))?;
+    terminal.clear()?;
+
+    let result = event_loop(debugger, &mut terminal, &capture);
+
+    disable_raw_mode().ok();
+    execute!(terminal.backend_mut(), LeaveAlternateScreen).ok();
+    terminal.show_cursor().ok();
+    capture.stop();
+    result
+}
+
+fn event_loop<B: Backend>(
+    debugger: &mut Debugger,
+    terminal: &mut Terminal<B>,
+    capture: &OutputCapture,
+) -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    let mut history: Vec<String> = Vec::new();
+    let mut history_pos = 0;
+    loop {
+        terminal.draw(|f| draw(f, debugger, capture, &input))?;
+
+        // Poll with a timeout so that output from a running inferior keeps showing up.
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) => key,
+            _ => continue,
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') if ctrl => {
+                println!("Type \"quit\" to exit");
+            }
+            KeyCode::Char('d') if ctrl && input.is_empty() => {
+                debugger.execute(DebuggerCommand::Quit);
+                return Ok(());
+            }
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Up if history_pos > 0 => {
+                history_pos -= 1;
+                input = history[history_pos].clone();
+            }
+            KeyCode::Down if history_pos < history.len() => {
+                history_pos += 1;
+                input = history.get(history_pos).cloned().unwrap_or_default();
+            }
+            KeyCode::Enter => {
+                let line = std::mem::replace(&mut input, String::new());
+                if line.trim().is_empty() {
+                    continue;
+                }
+                println!("(deet) {}", line);
+                history.push(line.clone());
+                history_pos = history.len();
+
+                if debugger.add_command_list_line(&line) {
+                    continue;
+                }
+                match DebuggerCommand::from_line(&line) {
+                    Some(cmd) => {
+                        // Leave raw mode while the command runs, so that ctrl+c reaches the
+                        // inferior as a SIGINT like it does in the line-oriented mode.
+                        disable_raw_mode()?;
+                        let keep_going = debugger.execute(cmd);
+                        enable_raw_mode()?;
+                        if !keep_going {
+                            return Ok(());
+                        }
+                    }
+                    None => println!("Unrecognized command."),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw<B: Backend>(f: &mut Frame<B>, debugger: &Debugger, capture: &OutputCapture, input: &str) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+        .split(f.size());
+    let top = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+        .split(rows[0]);
+    let bottom = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(65), Constraint::Percentage(35)].as_ref())
+        .split(rows[1]);
+
+    let (title, source) = source_pane(debugger, inner_height(top[0]));
+    f.render_widget(pane(source, title), top[0]);
+    f.render_widget(pane(register_pane(debugger), "Registers".to_string()), top[1]);
+    f.render_widget(pane(backtrace_pane(debugger), "Backtrace".to_string()), bottom[1]);
+
+    let area = bottom[0];
+    let mut command: Vec<Spans> = capture
+        .tail(inner_height(area).saturating_sub(1))
+        .into_iter()
+        .map(Spans::from)
+        .collect();
+    let prompt = format!("(deet) {}", input);
+    let cursor_y = area.y + 1 + command.len() as u16;
+    let cursor_x = area.x + 1 + prompt.len() as u16;
+    command.push(Spans::from(prompt));
+    f.render_widget(pane(command, "Command".to_string()), area);
+    f.set_cursor(cursor_x.min(area.x + area.width.saturating_sub(2)), cursor_y);
+}
+
+fn pane(text: Vec<Spans>, title: String) -> Paragraph {
+    Paragraph::new(text).block(Block::default().borders(Borders::ALL).title(title))
+}
+
+/// Number of text rows that fit inside a bordered pane.
+fn inner_height(area: Rect) -> usize {
+    area.height.saturating_sub(2) as usize
+}
+
+/// Shows the source around the current line, marking the current line with `>` and lines that
+/// have breakpoints with `*`.
+fn source_pane(debugger: &Debugger, height: usize) -> (String, Vec<Spans<'static>>) {
+    let line = match debugger.current_line() {
+        Some(line) => line,
+        None => {
+            return (
+                "Source".to_string(),
+                vec![Spans::from("The program is not being run.")],
+            )
+        }
+    };
+    let contents = match fs::read_to_string(&line.file) {
+        Ok(contents) => contents,
+        Err(_) => return (line.to_string(), vec![Spans::from(format!("Could not read {}", line.file))]),
+    };
+    let breakpoints: Vec<usize> = debugger
+        .breakpoint_lines()
+        .iter()
+        .filter(|bp| bp.file == line.file)
+        .map(|bp| bp.number)
+        .collect();
+
+    let first = line.number.saturating_sub(height / 2).max(1);
+    let text = contents
+        .lines()
+        .enumerate()
+        .skip(first - 1)
+        .take(height)
+        .map(|(i, text)| {
+            let number = i + 1;
+            let is_breakpoint = breakpoints.contains(&number);
+            let is_current = number == line.number;
+            let style = if is_current {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else if is_breakpoint {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            };
+            Spans::from(Span::styled(
+                format!(
+                    "{}{} {:4}  {}",
+                    if is_breakpoint { "*" } else { " " },
+                    if is_current { ">" } else { " " },
+                    number,
+                    text
+                ),
+                style,
+            ))
+        })
+        .collect();
+    (line.to_string(), text)
+}
+
+fn register_pane(debugger: &Debugger) -> Vec<Spans<'static>> {
+    let regs = match debugger.registers() {
+        Some(regs) => regs,
+        None => return vec![Spans::from("The program has no registers now.")],
+    };
+    // The pc, stack and frame pointers first, then the rest in DWARF order
+    let arch = arch::native();
+    let first = [arch.pc_register(), arch.sp_register(), arch.fp_register()];
+    let rest = (0..regs.count()).filter(|register| !first.contains(register));
+    first
+        .iter()
+        .cloned()
+        .chain(rest)
+        .filter_map(|register| Some((arch.register_name(register)?, regs.get(register)?)))
+        .chain(std::iter::once(("flags", regs.flags)))
+        .map(|(name, value)| Spans::from(format!("{:<7}{:#018x}", name, value)))
+        .collect()
+}
+
+fn backtrace_pane(debugger: &Debugger) -> Vec<Spans<'static>> {
+    debugger
+        .backtrace()
+        .into_iter()
+        .enumerate()
+        .map(|(i, frame)| Spans::from(format!("#{} {}", i, frame)))
+        .collect()
+}
@@ -0,0 +1,428 @@
+//! Runs the programs in `samples/` under a Session and checks where they stop, what their
+//! backtraces look like and how they exit.
+
+use deet::{DebuggerCommand, Error, Session, StopEvent, StopLocation};
+use nix::sys::signal::Signal;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// Serializes compiling, since several tests share a sample
+static BUILD_LOCK: Mutex<()> = Mutex::new(());
+
+/// Compiles `samples/<name>.c` with the flags the Makefile uses, into a scratch directory,
+/// and returns the path of the program.
+fn build_sample(name: &str) -> PathBuf {
+    compile_sample(name, "-O0")
+}
+
+/// Compiles `samples/<name>.c` with the Makefile's flags, but at `optimization` rather than -O0.
+fn compile_sample(name: &str, optimization: &str) -> PathBuf {
+    let _guard = BUILD_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("samples")
+        .join(format!("{}.c", name));
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("samples");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let program = out_dir.join(name);
+    let up_to_date = match (program.metadata(), source.metadata()) {
+        (Ok(program), Ok(source)) => program.modified().unwrap() >= source.modified().unwrap(),
+        _ => false,
+    };
+    if !up_to_date {
+        let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+            .args(&[optimization, "-g", "-no-pie", "-fno-omit-frame-pointer", "-o"])
+            .arg(&program)
+            .arg(&source)
+            .status()
+            .expect("Could not run the C compiler");
+        assert!(status.success(), "Could not compile {}", source.display());
+    }
+    program
+}
+
+/// Compiles the pretty-printer plugin `samples/plugins/<name>.c` into a shared library.
+fn build_plugin(name: &str) -> PathBuf {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("samples")
+        .join("plugins")
+        .join(format!("{}.c", name));
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("samples");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let library = out_dir.join(format!("{}.so", name));
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .args(&["-shared", "-fPIC", "-o"])
+        .arg(&library)
+        .arg(&source)
+        .status()
+        .expect("Could not run the C compiler");
+    assert!(status.success(), "Could not compile {}", source.display());
+    library
+}
+
+fn session(name: &str) -> Session {
+    Session::new(build_sample(name).to_str().unwrap()).expect("Could not load the sample")
+}
+
+/// The function and line of a stop, for comparing against what the source says.
+fn place(location: &StopLocation) -> (String, usize) {
+    (
+        location.function.clone().unwrap_or_default(),
+        location.line.as_ref().map_or(0, |line| line.number),
+    )
+}
+
+fn stopped_at(event: StopEvent) -> (String, usize) {
+    match event {
+        StopEvent::Stopped(location) => place(&location),
+        other => panic!("Expected a stop, got {:?}", other),
+    }
+}
+
+fn backtrace(session: &Session) -> Vec<(String, usize)> {
+    session
+        .backtrace()
+        .unwrap()
+        .iter()
+        .map(|frame| (frame.function.clone(), frame.line.as_ref().map_or(0, |line| line.number)))
+        .collect()
+}
+
+/// The functions in the backtrace, innermost first.
+fn functions(session: &Session) -> Vec<String> {
+    backtrace(session).into_iter().map(|(function, _)| function).collect()
+}
+
+#[test]
+fn runs_to_exit() {
+    let mut session = session("hello");
+    assert_eq!(session.run(&[]).unwrap(), StopEvent::Exited(0));
+
+    // sleepy_print exits with 1 without an argument
+    let mut session = self::session("sleepy_print");
+    assert_eq!(session.run(&[]).unwrap(), StopEvent::Exited(1));
+    assert_eq!(session.run(&["0"]).unwrap(), StopEvent::Exited(1));
+}
+
+#[test]
+fn exit_syscall() {
+    let mut session = session("exit");
+    assert_eq!(session.run(&[]).unwrap(), StopEvent::Exited(0));
+}
+
+#[test]
+fn line_breakpoints_and_next() {
+    let mut session = session("count");
+    assert_eq!(session.break_at("count.c:6").unwrap().len(), 1);
+    assert_eq!(stopped_at(session.run(&[]).unwrap()), ("main".to_string(), 6));
+    // Each line ends with its call to printf, which returns to where the next line starts. next
+    // stops there rather than stepping on into the line after.
+    assert_eq!(stopped_at(session.next().unwrap()), ("main".to_string(), 7));
+    assert_eq!(stopped_at(session.next().unwrap()), ("main".to_string(), 8));
+    assert_eq!(session.cont().unwrap(), StopEvent::Exited(0));
+
+    let mut session = self::session("function_calls");
+    session.break_at("func1").unwrap();
+    assert_eq!(stopped_at(session.run(&[]).unwrap()), ("func1".to_string(), 17));
+    for line in 18..=20 {
+        assert_eq!(stopped_at(session.next().unwrap()), ("func1".to_string(), line));
+    }
+}
+
+#[test]
+fn function_breakpoints_variables_and_backtrace() {
+    let mut session = session("function_calls");
+    session.break_at("func2").unwrap();
+    assert_eq!(stopped_at(session.run(&[]).unwrap()).0, "func2");
+    assert_eq!(session.read_var("a").unwrap(), "42");
+    assert_eq!(session.read_var("b").unwrap(), "5");
+    assert_eq!(session.read_var("global").unwrap(), "5");
+    assert_eq!(
+        backtrace(&session),
+        vec![
+            ("func2".to_string(), 10),
+            ("func1".to_string(), 18),
+            ("main".to_string(), 24),
+        ]
+    );
+    assert!(matches!(session.read_var("nonexistent"), Err(Error::Evaluation(_))));
+    assert_eq!(session.cont().unwrap(), StopEvent::Exited(0));
+}
+
+#[test]
+fn function_breakpoints_skip_the_prologue() {
+    let mut session = session("function_calls");
+    // The prologue stores the arguments, so a breakpoint on the function goes where its first
+    // line starts, after it
+    let addrs = session.break_at("func3").unwrap();
+    assert_eq!(session.break_at("function_calls.c:6").unwrap(), addrs);
+    assert_eq!(stopped_at(session.run(&[]).unwrap()), ("func3".to_string(), 6));
+    assert_eq!(session.read_var("a").unwrap(), "100");
+}
+
+#[test]
+fn step_into_call() {
+    let mut session = session("function_calls");
+    session.break_at("main").unwrap();
+    assert_eq!(stopped_at(session.run(&[]).unwrap()), ("main".to_string(), 24));
+    // step goes on through the prologue, as far as a breakpoint on func1 would stop, so the
+    // argument has been stored
+    assert_eq!(stopped_at(session.step().unwrap()), ("func1".to_string(), 17));
+    assert_eq!(session.read_var("a").unwrap(), "42");
+    assert_eq!(backtrace(&session).len(), 2);
+}
+
+#[test]
+fn inlined_frames() {
+    let program = compile_sample("inline", "-O2");
+    let mut session = Session::new(program.to_str().unwrap()).unwrap();
+    // At -O2 main comes before compute, so the closest line with code, not the first one in
+    // address order, is what a line breakpoint goes on
+    assert_eq!(session.break_at("inline.c:22").unwrap().len(), 1);
+    assert_eq!(stopped_at(session.run(&[]).unwrap()), ("compute".to_string(), 22));
+
+    let mut session = Session::new(program.to_str().unwrap()).unwrap();
+    // square is inlined twice, into sum_squares, itself inlined into compute
+    assert_eq!(session.break_at("square").unwrap().len(), 2);
+    assert_eq!(stopped_at(session.run(&[]).unwrap()).0, "square");
+    assert_eq!(
+        backtrace(&session)[..3],
+        [
+            ("square".to_string(), 6),
+            ("sum_squares".to_string(), 11),
+            ("compute".to_string(), 23),
+        ]
+    );
+    assert_eq!(stopped_at(session.cont().unwrap()).0, "square");
+    assert_eq!(functions(&session)[..3], ["square", "sum_squares", "compute"]);
+    assert_eq!(session.cont().unwrap(), StopEvent::Exited(0));
+
+    // next steps over the inlined calls on line 23 as it would over real ones, while step goes
+    // into them
+    let mut session = Session::new(program.to_str().unwrap()).unwrap();
+    session.break_at("compute").unwrap();
+    assert_eq!(stopped_at(session.run(&[]).unwrap()).0, "compute");
+    assert_eq!(session.read_var("a").unwrap(), "1");
+    loop {
+        let (function, line) = stopped_at(session.next().unwrap());
+        assert_eq!(function, "compute");
+        if line == 24 {
+            break;
+        }
+    }
+    // a is dead by now, and a value that's optimized out is an error rather than text
+    assert!(matches!(session.read_var("a"), Err(Error::Evaluation(_))));
+    assert_eq!(stopped_at(session.run(&[]).unwrap()).0, "compute");
+    while stopped_at(session.step().unwrap()).0 == "compute" {}
+    assert_eq!(functions(&session)[..3], ["square", "sum_squares", "compute"]);
+}
+
+#[test]
+fn advance_past_the_end_of_main() {
+    // main exits with a syscall, so neither line 5 nor main's caller is reached, and the stops
+    // advance put there must not come back as breakpoints when the program is run again
+    let mut session = session("exit");
+    session.break_at("main").unwrap();
+    assert_eq!(stopped_at(session.run(&[]).unwrap()), ("main".to_string(), 4));
+    assert_eq!(session.advance("5").unwrap(), StopEvent::Exited(0));
+    let lines = |session: &mut Session| -> Vec<usize> {
+        session.debugger().breakpoint_lines().iter().map(|line| line.number).collect()
+    };
+    assert_eq!(lines(&mut session), [4]);
+    assert_eq!(stopped_at(session.run(&[]).unwrap()), ("main".to_string(), 4));
+    assert_eq!(lines(&mut session), [4]);
+    assert_eq!(session.cont().unwrap(), StopEvent::Exited(0));
+}
+
+#[test]
+fn temporary_breakpoints_until_and_advance() {
+    let mut session = session("function_calls");
+    session.break_at("func2").unwrap();
+    assert_eq!(stopped_at(session.run(&[]).unwrap()).0, "func2");
+    // func2 returns before line 20 is reached. The call is the last thing on line 18, so it
+    // returns to the start of line 19.
+    assert_eq!(stopped_at(session.advance("20").unwrap()), ("func1".to_string(), 19));
+    assert_eq!(stopped_at(session.until("20").unwrap()), ("func1".to_string(), 20));
+    assert!(matches!(session.advance("1000"), Err(Error::NoLocation(_))));
+    assert_eq!(session.cont().unwrap(), StopEvent::Exited(0));
+
+    // func3 is called twice, but the temporary breakpoint only stops the first time
+    assert_eq!(stopped_at(session.run(&[]).unwrap()).0, "func2");
+    assert_eq!(session.tbreak("func3").unwrap().len(), 1);
+    assert_eq!(stopped_at(session.cont().unwrap()).0, "func3");
+    assert_eq!(session.cont().unwrap(), StopEvent::Exited(0));
+
+    assert_eq!(stopped_at(session.run(&[]).unwrap()).0, "func2");
+    assert_eq!(stopped_at(session.advance("func3").unwrap()).0, "func3");
+    assert_eq!(backtrace(&session).len(), 4);
+}
+
+#[test]
+fn segfault() {
+    let mut session = session("segfault");
+    match session.run(&[]).unwrap() {
+        StopEvent::Signal(Signal::SIGSEGV, location) => {
+            assert_eq!(place(&location), ("func2".to_string(), 5))
+        }
+        other => panic!("Expected a segfault, got {:?}", other),
+    }
+    assert_eq!(
+        backtrace(&session),
+        vec![
+            ("func2".to_string(), 5),
+            ("func1".to_string(), 11),
+            ("main".to_string(), 15),
+        ]
+    );
+    // Continuing delivers the signal, which kills it
+    assert_eq!(session.cont().unwrap(), StopEvent::Killed(Signal::SIGSEGV));
+}
+
+#[test]
+fn vfork_and_threads() {
+    let mut session = session("forks");
+    session.break_at("after").unwrap();
+    // The vfork child runs with the breakpoint out of the memory it borrows, and the thread is
+    // stepped past it, so only the main thread stops there. The SIGCHLD from the child's exit
+    // is passed on without stopping.
+    assert_eq!(stopped_at(session.run(&[]).unwrap()), ("after".to_string(), 6));
+    assert_eq!(session.read_var("x").unwrap(), "3");
+    assert_eq!(session.cont().unwrap(), StopEvent::Exited(4));
+}
+
+#[test]
+fn save_and_restore_breakpoints() {
+    let file = Path::new(env!("CARGO_TARGET_TMPDIR")).join("function_calls.breakpoints");
+    let mut session = session("function_calls");
+    session.break_at("func3").unwrap();
+    command_list(&mut session, &["silent", "print a"]);
+    session.debugger().execute(DebuggerCommand::Display(String::from("global")));
+    session.debugger().execute(DebuggerCommand::Save(vec![
+        String::from("breakpoints"),
+        file.to_str().unwrap().to_string(),
+    ]));
+    let saved = std::fs::read_to_string(&file).unwrap();
+    assert!(saved.lines().any(|line| line == "break func3"), "{}", saved);
+    assert!(saved.lines().any(|line| line == "display global"), "{}", saved);
+    assert!(saved.contains("break func3\ncommands\nsilent\nprint a\nend\n"), "{}", saved);
+
+    // A fresh session picks the breakpoint up from the file
+    let mut session = self::session("function_calls");
+    session.debugger().execute(DebuggerCommand::Restore(file.to_str().unwrap().to_string()));
+    assert_eq!(stopped_at(session.run(&[]).unwrap()).0, "func3");
+    // and the commands with it: saving again writes them back the same
+    session.debugger().execute(DebuggerCommand::Save(vec![
+        String::from("breakpoints"),
+        file.to_str().unwrap().to_string(),
+    ]));
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), saved);
+}
+
+/// Gives the breakpoint set last the commands in `lines`, as if typed after `commands`.
+fn command_list(session: &mut Session, lines: &[&str]) {
+    let debugger = session.debugger();
+    assert!(!debugger.add_command_list_line("end"));
+    debugger.execute(DebuggerCommand::Commands(String::new()));
+    for line in lines.iter().chain(&["end"]) {
+        assert!(debugger.add_command_list_line(line));
+    }
+    // The list is over, so the next line is a command again
+    assert!(!debugger.add_command_list_line("next"));
+}
+
+#[test]
+fn breakpoint_commands() {
+    // func2's breakpoint continues without stopping, so the program first stops in func3
+    let mut session = session("function_calls");
+    session.break_at("func2").unwrap();
+    command_list(&mut session, &["silent", "continue"]);
+    session.break_at("func3").unwrap();
+    session.debugger().execute(DebuggerCommand::Run(Vec::new()));
+    assert_eq!(place(&session.location().unwrap()), ("func3".to_string(), 6));
+    assert_eq!(session.read_var("a").unwrap(), "100");
+
+    // The same goes for a breakpoint that next stops at, which continues to the end here
+    let mut session = self::session("function_calls");
+    session.break_at("func1").unwrap();
+    session.break_at("function_calls.c:19").unwrap();
+    command_list(&mut session, &["continue"]);
+    assert_eq!(stopped_at(session.run(&[]).unwrap()), ("func1".to_string(), 17));
+    session.debugger().execute(DebuggerCommand::Next);
+    assert_eq!(place(&session.location().unwrap()), ("func1".to_string(), 18));
+    session.debugger().execute(DebuggerCommand::Next);
+    assert!(session.location().is_err());
+
+    // Two breakpoints on one line: the first one's `continue` doesn't stop the second one's
+    // list, whose `next` ends at another breakpoint, and that one's list runs in turn
+    let mut session = self::session("function_calls");
+    session.break_at("func2").unwrap();
+    command_list(&mut session, &["silent", "continue"]);
+    session.break_at("function_calls.c:10").unwrap();
+    command_list(&mut session, &["next"]);
+    session.break_at("function_calls.c:11").unwrap();
+    command_list(&mut session, &["next"]);
+    session.debugger().execute(DebuggerCommand::Run(Vec::new()));
+    assert_eq!(place(&session.location().unwrap()), ("func2".to_string(), 12));
+    assert_eq!(session.read_var("sum").unwrap(), "47");
+}
+
+#[test]
+fn errors() {
+    assert!(matches!(
+        Session::new("samples/no_such_program"),
+        Err(Error::OpeningFile(..))
+    ));
+    let mut session = session("hello");
+    assert!(matches!(session.cont(), Err(Error::NotRunning)));
+    assert!(matches!(session.read_var("x"), Err(Error::NotRunning)));
+    assert!(matches!(session.break_at("hello.c:1000"), Err(Error::NoLocation(_))));
+
+    // The same program, labelled as one for aarch64 (EM_AARCH64 is 183)
+    let mut program = std::fs::read(build_sample("hello")).unwrap();
+    program[18..20].copy_from_slice(&183u16.to_le_bytes());
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hello.aarch64");
+    std::fs::write(&path, program).unwrap();
+    match Session::new(path.to_str().unwrap()) {
+        Err(Error::WrongArchitecture(_, arch)) => assert_eq!(arch, "aarch64"),
+        other => panic!("Expected WrongArchitecture, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn pretty_printer_file() {
+    let mut session = session("containers");
+    let printers = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples/containers.printers");
+    assert_eq!(session.load_printers(printers.to_str().unwrap()).unwrap(), 5);
+    session.break_at("containers.c:68").unwrap();
+    assert_eq!(stopped_at(session.run(&[]).unwrap()), ("main".to_string(), 68));
+    assert_eq!(session.read_var("numbers").unwrap(), "[1, 2, 3]");
+    assert!(session.read_var("first").unwrap().ends_with(" [1, 2, 3]"));
+    assert_eq!(
+        session.read_var("ages").unwrap(),
+        r#"{"carol": 45, "bob": 27, "ann": 31}"#
+    );
+    assert_eq!(session.read_var("name").unwrap(), r#""hello""#);
+    assert_eq!(session.read_var("origin").unwrap(), "(3, -4)");
+    assert!(matches!(
+        session.load_printers("samples/no_such_file.printers"),
+        Err(Error::OpeningFile(..))
+    ));
+}
+
+#[test]
+fn pretty_printer_plugin() {
+    let mut session = session("containers");
+    let plugin = build_plugin("point_printer");
+    assert_eq!(session.load_printers(plugin.to_str().unwrap()).unwrap(), 2);
+    session.break_at("containers.c:68").unwrap();
+    assert_eq!(stopped_at(session.run(&[]).unwrap()), ("main".to_string(), 68));
+    assert_eq!(session.read_var("origin").unwrap(), "point(3, -4)");
+    assert!(session.read_var("first").unwrap().ends_with(" 1 -> 2 -> 3"));
+    // Without a printer of its own, the list struct shows its fields, and the head through the
+    // plugin
+    assert!(session.read_var("numbers").unwrap().contains("1 -> 2 -> 3"));
+    assert!(matches!(
+        session.load_printers("samples/containers.c.so"),
+        Err(Error::Printer(_))
+    ));
+}